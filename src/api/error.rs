@@ -0,0 +1,187 @@
+use crate::datastore::error::DatastoreError;
+use rocket::data::ByteUnit;
+use rocket::http::Status;
+use rocket::request::Request;
+use rocket::response::{self, Responder, Response};
+use rocket::serde::json::{self, Json};
+use serde::Serialize;
+use serde_json::{json, Value};
+use std::io;
+
+/// Body of every failed response
+#[derive(Debug, Serialize)]
+pub struct ErrorBody {
+    /// Stable, machine readable name of the error, e.g. `DUPLICATE_KEY`
+    pub code: String,
+    pub message: String,
+    /// What a client needs to act on the error beyond its message, or null
+    pub details: Value,
+}
+
+/// A failed request, responding with its status and an `ErrorBody`
+///
+/// # Examples
+///
+/// ```json
+/// # 409 Conflict
+/// {
+///   "code": "DUPLICATE_KEY",
+///   "message": "duplicate key in index email_1: email=\"john@example.com\"",
+///   "details": {"index": "email_1", "key": {"email": "john@example.com"}}
+/// }
+/// ```
+#[derive(Debug)]
+pub struct ApiError {
+    pub status: Status,
+    /// Boxed to keep the `Err` of route results small
+    pub body: Box<ErrorBody>,
+}
+
+impl ApiError {
+    /// Produces an ApiError without details
+    ///
+    /// # Arguments
+    ///
+    /// * `status` - the HTTP status to respond with
+    /// * `code` - stable, machine readable name of the error
+    /// * `message` - what went wrong, for people
+    pub fn new(status: Status, code: &str, message: impl Into<String>) -> ApiError {
+        ApiError {
+            status,
+            body: Box::new(ErrorBody {
+                code: String::from(code),
+                message: message.into(),
+                details: Value::Null,
+            }),
+        }
+    }
+
+    /// Produces the ApiError of a body larger than its limit
+    pub fn payload_too_large(limit: ByteUnit) -> ApiError {
+        ApiError::new(
+            Status::PayloadTooLarge,
+            "PAYLOAD_TOO_LARGE",
+            format!("the body is larger than the limit of {}", limit),
+        )
+    }
+
+    /// Produces the ApiError of a body that could not be read
+    pub fn unreadable(error: io::Error) -> ApiError {
+        ApiError::new(
+            Status::BadRequest,
+            "UNREADABLE_BODY",
+            format!("the body could not be read: {}", error),
+        )
+    }
+
+    /// Produces the ApiError Rocket's error status would otherwise respond
+    /// with, named after the status, e.g. `NOT_FOUND` for 404
+    pub fn from_status(status: Status) -> ApiError {
+        let reason = status.reason().unwrap_or("Unknown Error");
+        let code = reason
+            .chars()
+            .map(|c| match c {
+                ' ' | '-' => '_',
+                c => c.to_ascii_uppercase(),
+            })
+            .filter(|c| c.is_ascii_alphanumeric() || *c == '_')
+            .collect::<String>();
+        ApiError::new(status, &code, reason)
+    }
+}
+
+impl From<DatastoreError> for ApiError {
+    fn from(error: DatastoreError) -> Self {
+        let status = match &error {
+            DatastoreError::InvalidId => Status::InternalServerError,
+            DatastoreError::DuplicateKey { .. }
+            | DatastoreError::WriteConflict { .. }
+            | DatastoreError::CollectionExists(_) => Status::Conflict,
+            DatastoreError::TransactionNotFound | DatastoreError::CollectionNotFound(_) => {
+                Status::NotFound
+            }
+            DatastoreError::ChangeHistoryLost(_) => Status::Gone,
+            _ => Status::BadRequest,
+        };
+        ApiError {
+            status,
+            body: Box::new(ErrorBody {
+                code: String::from(error.code()),
+                message: error.to_string(),
+                details: error.details(),
+            }),
+        }
+    }
+}
+
+/// A JSON body that could not be read or parsed. A JSON guard wrapped in a
+/// `Result` hands the error to the route, which responds with this instead
+/// of Rocket's HTML error page.
+impl<'a> From<json::Error<'a>> for ApiError {
+    fn from(error: json::Error<'a>) -> Self {
+        match error {
+            json::Error::Io(e) if e.kind() == io::ErrorKind::UnexpectedEof => {
+                ApiError::new(Status::PayloadTooLarge, "PAYLOAD_TOO_LARGE", e.to_string())
+            }
+            json::Error::Io(e) => ApiError::unreadable(e),
+            json::Error::Parse(_, e) => ApiError::from(DatastoreError::Malformed(e.to_string())),
+        }
+    }
+}
+
+impl<'r> Responder<'r, 'static> for ApiError {
+    fn respond_to(self, request: &'r Request<'_>) -> response::Result<'static> {
+        Response::build_from(Json(*self.body).respond_to(request)?)
+            .status(self.status)
+            .ok()
+    }
+}
+
+/// Responds to every failure no route handled, such as an unknown path or
+/// a request guard that failed, with an `ErrorBody` rather than HTML
+#[catch(default)]
+pub fn default_catcher(status: Status, request: &Request) -> ApiError {
+    let mut error = ApiError::from_status(status);
+    error.body.details = json!({
+        "method": request.method().as_str(),
+        "path": request.uri().path().as_str(),
+    });
+    error
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::datastore::datatypes::DataType;
+
+    #[test]
+    fn statuses_and_codes() {
+        let error = ApiError::from(DatastoreError::DuplicateKey {
+            index: String::from("email_1"),
+            key: vec![(String::from("email"), DataType::I64(1))],
+        });
+        assert_eq!(Status::Conflict, error.status);
+        assert_eq!("DUPLICATE_KEY", error.body.code);
+        assert_eq!(
+            json!({"index": "email_1", "key": {"email": 1}}),
+            error.body.details
+        );
+
+        let error = ApiError::from(DatastoreError::TransactionNotFound);
+        assert_eq!(Status::NotFound, error.status);
+        assert_eq!("TRANSACTION_NOT_FOUND", error.body.code);
+
+        let error = ApiError::from_status(Status::UnprocessableEntity);
+        assert_eq!("UNPROCESSABLE_ENTITY", error.body.code);
+        assert_eq!("Unprocessable Entity", error.body.message);
+    }
+
+    #[test]
+    fn malformed_json() {
+        let parsed = serde_json::from_str::<Value>("{\"a\": ").unwrap_err();
+        let error = ApiError::from(json::Error::Parse("{\"a\": ", parsed));
+        assert_eq!(Status::BadRequest, error.status);
+        assert_eq!("MALFORMED", error.body.code);
+        assert!(error.body.message.starts_with("malformed input: EOF"));
+    }
+}
@@ -0,0 +1,483 @@
+use crate::api::error::{ApiError, ErrorBody};
+use crate::api::v2::to_json_document;
+use crate::datastore::database::{self, Database, SafeCollection};
+use crate::datastore::error::DatastoreError;
+use crate::datastore::live::{Diff, LiveQuery};
+use rocket::fairing::AdHoc;
+use rocket::futures::{SinkExt, StreamExt};
+use rocket::tokio::net::{TcpListener, TcpStream};
+use rocket::tokio::time::sleep;
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use std::collections::BTreeMap;
+use std::io;
+use std::net::{IpAddr, SocketAddr};
+use std::sync::Arc;
+use std::time::Duration;
+use tokio_tungstenite::tungstenite::error::ProtocolError;
+use tokio_tungstenite::tungstenite::protocol::WebSocketConfig;
+use tokio_tungstenite::tungstenite::{Error, Message};
+
+/// Largest message accepted from a client
+pub const MAX_MESSAGE_SIZE: usize = 1024 * 1024;
+/// Milliseconds between looking for writes to the subscribed collections
+const POLL_MILLIS: u64 = 100;
+
+/// Produces the fairing starting the live query listener once Rocket has
+/// launched. Rocket cannot upgrade its own connections to WebSockets, so
+/// the listener serves the managed Database on a port of its own. It is
+/// off unless the `live_port` configuration value is set, e.g.
+/// `ROCKET_LIVE_PORT=8001`, and binds the `live_address` value, Rocket's
+/// address by default.
+pub fn listener() -> AdHoc {
+    AdHoc::on_liftoff("Live queries", |rocket| {
+        Box::pin(async move {
+            let port = match rocket.figment().extract_inner::<u16>("live_port") {
+                Ok(port) => port,
+                Err(_) => return,
+            };
+            let address = rocket
+                .figment()
+                .extract_inner::<IpAddr>("live_address")
+                .unwrap_or(rocket.config().address);
+            let address = SocketAddr::new(address, port);
+            let database = match rocket.state::<Database>() {
+                Some(database) => database.clone(),
+                None => return println!("LIVE: no Database is managed, not listening"),
+            };
+            match TcpListener::bind(address).await {
+                Ok(listener) => {
+                    println!("LIVE: listening on ws://{}", address);
+                    rocket::tokio::spawn(serve(listener, database));
+                }
+                Err(e) => println!("LIVE: cannot listen on {} - {}", address, e),
+            }
+        })
+    })
+}
+
+/// Accepts WebSocket connections and serves their live queries against a
+/// Database, each connection in its own task
+///
+/// # Arguments
+///
+/// * `listener` - the bound listener
+/// * `database` - the Database to serve
+pub async fn serve(listener: TcpListener, database: Database) {
+    loop {
+        match listener.accept().await {
+            Ok((stream, peer)) => {
+                let database = database.clone();
+                rocket::tokio::spawn(async move {
+                    match connection(stream, database).await {
+                        // Clients may hang up without closing first
+                        Ok(())
+                        | Err(Error::ConnectionClosed)
+                        | Err(Error::Protocol(ProtocolError::ResetWithoutClosingHandshake)) => {}
+                        Err(e) => println!("LIVE: {} - {}", peer, e),
+                    }
+                });
+            }
+            Err(e) => println!("LIVE: accept - {}", e),
+        }
+    }
+}
+
+/// Answers the requests of a connection and sends the diffs of its live
+/// queries until it closes. Pings are answered and close handshakes
+/// completed as messages are read.
+async fn connection(stream: TcpStream, database: Database) -> Result<(), Error> {
+    let config = WebSocketConfig {
+        max_message_size: Some(MAX_MESSAGE_SIZE),
+        max_frame_size: Some(MAX_MESSAGE_SIZE),
+        ..WebSocketConfig::default()
+    };
+    let mut socket = tokio_tungstenite::accept_async_with_config(stream, Some(config)).await?;
+    let mut session = Session::new(database);
+    loop {
+        let message = rocket::tokio::select! {
+            message = socket.next() => Some(message),
+            _ = sleep(Duration::from_millis(POLL_MILLIS)) => None,
+        };
+        let mut notices = match message {
+            None => Vec::new(),
+            Some(None) => return Ok(()),
+            Some(Some(message)) => match message? {
+                Message::Text(text) => session.request(&text),
+                Message::Binary(_) => vec![Notice::error(
+                    None,
+                    DatastoreError::Malformed(String::from("requests are text messages")),
+                )],
+                // Sends the reply to the close
+                Message::Close(_) => return socket.flush().await,
+                Message::Ping(_) | Message::Pong(_) | Message::Frame(_) => Vec::new(),
+            },
+        };
+        notices.extend(session.poll());
+        for notice in notices {
+            let text = serde_json::to_string(&notice).map_err(io::Error::from)?;
+            socket.feed(Message::Text(text)).await?;
+        }
+        socket.flush().await?;
+    }
+}
+
+/// A request of a client: subscribe a live query under a name of the
+/// client's choosing, or unsubscribe it
+///
+/// # Examples
+///
+/// ```json
+/// {"subscribe": "captains", "collection": "crew", "filter": {"rank": "captain"}}
+/// {"unsubscribe": "captains"}
+/// ```
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct LiveRequest {
+    pub subscribe: Option<String>,
+    pub unsubscribe: Option<String>,
+    pub collection: Option<String>,
+    /// JSON filter, see `Query::from_json`, every document if absent
+    #[serde(default)]
+    pub filter: Value,
+}
+
+/// A document of a live query's result set
+#[derive(Debug, Serialize)]
+pub struct LiveDocument {
+    pub id: usize,
+    pub document: BTreeMap<String, Value>,
+}
+
+/// A message to a client, naming the live query it is about
+#[derive(Debug, Serialize)]
+#[serde(tag = "type", rename_all = "lowercase")]
+pub enum Notice {
+    /// The result set of a new live query, in insertion order
+    Initial {
+        query: String,
+        documents: Vec<LiveDocument>,
+    },
+    Added {
+        query: String,
+        id: usize,
+        document: BTreeMap<String, Value>,
+    },
+    Changed {
+        query: String,
+        id: usize,
+        document: BTreeMap<String, Value>,
+    },
+    Removed {
+        query: String,
+        id: usize,
+    },
+    /// The live query ended as its collection was dropped or renamed
+    Invalidate {
+        query: String,
+    },
+    /// A request that failed, or a live query that ended with an error
+    Error {
+        #[serde(skip_serializing_if = "Option::is_none")]
+        query: Option<String>,
+        #[serde(flatten)]
+        error: ErrorBody,
+    },
+}
+
+impl Notice {
+    fn error(query: Option<String>, error: DatastoreError) -> Notice {
+        Notice::Error {
+            query,
+            error: *ApiError::from(error).body,
+        }
+    }
+
+    fn diff(query: &str, diff: Diff) -> Notice {
+        let query = String::from(query);
+        match diff {
+            Diff::Added(id, document) => Notice::Added {
+                query,
+                id,
+                document: to_json_document(&document),
+            },
+            Diff::Changed(id, document) => Notice::Changed {
+                query,
+                id,
+                document: to_json_document(&document),
+            },
+            Diff::Removed(id) => Notice::Removed { query, id },
+        }
+    }
+}
+
+/// A live query of a connection and the collection it follows
+struct Subscription {
+    collection_name: String,
+    collection: SafeCollection,
+    query: LiveQuery,
+}
+
+/// The live queries of one connection
+pub struct Session {
+    database: Database,
+    subscriptions: BTreeMap<String, Subscription>,
+}
+
+impl Session {
+    /// Produces a Session without live queries
+    ///
+    /// # Arguments
+    ///
+    /// * `database` - the Database the live queries read
+    pub fn new(database: Database) -> Session {
+        Session {
+            database,
+            subscriptions: BTreeMap::new(),
+        }
+    }
+
+    /// Produces the answer to a request, see `LiveRequest`
+    ///
+    /// # Arguments
+    ///
+    /// * `text` - the request, a JSON object
+    pub fn request(&mut self, text: &str) -> Vec<Notice> {
+        let request: LiveRequest = match serde_json::from_str(text) {
+            Ok(request) => request,
+            Err(e) => {
+                return vec![Notice::error(
+                    None,
+                    DatastoreError::Malformed(e.to_string()),
+                )]
+            }
+        };
+        match (request.subscribe, request.unsubscribe) {
+            (Some(query), None) => {
+                match self.subscribe(&query, request.collection, &request.filter) {
+                    Ok(notice) => vec![notice],
+                    Err(error) => vec![Notice::error(Some(query), error)],
+                }
+            }
+            (None, Some(query)) => match self.subscriptions.remove(&query) {
+                Some(_) => Vec::new(),
+                None => {
+                    let error = DatastoreError::InvalidOptions(format!("no query named {}", query));
+                    vec![Notice::error(Some(query), error)]
+                }
+            },
+            _ => vec![Notice::error(
+                None,
+                DatastoreError::Malformed(String::from(
+                    "a request either subscribes or unsubscribes",
+                )),
+            )],
+        }
+    }
+
+    fn subscribe(
+        &mut self,
+        query: &str,
+        collection_name: Option<String>,
+        filter: &Value,
+    ) -> Result<Notice, DatastoreError> {
+        if self.subscriptions.contains_key(query) {
+            return Err(DatastoreError::InvalidOptions(format!(
+                "a query named {} is already subscribed",
+                query
+            )));
+        }
+        let collection_name = collection_name.ok_or_else(|| {
+            DatastoreError::InvalidOptions(String::from("subscribe requires a collection"))
+        })?;
+        let collection = self
+            .database
+            .get(&collection_name)
+            .ok_or_else(|| DatastoreError::CollectionNotFound(collection_name.clone()))?;
+        let (live_query, initial) = LiveQuery::new(&database::read(&collection), filter)?;
+        println!(
+            "LIVE: Collection - {} - {} - {}",
+            &collection_name, query, filter
+        );
+        self.subscriptions.insert(
+            String::from(query),
+            Subscription {
+                collection_name,
+                collection,
+                query: live_query,
+            },
+        );
+        Ok(Notice::Initial {
+            query: String::from(query),
+            documents: initial
+                .iter()
+                .map(|(id, document)| LiveDocument {
+                    id: *id,
+                    document: to_json_document(document),
+                })
+                .collect(),
+        })
+    }
+
+    /// Produces the diffs of every live query since the last poll, ending
+    /// the live queries whose collection was dropped or renamed
+    pub fn poll(&mut self) -> Vec<Notice> {
+        let mut notices = Vec::new();
+        let mut ended = Vec::new();
+        for (query, subscription) in self.subscriptions.iter_mut() {
+            let current = self
+                .database
+                .get(&subscription.collection_name)
+                .is_some_and(|current| Arc::ptr_eq(&current, &subscription.collection));
+            if !current {
+                notices.push(Notice::Invalidate {
+                    query: query.clone(),
+                });
+                ended.push(query.clone());
+                continue;
+            }
+            let diffs = subscription
+                .query
+                .poll(&database::read(&subscription.collection));
+            match diffs {
+                Ok(diffs) => {
+                    notices.extend(diffs.into_iter().map(|diff| Notice::diff(query, diff)))
+                }
+                Err(error) => {
+                    notices.push(Notice::error(Some(query.clone()), error));
+                    ended.push(query.clone());
+                }
+            }
+        }
+        for query in ended {
+            self.subscriptions.remove(&query);
+        }
+        notices
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::datastore::collection::Document;
+    use crate::datastore::datatypes::DataType;
+    use serde_json::json;
+
+    fn notices(notices: Vec<Notice>) -> Value {
+        serde_json::to_value(notices).unwrap()
+    }
+
+    fn rank(value: &str) -> Document {
+        let mut document = Document::new();
+        document.insert(String::from("rank"), DataType::String(String::from(value)));
+        document
+    }
+
+    #[test]
+    fn subscribe_and_follow() {
+        let database = Database::new();
+        let crew = database.get_or_create("crew");
+        let john = database::write(&crew).insert(rank("captain")).unwrap();
+        let mut session = Session::new(database.clone());
+        assert_eq!(
+            json!([{"type": "initial", "query": "captains", "documents": [
+                {"id": john, "document": {"rank": "captain"}}
+            ]}]),
+            notices(session.request(
+                r#"{"subscribe": "captains", "collection": "crew", "filter": {"rank": "captain"}}"#
+            ))
+        );
+
+        let louis = database::write(&crew).insert(rank("private")).unwrap();
+        database::write(&crew).put(louis, rank("captain"));
+        database::write(&crew).remove(john);
+        assert_eq!(
+            json!([
+                {"type": "added", "query": "captains", "id": louis, "document": {"rank": "captain"}},
+                {"type": "removed", "query": "captains", "id": john}
+            ]),
+            notices(session.poll())
+        );
+
+        database.rename("crew", "staff", false).unwrap();
+        assert_eq!(
+            json!([{"type": "invalidate", "query": "captains"}]),
+            notices(session.poll())
+        );
+        assert!(session.poll().is_empty());
+    }
+
+    #[rocket::async_test]
+    async fn serve_a_connection() {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let address = listener.local_addr().unwrap();
+        let database = Database::new();
+        let crew = database.get_or_create("crew");
+        rocket::tokio::spawn(serve(listener, database));
+        let stream = TcpStream::connect(address).await.unwrap();
+        let (mut socket, _) = tokio_tungstenite::client_async("ws://localhost/", stream)
+            .await
+            .unwrap();
+
+        let subscribe = r#"{"subscribe": "captains", "collection": "crew"}"#;
+        socket
+            .send(Message::Text(String::from(subscribe)))
+            .await
+            .unwrap();
+        let initial = socket.next().await.unwrap().unwrap();
+        assert_eq!(
+            json!({"type": "initial", "query": "captains", "documents": []}),
+            serde_json::from_str::<Value>(initial.to_text().unwrap()).unwrap()
+        );
+        let john = database::write(&crew).insert(rank("captain")).unwrap();
+        let added = socket.next().await.unwrap().unwrap();
+        assert_eq!(
+            json!({"type": "added", "query": "captains", "id": john, "document": {"rank": "captain"}}),
+            serde_json::from_str::<Value>(added.to_text().unwrap()).unwrap()
+        );
+        socket.close(None).await.unwrap();
+        assert!(matches!(
+            socket.next().await,
+            Some(Ok(Message::Close(_))) | None
+        ));
+    }
+
+    #[test]
+    fn failed_requests() {
+        let database = Database::new();
+        database.get_or_create("crew");
+        let mut session = Session::new(database);
+        let codes = |notices: Vec<Notice>| {
+            notices
+                .iter()
+                .map(|notice| match notice {
+                    Notice::Error { error, .. } => error.code.clone(),
+                    _ => String::from("none"),
+                })
+                .collect::<Vec<_>>()
+        };
+        assert_eq!(vec!["MALFORMED"], codes(session.request("{")));
+        assert_eq!(vec!["MALFORMED"], codes(session.request("{}")));
+        assert_eq!(
+            vec!["COLLECTION_NOT_FOUND"],
+            codes(session.request(r#"{"subscribe": "q", "collection": "nope"}"#))
+        );
+        assert_eq!(
+            vec!["INVALID_QUERY"],
+            codes(session.request(r#"{"subscribe": "q", "collection": "crew", "filter": 1}"#))
+        );
+        assert_eq!(
+            vec!["none"],
+            codes(session.request(r#"{"subscribe": "q", "collection": "crew"}"#))
+        );
+        assert_eq!(
+            vec!["INVALID_OPTIONS"],
+            codes(session.request(r#"{"subscribe": "q", "collection": "crew"}"#))
+        );
+        assert!(session.request(r#"{"unsubscribe": "q"}"#).is_empty());
+        assert_eq!(
+            vec!["INVALID_OPTIONS"],
+            codes(session.request(r#"{"unsubscribe": "q"}"#))
+        );
+    }
+}
@@ -1 +1,8 @@
+pub mod error;
+pub mod live;
+pub mod sweeper;
+// rocket 0.5.0-rc.1's route attributes emit a `pub use` of each handler's
+// uri macro that is never used from within this crate.
+#[allow(unused_imports)]
 pub mod v2;
+pub mod wire;
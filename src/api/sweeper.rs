@@ -0,0 +1,46 @@
+use crate::datastore::database::Database;
+use crate::datastore::ttl;
+use rocket::fairing::AdHoc;
+use rocket::tokio::time::sleep;
+use std::time::Duration;
+
+/// Seconds between sweeps when no `ttl_sweep_seconds` is configured, as
+/// in MongoDB
+pub const DEFAULT_SWEEP_SECONDS: u64 = 60;
+
+/// Produces the fairing starting the TTL sweeper once Rocket has launched.
+/// The sweeper removes the expired documents of every collection with a
+/// TTL index from the managed Database every `ttl_sweep_seconds`, 60 by
+/// default, e.g. `ROCKET_TTL_SWEEP_SECONDS=5`. Documents may therefore
+/// outlive their expiry by up to one interval.
+pub fn sweeper() -> AdHoc {
+    AdHoc::on_liftoff("TTL sweeper", |rocket| {
+        Box::pin(async move {
+            let interval = rocket
+                .figment()
+                .extract_inner::<u64>("ttl_sweep_seconds")
+                .unwrap_or(DEFAULT_SWEEP_SECONDS)
+                .max(1);
+            let database = match rocket.state::<Database>() {
+                Some(database) => database.clone(),
+                None => return println!("TTL: no Database is managed, not sweeping"),
+            };
+            rocket::tokio::spawn(sweep(database, Duration::from_secs(interval)));
+        })
+    })
+}
+
+/// Removes expired documents from a Database at a fixed interval, forever
+///
+/// # Arguments
+///
+/// * `database` - the Database to sweep
+/// * `interval` - the time between sweeps
+pub async fn sweep(database: Database, interval: Duration) {
+    loop {
+        sleep(interval).await;
+        for (name, expired) in database.expire(ttl::now_millis()) {
+            println!("TTL: Collection - {} - {} expired", name, expired);
+        }
+    }
+}
@@ -1,51 +1,616 @@
-use crate::datastore::datatypes;
-use crate::datastore::query_proc::QueryResult;
-use rocket::http::Status;
+use crate::api::error::{ApiError, ErrorBody};
+use crate::datastore::bson;
+use crate::datastore::bulk::{self, BulkOperation};
+use crate::datastore::capped::Cap;
+use crate::datastore::changes::{ChangeEvent, Operation};
+use crate::datastore::collection::{field_value, Collection, CollectionOptions, Document};
+use crate::datastore::csv;
+use crate::datastore::database::{self, Database};
+use crate::datastore::datatypes::{self, DataType, Float};
+use crate::datastore::error::DatastoreError;
+use crate::datastore::index;
+use crate::datastore::query_proc::{Explain, Filter, FindOptions, Matches, Query};
+use crate::datastore::schema::Validator;
+use crate::datastore::text;
+use crate::datastore::transaction::{Transaction, Transactions};
+use crate::datastore::update as update_doc;
+use rocket::data::{Data, Limits, ToByteUnit};
+use rocket::http::{Accept, ContentType, Status};
+use rocket::request::{self, FromRequest, Outcome, Request};
 use rocket::response::status;
-use rocket::serde::json::Json;
+use rocket::response::stream::{ByteStream, Event, EventStream};
+use rocket::serde::json::{self, Json};
+use rocket::tokio::io::{AsyncBufReadExt, BufReader};
+use rocket::tokio::time::sleep;
+use serde::{Deserialize, Serialize};
 use serde_json::Value;
-use std::collections::{BTreeMap, HashMap};
+use std::borrow::Cow;
+use std::collections::{BTreeMap, BTreeSet, HashMap};
+use std::convert::TryFrom;
+use std::sync::Arc;
+use std::time::Duration;
 
-#[get("/<collection_name>?<query>")]
+/// Largest BSON body accepted when no `bson` limit is configured
+const BSON_LIMIT_MIB: usize = 16;
+/// Largest CSV body accepted when no `csv` limit is configured
+const CSV_LIMIT_MIB: usize = 64;
+/// Largest NDJSON body accepted when no `ndjson` limit is configured
+const NDJSON_LIMIT_MIB: usize = 256;
+/// Number of documents read from a collection per chunk of an export
+const EXPORT_PAGE_SIZE: usize = 1000;
+/// Milliseconds a watch waits before looking for new change events
+const WATCH_POLL_MILLIS: u64 = 100;
+
+/// Header naming the transaction a request runs in
+pub const TRANSACTION_HEADER: &str = "X-Transaction-Id";
+
+/// The transaction a request runs in, if any
+pub struct TransactionId(pub Option<u64>);
+
+#[rocket::async_trait]
+impl<'r> FromRequest<'r> for TransactionId {
+    type Error = ();
+
+    async fn from_request(request: &'r Request<'_>) -> request::Outcome<Self, Self::Error> {
+        match request.headers().get_one(TRANSACTION_HEADER) {
+            None => Outcome::Success(TransactionId(None)),
+            Some(value) => match value.parse() {
+                Ok(id) => Outcome::Success(TransactionId(Some(id))),
+                Err(_) => Outcome::Failure((Status::BadRequest, ())),
+            },
+        }
+    }
+}
+
+#[derive(Serialize)]
+pub struct UpdateSummary {
+    pub matched: usize,
+    pub modified: usize,
+}
+
+#[derive(Serialize)]
+pub struct DeleteSummary {
+    pub deleted: usize,
+}
+
+#[derive(Serialize)]
+pub struct TransactionSummary {
+    pub id: u64,
+    pub status: &'static str,
+}
+
+/// A line of an import that could not be inserted
+#[derive(Serialize)]
+pub struct LineError {
+    pub line: usize,
+    pub message: String,
+}
+
+/// A document of a BSON import that could not be inserted
+#[derive(Serialize)]
+pub struct DocumentError {
+    /// Position of the document in the dump, from 1
+    pub document: usize,
+    pub message: String,
+}
+
+/// A document of an insert, or an operation of a bulk write, that could not
+/// be applied
+#[derive(Serialize)]
+pub struct BatchError {
+    /// Position of the document or operation in the request
+    pub index: usize,
+    #[serde(flatten)]
+    pub error: ErrorBody,
+}
+
+/// Result of an insert, like MongoDB's `insertMany`: the ids of the
+/// inserted documents in the order they were given, and the documents that
+/// could not be inserted
+#[derive(Serialize)]
+pub struct InsertReport {
+    pub inserted: Vec<usize>,
+    pub errors: Vec<BatchError>,
+}
+
+/// A document a bulk write's update or replace inserted as nothing matched
+#[derive(Serialize)]
+pub struct Upserted {
+    /// Position of the operation in the bulk write
+    pub index: usize,
+    pub id: usize,
+}
+
+/// Result of a bulk write, like MongoDB's `bulkWrite`
+#[derive(Serialize)]
+pub struct BulkSummary {
+    pub inserted: Vec<usize>,
+    pub matched: usize,
+    pub modified: usize,
+    pub deleted: usize,
+    pub upserted: Vec<Upserted>,
+    pub errors: Vec<BatchError>,
+}
+
+/// Result of an import: the ids of the inserted documents in the order
+/// they were read, and the lines or documents that could not be inserted
+#[derive(Serialize)]
+pub struct ImportReport<E = LineError> {
+    pub inserted: Vec<usize>,
+    pub errors: Vec<E>,
+}
+
+/// The `type` of the index answering `$text` searches
+const TEXT_INDEX: &str = "text";
+
+/// Options for a new index, over either one `field` or a list of `fields`
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct IndexOptions {
+    pub field: Option<String>,
+    pub fields: Option<Vec<String>>,
+    #[serde(default)]
+    pub unique: bool,
+    /// Makes the index over one date field a TTL index, removing each
+    /// document this long after its date
+    pub expire_after_seconds: Option<u64>,
+    /// `text` for the index answering `$text` searches of the fields
+    #[serde(rename = "type")]
+    pub kind: Option<String>,
+}
+
+#[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct IndexSummary {
+    pub name: String,
+    pub fields: Vec<String>,
+    pub unique: bool,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub expire_after_seconds: Option<u64>,
+    #[serde(rename = "type", skip_serializing_if = "Option::is_none")]
+    pub kind: Option<&'static str>,
+}
+
+#[derive(Serialize)]
+pub struct CollectionSummary {
+    pub name: String,
+    pub documents: usize,
+    pub indexes: Vec<IndexSummary>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub capped: Option<Cap>,
+}
+
+/// Body of a rename: the new name, and whether a collection already
+/// holding it is dropped
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields, rename_all = "camelCase")]
+pub struct RenameOptions {
+    pub to: String,
+    #[serde(default)]
+    pub drop_target: bool,
+}
+
+/// Body of a find request: a JSON filter, see `Query::from_json`,
+/// and the options of `FindOptions`
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct FindRequest {
+    #[serde(default)]
+    pub filter: Value,
+    pub projection: Option<Value>,
+    pub sort: Option<Value>,
+    #[serde(default)]
+    pub skip: usize,
+    #[serde(default)]
+    pub limit: usize,
+    #[serde(default)]
+    pub explain: bool,
+}
+
+/// Response for a find, either the matching documents or how they would be
+/// found
+#[derive(Responder)]
+pub enum FindResponse {
+    Documents(status::Custom<Json<Vec<BTreeMap<String, Value>>>>),
+    Bson((ContentType, Vec<u8>)),
+    Explain(Json<Explain>),
+    Error(ApiError),
+}
+
+impl<E: Into<ApiError>> From<E> for FindResponse {
+    fn from(error: E) -> Self {
+        FindResponse::Error(error.into())
+    }
+}
+
+pub(crate) fn to_json_document(doc: &Document) -> BTreeMap<String, Value> {
+    let mut converted_doc = BTreeMap::new();
+    for (field, value) in doc.iter() {
+        converted_doc.insert(field.clone(), datatypes::to_json(value));
+    }
+    converted_doc
+}
+
+fn to_json_results(documents: &[&Document]) -> FindResponse {
+    let results = documents.iter().map(|doc| to_json_document(doc)).collect();
+    FindResponse::Documents(status::Custom(Status::Ok, Json(results)))
+}
+
+fn from_json_document(doc: &HashMap<String, Value>) -> Document {
+    let mut converted_doc = HashMap::new();
+    for (field, value) in doc.iter() {
+        converted_doc.insert(field.clone(), datatypes::from_json(value));
+    }
+    converted_doc
+}
+
+/// Produces the BSON media type, `application/bson`
+fn bson_type() -> ContentType {
+    ContentType::new("application", "bson")
+}
+
+/// Produces the newline-delimited JSON media type, `application/x-ndjson`
+fn ndjson_type() -> ContentType {
+    ContentType::new("application", "x-ndjson")
+}
+
+/// Produces whether a media type is the one a client prefers
+fn prefers(accept: Option<&Accept>, content_type: &ContentType) -> bool {
+    accept.is_some_and(|accept| accept.preferred().media_type() == content_type.media_type())
+}
+
+/// Produces the concatenated BSON encoding of a list of documents
+fn to_bson(documents: &[&Document]) -> FindResponse {
+    let mut bytes = Vec::new();
+    for document in documents {
+        match bson::encode(document) {
+            Ok(encoded) => bytes.extend(encoded),
+            Err(e) => {
+                return FindResponse::Error(ApiError::new(
+                    Status::InternalServerError,
+                    "ENCODING_FAILED",
+                    e,
+                ))
+            }
+        }
+    }
+    FindResponse::Bson((bson_type(), bytes))
+}
+
+/// Where a find reads documents from: a collection, or the view a
+/// transaction has of one
+enum Source<'a> {
+    Collection(&'a Collection),
+    Transaction(&'a Transaction, &'a str),
+}
+
+impl<'a> Source<'a> {
+    fn run(&self, query: &Query) -> Result<Matches, DatastoreError> {
+        match self {
+            Source::Collection(collection) => collection.run(query),
+            Source::Transaction(transaction, name) => transaction.run(name, query),
+        }
+    }
+
+    fn documents(&self, ids: &[usize]) -> Result<Vec<Cow<'a, Document>>, DatastoreError> {
+        match self {
+            Source::Collection(collection) => Ok(collection
+                .documents(ids)?
+                .into_iter()
+                .map(Cow::Borrowed)
+                .collect()),
+            Source::Transaction(transaction, name) => Ok(transaction
+                .documents(name, ids)?
+                .into_iter()
+                .map(Cow::Owned)
+                .collect()),
+        }
+    }
+}
+
+/// Produces the documents a `$text` search found, each with its relevance
+/// in the `$score` field
+fn scored(documents: Vec<Cow<Document>>, matches: &Matches) -> Vec<Document> {
+    matches
+        .ids
+        .iter()
+        .zip(documents)
+        .map(|(id, document)| {
+            let mut document = document.into_owned();
+            let score = matches.scores.get(id).copied().unwrap_or(0.0);
+            document.insert(
+                String::from(text::SCORE_FIELD),
+                DataType::F64(Float::new(score)),
+            );
+            document
+        })
+        .collect()
+}
+
+fn find_in(source: Source, search: &str, explain: bool, as_bson: bool) -> FindResponse {
+    let query = match Query::parse(search) {
+        Ok(query) => query,
+        Err(error) => return error.into(),
+    };
+    let matches = match source.run(&query) {
+        Ok(matches) => matches,
+        Err(error) => return error.into(),
+    };
+    if explain {
+        return FindResponse::Explain(Json(matches.explain));
+    }
+    let documents = match source.documents(&matches.ids) {
+        Ok(documents) if query.text_search().is_some() => scored(documents, &matches)
+            .into_iter()
+            .map(Cow::Owned)
+            .collect(),
+        Ok(documents) => documents,
+        Err(error) => return error.into(),
+    };
+    let documents: Vec<&Document> = documents.iter().map(|document| document.as_ref()).collect();
+    if as_bson {
+        return to_bson(&documents);
+    }
+    to_json_results(&documents)
+}
+
+/// Find the documents in a collection matching a query
+///
+/// # Arguments
+///
+/// * `collection_name` - the collection to search
+/// * `query` - query selecting the documents, every document if absent
+/// * `explain` - produce the chosen query plan and the number of documents
+///   each of its stages examined and returned instead of the documents
+/// * `transaction` - the transaction to search in, if any
+/// * `accept` - the documents are produced as concatenated BSON documents
+///   when `application/bson` is preferred, JSON otherwise
+/// * `db` - registry of thread-safe collections
+/// * `transactions` - registry of open transactions
+#[get("/<collection_name>?<query>&<explain>")]
 pub fn find(
     collection_name: String,
     query: Option<String>,
-    db: &rocket::State<crate::SafeMemStore>,
-) -> status::Custom<Json<Vec<BTreeMap<String, Value>>>> {
-    let mutex = db.inner();
-    let collection = mutex.lock().unwrap();
+    explain: Option<bool>,
+    transaction: TransactionId,
+    accept: Option<&Accept>,
+    db: &rocket::State<Database>,
+    transactions: &rocket::State<Transactions>,
+) -> FindResponse {
     let search = match query {
         Some(q) => q,
         None => String::from("{}"),
     };
+    let explain = explain.unwrap_or(false);
+    let as_bson =
+        accept.is_some_and(|accept| accept.preferred().media_type() == bson_type().media_type());
     println!("FIND: Collection - {} - {}", &collection_name, &search);
 
-    match collection.find(&search) {
-        QueryResult::Data(values) => {
-            let mut results = Vec::new();
-            for doc in values.into_iter() {
-                let mut converted_doc = BTreeMap::new();
-                for (field, value) in doc.iter() {
-                    converted_doc.insert(field.clone(), datatypes::to_json(value));
+    if let Some(id) = transaction.0 {
+        let safe_transaction = match transactions.get(id) {
+            Ok(safe_transaction) => safe_transaction,
+            Err(error) => return error.into(),
+        };
+        let txn = database::lock(&safe_transaction);
+        return find_in(
+            Source::Transaction(&txn, &collection_name),
+            &search,
+            explain,
+            as_bson,
+        );
+    }
+
+    let safe_collection = match db.get(&collection_name) {
+        Some(safe_collection) => safe_collection,
+        None => {
+            let collection = Collection::new(collection_name);
+            return find_in(Source::Collection(&collection), &search, explain, as_bson);
+        }
+    };
+    let collection = database::read(&safe_collection);
+    find_in(Source::Collection(&collection), &search, explain, as_bson)
+}
+
+fn find_request_in(
+    source: Source,
+    request: &FindRequest,
+    options: &FindOptions,
+    as_bson: bool,
+) -> FindResponse {
+    let query = match Query::from_json(&request.filter) {
+        Ok(query) => query,
+        Err(error) => return error.into(),
+    };
+    let matches = match source.run(&query) {
+        Ok(matches) => matches,
+        Err(error) => return error.into(),
+    };
+    if request.explain {
+        return FindResponse::Explain(Json(matches.explain));
+    }
+    let documents = match source.documents(&matches.ids) {
+        Ok(documents) if query.text_search().is_some() => {
+            options.apply(scored(documents, &matches).iter().collect())
+        }
+        Ok(documents) => {
+            options.apply(documents.iter().map(|document| document.as_ref()).collect())
+        }
+        Err(error) => return error.into(),
+    };
+    let documents: Vec<&Document> = documents.iter().collect();
+    if as_bson {
+        return to_bson(&documents);
+    }
+    to_json_results(&documents)
+}
+
+/// Find the documents in a collection matching a filter given in the
+/// request body, which unlike a query string has no length limit and stays
+/// out of access logs
+///
+/// # Arguments
+///
+/// * `collection_name` - the collection to search
+/// * `request` - HTTP request body holding the `filter`, which documents to
+///   produce, and in which order, see `FindRequest`
+/// * `transaction` - the transaction to search in, if any
+/// * `accept` - the documents are produced as concatenated BSON documents
+///   when `application/bson` is preferred, JSON otherwise
+/// * `db` - registry of thread-safe collections
+/// * `transactions` - registry of open transactions
+///
+/// # Example
+///
+/// ```json
+/// # request
+/// {
+///   "filter": {"age": {"$gte": 30}, "username": {"$in": ["johnperry", "louiswu"]}},
+///   "projection": {"username": 1, "age": 1},
+///   "sort": {"age": -1},
+///   "skip": 0,
+///   "limit": 10
+/// }
+/// ```
+#[post("/<collection_name>/find", format = "json", data = "<request>")]
+pub fn find_request(
+    collection_name: String,
+    request: Result<Json<FindRequest>, json::Error<'_>>,
+    transaction: TransactionId,
+    accept: Option<&Accept>,
+    db: &rocket::State<Database>,
+    transactions: &rocket::State<Transactions>,
+) -> FindResponse {
+    let request = match request {
+        Ok(request) => request,
+        Err(error) => return error.into(),
+    };
+    let options = match FindOptions::from_json(
+        request.projection.as_ref(),
+        request.sort.as_ref(),
+        request.skip,
+        request.limit,
+    ) {
+        Ok(options) => options,
+        Err(message) => return DatastoreError::InvalidOptions(message).into(),
+    };
+    let as_bson = prefers(accept, &bson_type());
+    println!(
+        "FIND: Collection - {} - {}",
+        &collection_name, &request.filter
+    );
+
+    if let Some(id) = transaction.0 {
+        let safe_transaction = match transactions.get(id) {
+            Ok(safe_transaction) => safe_transaction,
+            Err(error) => return error.into(),
+        };
+        let txn = database::lock(&safe_transaction);
+        return find_request_in(
+            Source::Transaction(&txn, &collection_name),
+            &request,
+            &options,
+            as_bson,
+        );
+    }
+
+    let safe_collection = match db.get(&collection_name) {
+        Some(safe_collection) => safe_collection,
+        None => {
+            return find_request_in(
+                Source::Collection(&Collection::new(collection_name)),
+                &request,
+                &options,
+                as_bson,
+            )
+        }
+    };
+    let collection = database::read(&safe_collection);
+    find_request_in(Source::Collection(&collection), &request, &options, as_bson)
+}
+
+/// Inserts documents one at a time, stopping at the first that cannot be
+/// inserted when `ordered`, carrying on past it otherwise
+///
+/// # Arguments
+///
+/// * `documents` - the documents to insert, in order
+/// * `ordered` - whether a document that cannot be inserted stops the insert
+/// * `insert` - inserts one document, producing its id
+///
+/// Produces the InsertReport and the status of the first failure, if any
+fn insert_all(
+    documents: &[HashMap<String, Value>],
+    ordered: bool,
+    mut insert: impl FnMut(Document) -> Result<usize, ApiError>,
+) -> (InsertReport, Option<Status>) {
+    let mut report = InsertReport {
+        inserted: Vec::new(),
+        errors: Vec::new(),
+    };
+    let mut status = None;
+    for (index, document) in documents.iter().enumerate() {
+        match insert(from_json_document(document)) {
+            Ok(id) => report.inserted.push(id),
+            Err(error) => {
+                status.get_or_insert(error.status);
+                report.errors.push(BatchError {
+                    index,
+                    error: *error.body,
+                });
+                if ordered {
+                    break;
                 }
-                results.push(converted_doc);
             }
-            status::Custom(Status::Ok, Json(results))
-        }
-        QueryResult::InvalidQueryError => status::Custom(Status::BadRequest, Json(Vec::new())),
-        QueryResult::InvalidIdError => {
-            status::Custom(Status::InternalServerError, Json(Vec::new()))
         }
     }
+    (report, status)
 }
 
-/// Insert a list of documents into a collection
+/// Produces the `BULK_WRITE_ERROR` of an insert or bulk write some
+/// documents or operations of failed
+///
+/// # Arguments
+///
+/// * `status` - the status of the first failure
+/// * `noun` - what failed, `document` or `operation`
+/// * `verb` - what could not be done to it, e.g. `inserted`
+/// * `errors` - the failures
+/// * `report` - the result of the insert or bulk write, the details
+fn bulk_write_error(
+    status: Status,
+    noun: &str,
+    verb: &str,
+    errors: &[BatchError],
+    report: &impl Serialize,
+) -> ApiError {
+    let message = match errors {
+        [error] => format!(
+            "{} {} could not be {}: {}",
+            noun, error.index, verb, error.error.message
+        ),
+        errors => format!("{} {}s could not be {}", errors.len(), noun, verb),
+    };
+    let mut error = ApiError::new(status, "BULK_WRITE_ERROR", message);
+    error.body.details = serde_json::to_value(report).unwrap_or(Value::Null);
+    error
+}
+
+/// Insert a list of documents into a collection, like MongoDB's
+/// `insertMany`. An ordered insert stops at the first document that cannot
+/// be inserted, keeping the documents inserted before it; an unordered
+/// insert tries every document. When any document cannot be inserted the
+/// response is a `BULK_WRITE_ERROR` with the status of the first failure
+/// and the InsertReport as its details.
 ///
 /// # Arguments
 ///
 /// * `collection_name` - the collection to insert the documents into
+/// * `ordered` - whether a document that cannot be inserted stops the
+///   insert, true if absent
 /// * `values` - HTTP request body containing a list of documents
-/// * `db` - thread-safe collection
+/// * `transaction` - the transaction to insert the documents in, if any
+/// * `db` - registry of thread-safe collections
+/// * `transactions` - registry of open transactions
 ///
 /// # Example
 ///
@@ -68,27 +633,1171 @@ pub fn find(
 ///   }
 /// ]
 /// ```
-#[post("/<collection_name>", format = "json", data = "<values>")]
+#[post("/<collection_name>?<ordered>", format = "json", data = "<values>")]
 pub fn insert(
     collection_name: String,
-    values: Json<Vec<HashMap<String, Value>>>,
-    db: &rocket::State<crate::SafeMemStore>,
-) -> status::Custom<Json<Vec<usize>>> {
-    let mut collection = db.lock().unwrap();
+    ordered: Option<bool>,
+    values: Result<Json<Vec<HashMap<String, Value>>>, json::Error<'_>>,
+    transaction: TransactionId,
+    db: &rocket::State<Database>,
+    transactions: &rocket::State<Transactions>,
+) -> Result<status::Custom<Json<InsertReport>>, ApiError> {
+    let values = values?;
+    let ordered = ordered.unwrap_or(true);
+    let (report, failure) = if let Some(id) = transaction.0 {
+        let safe_transaction = transactions.get(id)?;
+        let mut txn = database::lock(&safe_transaction);
+        insert_all(&values, ordered, |document| {
+            Ok(txn.insert(&collection_name, document)?)
+        })
+    } else {
+        let safe_collection = db.get_or_create(&collection_name);
+        let mut collection = database::write(&safe_collection);
+        insert_all(
+            &values,
+            ordered,
+            |document| Ok(collection.insert(document)?),
+        )
+    };
+    println!(
+        "INSERT: Collection - {} - {} of {} documents",
+        &collection_name,
+        report.inserted.len(),
+        values.len()
+    );
+
+    match failure {
+        Some(status) => Err(bulk_write_error(
+            status,
+            "document",
+            "inserted",
+            &report.errors,
+            &report,
+        )),
+        None => Ok(status::Custom(Status::Created, Json(report))),
+    }
+}
+
+/// Apply a list of inserts, updates, replaces and deletes to a collection,
+/// like MongoDB's `bulkWrite`. The collection is locked once for the whole
+/// bulk write, so other requests see either none or all of its writes. An
+/// ordered bulk write stops at the first operation that cannot be applied,
+/// keeping the writes before it; an unordered one tries every operation.
+/// Operations are checked before any is applied, and a malformed one
+/// rejects the bulk write whole.
+///
+/// # Arguments
+///
+/// * `collection_name` - the collection to write to
+/// * `ordered` - whether an operation that cannot be applied stops the bulk
+///   write, true if absent
+/// * `operations` - HTTP request body containing a list of operations,
+///   see `BulkOperation::from_json`
+/// * `db` - registry of thread-safe collections
+///
+/// # Example
+///
+/// ```json
+/// # operations
+/// [
+///   {"insertOne": {"document": {"username": "johnperry", "rank": "private"}}},
+///   {"updateMany": {"filter": {"rank": "private"}, "update": {"$set": {"rank": "captain"}}}},
+///   {"replaceOne": {"filter": {"username": "louiswu"}, "replacement": {"username": "louiswu"}, "upsert": true}},
+///   {"deleteOne": {"filter": {"username": "speaker"}}}
+/// ]
+/// ```
+#[post(
+    "/<collection_name>/bulk?<ordered>",
+    format = "json",
+    data = "<operations>"
+)]
+pub fn bulk_write(
+    collection_name: String,
+    ordered: Option<bool>,
+    operations: Result<Json<Vec<Value>>, json::Error<'_>>,
+    db: &rocket::State<Database>,
+) -> Result<Json<BulkSummary>, ApiError> {
+    let operations = operations?
+        .iter()
+        .enumerate()
+        .map(|(index, operation)| {
+            BulkOperation::from_json(operation)
+                .map_err(|e| DatastoreError::Malformed(format!("operation {}: {}", index, e)))
+        })
+        .collect::<Result<Vec<BulkOperation>, DatastoreError>>()?;
+    let result = {
+        let safe_collection = db.get_or_create(&collection_name);
+        let mut collection = database::write(&safe_collection);
+        bulk::execute(&mut collection, &operations, ordered.unwrap_or(true))
+    };
+    println!(
+        "BULK: Collection - {} - {} operations - {} errors",
+        &collection_name,
+        operations.len(),
+        result.errors.len()
+    );
+
+    let mut status = None;
+    let errors = result
+        .errors
+        .into_iter()
+        .map(|(index, error)| {
+            let error = ApiError::from(error);
+            status.get_or_insert(error.status);
+            BatchError {
+                index,
+                error: *error.body,
+            }
+        })
+        .collect();
+    let summary = BulkSummary {
+        inserted: result.inserted,
+        matched: result.matched,
+        modified: result.modified,
+        deleted: result.deleted,
+        upserted: result
+            .upserted
+            .into_iter()
+            .map(|(index, id)| Upserted { index, id })
+            .collect(),
+        errors,
+    };
+    match status {
+        Some(status) => Err(bulk_write_error(
+            status,
+            "operation",
+            "applied",
+            &summary.errors,
+            &summary,
+        )),
+        None => Ok(Json(summary)),
+    }
+}
+
+/// Update every document in a collection matching a query
+///
+/// # Arguments
+///
+/// * `collection_name` - the collection to update
+/// * `query` - query selecting the documents to update
+/// * `update` - HTTP request body containing a MongoDB style update document
+/// * `transaction` - the transaction to update the documents in, if any
+/// * `db` - registry of thread-safe collections
+/// * `transactions` - registry of open transactions
+///
+/// # Example
+///
+/// ```json
+/// # update
+/// {"$set": {"email": "john@example.com"}, "$inc": {"age": 1}}
+/// ```
+#[patch("/<collection_name>?<query>", format = "json", data = "<update>")]
+pub fn update(
+    collection_name: String,
+    query: String,
+    update: Result<Json<HashMap<String, Value>>, json::Error<'_>>,
+    transaction: TransactionId,
+    db: &rocket::State<Database>,
+    transactions: &rocket::State<Transactions>,
+) -> Result<Json<UpdateSummary>, ApiError> {
+    let update =
+        update_doc::from_json(&update?.into_inner()).map_err(DatastoreError::InvalidUpdate)?;
+    let result = if let Some(id) = transaction.0 {
+        let safe_transaction = transactions.get(id)?;
+        let mut txn = database::lock(&safe_transaction);
+        txn.update(&collection_name, &query, &update)?
+    } else {
+        let safe_collection = db.get_or_create(&collection_name);
+        let mut collection = database::write(&safe_collection);
+        collection.update(&query, &update)?
+    };
+    println!(
+        "UPDATE: Collection - {} - {} - {} documents",
+        &collection_name,
+        &query,
+        result.modified.len()
+    );
+
+    Ok(Json(UpdateSummary {
+        matched: result.matched,
+        modified: result.modified.len(),
+    }))
+}
+
+/// Delete every document in a collection matching a query
+///
+/// # Arguments
+///
+/// * `collection_name` - the collection to delete the documents from
+/// * `query` - query selecting the documents to delete, a request without
+///   one fails with 400 rather than deleting every document
+/// * `transaction` - the transaction to delete the documents in, if any
+/// * `db` - registry of thread-safe collections
+/// * `transactions` - registry of open transactions
+#[delete("/<collection_name>?<query>")]
+pub fn delete(
+    collection_name: String,
+    query: Option<String>,
+    transaction: TransactionId,
+    db: &rocket::State<Database>,
+    transactions: &rocket::State<Transactions>,
+) -> Result<Json<DeleteSummary>, ApiError> {
+    let query = query.ok_or_else(|| {
+        ApiError::new(
+            Status::BadRequest,
+            "MISSING_QUERY",
+            "a delete needs a query, drop a collection with DELETE /{collection}/drop",
+        )
+    })?;
+    let deleted = if let Some(id) = transaction.0 {
+        let safe_transaction = transactions.get(id)?;
+        let mut txn = database::lock(&safe_transaction);
+        txn.delete(&collection_name, &query)?
+    } else {
+        let safe_collection = match db.get(&collection_name) {
+            Some(safe_collection) => safe_collection,
+            None => return Ok(Json(DeleteSummary { deleted: 0 })),
+        };
+        let mut collection = database::write(&safe_collection);
+        collection.delete(&query)?
+    };
+    println!(
+        "DELETE: Collection - {} - {} - {} documents",
+        &collection_name,
+        &query,
+        deleted.len()
+    );
+
+    Ok(Json(DeleteSummary {
+        deleted: deleted.len(),
+    }))
+}
+
+/// Begin a transaction. Pass the produced id in the `X-Transaction-Id`
+/// header of find, insert, update and delete requests to run them in the
+/// transaction.
+#[post("/transactions")]
+pub fn begin_transaction(
+    db: &rocket::State<Database>,
+    transactions: &rocket::State<Transactions>,
+) -> status::Custom<Json<TransactionSummary>> {
+    let id = transactions.begin(db);
+    println!("TRANSACTION: {} - begin", id);
+    status::Custom(
+        Status::Created,
+        Json(TransactionSummary { id, status: "open" }),
+    )
+}
+
+/// Commit a transaction. A transaction that conflicts with a write made
+/// since it began is aborted and produces 409, one that wrote a collection
+/// dropped or renamed since 404.
+#[post("/transactions/<id>/commit")]
+pub fn commit_transaction(
+    id: u64,
+    transactions: &rocket::State<Transactions>,
+) -> Result<Json<TransactionSummary>, ApiError> {
+    let result = transactions.commit(id);
+    println!("TRANSACTION: {} - commit - {:?}", id, &result);
+    result?;
+    Ok(Json(TransactionSummary {
+        id,
+        status: "committed",
+    }))
+}
+
+/// Abort a transaction, discarding its writes
+#[post("/transactions/<id>/abort")]
+pub fn abort_transaction(
+    id: u64,
+    transactions: &rocket::State<Transactions>,
+) -> Result<Json<TransactionSummary>, ApiError> {
+    transactions.abort(id)?;
+    println!("TRANSACTION: {} - abort", id);
+    Ok(Json(TransactionSummary {
+        id,
+        status: "aborted",
+    }))
+}
+
+/// Produces the name, number of documents and indexes of a collection
+fn collection_summary(collection: &Collection) -> CollectionSummary {
+    let mut indexes: Vec<IndexSummary> = collection
+        .indices()
+        .iter()
+        .map(|(field, index)| IndexSummary {
+            name: index::index_name(std::slice::from_ref(field)),
+            fields: vec![field.clone()],
+            unique: index.unique,
+            expire_after_seconds: collection.expire_after(field),
+            kind: None,
+        })
+        .chain(
+            collection
+                .compound_indices()
+                .iter()
+                .map(|index| IndexSummary {
+                    name: index.name(),
+                    fields: index.fields.clone(),
+                    unique: index.unique,
+                    expire_after_seconds: None,
+                    kind: None,
+                }),
+        )
+        .chain(collection.text_index().map(|index| IndexSummary {
+            name: index.name(),
+            fields: index.fields.clone(),
+            unique: false,
+            expire_after_seconds: None,
+            kind: Some(TEXT_INDEX),
+        }))
+        .collect();
+    indexes.sort_by(|a, b| a.name.cmp(&b.name));
+    CollectionSummary {
+        name: collection.name.clone(),
+        documents: collection.len(),
+        indexes,
+        capped: collection.cap().copied(),
+    }
+}
+
+/// List every collection with its number of documents and indexes, ordered
+/// by name
+///
+/// # Arguments
+///
+/// * `db` - registry of thread-safe collections
+#[get("/")]
+pub fn list_collections(db: &rocket::State<Database>) -> Json<Vec<CollectionSummary>> {
+    let collections: Vec<CollectionSummary> = db
+        .list()
+        .iter()
+        .map(|(_, safe_collection)| collection_summary(&database::read(safe_collection)))
+        .collect();
+    println!("COLLECTIONS: {} collections", collections.len());
+    Json(collections)
+}
+
+/// Create a collection, or set the options of an existing one. Options
+/// absent from the body are left as they are. A capped collection evicts
+/// its oldest documents on insert to stay within a `size` in bytes, a `max`
+/// number of documents, or both. `expireAfterSeconds` with a `timeField`
+/// makes the field a TTL index, see `create_index`.
+///
+/// # Arguments
+///
+/// * `collection_name` - the collection to create or configure
+/// * `options` - HTTP request body containing the options, see
+///   `CollectionOptions::from_json`
+/// * `db` - registry of thread-safe collections
+///
+/// # Example
+///
+/// ```json
+/// # options
+/// {
+///   "schema": {"type": "object", "required": ["username"]},
+///   "validationAction": "warn"
+/// }
+/// {"capped": true, "size": 1048576, "max": 1000}
+/// {"expireAfterSeconds": 3600, "timeField": "lastSeen"}
+/// ```
+#[put("/<collection_name>", format = "json", data = "<options>")]
+pub fn create_collection(
+    collection_name: String,
+    options: Result<Json<Value>, json::Error<'_>>,
+    db: &rocket::State<Database>,
+) -> Result<status::Custom<Json<CollectionSummary>>, ApiError> {
+    let options = CollectionOptions::from_json(&options?.into_inner())
+        .map_err(DatastoreError::InvalidOptions)?;
+    let created = db.get(&collection_name).is_none();
+    let safe_collection = db.get_or_create(&collection_name);
+    let mut collection = database::write(&safe_collection);
+    collection.set_options(options)?;
+    println!(
+        "COLLECTION: {} - {}",
+        &collection_name,
+        if created { "created" } else { "configured" }
+    );
+    let status = if created { Status::Created } else { Status::Ok };
+    Ok(status::Custom(
+        status,
+        Json(collection_summary(&collection)),
+    ))
+}
+
+/// Drop a collection with every document and index in it. The route is
+/// apart from `delete`'s so that a DELETE missing its `query` can't drop a
+/// collection.
+///
+/// # Arguments
+///
+/// * `collection_name` - the collection to drop
+/// * `db` - registry of thread-safe collections
+#[delete("/<collection_name>/drop")]
+pub fn drop_collection(
+    collection_name: String,
+    db: &rocket::State<Database>,
+) -> Result<status::NoContent, ApiError> {
+    db.remove(&collection_name)
+        .ok_or_else(|| DatastoreError::CollectionNotFound(collection_name.clone()))?;
+    println!("COLLECTION: {} - dropped", &collection_name);
+    Ok(status::NoContent)
+}
 
-    let mut ids = Vec::new();
-    for doc in values.into_inner().into_iter() {
-        let mut converted_doc = HashMap::new();
-        for (field, value) in doc.iter() {
-            converted_doc.insert(field.clone(), datatypes::from_json(value));
+/// Rename a collection, keeping its documents, indexes and options
+///
+/// # Arguments
+///
+/// * `collection_name` - the collection to rename
+/// * `options` - HTTP request body naming the new name `to`, and with
+///   `dropTarget` whether a collection already named so is dropped rather
+///   than failing the rename with 409
+/// * `db` - registry of thread-safe collections
+///
+/// # Example
+///
+/// ```json
+/// # options
+/// {"to": "accounts", "dropTarget": false}
+/// ```
+#[post("/<collection_name>/rename", format = "json", data = "<options>")]
+pub fn rename_collection(
+    collection_name: String,
+    options: Result<Json<RenameOptions>, json::Error<'_>>,
+    db: &rocket::State<Database>,
+) -> Result<Json<CollectionSummary>, ApiError> {
+    let options = options?;
+    db.rename(&collection_name, &options.to, options.drop_target)?;
+    println!(
+        "COLLECTION: {} - renamed to {}",
+        &collection_name, &options.to
+    );
+    let safe_collection = db
+        .get(&options.to)
+        .ok_or_else(|| DatastoreError::CollectionNotFound(options.to.clone()))?;
+    let summary = collection_summary(&database::read(&safe_collection));
+    Ok(Json(summary))
+}
+
+/// Set the JSON Schema documents inserted into or updated in a collection
+/// must match
+///
+/// # Arguments
+///
+/// * `collection_name` - the collection to validate
+/// * `options` - HTTP request body containing the schema and validation
+///   action, a null schema removes the collection's validator
+/// * `db` - registry of thread-safe collections
+///
+/// # Example
+///
+/// ```json
+/// # options
+/// {
+///   "schema": {
+///     "type": "object",
+///     "required": ["username"],
+///     "properties": {"age": {"type": "integer", "minimum": 0}}
+///   },
+///   "validationAction": "error"
+/// }
+/// ```
+#[put("/<collection_name>/schema", format = "json", data = "<options>")]
+pub fn set_schema(
+    collection_name: String,
+    options: Result<Json<Value>, json::Error<'_>>,
+    db: &rocket::State<Database>,
+) -> Result<status::NoContent, ApiError> {
+    let options = options?;
+    let validator = match options.get("schema") {
+        Some(Value::Null) => None,
+        _ => Some(Validator::from_json(&options).map_err(DatastoreError::InvalidOptions)?),
+    };
+    println!(
+        "SCHEMA: Collection - {} - {}",
+        &collection_name,
+        if validator.is_some() {
+            "set"
+        } else {
+            "removed"
+        }
+    );
+    let safe_collection = db.get_or_create(&collection_name);
+    database::write(&safe_collection).set_validator(validator);
+    Ok(status::NoContent)
+}
+
+/// Index a field, or a list of fields, of a collection. A unique index
+/// rejects inserts and updates that would give two documents the same key,
+/// and cannot be created over documents that already share one. Queries on
+/// a prefix of a compound index's fields, optionally followed by a range on
+/// the next field, use the index. An index over one date field with
+/// `expireAfterSeconds` is a TTL index: the TTL sweeper removes each
+/// document that long after the date in its field. An index of `type`
+/// `text` answers `$text` searches of the words in its fields; a collection
+/// has at most one.
+///
+/// # Arguments
+///
+/// * `collection_name` - the collection to index
+/// * `options` - HTTP request body naming the field or fields, whether the
+///   index is unique and, for a TTL index, when documents expire, or that
+///   it is a text index
+/// * `db` - registry of thread-safe collections
+///
+/// # Example
+///
+/// ```json
+/// # options
+/// {"fields": ["tenant", "status"], "unique": false}
+/// {"field": "lastSeen", "expireAfterSeconds": 3600}
+/// {"fields": ["title", "body"], "type": "text"}
+/// ```
+#[post("/<collection_name>/indexes", format = "json", data = "<options>")]
+pub fn create_index(
+    collection_name: String,
+    options: Result<Json<IndexOptions>, json::Error<'_>>,
+    db: &rocket::State<Database>,
+) -> Result<status::Created<Json<IndexSummary>>, ApiError> {
+    let options = options?.into_inner();
+    let fields = match (options.field, options.fields) {
+        (Some(field), None) => vec![field],
+        (None, Some(fields)) if !fields.is_empty() => fields,
+        _ => {
+            return Err(DatastoreError::InvalidOptions(String::from(
+                "an index needs either a field or a non-empty list of fields",
+            ))
+            .into())
+        }
+    };
+    if options.expire_after_seconds.is_some() && fields.len() > 1 {
+        return Err(DatastoreError::InvalidOptions(String::from(
+            "expireAfterSeconds requires an index over one field",
+        ))
+        .into());
+    }
+    match options.kind.as_deref() {
+        None => {}
+        Some(TEXT_INDEX) if options.unique || options.expire_after_seconds.is_some() => {
+            return Err(DatastoreError::InvalidOptions(String::from(
+                "a text index can be neither unique nor expire documents",
+            ))
+            .into())
+        }
+        Some(TEXT_INDEX) => return create_text_index(collection_name, fields, db),
+        Some(kind) => {
+            return Err(
+                DatastoreError::InvalidOptions(format!("unknown index type {}", kind)).into(),
+            )
+        }
+    }
+    let safe_collection = db.get_or_create(&collection_name);
+    {
+        let mut collection = database::write(&safe_collection);
+        match (fields.as_slice(), options.expire_after_seconds) {
+            ([field], Some(seconds)) => {
+                collection.create_ttl_index(field, options.unique, seconds)?
+            }
+            ([field], None) => collection.create_index(field, options.unique)?,
+            _ => collection.create_compound_index(fields.clone(), options.unique)?,
         }
-        ids.push(collection.insert(converted_doc))
     }
+    let name = index::index_name(&fields);
     println!(
-        "INSERT: Collection - {} - {} documents",
+        "INDEX: Collection - {} - {}{}{}",
         &collection_name,
-        ids.len()
+        &name,
+        if options.unique { " (unique)" } else { "" },
+        match options.expire_after_seconds {
+            Some(seconds) => format!(" (expires after {}s)", seconds),
+            None => String::new(),
+        }
     );
+    let location = format!("/api/v2/{}/indexes", &collection_name);
+    Ok(status::Created::new(location).body(Json(IndexSummary {
+        name,
+        fields,
+        unique: options.unique,
+        expire_after_seconds: options.expire_after_seconds,
+        kind: None,
+    })))
+}
 
-    status::Custom(Status::Created, Json(ids))
+/// Creates the index answering `$text` searches of a collection's fields,
+/// replacing its text index if it has one
+fn create_text_index(
+    collection_name: String,
+    fields: Vec<String>,
+    db: &rocket::State<Database>,
+) -> Result<status::Created<Json<IndexSummary>>, ApiError> {
+    let safe_collection = db.get_or_create(&collection_name);
+    let mut collection = database::write(&safe_collection);
+    collection.create_text_index(fields);
+    let index = collection
+        .text_index()
+        .expect("the text index was just created");
+    println!(
+        "INDEX: Collection - {} - {} (text)",
+        &collection_name,
+        index.name()
+    );
+    let location = format!("/api/v2/{}/indexes", &collection_name);
+    Ok(status::Created::new(location).body(Json(IndexSummary {
+        name: index.name(),
+        fields: index.fields.clone(),
+        unique: false,
+        expire_after_seconds: None,
+        kind: Some(TEXT_INDEX),
+    })))
+}
+
+/// Export the documents of a collection matching a query, in insertion
+/// order. The format follows the Accept header: newline-delimited JSON by
+/// default, a `.bson` dump of the documents' BSON encodings one after
+/// another for `application/bson`, or CSV for `text/csv`. The documents are
+/// read a page at a time while the response streams, so documents written
+/// during the export may or may not be included.
+///
+/// # Arguments
+///
+/// * `collection_name` - the collection to export
+/// * `query` - query selecting the documents, every document if absent
+/// * `fields` - comma separated fields, or dotted paths into embedded
+///   documents, making up the CSV columns; every field of the exported
+///   documents if absent
+/// * `accept` - the media types the client accepts
+/// * `db` - registry of thread-safe collections
+#[get("/<collection_name>/export?<query>&<fields>")]
+pub fn export(
+    collection_name: String,
+    query: Option<String>,
+    fields: Option<String>,
+    accept: Option<&Accept>,
+    db: &rocket::State<Database>,
+) -> Result<(ContentType, ByteStream![Vec<u8>]), ApiError> {
+    let format = if prefers(accept, &bson_type()) {
+        ExportFormat::Bson
+    } else if prefers(accept, &ContentType::CSV) {
+        ExportFormat::Csv
+    } else {
+        ExportFormat::Ndjson
+    };
+    let safe_collection = db.get(&collection_name);
+    // With a query only the matching ids are gathered up front
+    let ids = match (&safe_collection, &query) {
+        (Some(safe_collection), Some(query)) => {
+            Some(database::read(safe_collection).find_ids(query)?)
+        }
+        (None, _) => Some(Vec::new()),
+        _ => None,
+    };
+    let columns = match (&format, fields) {
+        (ExportFormat::Csv, Some(fields)) => fields.split(',').map(String::from).collect(),
+        (ExportFormat::Csv, None) => {
+            let mut columns = BTreeSet::new();
+            if let Some(safe_collection) = &safe_collection {
+                let collection = database::read(safe_collection);
+                let mut page = ExportPage::new(ids.as_deref());
+                while let Some(documents) = page.next(&collection) {
+                    for (_, document) in documents {
+                        columns.extend(csv::field_paths(document));
+                    }
+                }
+            }
+            columns.into_iter().collect()
+        }
+        _ => Vec::new(),
+    };
+    println!(
+        "EXPORT: Collection - {} - {} - {}",
+        &collection_name,
+        query.as_deref().unwrap_or("{}"),
+        format.name()
+    );
+
+    let content_type = match format {
+        ExportFormat::Bson => bson_type(),
+        ExportFormat::Csv => ContentType::CSV,
+        ExportFormat::Ndjson => ndjson_type(),
+    };
+    let stream = ByteStream! {
+        if let ExportFormat::Csv = format {
+            yield csv::write_record(&columns).into_bytes();
+        }
+        let mut page = ExportPage::new(ids.as_deref());
+        while let Some(safe_collection) = &safe_collection {
+            let chunk = {
+                let collection = database::read(safe_collection);
+                let documents = match page.next(&collection) {
+                    Some(documents) => documents,
+                    None => break,
+                };
+                let mut chunk = Vec::new();
+                for (key, document) in documents {
+                    match format {
+                        ExportFormat::Bson => match bson::encode(document) {
+                            Ok(bytes) => chunk.extend(bytes),
+                            Err(e) => println!("EXPORT: Collection - {} - skipped {} - {}", &collection_name, key, e),
+                        },
+                        ExportFormat::Csv => {
+                            let values: Vec<String> = columns
+                                .iter()
+                                .map(|column| csv::field_text(field_value(document, column)))
+                                .collect();
+                            chunk.extend(csv::write_record(&values).into_bytes());
+                        }
+                        ExportFormat::Ndjson => {
+                            chunk.extend(serde_json::to_vec(&to_json_document(document)).unwrap());
+                            chunk.push(b'\n');
+                        }
+                    }
+                }
+                chunk
+            };
+            yield chunk;
+        }
+    };
+    Ok((content_type, stream))
+}
+
+/// The formats a collection can be exported in
+enum ExportFormat {
+    Ndjson,
+    Bson,
+    Csv,
+}
+
+impl ExportFormat {
+    fn name(&self) -> &'static str {
+        match self {
+            ExportFormat::Ndjson => "ndjson",
+            ExportFormat::Bson => "bson",
+            ExportFormat::Csv => "csv",
+        }
+    }
+}
+
+/// Reads the documents of an export a page at a time, either every document
+/// of the collection or those under a list of ids
+struct ExportPage<'a> {
+    ids: Option<&'a [usize]>,
+    /// The last key read, or the number of ids read
+    position: usize,
+}
+
+impl<'a> ExportPage<'a> {
+    fn new(ids: Option<&'a [usize]>) -> ExportPage<'a> {
+        ExportPage { ids, position: 0 }
+    }
+
+    /// Produces the next page of documents, None once every page is read
+    fn next<'c>(&mut self, collection: &'c Collection) -> Option<Vec<(usize, &'c Document)>> {
+        let documents = match self.ids {
+            None => {
+                let documents = collection.documents_after(self.position, EXPORT_PAGE_SIZE);
+                if let Some((key, _)) = documents.last() {
+                    self.position = *key;
+                }
+                documents
+            }
+            Some(ids) => {
+                let page = &ids[self.position.min(ids.len())..];
+                let page = &page[..page.len().min(EXPORT_PAGE_SIZE)];
+                self.position += page.len();
+                // Documents removed since the query ran are skipped
+                page.iter()
+                    .filter_map(|id| collection.get(*id).map(|document| (*id, document)))
+                    .collect()
+            }
+        };
+        if documents.is_empty() && self.exhausted() {
+            return None;
+        }
+        Some(documents)
+    }
+
+    fn exhausted(&self) -> bool {
+        match self.ids {
+            None => true,
+            Some(ids) => self.position >= ids.len(),
+        }
+    }
+}
+
+/// Header an EventSource sends when it reconnects, holding the id of the
+/// last event it received
+pub const LAST_EVENT_ID_HEADER: &str = "Last-Event-ID";
+
+/// The resume token of a reconnecting EventSource, if any
+pub struct LastEventId(pub Option<String>);
+
+#[rocket::async_trait]
+impl<'r> FromRequest<'r> for LastEventId {
+    type Error = ();
+
+    async fn from_request(request: &'r Request<'_>) -> request::Outcome<Self, Self::Error> {
+        Outcome::Success(LastEventId(
+            request
+                .headers()
+                .get_one(LAST_EVENT_ID_HEADER)
+                .map(String::from),
+        ))
+    }
+}
+
+/// A write to a watched collection, sent as the data of a server-sent
+/// event named after its operation
+#[derive(Serialize)]
+pub struct ChangeNotice {
+    pub operation: Operation,
+    pub id: usize,
+    /// The document after the write, absent for a delete
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub document: Option<BTreeMap<String, Value>>,
+    /// Resume token of the event, also its event id
+    pub token: String,
+}
+
+/// Watch the writes to a collection as server-sent events. Each insert,
+/// update or delete of a document matching the query is sent as an event
+/// named after the operation, with a ChangeNotice as its data and its
+/// resume token as its id. A client resuming after a token receives every
+/// matching write since, as long as the collection still keeps them; an
+/// EventSource does so on its own by sending the `Last-Event-ID` header
+/// when it reconnects. An `invalidate` event ends the stream when the
+/// collection is dropped or renamed.
+///
+/// # Arguments
+///
+/// * `collection_name` - the collection to watch
+/// * `query` - query the written documents must match, the removed
+///   document for a delete, every document if absent
+/// * `resume_after` - the token of the last event already received, now
+///   if absent
+/// * `last_event_id` - the token an EventSource resumes after, used when
+///   there is no `resume_after`
+/// * `db` - registry of thread-safe collections
+///
+/// # Example
+///
+/// ```text
+/// event: update
+/// id: 42
+/// data: {"operation":"update","id":3,"document":{"username":"johnperry","age":76},"token":"42"}
+/// ```
+#[get("/<collection_name>/watch?<query>&<resume_after>")]
+pub fn watch(
+    collection_name: String,
+    query: Option<String>,
+    resume_after: Option<String>,
+    last_event_id: LastEventId,
+    db: &rocket::State<Database>,
+) -> Result<EventStream![], ApiError> {
+    let filter = Filter::try_from(match &query {
+        Some(query) => Query::parse(query)?,
+        None => Query::from_json(&Value::Null)?,
+    })?;
+    let safe_collection = db
+        .get(&collection_name)
+        .ok_or_else(|| DatastoreError::CollectionNotFound(collection_name.clone()))?;
+    let mut token = match resume_after.or(last_event_id.0) {
+        Some(token) => {
+            let token = token.parse().map_err(|_| {
+                DatastoreError::InvalidOptions(format!("{} is not a resume token", token))
+            })?;
+            database::read(&safe_collection).changes_since(token)?;
+            token
+        }
+        None => database::read(&safe_collection).version(),
+    };
+    println!(
+        "WATCH: Collection - {} - {} - after {}",
+        &collection_name,
+        query.as_deref().unwrap_or("{}"),
+        token
+    );
+
+    let db = db.inner().clone();
+    Ok(EventStream! {
+        loop {
+            let watched = db
+                .get(&collection_name)
+                .is_some_and(|current| Arc::ptr_eq(&current, &safe_collection));
+            if !watched {
+                yield Event::json(&serde_json::json!({
+                    "operation": "invalidate",
+                    "token": token.to_string(),
+                }))
+                .event("invalidate")
+                .id(token.to_string());
+                break;
+            }
+            let changes = database::read(&safe_collection).changes_since(token);
+            match changes {
+                Ok(changes) => {
+                    for change in changes {
+                        token = change.token;
+                        if filter.matches(&change.document) {
+                            yield change_event(change);
+                        }
+                    }
+                }
+                // The client fell further behind than the collection keeps
+                Err(error) => {
+                    yield Event::json(&*ApiError::from(error).body).event("error");
+                    break;
+                }
+            }
+            sleep(Duration::from_millis(WATCH_POLL_MILLIS)).await;
+        }
+    })
+}
+
+fn change_event(change: ChangeEvent) -> Event {
+    let token = change.token.to_string();
+    let notice = ChangeNotice {
+        operation: change.operation,
+        id: change.key,
+        document: match change.operation {
+            Operation::Delete => None,
+            _ => Some(to_json_document(&change.document)),
+        },
+        token: token.clone(),
+    };
+    Event::json(&notice)
+        .event(change.operation.name())
+        .id(token)
+}
+
+/// Import a `.bson` dump into a collection. The dump is decoded in full
+/// before any document is inserted, so a malformed dump inserts nothing;
+/// documents that cannot be inserted are reported with their position in
+/// the dump and the import carries on.
+///
+/// # Arguments
+///
+/// * `collection_name` - the collection to insert the documents into
+/// * `data` - HTTP request body containing concatenated BSON documents, at
+///   most the `bson` limit or 16 MiB
+/// * `limits` - the configured request body limits
+/// * `db` - registry of thread-safe collections
+#[post(
+    "/<collection_name>/import",
+    format = "application/bson",
+    data = "<data>"
+)]
+pub async fn import_bson(
+    collection_name: String,
+    data: Data<'_>,
+    limits: &Limits,
+    db: &rocket::State<Database>,
+) -> Result<Json<ImportReport<DocumentError>>, ApiError> {
+    let limit = limits
+        .get("bson")
+        .unwrap_or_else(|| BSON_LIMIT_MIB.mebibytes());
+    let bytes = data
+        .open(limit)
+        .into_bytes()
+        .await
+        .map_err(ApiError::unreadable)?;
+    if !bytes.is_complete() {
+        return Err(ApiError::payload_too_large(limit));
+    }
+    let documents = bson::decode_all(&bytes).map_err(DatastoreError::Malformed)?;
+
+    let safe_collection = db.get_or_create(&collection_name);
+    let mut collection = database::write(&safe_collection);
+    let mut report = ImportReport {
+        inserted: Vec::new(),
+        errors: Vec::new(),
+    };
+    for (position, document) in documents.into_iter().enumerate() {
+        match collection.insert(document) {
+            Ok(id) => report.inserted.push(id),
+            Err(e) => report.errors.push(DocumentError {
+                document: position + 1,
+                message: e.to_string(),
+            }),
+        }
+    }
+    println!(
+        "IMPORT: Collection - {} - {} documents from BSON - {} errors",
+        &collection_name,
+        report.inserted.len(),
+        report.errors.len()
+    );
+    Ok(Json(report))
+}
+
+/// Import newline-delimited JSON, one document per line, into a collection.
+/// Lines are read and inserted as they arrive; a line that is not a JSON
+/// object or cannot be inserted is reported with its line number and the
+/// import carries on. Blank lines are skipped.
+///
+/// # Arguments
+///
+/// * `collection_name` - the collection to insert the documents into
+/// * `data` - HTTP request body of documents, one per line, at most the
+///   `ndjson` limit or 256 MiB
+/// * `limits` - the configured request body limits
+/// * `db` - registry of thread-safe collections
+///
+/// # Example
+///
+/// ```json
+/// # data
+/// {"username": "johnperry", "age": 75}
+/// {"username": "louiswu", "age": 200}
+/// ```
+#[post(
+    "/<collection_name>/import",
+    format = "application/x-ndjson",
+    data = "<data>"
+)]
+pub async fn import_ndjson(
+    collection_name: String,
+    data: Data<'_>,
+    limits: &Limits,
+    db: &rocket::State<Database>,
+) -> Json<ImportReport> {
+    let limit = limits
+        .get("ndjson")
+        .unwrap_or_else(|| NDJSON_LIMIT_MIB.mebibytes());
+    // A byte past the limit is read to tell a body cut at the limit, even
+    // at a line's end, from one that fits
+    let mut reader = BufReader::new(data.open(limit + 1));
+    let safe_collection = db.get_or_create(&collection_name);
+    let mut report = ImportReport {
+        inserted: Vec::new(),
+        errors: Vec::new(),
+    };
+    let (mut line, mut read) = (String::new(), 0u64);
+    for number in 1.. {
+        line.clear();
+        let length = match reader.read_line(&mut line).await {
+            Ok(0) => break,
+            Ok(length) => length,
+            Err(e) => {
+                report.errors.push(LineError {
+                    line: number,
+                    message: e.to_string(),
+                });
+                break;
+            }
+        };
+        read += length as u64;
+        if read > limit.as_u64() {
+            report.errors.push(LineError {
+                line: number,
+                message: format!("the import is larger than the limit of {}", limit),
+            });
+            break;
+        }
+        if line.trim().is_empty() {
+            continue;
+        }
+        let inserted = serde_json::from_str::<HashMap<String, Value>>(&line)
+            .map_err(|e| e.to_string())
+            .and_then(|document| {
+                let mut collection = database::write(&safe_collection);
+                collection
+                    .insert(from_json_document(&document))
+                    .map_err(|e| e.to_string())
+            });
+        match inserted {
+            Ok(id) => report.inserted.push(id),
+            Err(message) => report.errors.push(LineError {
+                line: number,
+                message,
+            }),
+        }
+    }
+    println!(
+        "IMPORT: Collection - {} - {} documents from NDJSON - {} errors",
+        &collection_name,
+        report.inserted.len(),
+        report.errors.len()
+    );
+    Json(report)
+}
+
+/// Import CSV into a collection, a document per record. The header row
+/// names the fields; dotted headers such as `address.city` produce embedded
+/// documents. Values are strings unless `infer` is set, in which case
+/// integers, floats, booleans and nulls are recognised. Records that cannot
+/// be inserted are reported with their line number and the import carries
+/// on; malformed CSV or headers reject the import whole.
+///
+/// # Arguments
+///
+/// * `collection_name` - the collection to insert the documents into
+/// * `infer` - whether to infer the values' types
+/// * `data` - HTTP request body of CSV, at most the `csv` limit or 64 MiB
+/// * `limits` - the configured request body limits
+/// * `db` - registry of thread-safe collections
+///
+/// # Example
+///
+/// ```text
+/// # data
+/// username,age,address.city
+/// johnperry,75,Ohio
+/// ```
+#[post(
+    "/<collection_name>/import?<infer>",
+    format = "text/csv",
+    data = "<data>"
+)]
+pub async fn import_csv(
+    collection_name: String,
+    infer: Option<bool>,
+    data: Data<'_>,
+    limits: &Limits,
+    db: &rocket::State<Database>,
+) -> Result<Json<ImportReport>, ApiError> {
+    let limit = limits
+        .get("csv")
+        .unwrap_or_else(|| CSV_LIMIT_MIB.mebibytes());
+    let text = data
+        .open(limit)
+        .into_string()
+        .await
+        .map_err(ApiError::unreadable)?;
+    if !text.is_complete() {
+        return Err(ApiError::payload_too_large(limit));
+    }
+    let records = csv::parse(&text).map_err(DatastoreError::Malformed)?;
+    let (headers, records) = match records.split_first() {
+        Some((headers, records)) => (headers, records),
+        None => {
+            return Err(DatastoreError::Malformed(String::from("the CSV has no header")).into())
+        }
+    };
+    let paths = csv::header_paths(&headers.fields).map_err(DatastoreError::Malformed)?;
+
+    let safe_collection = db.get_or_create(&collection_name);
+    let mut collection = database::write(&safe_collection);
+    let mut report = ImportReport {
+        inserted: Vec::new(),
+        errors: Vec::new(),
+    };
+    for record in records {
+        let inserted = csv::to_document(&paths, record, infer.unwrap_or(false))
+            .and_then(|document| collection.insert(document).map_err(|e| e.to_string()));
+        match inserted {
+            Ok(id) => report.inserted.push(id),
+            Err(message) => report.errors.push(LineError {
+                line: record.line,
+                message,
+            }),
+        }
+    }
+    println!(
+        "IMPORT: Collection - {} - {} documents from CSV - {} errors",
+        &collection_name,
+        report.inserted.len(),
+        report.errors.len()
+    );
+    Ok(Json(report))
 }
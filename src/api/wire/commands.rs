@@ -0,0 +1,1068 @@
+use crate::datastore::bson::{self, Bson, BsonDocument};
+use crate::datastore::bulk;
+use crate::datastore::collection::Document;
+use crate::datastore::database::{self, Database};
+use crate::datastore::datatypes::{self, DataType, Float};
+use crate::datastore::error::DatastoreError;
+use crate::datastore::query_proc::{Filter, Query, NATURAL};
+use crate::datastore::update as update_doc;
+use std::collections::{BTreeMap, HashMap};
+use std::convert::TryFrom;
+use std::sync::atomic::{AtomicI64, Ordering};
+use std::sync::Mutex;
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+
+/// Largest BSON document accepted, as advertised by `hello`
+pub const MAX_BSON_OBJECT_SIZE: i64 = 16 * 1024 * 1024;
+/// Largest message accepted, as advertised by `hello`
+pub const MAX_MESSAGE_SIZE: i64 = 48_000_000;
+/// Largest number of documents of a write command, as advertised by `hello`
+const MAX_WRITE_BATCH_SIZE: i64 = 100_000;
+/// Wire versions spoken, those of MongoDB 3.6 through 5.0. Every one of
+/// them has OP_MSG.
+const MIN_WIRE_VERSION: i64 = 6;
+const MAX_WIRE_VERSION: i64 = 13;
+/// The server version reported to drivers and shells
+const VERSION: [i64; 3] = [5, 0, 0];
+/// Number of documents in the first batch of a find without a batchSize
+const DEFAULT_BATCH_SIZE: usize = 101;
+/// How long a cursor that is not read from lives
+const CURSOR_TIMEOUT: Duration = Duration::from_secs(10 * 60);
+
+/// A failed command, with MongoDB's code and code name for the failure
+#[derive(Debug, Clone, PartialEq)]
+pub struct CommandError {
+    pub code: i64,
+    pub name: &'static str,
+    pub message: String,
+}
+
+impl CommandError {
+    fn bad_value(message: String) -> CommandError {
+        CommandError {
+            code: 2,
+            name: "BadValue",
+            message,
+        }
+    }
+
+    fn type_mismatch(field: &str, expected: &str) -> CommandError {
+        CommandError {
+            code: 14,
+            name: "TypeMismatch",
+            message: format!("{} must be {}", field, expected),
+        }
+    }
+
+    /// Produces the reply of the failed command
+    pub fn reply(&self) -> BsonDocument {
+        vec![
+            field("ok", DataType::F64(Float::new(0.0))),
+            field("errmsg", DataType::String(self.message.clone())),
+            field("code", DataType::I64(self.code)),
+            field("codeName", DataType::String(String::from(self.name))),
+        ]
+    }
+}
+
+impl From<DatastoreError> for CommandError {
+    fn from(error: DatastoreError) -> Self {
+        let (code, name) = match error {
+            DatastoreError::InvalidQuery => (2, "BadValue"),
+            DatastoreError::InvalidId => (1, "InternalError"),
+            DatastoreError::InvalidUpdate(_) | DatastoreError::Malformed(_) => (9, "FailedToParse"),
+            DatastoreError::Validation(_) => (121, "DocumentValidationFailure"),
+            DatastoreError::DuplicateKey { .. } => (11000, "DuplicateKey"),
+            DatastoreError::TransactionNotFound => (251, "NoSuchTransaction"),
+            DatastoreError::CollectionNotFound(_) => (26, "NamespaceNotFound"),
+            DatastoreError::CollectionExists(_) => (48, "NamespaceExists"),
+            DatastoreError::WriteConflict { .. } => (112, "WriteConflict"),
+            DatastoreError::InvalidOptions(_) => (72, "InvalidOptions"),
+            DatastoreError::ChangeHistoryLost(_) => (286, "ChangeStreamHistoryLost"),
+        };
+        CommandError {
+            code,
+            name,
+            message: error.to_string(),
+        }
+    }
+}
+
+/// The remaining documents of a find, read a batch at a time by getMore
+struct Cursor {
+    namespace: String,
+    collection: String,
+    ids: Vec<usize>,
+    position: usize,
+    last_used: Instant,
+    /// The filter of a tailable cursor, which stays open once read to the
+    /// end and reads the documents inserted since
+    tail: Option<Filter>,
+    /// The highest key read
+    last_key: usize,
+}
+
+/// Runs wire protocol commands against a Database. Cursors are shared by
+/// every connection, as drivers may read a cursor over any connection of
+/// their pool.
+pub struct Server {
+    database: Database,
+    cursors: Mutex<HashMap<i64, Cursor>>,
+    next_cursor: AtomicI64,
+}
+
+impl Server {
+    pub fn new(database: Database) -> Server {
+        Server {
+            database,
+            cursors: Mutex::new(HashMap::new()),
+            next_cursor: AtomicI64::new(1),
+        }
+    }
+
+    /// Produces the reply to a command, the command being named by its
+    /// first field. Failed commands reply `ok: 0` with an error code.
+    ///
+    /// # Arguments
+    ///
+    /// * `command` - the command's body, with the documents of any
+    ///   document sequences as arrays
+    pub fn run(&self, command: &[(String, Bson)]) -> BsonDocument {
+        let name = command.first().map(|(name, _)| name.as_str()).unwrap_or("");
+        let db = string_field(command, "$db").unwrap_or(Ok("test"));
+        let result = db.and_then(|db| match name {
+            "hello" | "isMaster" | "ismaster" => Ok(hello()),
+            "ping" | "endSessions" => Ok(Vec::new()),
+            "buildInfo" | "buildinfo" => Ok(build_info()),
+            "find" => self.find(command, db),
+            "getMore" => self.get_more(command, db),
+            "killCursors" => self.kill_cursors(command),
+            "insert" => self.insert(command),
+            "update" => self.update(command),
+            "delete" => self.delete(command),
+            _ => Err(CommandError {
+                code: 59,
+                name: "CommandNotFound",
+                message: format!("no such command: '{}'", name),
+            }),
+        });
+        match result {
+            Ok(mut reply) => {
+                reply.push(field("ok", DataType::F64(Float::new(1.0))));
+                reply
+            }
+            Err(error) => error.reply(),
+        }
+    }
+
+    fn find(&self, command: &[(String, Bson)], db: &str) -> Result<BsonDocument, CommandError> {
+        let collection_name = required_string(command, "find")?;
+        let filter = match get(command, "filter") {
+            None => Document::new(),
+            Some(Bson::Document(filter)) => bson::to_document(filter.clone()),
+            Some(_) => return Err(CommandError::type_mismatch("filter", "a document")),
+        };
+        if let Some(Bson::Document(value)) = get(command, "projection") {
+            if !value.is_empty() {
+                return Err(CommandError::bad_value(String::from(
+                    "projection is not supported",
+                )));
+            }
+        }
+        let reverse = match get(command, "sort") {
+            None => false,
+            Some(Bson::Document(sort)) => match sort.as_slice() {
+                [] => false,
+                [(field, Bson::Value(order))] if field == NATURAL => match integer(order) {
+                    Some(1) => false,
+                    Some(-1) => true,
+                    _ => {
+                        return Err(CommandError::bad_value(format!(
+                            "bad sort order {}",
+                            NATURAL
+                        )))
+                    }
+                },
+                _ => {
+                    return Err(CommandError::bad_value(format!(
+                        "sort is only supported on {}",
+                        NATURAL
+                    )))
+                }
+            },
+            Some(_) => return Err(CommandError::type_mismatch("sort", "a document")),
+        };
+        let skip = non_negative(command, "skip")?.unwrap_or(0);
+        let limit = integer_field(command, "limit")?.unwrap_or(0);
+        let batch_size = non_negative(command, "batchSize")?;
+        let single_batch = bool_field(command, "singleBatch")?.unwrap_or(false) || limit < 0;
+        let tailable = bool_field(command, "tailable")?.unwrap_or(false);
+        println!(
+            "FIND: Collection - {} - wire{}",
+            collection_name,
+            if tailable { " - tailable" } else { "" }
+        );
+
+        let safe_collection = self.database.get(collection_name);
+        // A new cursor loses no position to documents evicted before it
+        let (capped, evicted_through) = match &safe_collection {
+            Some(safe_collection) => {
+                let collection = database::read(safe_collection);
+                (collection.cap().is_some(), collection.evicted_through())
+            }
+            None => (false, 0),
+        };
+        if tailable && (!capped || reverse || single_batch) {
+            return Err(CommandError::bad_value(String::from(
+                "error processing query: tailable cursors need a capped collection, read in \
+                 natural order over more than one batch",
+            )));
+        }
+        let query = Query::from_document(&filter)?;
+        let tail = if tailable {
+            Some(Filter::try_from(query.clone())?)
+        } else {
+            None
+        };
+        let mut ids = match &safe_collection {
+            Some(safe_collection) => database::read(safe_collection).run(&query)?.ids,
+            None => Vec::new(),
+        };
+        if reverse {
+            ids.reverse();
+        }
+        ids.drain(..skip.min(ids.len()));
+        if limit != 0 {
+            ids.truncate(limit.unsigned_abs() as usize);
+        }
+        let namespace = format!("{}.{}", db, collection_name);
+        let mut cursor = Cursor {
+            namespace: namespace.clone(),
+            collection: String::from(collection_name),
+            ids,
+            position: 0,
+            last_used: Instant::now(),
+            tail,
+            last_key: evicted_through,
+        };
+        let batch = self.next_batch(&mut cursor, batch_size.unwrap_or(DEFAULT_BATCH_SIZE))?;
+        let id = if cursor.tail.is_some() {
+            self.register(cursor)
+        } else if single_batch || cursor.position == cursor.ids.len() {
+            0
+        } else {
+            self.register(cursor)
+        };
+        Ok(cursor_reply("firstBatch", batch, id, namespace))
+    }
+
+    fn get_more(&self, command: &[(String, Bson)], db: &str) -> Result<BsonDocument, CommandError> {
+        let id = integer_field(command, "getMore")?
+            .ok_or_else(|| CommandError::type_mismatch("getMore", "a cursor id"))?;
+        let collection_name = required_string(command, "collection")?;
+        let batch_size = non_negative(command, "batchSize")?.unwrap_or(usize::MAX);
+        let namespace = format!("{}.{}", db, collection_name);
+        let mut cursor = database::lock(&self.cursors)
+            .remove(&id)
+            .filter(|cursor| cursor.namespace == namespace)
+            .ok_or_else(|| CommandError {
+                code: 43,
+                name: "CursorNotFound",
+                message: format!("cursor id {} not found", id),
+            })?;
+        let batch = self.next_batch(&mut cursor, batch_size)?;
+        let id = if cursor.position == cursor.ids.len() && cursor.tail.is_none() {
+            0
+        } else {
+            cursor.last_used = Instant::now();
+            database::lock(&self.cursors).insert(id, cursor);
+            id
+        };
+        Ok(cursor_reply("nextBatch", batch, id, namespace))
+    }
+
+    fn kill_cursors(&self, command: &[(String, Bson)]) -> Result<BsonDocument, CommandError> {
+        let ids = match get(command, "cursors") {
+            Some(Bson::Array(ids)) => ids,
+            _ => return Err(CommandError::type_mismatch("cursors", "an array")),
+        };
+        let (mut killed, mut not_found) = (Vec::new(), Vec::new());
+        let mut cursors = database::lock(&self.cursors);
+        for id in ids.iter() {
+            let id = match id {
+                Bson::Value(value) => integer(value),
+                _ => None,
+            }
+            .ok_or_else(|| CommandError::type_mismatch("cursors", "an array of cursor ids"))?;
+            if cursors.remove(&id).is_some() {
+                killed.push(Bson::Value(DataType::I64(id)));
+            } else {
+                not_found.push(Bson::Value(DataType::I64(id)));
+            }
+        }
+        Ok(vec![
+            (String::from("cursorsKilled"), Bson::Array(killed)),
+            (String::from("cursorsNotFound"), Bson::Array(not_found)),
+            (String::from("cursorsAlive"), Bson::Array(Vec::new())),
+            (String::from("cursorsUnknown"), Bson::Array(Vec::new())),
+        ])
+    }
+
+    fn insert(&self, command: &[(String, Bson)]) -> Result<BsonDocument, CommandError> {
+        let collection_name = required_string(command, "insert")?;
+        let documents = required_array(command, "documents")?;
+        let ordered = bool_field(command, "ordered")?.unwrap_or(true);
+        let safe_collection = self.database.get_or_create(collection_name);
+        let mut collection = database::write(&safe_collection);
+        let mut inserted = 0;
+        let mut write_errors = Vec::new();
+        for (index, document) in documents.iter().enumerate() {
+            let result = to_stored_document(document, "documents")
+                .and_then(|document| collection.insert(document).map_err(CommandError::from));
+            match result {
+                Ok(_) => inserted += 1,
+                Err(error) => {
+                    write_errors.push(write_error(index, error));
+                    if ordered {
+                        break;
+                    }
+                }
+            }
+        }
+        println!(
+            "INSERT: Collection - {} - {} documents - wire",
+            collection_name, inserted
+        );
+        Ok(write_reply(inserted, write_errors))
+    }
+
+    fn update(&self, command: &[(String, Bson)]) -> Result<BsonDocument, CommandError> {
+        let collection_name = required_string(command, "update")?;
+        let statements = required_array(command, "updates")?;
+        let ordered = bool_field(command, "ordered")?.unwrap_or(true);
+        let safe_collection = self.database.get_or_create(collection_name);
+        let mut collection = database::write(&safe_collection);
+        let (mut matched, mut modified) = (0, 0);
+        let mut upserted = Vec::new();
+        let mut write_errors = Vec::new();
+        for (index, statement) in statements.iter().enumerate() {
+            let result = UpdateStatement::from_bson(statement).and_then(|statement| {
+                let mut ids = collection
+                    .run(&Query::from_document(&statement.filter)?)?
+                    .ids;
+                if !statement.multi {
+                    ids.truncate(1);
+                }
+                if ids.is_empty() && statement.upsert {
+                    let document = bulk::upsert_document(&statement.filter, &statement.update)?;
+                    let id = document.get("_id").cloned();
+                    let key = collection.insert(document)?;
+                    return Ok((0, 0, Some(id.unwrap_or(DataType::I64(key as i64)))));
+                }
+                let result = collection.update_ids(&ids, &statement.update)?;
+                Ok((result.matched, result.modified.len(), None))
+            });
+            match result {
+                Ok((statement_matched, statement_modified, upsert)) => {
+                    matched += statement_matched;
+                    modified += statement_modified;
+                    if let Some(id) = upsert {
+                        upserted.push(Bson::Document(vec![
+                            field("index", DataType::I64(index as i64)),
+                            field("_id", id),
+                        ]));
+                    }
+                }
+                Err(error) => {
+                    write_errors.push(write_error(index, error));
+                    if ordered {
+                        break;
+                    }
+                }
+            }
+        }
+        println!(
+            "UPDATE: Collection - {} - {} documents - wire",
+            collection_name, modified
+        );
+        let mut reply = write_reply(matched + upserted.len(), write_errors);
+        reply.push(field("nModified", DataType::I64(modified as i64)));
+        if !upserted.is_empty() {
+            reply.push((String::from("upserted"), Bson::Array(upserted)));
+        }
+        Ok(reply)
+    }
+
+    fn delete(&self, command: &[(String, Bson)]) -> Result<BsonDocument, CommandError> {
+        let collection_name = required_string(command, "delete")?;
+        let statements = required_array(command, "deletes")?;
+        let ordered = bool_field(command, "ordered")?.unwrap_or(true);
+        let safe_collection = self.database.get_or_create(collection_name);
+        let mut collection = database::write(&safe_collection);
+        let mut deleted = 0;
+        let mut write_errors = Vec::new();
+        for (index, statement) in statements.iter().enumerate() {
+            let result = match statement {
+                Bson::Document(statement) => Ok(statement),
+                _ => Err(CommandError::type_mismatch(
+                    "deletes",
+                    "an array of documents",
+                )),
+            }
+            .and_then(|statement| {
+                let filter = required_filter(statement, "q")?;
+                let limit = integer_field(statement, "limit")?.unwrap_or(0);
+                let mut ids = collection.run(&Query::from_document(&filter)?)?.ids;
+                if limit == 1 {
+                    ids.truncate(1);
+                }
+                Ok(ids
+                    .into_iter()
+                    .filter(|id| collection.remove(*id).is_some())
+                    .count())
+            });
+            match result {
+                Ok(count) => deleted += count,
+                Err(error) => {
+                    write_errors.push(write_error(index, error));
+                    if ordered {
+                        break;
+                    }
+                }
+            }
+        }
+        println!(
+            "DELETE: Collection - {} - {} documents - wire",
+            collection_name, deleted
+        );
+        Ok(write_reply(deleted, write_errors))
+    }
+
+    /// Registers a cursor with documents left to read, producing its id.
+    /// Cursors unused for longer than `CURSOR_TIMEOUT` are dropped.
+    fn register(&self, cursor: Cursor) -> i64 {
+        let id = self.next_cursor.fetch_add(1, Ordering::Relaxed);
+        let mut cursors = database::lock(&self.cursors);
+        cursors.retain(|_, cursor| cursor.last_used.elapsed() < CURSOR_TIMEOUT);
+        cursors.insert(id, cursor);
+        id
+    }
+
+    /// Produces up to `size` of a cursor's documents that still exist. A
+    /// tailable cursor read to the end first looks for documents inserted
+    /// after the last it read, and fails once the collection evicted
+    /// documents it had yet to read.
+    fn next_batch(&self, cursor: &mut Cursor, size: usize) -> Result<Vec<Bson>, CommandError> {
+        let safe_collection = match self.database.get(&cursor.collection) {
+            Some(safe_collection) => safe_collection,
+            None => {
+                cursor.tail = None;
+                cursor.position = cursor.ids.len();
+                return Ok(Vec::new());
+            }
+        };
+        let collection = database::read(&safe_collection);
+        if let Some(filter) = &cursor.tail {
+            if collection.evicted_through() > cursor.last_key {
+                return Err(CommandError {
+                    code: 136,
+                    name: "CappedPositionLost",
+                    message: format!(
+                        "the documents after {} in {} were evicted before they were read",
+                        cursor.last_key, &cursor.namespace
+                    ),
+                });
+            }
+            if cursor.position == cursor.ids.len() {
+                cursor.ids = collection.find_ids_after(filter, cursor.last_key);
+                cursor.position = 0;
+            }
+        }
+        let end = cursor.ids.len().min(cursor.position.saturating_add(size));
+        let ids = &cursor.ids[cursor.position..end];
+        cursor.position = end;
+        cursor.last_key = ids.iter().copied().fold(cursor.last_key, usize::max);
+        Ok(ids
+            .iter()
+            .filter_map(|id| collection.get(*id))
+            .map(|document| Bson::Document(to_bson_document(document)))
+            .collect())
+    }
+}
+
+/// An element of an update command's `updates`
+struct UpdateStatement {
+    filter: Document,
+    update: update_doc::Update,
+    multi: bool,
+    upsert: bool,
+}
+
+impl UpdateStatement {
+    fn from_bson(statement: &Bson) -> Result<UpdateStatement, CommandError> {
+        let statement = match statement {
+            Bson::Document(statement) => statement,
+            _ => {
+                return Err(CommandError::type_mismatch(
+                    "updates",
+                    "an array of documents",
+                ))
+            }
+        };
+        let update = match get(statement, "u") {
+            Some(Bson::Document(update)) => update,
+            Some(Bson::Array(_)) => {
+                return Err(CommandError::bad_value(String::from(
+                    "update pipelines are not supported",
+                )))
+            }
+            _ => return Err(CommandError::type_mismatch("u", "a document")),
+        };
+        // Updates are read from JSON, which holds every DataType as
+        // extended JSON
+        let mut json = HashMap::new();
+        for (field, value) in update.iter() {
+            json.insert(
+                field.clone(),
+                datatypes::to_json(&value.clone().into_data_type()),
+            );
+        }
+        Ok(UpdateStatement {
+            filter: required_filter(statement, "q")?,
+            update: update_doc::from_json(&json).map_err(DatastoreError::InvalidUpdate)?,
+            multi: bool_field(statement, "multi")?.unwrap_or(false),
+            upsert: bool_field(statement, "upsert")?.unwrap_or(false),
+        })
+    }
+}
+
+fn hello() -> BsonDocument {
+    let now = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|elapsed| elapsed.as_millis() as i64)
+        .unwrap_or(0);
+    vec![
+        field("helloOk", DataType::Bool(true)),
+        field("isWritablePrimary", DataType::Bool(true)),
+        field("ismaster", DataType::Bool(true)),
+        field("maxBsonObjectSize", DataType::I64(MAX_BSON_OBJECT_SIZE)),
+        field("maxMessageSizeBytes", DataType::I64(MAX_MESSAGE_SIZE)),
+        field("maxWriteBatchSize", DataType::I64(MAX_WRITE_BATCH_SIZE)),
+        field("localTime", DataType::DateTime(now)),
+        field("logicalSessionTimeoutMinutes", DataType::I64(30)),
+        field("minWireVersion", DataType::I64(MIN_WIRE_VERSION)),
+        field("maxWireVersion", DataType::I64(MAX_WIRE_VERSION)),
+        field("readOnly", DataType::Bool(false)),
+    ]
+}
+
+fn build_info() -> BsonDocument {
+    let version: Vec<String> = VERSION.iter().map(|part| part.to_string()).collect();
+    vec![
+        field("version", DataType::String(version.join("."))),
+        (
+            String::from("versionArray"),
+            Bson::Array(
+                VERSION
+                    .iter()
+                    .chain([0].iter())
+                    .map(|part| Bson::Value(DataType::I64(*part)))
+                    .collect(),
+            ),
+        ),
+        field("maxBsonObjectSize", DataType::I64(MAX_BSON_OBJECT_SIZE)),
+    ]
+}
+
+fn cursor_reply(batch_name: &str, batch: Vec<Bson>, id: i64, namespace: String) -> BsonDocument {
+    vec![(
+        String::from("cursor"),
+        Bson::Document(vec![
+            (String::from(batch_name), Bson::Array(batch)),
+            field("id", DataType::I64(id)),
+            field("ns", DataType::String(namespace)),
+        ]),
+    )]
+}
+
+fn write_reply(count: usize, write_errors: Vec<Bson>) -> BsonDocument {
+    let mut reply = vec![field("n", DataType::I64(count as i64))];
+    if !write_errors.is_empty() {
+        reply.push((String::from("writeErrors"), Bson::Array(write_errors)));
+    }
+    reply
+}
+
+fn write_error(index: usize, error: CommandError) -> Bson {
+    Bson::Document(vec![
+        field("index", DataType::I64(index as i64)),
+        field("code", DataType::I64(error.code)),
+        field("errmsg", DataType::String(error.message)),
+    ])
+}
+
+/// Produces a stored document's fields with `_id` first, then in name order
+fn to_bson_document(document: &Document) -> BsonDocument {
+    let sorted: BTreeMap<&String, &DataType> = document.iter().collect();
+    let mut fields: BsonDocument = Vec::with_capacity(sorted.len());
+    if let Some(id) = document.get("_id") {
+        fields.push(field("_id", id.clone()));
+    }
+    fields.extend(
+        sorted
+            .into_iter()
+            .filter(|(name, _)| *name != "_id")
+            .map(|(name, value)| (name.clone(), Bson::from(value.clone()))),
+    );
+    fields
+}
+
+fn to_stored_document(document: &Bson, field_name: &str) -> Result<Document, CommandError> {
+    match document {
+        Bson::Document(fields) => Ok(bson::to_document(fields.clone())),
+        _ => Err(CommandError::type_mismatch(
+            field_name,
+            "an array of documents",
+        )),
+    }
+}
+
+fn field(name: &str, value: DataType) -> (String, Bson) {
+    (String::from(name), Bson::from(value))
+}
+
+fn get<'a>(document: &'a [(String, Bson)], name: &str) -> Option<&'a Bson> {
+    document
+        .iter()
+        .find(|(field, _)| field == name)
+        .map(|(_, value)| value)
+}
+
+fn string_field<'a>(
+    document: &'a [(String, Bson)],
+    name: &str,
+) -> Option<Result<&'a str, CommandError>> {
+    get(document, name).map(|value| match value {
+        Bson::Value(DataType::String(value)) => Ok(value.as_str()),
+        _ => Err(CommandError::type_mismatch(name, "a string")),
+    })
+}
+
+fn required_string<'a>(
+    document: &'a [(String, Bson)],
+    name: &str,
+) -> Result<&'a str, CommandError> {
+    string_field(document, name)
+        .unwrap_or_else(|| Err(CommandError::type_mismatch(name, "a string")))
+}
+
+fn required_array<'a>(
+    document: &'a [(String, Bson)],
+    name: &str,
+) -> Result<&'a [Bson], CommandError> {
+    match get(document, name) {
+        Some(Bson::Array(values)) => Ok(values),
+        _ => Err(CommandError::type_mismatch(name, "an array")),
+    }
+}
+
+fn required_filter(document: &[(String, Bson)], name: &str) -> Result<Document, CommandError> {
+    match get(document, name) {
+        Some(filter @ Bson::Document(_)) => to_stored_document(filter, name),
+        _ => Err(CommandError::type_mismatch(name, "a document")),
+    }
+}
+
+/// Produces an integral number, drivers sending counts as int32, int64 or
+/// double
+fn integer(value: &DataType) -> Option<i64> {
+    match value {
+        DataType::I64(value) => Some(*value),
+        DataType::U64(value) => i64::try_from(*value).ok(),
+        DataType::F64(value) if value.get().fract() == 0.0 => Some(value.get() as i64),
+        _ => None,
+    }
+}
+
+fn integer_field(document: &[(String, Bson)], name: &str) -> Result<Option<i64>, CommandError> {
+    match get(document, name) {
+        None => Ok(None),
+        Some(Bson::Value(value)) => integer(value)
+            .map(Some)
+            .ok_or_else(|| CommandError::type_mismatch(name, "a number")),
+        Some(_) => Err(CommandError::type_mismatch(name, "a number")),
+    }
+}
+
+fn non_negative(document: &[(String, Bson)], name: &str) -> Result<Option<usize>, CommandError> {
+    match integer_field(document, name)? {
+        Some(value) if value < 0 => Err(CommandError::bad_value(format!(
+            "{} must not be negative",
+            name
+        ))),
+        value => Ok(value.map(|value| value as usize)),
+    }
+}
+
+fn bool_field(document: &[(String, Bson)], name: &str) -> Result<Option<bool>, CommandError> {
+    match get(document, name) {
+        None => Ok(None),
+        Some(Bson::Value(DataType::Bool(value))) => Ok(Some(*value)),
+        Some(Bson::Value(value)) => integer(value)
+            .map(|value| Some(value != 0))
+            .ok_or_else(|| CommandError::type_mismatch(name, "a boolean")),
+        Some(_) => Err(CommandError::type_mismatch(name, "a boolean")),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::datastore::capped::Cap;
+
+    fn string(value: &str) -> Bson {
+        Bson::Value(DataType::String(String::from(value)))
+    }
+
+    fn user(username: &str, age: i64) -> Bson {
+        Bson::Document(vec![
+            (String::from("username"), string(username)),
+            field("age", DataType::I64(age)),
+        ])
+    }
+
+    fn reply_field<'a>(reply: &'a [(String, Bson)], path: &[&str]) -> &'a Bson {
+        let (first, rest) = path.split_first().unwrap();
+        let value = get(reply, first).unwrap();
+        match (rest.is_empty(), value) {
+            (true, _) => value,
+            (false, Bson::Document(fields)) => reply_field(fields, rest),
+            _ => panic!("{} is not a document", first),
+        }
+    }
+
+    fn server_with_users() -> Server {
+        let server = Server::new(Database::new());
+        server.run(&[
+            (String::from("insert"), string("users")),
+            (
+                String::from("documents"),
+                Bson::Array(vec![
+                    user("johnperry", 75),
+                    user("louiswu", 200),
+                    user("nessus", 30),
+                ]),
+            ),
+        ]);
+        database::write(&server.database.get("users").unwrap())
+            .create_index("username", true)
+            .unwrap();
+        server
+    }
+
+    #[test]
+    fn insert_reports_write_errors() {
+        let server = server_with_users();
+        let documents = Bson::Array(vec![user("johnperry", 1), user("speaker", 2)]);
+        let ordered = server.run(&[
+            (String::from("insert"), string("users")),
+            (String::from("documents"), documents.clone()),
+        ]);
+        assert_eq!(
+            &Bson::Value(DataType::I64(0)),
+            reply_field(&ordered, &["n"])
+        );
+        let unordered = server.run(&[
+            (String::from("insert"), string("users")),
+            (String::from("documents"), documents),
+            field("ordered", DataType::Bool(false)),
+        ]);
+        assert_eq!(
+            &Bson::Value(DataType::I64(1)),
+            reply_field(&unordered, &["n"])
+        );
+        match reply_field(&unordered, &["writeErrors"]) {
+            Bson::Array(errors) => match &errors[..] {
+                [Bson::Document(error)] => {
+                    assert_eq!(
+                        &Bson::Value(DataType::I64(0)),
+                        reply_field(error, &["index"])
+                    );
+                    assert_eq!(
+                        &Bson::Value(DataType::I64(11000)),
+                        reply_field(error, &["code"])
+                    );
+                }
+                _ => panic!("expected one write error"),
+            },
+            _ => panic!("expected write errors"),
+        }
+    }
+
+    #[test]
+    fn find_in_batches() {
+        let server = server_with_users();
+        let reply = server.run(&[
+            (String::from("find"), string("users")),
+            (String::from("filter"), Bson::Document(Vec::new())),
+            field("skip", DataType::I64(1)),
+            field("batchSize", DataType::I64(1)),
+            field("$db", DataType::String(String::from("app"))),
+        ]);
+        assert_eq!(
+            &Bson::Array(vec![user("louiswu", 200)].into_iter().map(sorted).collect()),
+            reply_field(&reply, &["cursor", "firstBatch"])
+        );
+        assert_eq!(&string("app.users"), reply_field(&reply, &["cursor", "ns"]));
+        let id = reply_field(&reply, &["cursor", "id"]).clone();
+        let get_more = |id: Bson| {
+            server.run(&[
+                (String::from("getMore"), id),
+                (String::from("collection"), string("users")),
+                field("$db", DataType::String(String::from("app"))),
+            ])
+        };
+        let reply = get_more(id.clone());
+        assert_eq!(
+            &Bson::Array(vec![sorted(user("nessus", 30))]),
+            reply_field(&reply, &["cursor", "nextBatch"])
+        );
+        assert_eq!(
+            &Bson::Value(DataType::I64(0)),
+            reply_field(&reply, &["cursor", "id"])
+        );
+        let reply = get_more(id);
+        assert_eq!(
+            &Bson::Value(DataType::I64(43)),
+            reply_field(&reply, &["code"])
+        );
+    }
+
+    #[test]
+    fn find_with_in() {
+        let server = server_with_users();
+        let reply = server.run(&[
+            (String::from("find"), string("users")),
+            (
+                String::from("filter"),
+                Bson::Document(vec![(
+                    String::from("username"),
+                    Bson::Document(vec![(
+                        String::from("$in"),
+                        Bson::Array(vec![string("nessus"), string("johnperry")]),
+                    )]),
+                )]),
+            ),
+            field("$db", DataType::String(String::from("app"))),
+        ]);
+        assert_eq!(
+            &Bson::Array(
+                vec![user("johnperry", 75), user("nessus", 30)]
+                    .into_iter()
+                    .map(sorted)
+                    .collect()
+            ),
+            reply_field(&reply, &["cursor", "firstBatch"])
+        );
+    }
+
+    #[test]
+    fn tail_a_capped_collection() {
+        let server = server_with_users();
+        let find = || {
+            server.run(&[
+                (String::from("find"), string("users")),
+                field("tailable", DataType::Bool(true)),
+            ])
+        };
+        assert_eq!(
+            &Bson::Value(DataType::I64(2)),
+            reply_field(&find(), &["code"])
+        );
+
+        database::write(&server.database.get("users").unwrap()).set_cap(Some(Cap {
+            max_bytes: None,
+            max_documents: Some(3),
+        }));
+        let reply = find();
+        match reply_field(&reply, &["cursor", "firstBatch"]) {
+            Bson::Array(batch) => assert_eq!(3, batch.len()),
+            _ => panic!("expected a batch"),
+        }
+        let id = reply_field(&reply, &["cursor", "id"]).clone();
+        let get_more = || {
+            server.run(&[
+                (String::from("getMore"), id.clone()),
+                (String::from("collection"), string("users")),
+            ])
+        };
+        let insert = |username: &str| {
+            server.run(&[
+                (String::from("insert"), string("users")),
+                (
+                    String::from("documents"),
+                    Bson::Array(vec![user(username, 1)]),
+                ),
+            ])
+        };
+        assert_eq!(
+            &Bson::Array(Vec::new()),
+            reply_field(&get_more(), &["cursor", "nextBatch"])
+        );
+        insert("speaker");
+        let reply = get_more();
+        assert_eq!(
+            &Bson::Array(vec![sorted(user("speaker", 1))]),
+            reply_field(&reply, &["cursor", "nextBatch"])
+        );
+        assert_eq!(&id, reply_field(&reply, &["cursor", "id"]));
+
+        // Evicting documents the cursor has yet to read loses its position
+        for username in ["halrloprillalar", "teela", "seeker", "chmeee"] {
+            insert(username);
+        }
+        assert_eq!(
+            &Bson::Value(DataType::I64(136)),
+            reply_field(&get_more(), &["code"])
+        );
+    }
+
+    #[test]
+    fn find_in_natural_order() {
+        let server = server_with_users();
+        let reply = server.run(&[
+            (String::from("find"), string("users")),
+            (
+                String::from("sort"),
+                Bson::Document(vec![field(NATURAL, DataType::I64(-1))]),
+            ),
+            field("limit", DataType::I64(1)),
+        ]);
+        assert_eq!(
+            &Bson::Array(vec![sorted(user("nessus", 30))]),
+            reply_field(&reply, &["cursor", "firstBatch"])
+        );
+        let reply = server.run(&[
+            (String::from("find"), string("users")),
+            (
+                String::from("sort"),
+                Bson::Document(vec![field("age", DataType::I64(1))]),
+            ),
+        ]);
+        assert_eq!(
+            &Bson::Value(DataType::I64(2)),
+            reply_field(&reply, &["code"])
+        );
+    }
+
+    #[test]
+    fn kill_cursors() {
+        let server = server_with_users();
+        let reply = server.run(&[
+            (String::from("find"), string("users")),
+            field("batchSize", DataType::I64(0)),
+        ]);
+        let id = reply_field(&reply, &["cursor", "id"]).clone();
+        let reply = server.run(&[
+            (String::from("killCursors"), string("users")),
+            (
+                String::from("cursors"),
+                Bson::Array(vec![id.clone(), Bson::Value(DataType::I64(99))]),
+            ),
+        ]);
+        assert_eq!(
+            &Bson::Array(vec![id]),
+            reply_field(&reply, &["cursorsKilled"])
+        );
+        assert_eq!(
+            &Bson::Array(vec![Bson::Value(DataType::I64(99))]),
+            reply_field(&reply, &["cursorsNotFound"])
+        );
+    }
+
+    #[test]
+    fn update_and_delete() {
+        let server = server_with_users();
+        let statement = |filter: Bson, update: Bson, upsert: bool| {
+            Bson::Document(vec![
+                (String::from("q"), filter),
+                (String::from("u"), update),
+                field("multi", DataType::Bool(true)),
+                field("upsert", DataType::Bool(upsert)),
+            ])
+        };
+        let older = Bson::Document(vec![(
+            String::from("age"),
+            Bson::Document(vec![field("$gt", DataType::I64(50))]),
+        )]);
+        let increment = Bson::Document(vec![(
+            String::from("$inc"),
+            Bson::Document(vec![field("age", DataType::I64(1))]),
+        )]);
+        let reply = server.run(&[
+            (String::from("update"), string("users")),
+            (
+                String::from("updates"),
+                Bson::Array(vec![
+                    statement(older.clone(), increment.clone(), false),
+                    statement(user("speaker", 0), increment, true),
+                ]),
+            ),
+        ]);
+        assert_eq!(&Bson::Value(DataType::I64(3)), reply_field(&reply, &["n"]));
+        assert_eq!(
+            &Bson::Value(DataType::I64(2)),
+            reply_field(&reply, &["nModified"])
+        );
+        let safe_collection = server.database.get("users").unwrap();
+        let mut filter = Document::new();
+        filter.insert(
+            String::from("username"),
+            DataType::String(String::from("speaker")),
+        );
+        let collection = database::read(&safe_collection);
+        let ids = collection
+            .run(&Query::from_document(&filter).unwrap())
+            .unwrap()
+            .ids;
+        assert_eq!(
+            Some(&DataType::I64(1)),
+            collection.get(ids[0]).unwrap().get("age")
+        );
+        drop(collection);
+
+        let reply = server.run(&[
+            (String::from("delete"), string("users")),
+            (
+                String::from("deletes"),
+                Bson::Array(vec![Bson::Document(vec![
+                    (String::from("q"), older),
+                    field("limit", DataType::I64(1)),
+                ])]),
+            ),
+        ]);
+        assert_eq!(&Bson::Value(DataType::I64(1)), reply_field(&reply, &["n"]));
+    }
+
+    #[test]
+    fn unknown_command() {
+        let server = Server::new(Database::new());
+        let reply = server.run(&[field("shutdown", DataType::I64(1))]);
+        assert_eq!(
+            &Bson::Value(DataType::F64(Float::new(0.0))),
+            reply_field(&reply, &["ok"])
+        );
+        assert_eq!(
+            &string("CommandNotFound"),
+            reply_field(&reply, &["codeName"])
+        );
+    }
+
+    /// Orders a document's fields as replies do
+    fn sorted(document: Bson) -> Bson {
+        match document {
+            Bson::Document(fields) => Bson::Document(to_bson_document(&bson::to_document(fields))),
+            other => other,
+        }
+    }
+}
@@ -0,0 +1,397 @@
+mod commands;
+
+pub use commands::{CommandError, Server};
+
+use crate::datastore::bson::{self, Bson, BsonDocument};
+use crate::datastore::database::Database;
+use crate::datastore::datatypes::DataType;
+use rocket::fairing::AdHoc;
+use rocket::tokio::io::{AsyncReadExt, AsyncWriteExt};
+use rocket::tokio::net::{TcpListener, TcpStream};
+use std::convert::TryInto;
+use std::io;
+use std::net::{IpAddr, SocketAddr};
+use std::sync::Arc;
+
+const OP_REPLY: i32 = 1;
+const OP_QUERY: i32 = 2004;
+const OP_MSG: i32 = 2013;
+/// OP_MSG flag set when a CRC-32C checksum follows the sections
+const CHECKSUM_PRESENT: u32 = 1;
+/// OP_MSG flag set when the sender expects no reply
+const MORE_TO_COME: u32 = 1 << 1;
+const HEADER_LENGTH: usize = 16;
+
+/// Produces the fairing starting the MongoDB wire protocol listener once
+/// Rocket has launched. The listener is off unless the `wire_port`
+/// configuration value is set, e.g. `ROCKET_WIRE_PORT=27017`, and binds
+/// the `wire_address` value, Rocket's address by default.
+pub fn listener() -> AdHoc {
+    AdHoc::on_liftoff("MongoDB wire protocol", |rocket| {
+        Box::pin(async move {
+            let port = match rocket.figment().extract_inner::<u16>("wire_port") {
+                Ok(port) => port,
+                Err(_) => return,
+            };
+            let address = rocket
+                .figment()
+                .extract_inner::<IpAddr>("wire_address")
+                .unwrap_or(rocket.config().address);
+            let address = SocketAddr::new(address, port);
+            let database = match rocket.state::<Database>() {
+                Some(database) => database.clone(),
+                None => return println!("WIRE: no Database is managed, not listening"),
+            };
+            match TcpListener::bind(address).await {
+                Ok(listener) => {
+                    println!("WIRE: listening on {}", address);
+                    rocket::tokio::spawn(serve(listener, database));
+                }
+                Err(e) => println!("WIRE: cannot listen on {} - {}", address, e),
+            }
+        })
+    })
+}
+
+/// Accepts connections speaking the MongoDB wire protocol and runs their
+/// commands against a Database, each connection in its own task
+///
+/// # Arguments
+///
+/// * `listener` - the bound listener
+/// * `database` - the Database to serve
+pub async fn serve(listener: TcpListener, database: Database) {
+    let server = Arc::new(Server::new(database));
+    loop {
+        match listener.accept().await {
+            Ok((stream, peer)) => {
+                let server = server.clone();
+                rocket::tokio::spawn(async move {
+                    if let Err(e) = connection(stream, &server).await {
+                        println!("WIRE: {} - {}", peer, e);
+                    }
+                });
+            }
+            Err(e) => println!("WIRE: accept - {}", e),
+        }
+    }
+}
+
+/// Replies to the messages of a connection until it closes
+async fn connection(mut stream: TcpStream, server: &Server) -> io::Result<()> {
+    let mut next_request_id: i32 = 1;
+    loop {
+        let mut header = [0u8; HEADER_LENGTH];
+        match stream.read_exact(&mut header).await {
+            Ok(_) => {}
+            Err(e) if e.kind() == io::ErrorKind::UnexpectedEof => return Ok(()),
+            Err(e) => return Err(e),
+        }
+        let length = i32::from_le_bytes(header[0..4].try_into().unwrap());
+        let request_id = i32::from_le_bytes(header[4..8].try_into().unwrap());
+        let op_code = i32::from_le_bytes(header[12..16].try_into().unwrap());
+        if length < HEADER_LENGTH as i32 || length as i64 > commands::MAX_MESSAGE_SIZE {
+            return Err(invalid_data(format!("message length {}", length)));
+        }
+        let mut body = vec![0u8; length as usize - HEADER_LENGTH];
+        stream.read_exact(&mut body).await?;
+
+        let (op_code, reply) = match op_code {
+            OP_MSG => {
+                let flags = u32::from_le_bytes(body_prefix(&body)?);
+                // A corrupt message closes the connection, as with MongoDB
+                if flags & CHECKSUM_PRESENT != 0 {
+                    verify_checksum(&header, &body).map_err(invalid_data)?;
+                }
+                let reply = match parse_msg(&body) {
+                    Ok(command) => server.run(&command),
+                    Err(message) => invalid_bson(message),
+                };
+                if flags & MORE_TO_COME != 0 {
+                    continue;
+                }
+                (OP_MSG, encode_msg(&reply))
+            }
+            OP_QUERY => {
+                let reply = match parse_query(&body) {
+                    Ok(command) => server.run(&command),
+                    Err(message) => invalid_bson(message),
+                };
+                (OP_REPLY, encode_reply(&reply))
+            }
+            other => return Err(invalid_data(format!("unsupported op code {}", other))),
+        };
+        let reply = reply.map_err(invalid_data)?;
+        let mut message = Vec::with_capacity(HEADER_LENGTH + reply.len());
+        message.extend_from_slice(&((HEADER_LENGTH + reply.len()) as i32).to_le_bytes());
+        message.extend_from_slice(&next_request_id.to_le_bytes());
+        message.extend_from_slice(&request_id.to_le_bytes());
+        message.extend_from_slice(&op_code.to_le_bytes());
+        message.extend_from_slice(&reply);
+        stream.write_all(&message).await?;
+        next_request_id = next_request_id.wrapping_add(1);
+    }
+}
+
+/// Produces the command of an OP_MSG body: the body section's document with
+/// each document sequence added as an array field, as drivers send the
+/// documents of inserts
+///
+/// # Arguments
+///
+/// * `body` - the message after its header
+fn parse_msg(body: &[u8]) -> Result<BsonDocument, String> {
+    let flags = u32::from_le_bytes(body_prefix(body).map_err(|e| e.to_string())?);
+    let end = if flags & CHECKSUM_PRESENT != 0 {
+        body.len()
+            .checked_sub(4)
+            .ok_or_else(|| String::from("message is too short for its checksum"))?
+    } else {
+        body.len()
+    };
+    let mut position = 4;
+    let mut command = None;
+    let mut sequences = Vec::new();
+    while position < end {
+        let kind = body[position];
+        position += 1;
+        match kind {
+            0 => {
+                let (document, length) = bson::decode_ordered(&body[position..end])?;
+                position += length;
+                command = Some(document);
+            }
+            1 => {
+                let length = body
+                    .get(position..position + 4)
+                    .map(|bytes| i32::from_le_bytes(bytes.try_into().unwrap()))
+                    .filter(|length| *length >= 4 && position + *length as usize <= end)
+                    .ok_or_else(|| String::from("document sequence length is out of bounds"))?;
+                let section = &body[position + 4..position + length as usize];
+                position += length as usize;
+                let name_end = section
+                    .iter()
+                    .position(|byte| *byte == 0)
+                    .ok_or_else(|| String::from("document sequence name is not terminated"))?;
+                let name = String::from_utf8(section[..name_end].to_vec())
+                    .map_err(|_| String::from("document sequence name is not valid UTF-8"))?;
+                let mut documents = Vec::new();
+                let mut rest = &section[name_end + 1..];
+                while !rest.is_empty() {
+                    let (document, length) = bson::decode_ordered(rest)?;
+                    documents.push(Bson::Document(document));
+                    rest = &rest[length..];
+                }
+                sequences.push((name, Bson::Array(documents)));
+            }
+            other => return Err(format!("unknown section kind {}", other)),
+        }
+    }
+    let mut command = command.ok_or_else(|| String::from("message has no body section"))?;
+    command.extend(sequences);
+    Ok(command)
+}
+
+/// Checks the CRC-32C checksum ending an OP_MSG, computed over the rest of
+/// the message, header included
+///
+/// # Arguments
+///
+/// * `header` - the message's header
+/// * `body` - the message after its header, checksum included
+fn verify_checksum(header: &[u8], body: &[u8]) -> Result<(), String> {
+    let end = body
+        .len()
+        .checked_sub(4)
+        .filter(|end| *end >= 4)
+        .ok_or_else(|| String::from("message is too short for its checksum"))?;
+    let expected = u32::from_le_bytes(body[end..].try_into().unwrap());
+    let actual = crc32c::crc32c_append(crc32c::crc32c(header), &body[..end]);
+    if actual != expected {
+        return Err(format!(
+            "checksum {:08x} does not match the message's, {:08x}",
+            expected, actual
+        ));
+    }
+    Ok(())
+}
+
+/// Produces the command of a legacy OP_QUERY against a `<db>.$cmd`
+/// collection, which drivers use for their first hello
+fn parse_query(body: &[u8]) -> Result<BsonDocument, String> {
+    let name_end = body
+        .iter()
+        .skip(4)
+        .position(|byte| *byte == 0)
+        .map(|end| end + 4)
+        .ok_or_else(|| String::from("collection name is not terminated"))?;
+    let namespace = String::from_utf8(body[4..name_end].to_vec())
+        .map_err(|_| String::from("collection name is not valid UTF-8"))?;
+    let db = namespace
+        .strip_suffix(".$cmd")
+        .ok_or_else(|| format!("{} is not a command collection", namespace))?;
+    // The number of documents to skip and return follow the name
+    let start = name_end + 1 + 8;
+    let (mut command, _) = bson::decode_ordered(body.get(start..).unwrap_or(&[]))?;
+    if let Some(position) = command.iter().position(|(field, _)| field == "$query") {
+        match command.swap_remove(position).1 {
+            Bson::Document(query) => command = query,
+            _ => return Err(String::from("$query must be a document")),
+        }
+    }
+    if !command.iter().any(|(field, _)| field == "$db") {
+        command.push((
+            String::from("$db"),
+            Bson::Value(DataType::String(String::from(db))),
+        ));
+    }
+    Ok(command)
+}
+
+/// Produces an OP_MSG body holding a single document
+fn encode_msg(reply: &[(String, Bson)]) -> Result<Vec<u8>, String> {
+    let mut body = vec![0u8; 4];
+    body.push(0);
+    body.extend(bson::encode_ordered(reply)?);
+    Ok(body)
+}
+
+/// Produces an OP_REPLY body holding a single document
+fn encode_reply(reply: &[(String, Bson)]) -> Result<Vec<u8>, String> {
+    let mut body = Vec::new();
+    body.extend_from_slice(&0i32.to_le_bytes());
+    body.extend_from_slice(&0i64.to_le_bytes());
+    body.extend_from_slice(&0i32.to_le_bytes());
+    body.extend_from_slice(&1i32.to_le_bytes());
+    body.extend(bson::encode_ordered(reply)?);
+    Ok(body)
+}
+
+fn body_prefix(body: &[u8]) -> io::Result<[u8; 4]> {
+    body.get(..4)
+        .map(|prefix| prefix.try_into().unwrap())
+        .ok_or_else(|| invalid_data(String::from("message is too short")))
+}
+
+fn invalid_bson(message: String) -> BsonDocument {
+    CommandError {
+        code: 22,
+        name: "InvalidBSON",
+        message,
+    }
+    .reply()
+}
+
+fn invalid_data(message: String) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, message)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn string(value: &str) -> Bson {
+        Bson::Value(DataType::String(String::from(value)))
+    }
+
+    #[test]
+    fn parse_document_sequences() {
+        let command = vec![(String::from("insert"), string("users"))];
+        let document = vec![(String::from("username"), string("johnperry"))];
+        let mut body = (CHECKSUM_PRESENT).to_le_bytes().to_vec();
+        body.push(0);
+        body.extend(bson::encode_ordered(&command).unwrap());
+        let mut section = b"documents\0".to_vec();
+        section.extend(bson::encode_ordered(&document).unwrap());
+        body.push(1);
+        body.extend_from_slice(&(section.len() as i32 + 4).to_le_bytes());
+        body.extend(section);
+        body.extend_from_slice(&[0xde, 0xad, 0xbe, 0xef]);
+        assert_eq!(
+            vec![
+                (String::from("insert"), string("users")),
+                (
+                    String::from("documents"),
+                    Bson::Array(vec![Bson::Document(document)])
+                ),
+            ],
+            parse_msg(&body).unwrap()
+        );
+        assert!(parse_msg(&body[..body.len() - 6]).is_err());
+    }
+
+    #[test]
+    fn verify_message_checksum() {
+        let mut body = CHECKSUM_PRESENT.to_le_bytes().to_vec();
+        body.push(0);
+        body.extend(bson::encode_ordered(&[(String::from("ping"), string("1"))]).unwrap());
+        let mut header = ((HEADER_LENGTH + body.len() + 4) as i32)
+            .to_le_bytes()
+            .to_vec();
+        header.extend_from_slice(&1i32.to_le_bytes());
+        header.extend_from_slice(&0i32.to_le_bytes());
+        header.extend_from_slice(&OP_MSG.to_le_bytes());
+        let checksum = crc32c::crc32c(&[header.as_slice(), body.as_slice()].concat());
+        body.extend_from_slice(&checksum.to_le_bytes());
+        assert_eq!(Ok(()), verify_checksum(&header, &body));
+
+        let last = body.len() - 1;
+        body[last] ^= 1;
+        assert!(verify_checksum(&header, &body).is_err());
+        assert!(verify_checksum(&header, &body[..3]).is_err());
+    }
+
+    #[test]
+    fn parse_legacy_query() {
+        let query = vec![(
+            String::from("$query"),
+            Bson::Document(vec![(
+                String::from("isMaster"),
+                Bson::Value(DataType::I64(1)),
+            )]),
+        )];
+        let mut body = 0i32.to_le_bytes().to_vec();
+        body.extend_from_slice(b"admin.$cmd\0");
+        body.extend_from_slice(&0i32.to_le_bytes());
+        body.extend_from_slice(&(-1i32).to_le_bytes());
+        body.extend(bson::encode_ordered(&query).unwrap());
+        assert_eq!(
+            vec![
+                (String::from("isMaster"), Bson::Value(DataType::I64(1))),
+                (String::from("$db"), string("admin")),
+            ],
+            parse_query(&body).unwrap()
+        );
+        body[10..14].copy_from_slice(b"user");
+        assert!(parse_query(&body).is_err());
+    }
+
+    #[rocket::async_test]
+    async fn serve_a_connection() {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let address = listener.local_addr().unwrap();
+        rocket::tokio::spawn(serve(listener, Database::new()));
+        let mut stream = TcpStream::connect(address).await.unwrap();
+        let ping = encode_msg(&[(String::from("ping"), Bson::Value(DataType::I64(1)))]).unwrap();
+        let mut message = ((HEADER_LENGTH + ping.len()) as i32).to_le_bytes().to_vec();
+        message.extend_from_slice(&7i32.to_le_bytes());
+        message.extend_from_slice(&0i32.to_le_bytes());
+        message.extend_from_slice(&OP_MSG.to_le_bytes());
+        message.extend(ping);
+        stream.write_all(&message).await.unwrap();
+
+        let mut header = [0u8; HEADER_LENGTH];
+        stream.read_exact(&mut header).await.unwrap();
+        assert_eq!(7, i32::from_le_bytes(header[8..12].try_into().unwrap()));
+        let mut reply =
+            vec![0u8; i32::from_le_bytes(header[..4].try_into().unwrap()) as usize - HEADER_LENGTH];
+        stream.read_exact(&mut reply).await.unwrap();
+        assert_eq!(
+            vec![(
+                String::from("ok"),
+                Bson::Value(DataType::F64(crate::datastore::datatypes::Float::new(1.0)))
+            )],
+            parse_msg(&reply).unwrap()
+        );
+    }
+}
@@ -0,0 +1,570 @@
+use crate::datastore::collection::Document;
+use crate::datastore::datatypes::{DataType, Decimal128, Float, ObjectId};
+use std::borrow::Cow;
+use std::convert::{TryFrom, TryInto};
+
+const DOUBLE: u8 = 0x01;
+const STRING: u8 = 0x02;
+const EMBEDDED_DOCUMENT: u8 = 0x03;
+const ARRAY: u8 = 0x04;
+const BINARY: u8 = 0x05;
+const OBJECT_ID: u8 = 0x07;
+const BOOLEAN: u8 = 0x08;
+const DATE_TIME: u8 = 0x09;
+const NULL: u8 = 0x0A;
+const INT32: u8 = 0x10;
+const TIMESTAMP: u8 = 0x11;
+const INT64: u8 = 0x12;
+const DECIMAL128: u8 = 0x13;
+
+/// The exponent bias of a decimal128
+const DECIMAL_BIAS: i32 = 6176;
+
+/// A BSON value of a document kept in field order, such as a command of the
+/// MongoDB wire protocol
+#[derive(Debug, Clone, PartialEq)]
+pub enum Bson {
+    /// Any value but an embedded document
+    Value(DataType),
+    Array(Vec<Bson>),
+    Document(BsonDocument),
+}
+
+/// The fields of a BSON document in order
+pub type BsonDocument = Vec<(String, Bson)>;
+
+impl Bson {
+    /// Produces the DataType of the value
+    pub fn into_data_type(self) -> DataType {
+        match self {
+            Bson::Value(value) => value,
+            Bson::Array(values) => {
+                DataType::Array(values.into_iter().map(Bson::into_data_type).collect())
+            }
+            Bson::Document(fields) => DataType::Document(to_document(fields).into_iter().collect()),
+        }
+    }
+}
+
+impl From<DataType> for Bson {
+    fn from(value: DataType) -> Self {
+        match value {
+            DataType::Document(fields) => Bson::Document(
+                fields
+                    .into_iter()
+                    .map(|(field, value)| (field, Bson::from(value)))
+                    .collect(),
+            ),
+            DataType::Array(values) => Bson::Array(values.into_iter().map(Bson::from).collect()),
+            value => Bson::Value(value),
+        }
+    }
+}
+
+/// Produces the Document of a BSON document's fields
+///
+/// # Arguments
+///
+/// * `fields` - the document's fields
+pub fn to_document(fields: BsonDocument) -> Document {
+    fields
+        .into_iter()
+        .map(|(field, value)| (field, value.into_data_type()))
+        .collect()
+}
+
+/// Produces the BSON encoding of a Document. Fields are written in name
+/// order. BSON has no unsigned integers, a U64 above the largest int64 is
+/// written as a decimal128 holding the same value.
+///
+/// # Arguments
+///
+/// * `document` - the document to encode
+///
+/// # Examples
+///
+/// ```rust
+/// use rockumentdb::datastore::bson;
+/// use rockumentdb::datastore::datatypes::DataType;
+/// use std::collections::HashMap;
+///
+/// let mut document = HashMap::new();
+/// document.insert(String::from("age"), DataType::I64(75));
+/// let bytes = bson::encode(&document).unwrap();
+/// assert_eq!((document, bytes.len()), bson::decode(&bytes).unwrap());
+/// ```
+pub fn encode(document: &Document) -> Result<Vec<u8>, String> {
+    let mut fields: Vec<(&String, &DataType)> = document.iter().collect();
+    fields.sort_by(|a, b| a.0.cmp(b.0));
+    encode_fields(fields)
+}
+
+/// Produces the BSON encoding of a document's fields in the order given
+fn encode_fields<'a>(
+    fields: impl IntoIterator<Item = (&'a String, &'a DataType)>,
+) -> Result<Vec<u8>, String> {
+    // The length is filled in once the elements are written
+    let mut bytes = vec![0u8; 4];
+    for (field, value) in fields {
+        write_value(&mut bytes, field, value)?;
+    }
+    Ok(finish(bytes))
+}
+
+/// Produces the BSON encoding of a document kept in field order
+///
+/// # Arguments
+///
+/// * `document` - the document's fields
+///
+/// # Examples
+///
+/// ```rust
+/// use rockumentdb::datastore::bson::{self, Bson};
+/// use rockumentdb::datastore::datatypes::DataType;
+///
+/// let document = vec![
+///     (String::from("insert"), Bson::Value(DataType::String(String::from("users")))),
+///     (String::from("documents"), Bson::Array(vec![Bson::Document(vec![])])),
+/// ];
+/// let bytes = bson::encode_ordered(&document).unwrap();
+/// assert_eq!((document, bytes.len()), bson::decode_ordered(&bytes).unwrap());
+/// ```
+pub fn encode_ordered(document: &[(String, Bson)]) -> Result<Vec<u8>, String> {
+    encode_elements(
+        document
+            .iter()
+            .map(|(field, value)| (Cow::from(field.as_str()), value))
+            .collect(),
+    )
+}
+
+fn encode_elements(elements: Vec<(Cow<str>, &Bson)>) -> Result<Vec<u8>, String> {
+    let mut bytes = vec![0u8; 4];
+    for (field, value) in elements {
+        let field = field.as_ref();
+        match value {
+            Bson::Value(value) => write_value(&mut bytes, field, value)?,
+            Bson::Array(values) => {
+                write_name(&mut bytes, ARRAY, field)?;
+                bytes.extend(encode_elements(
+                    values
+                        .iter()
+                        .enumerate()
+                        .map(|(position, value)| (Cow::from(position.to_string()), value))
+                        .collect(),
+                )?);
+            }
+            Bson::Document(fields) => {
+                write_name(&mut bytes, EMBEDDED_DOCUMENT, field)?;
+                bytes.extend(encode_ordered(fields)?);
+            }
+        }
+    }
+    Ok(finish(bytes))
+}
+
+/// Writes an element's type and name
+fn write_name(bytes: &mut Vec<u8>, element_type: u8, field: &str) -> Result<(), String> {
+    if field.contains('\0') {
+        return Err(format!("field {:?} contains a null character", field));
+    }
+    bytes.push(element_type);
+    bytes.extend_from_slice(field.as_bytes());
+    bytes.push(0);
+    Ok(())
+}
+
+/// Writes the element of a field's value
+fn write_value(bytes: &mut Vec<u8>, field: &str, value: &DataType) -> Result<(), String> {
+    let element_type = match value {
+        DataType::F64(_) => DOUBLE,
+        DataType::String(_) => STRING,
+        DataType::Document(_) => EMBEDDED_DOCUMENT,
+        DataType::Array(_) => ARRAY,
+        DataType::Binary { .. } => BINARY,
+        DataType::ObjectId(_) => OBJECT_ID,
+        DataType::Bool(_) => BOOLEAN,
+        DataType::DateTime(_) => DATE_TIME,
+        DataType::Null => NULL,
+        DataType::I64(_) => INT64,
+        DataType::U64(val) if i64::try_from(*val).is_ok() => INT64,
+        DataType::U64(_) | DataType::Decimal128(_) => DECIMAL128,
+    };
+    write_name(bytes, element_type, field)?;
+    match value {
+        DataType::Null => {}
+        DataType::Bool(val) => bytes.push(*val as u8),
+        DataType::F64(val) => bytes.extend_from_slice(&val.get().to_le_bytes()),
+        DataType::I64(val) => bytes.extend_from_slice(&val.to_le_bytes()),
+        DataType::U64(val) => match i64::try_from(*val) {
+            Ok(val) => bytes.extend_from_slice(&val.to_le_bytes()),
+            Err(_) => encode_decimal(bytes, &Decimal128::new(*val as i128, 0).unwrap()),
+        },
+        DataType::Decimal128(val) => encode_decimal(bytes, val),
+        DataType::String(val) => {
+            bytes.extend_from_slice(&(val.len() as i32 + 1).to_le_bytes());
+            bytes.extend_from_slice(val.as_bytes());
+            bytes.push(0);
+        }
+        DataType::DateTime(val) => bytes.extend_from_slice(&val.to_le_bytes()),
+        DataType::Binary {
+            subtype,
+            bytes: data,
+        } => {
+            bytes.extend_from_slice(&(data.len() as i32).to_le_bytes());
+            bytes.push(*subtype);
+            bytes.extend_from_slice(data);
+        }
+        DataType::ObjectId(val) => bytes.extend_from_slice(&val.bytes()),
+        DataType::Document(val) => bytes.extend(encode_fields(val.iter())?),
+        // An array is a document whose fields are named by position
+        DataType::Array(val) => {
+            let positions: Vec<String> = (0..val.len())
+                .map(|position| position.to_string())
+                .collect();
+            bytes.extend(encode_fields(positions.iter().zip(val.iter()))?)
+        }
+    }
+    Ok(())
+}
+
+/// Terminates a document's elements and fills in its length
+fn finish(mut bytes: Vec<u8>) -> Vec<u8> {
+    bytes.push(0);
+    let length = bytes.len() as i32;
+    bytes[..4].copy_from_slice(&length.to_le_bytes());
+    bytes
+}
+
+/// Writes a Decimal128 in the IEEE 754 binary integer decimal encoding
+fn encode_decimal(bytes: &mut Vec<u8>, value: &Decimal128) {
+    let sign = (value.coefficient() < 0) as u128;
+    let exponent = (value.exponent() + DECIMAL_BIAS) as u128;
+    let bits = sign << 127 | exponent << 113 | value.coefficient().unsigned_abs();
+    bytes.extend_from_slice(&bits.to_le_bytes());
+}
+
+/// Produces the Document at the start of BSON encoded bytes and the number
+/// of bytes it took. Int32s are read as I64s and timestamps as U64s; the
+/// remaining BSON types have no DataType and are an error.
+///
+/// # Arguments
+///
+/// * `bytes` - a BSON document, possibly followed by others
+pub fn decode(bytes: &[u8]) -> Result<(Document, usize), String> {
+    let (fields, length) = decode_ordered(bytes)?;
+    Ok((to_document(fields), length))
+}
+
+/// Produces the document at the start of BSON encoded bytes, in field
+/// order, and the number of bytes it took. Values are read as by
+/// `decode`.
+///
+/// # Arguments
+///
+/// * `bytes` - a BSON document, possibly followed by others
+pub fn decode_ordered(bytes: &[u8]) -> Result<(BsonDocument, usize), String> {
+    let mut reader = Reader { bytes, position: 0 };
+    let length = reader.i32()?;
+    if length < 5 || length as usize > bytes.len() {
+        return Err(format!("document length {} is out of bounds", length));
+    }
+    reader.bytes = &bytes[..length as usize];
+
+    let mut document = Vec::new();
+    loop {
+        let element_type = reader.u8()?;
+        if element_type == 0 {
+            break;
+        }
+        let field = reader.cstring()?;
+        let value = match element_type {
+            DOUBLE => DataType::F64(Float::new(f64::from_le_bytes(reader.array()?))),
+            STRING => {
+                let length = reader.i32()?;
+                if length < 1 {
+                    return Err(format!("string {} has length {}", field, length));
+                }
+                let text = reader.take(length as usize)?;
+                if text[text.len() - 1] != 0 {
+                    return Err(format!("string {} is not null terminated", field));
+                }
+                DataType::String(utf8(&text[..text.len() - 1])?)
+            }
+            BINARY => {
+                let length = reader.i32()?;
+                if length < 0 {
+                    return Err(format!("binary {} has length {}", field, length));
+                }
+                let subtype = reader.u8()?;
+                DataType::Binary {
+                    subtype,
+                    bytes: reader.take(length as usize)?.to_vec(),
+                }
+            }
+            OBJECT_ID => DataType::ObjectId(ObjectId::from_bytes(reader.array()?)),
+            BOOLEAN => DataType::Bool(reader.u8()? != 0),
+            DATE_TIME => DataType::DateTime(i64::from_le_bytes(reader.array()?)),
+            NULL => DataType::Null,
+            INT32 => DataType::I64(i32::from_le_bytes(reader.array()?) as i64),
+            TIMESTAMP => DataType::U64(u64::from_le_bytes(reader.array()?)),
+            INT64 => DataType::I64(i64::from_le_bytes(reader.array()?)),
+            DECIMAL128 => {
+                DataType::Decimal128(decode_decimal(u128::from_le_bytes(reader.array()?))?)
+            }
+            EMBEDDED_DOCUMENT | ARRAY => {
+                let (embedded, length) = decode_ordered(&reader.bytes[reader.position..])
+                    .map_err(|e| format!("field {}: {}", field, e))?;
+                reader.take(length)?;
+                let value = if element_type == ARRAY {
+                    Bson::Array(embedded.into_iter().map(|(_, value)| value).collect())
+                } else {
+                    Bson::Document(embedded)
+                };
+                document.push((field, value));
+                continue;
+            }
+            other => {
+                return Err(format!(
+                    "field {} has unsupported BSON type {:#04x}",
+                    field, other
+                ))
+            }
+        };
+        document.push((field, Bson::Value(value)));
+    }
+    if reader.position != length as usize {
+        return Err(String::from("document ended before its length"));
+    }
+    Ok((document, length as usize))
+}
+
+/// Produces every Document of concatenated BSON documents, such as a
+/// `.bson` dump
+///
+/// # Arguments
+///
+/// * `bytes` - zero or more BSON documents
+pub fn decode_all(mut bytes: &[u8]) -> Result<Vec<Document>, String> {
+    let mut documents = Vec::new();
+    while !bytes.is_empty() {
+        let (document, length) =
+            decode(bytes).map_err(|e| format!("document {}: {}", documents.len() + 1, e))?;
+        documents.push(document);
+        bytes = &bytes[length..];
+    }
+    Ok(documents)
+}
+
+/// Produces the Decimal128 of its IEEE 754 binary integer decimal encoding
+fn decode_decimal(bits: u128) -> Result<Decimal128, String> {
+    let negative = bits >> 127 == 1;
+    let (exponent, coefficient) = if (bits >> 125) & 0b11 == 0b11 {
+        if (bits >> 122) & 0b1111 == 0b1111 {
+            return Err(String::from("decimal NaN and infinities are not supported"));
+        }
+        // Coefficients this form holds exceed 34 digits, which reads as 0
+        ((bits >> 111) & 0x3fff, 0)
+    } else {
+        ((bits >> 113) & 0x3fff, bits & ((1u128 << 113) - 1))
+    };
+    let coefficient = coefficient as i128;
+    let coefficient = if negative { -coefficient } else { coefficient };
+    Decimal128::new(coefficient, exponent as i32 - DECIMAL_BIAS)
+        .or_else(|| Decimal128::new(0, exponent as i32 - DECIMAL_BIAS))
+        .ok_or_else(|| String::from("decimal exponent is out of range"))
+}
+
+fn utf8(bytes: &[u8]) -> Result<String, String> {
+    String::from_utf8(bytes.to_vec()).map_err(|_| String::from("string is not valid UTF-8"))
+}
+
+/// Reads little endian values from the start of a byte slice
+struct Reader<'a> {
+    bytes: &'a [u8],
+    position: usize,
+}
+
+impl<'a> Reader<'a> {
+    fn take(&mut self, count: usize) -> Result<&'a [u8], String> {
+        let end = self
+            .position
+            .checked_add(count)
+            .filter(|end| *end <= self.bytes.len())
+            .ok_or_else(|| String::from("unexpected end of document"))?;
+        let taken = &self.bytes[self.position..end];
+        self.position = end;
+        Ok(taken)
+    }
+
+    fn array<const N: usize>(&mut self) -> Result<[u8; N], String> {
+        Ok(self.take(N)?.try_into().unwrap())
+    }
+
+    fn u8(&mut self) -> Result<u8, String> {
+        Ok(self.take(1)?[0])
+    }
+
+    fn i32(&mut self) -> Result<i32, String> {
+        Ok(i32::from_le_bytes(self.array()?))
+    }
+
+    fn cstring(&mut self) -> Result<String, String> {
+        let rest = &self.bytes[self.position..];
+        let end = rest
+            .iter()
+            .position(|byte| *byte == 0)
+            .ok_or_else(|| String::from("field name is not null terminated"))?;
+        let text = utf8(&rest[..end])?;
+        self.position += end + 1;
+        Ok(text)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::collections::HashMap;
+
+    fn every_type() -> Document {
+        let mut document = HashMap::new();
+        let mut set = |field: &str, value: DataType| document.insert(String::from(field), value);
+        set("null", DataType::Null);
+        set("bool", DataType::Bool(true));
+        set("f64", DataType::F64(Float::new(-2.5)));
+        set("i64", DataType::I64(-75));
+        set("u64", DataType::U64(75));
+        set("big", DataType::U64(u64::MAX));
+        set("decimal", DataType::Decimal128("-19.990".parse().unwrap()));
+        set("string", DataType::String(String::from("johnperry")));
+        set("date", DataType::DateTime(1_622_550_600_000));
+        set(
+            "binary",
+            DataType::Binary {
+                subtype: 4,
+                bytes: vec![1, 2, 3],
+            },
+        );
+        set(
+            "id",
+            DataType::ObjectId("60b5fbd8e1b2c3d4e5f60718".parse().unwrap()),
+        );
+        let mut address = std::collections::BTreeMap::new();
+        address.insert(
+            String::from("city"),
+            DataType::String(String::from("Paris")),
+        );
+        set("address", DataType::Document(address));
+        set(
+            "tags",
+            DataType::Array(vec![
+                DataType::String(String::from("captain")),
+                DataType::Array(vec![DataType::I64(1)]),
+            ]),
+        );
+        document
+    }
+
+    #[test]
+    fn round_trip() {
+        let document = every_type();
+        let bytes = encode(&document).unwrap();
+        let (decoded, length) = decode(&bytes).unwrap();
+        assert_eq!(bytes.len(), length);
+        assert_eq!(document, decoded);
+        // Decimals keep their trailing zeros
+        assert_eq!(
+            "-19.990",
+            match &decoded["decimal"] {
+                DataType::Decimal128(val) => val.to_string(),
+                _ => panic!("expected a decimal"),
+            }
+        );
+    }
+
+    #[test]
+    fn decode_known_bytes() {
+        // {"hello": "world"} from the BSON specification
+        let bytes = b"\x16\x00\x00\x00\x02hello\x00\x06\x00\x00\x00world\x00\x00";
+        let (document, _) = decode(bytes).unwrap();
+        assert_eq!(
+            Some(&DataType::String(String::from("world"))),
+            document.get("hello")
+        );
+        // Decimal128 1.0 is a coefficient of 10 and an exponent of -1
+        let mut one = HashMap::new();
+        one.insert(
+            String::from("d"),
+            DataType::Decimal128("1.0".parse().unwrap()),
+        );
+        assert_eq!(
+            b"\x18\x00\x00\x00\x13d\x00\x0a\x00\x00\x00\x00\x00\x00\x00\x00\x00\x00\x00\x00\x00\x3e\x30\x00",
+            encode(&one).unwrap().as_slice()
+        );
+    }
+
+    #[test]
+    fn decode_many_and_errors() {
+        let bytes = [
+            encode(&every_type()).unwrap(),
+            encode(&HashMap::new()).unwrap(),
+        ]
+        .concat();
+        assert_eq!(2, decode_all(&bytes).unwrap().len());
+        assert!(decode_all(&bytes[..bytes.len() - 1]).is_err());
+        // {"a": []}
+        let array = b"\x0d\x00\x00\x00\x04a\x00\x05\x00\x00\x00\x00\x00";
+        assert_eq!(
+            Some(&DataType::Array(vec![])),
+            decode(array).unwrap().0.get("a")
+        );
+        assert_eq!(
+            vec![(String::from("a"), Bson::Array(vec![]))],
+            decode_ordered(array).unwrap().0
+        );
+        // {"a": 1} with an unsupported undefined value, type 0x06
+        assert!(decode(b"\x08\x00\x00\x00\x06a\x00\x00").is_err());
+    }
+
+    #[test]
+    fn ordered_round_trip() {
+        let document = vec![
+            (
+                String::from("find"),
+                Bson::Value(DataType::String(String::from("users"))),
+            ),
+            (
+                String::from("filter"),
+                Bson::from(DataType::Document(
+                    every_type()
+                        .into_iter()
+                        .collect::<std::collections::BTreeMap<_, _>>(),
+                )),
+            ),
+            (
+                String::from("batch"),
+                Bson::Array(vec![
+                    Bson::Value(DataType::I64(1)),
+                    Bson::Array(vec![Bson::Document(vec![])]),
+                ]),
+            ),
+        ];
+        let bytes = encode_ordered(&document).unwrap();
+        let (decoded, length) = decode_ordered(&bytes).unwrap();
+        assert_eq!(bytes.len(), length);
+        assert_eq!(document, decoded);
+        let filter = decoded[1].1.clone().into_data_type();
+        assert_eq!(
+            DataType::Document(every_type().into_iter().collect()),
+            filter
+        );
+        assert_eq!(
+            Some(&DataType::Array(vec![
+                DataType::I64(1),
+                DataType::Array(vec![DataType::Document(Default::default())]),
+            ])),
+            to_document(decoded).get("batch")
+        );
+    }
+}
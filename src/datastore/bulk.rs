@@ -0,0 +1,331 @@
+use crate::datastore::collection::{Collection, Document};
+use crate::datastore::datatypes::{self, DataType};
+use crate::datastore::error::DatastoreError;
+use crate::datastore::query_proc::Query;
+use crate::datastore::update::{self, Update};
+use serde_json::{Map, Value};
+use std::collections::HashMap;
+
+/// One write of a bulk write, in the shape of MongoDB's `bulkWrite`
+#[derive(Debug, Clone, PartialEq)]
+pub enum BulkOperation {
+    InsertOne(Document),
+    /// `updateOne`, `updateMany` or `replaceOne`
+    Update {
+        filter: Value,
+        update: Update,
+        /// Whether every matching document is updated, or only the first
+        multi: bool,
+        /// Whether a document is inserted when none match
+        upsert: bool,
+    },
+    /// `deleteOne` or `deleteMany`
+    Delete {
+        filter: Value,
+        multi: bool,
+    },
+}
+
+/// What a bulk write did, and the operations that could not be applied by
+/// their position in the bulk write
+#[derive(Debug, Default, PartialEq)]
+pub struct BulkResult {
+    pub inserted: Vec<usize>,
+    pub matched: usize,
+    pub modified: usize,
+    pub deleted: usize,
+    /// Positions of the operations that upserted a document, and its key
+    pub upserted: Vec<(usize, usize)>,
+    pub errors: Vec<(usize, DatastoreError)>,
+}
+
+impl BulkOperation {
+    /// Produces the BulkOperation of a MongoDB style bulk write operation
+    ///
+    /// # Arguments
+    ///
+    /// * `value` - an object with a single field naming the operation,
+    ///   `insertOne`, `updateOne`, `updateMany`, `replaceOne`, `deleteOne` or
+    ///   `deleteMany`, holding its arguments
+    ///
+    /// # Examples
+    ///
+    /// ```rust
+    /// use rockumentdb::datastore::bulk::BulkOperation;
+    /// use serde_json::json;
+    ///
+    /// let operation = BulkOperation::from_json(&json!({
+    ///     "updateMany": {"filter": {"rank": "private"}, "update": {"$inc": {"age": 1}}}
+    /// }));
+    /// assert!(matches!(operation, Ok(BulkOperation::Update { multi: true, .. })));
+    /// ```
+    pub fn from_json(value: &Value) -> Result<BulkOperation, String> {
+        let (name, arguments) = match value {
+            Value::Object(fields) if fields.len() == 1 => fields.iter().next().unwrap(),
+            _ => {
+                return Err(String::from(
+                    "an operation must be an object with one field",
+                ))
+            }
+        };
+        let arguments = match arguments {
+            Value::Object(arguments) => arguments,
+            _ => return Err(format!("{} expects an object", name)),
+        };
+        let filter = || arguments.get("filter").cloned().unwrap_or(Value::Null);
+        let upsert = match arguments.get("upsert") {
+            None => false,
+            Some(Value::Bool(upsert)) => *upsert,
+            Some(_) => return Err(String::from("upsert must be true or false")),
+        };
+        match name.as_str() {
+            "insertOne" => Ok(BulkOperation::InsertOne(document_argument(
+                arguments, "document",
+            )?)),
+            "updateOne" | "updateMany" => {
+                let update = update::from_json(&object_argument(arguments, "update")?)?;
+                if let Update::Replace(_) = update {
+                    return Err(format!("{} expects update operators", name));
+                }
+                Ok(BulkOperation::Update {
+                    filter: filter(),
+                    update,
+                    multi: name == "updateMany",
+                    upsert,
+                })
+            }
+            "replaceOne" => {
+                let replacement = object_argument(arguments, "replacement")?;
+                if replacement.keys().any(|key| key.starts_with('$')) {
+                    return Err(String::from("replaceOne expects a document, not operators"));
+                }
+                Ok(BulkOperation::Update {
+                    filter: filter(),
+                    update: update::from_json(&replacement)?,
+                    multi: false,
+                    upsert,
+                })
+            }
+            "deleteOne" | "deleteMany" => Ok(BulkOperation::Delete {
+                filter: filter(),
+                multi: name == "deleteMany",
+            }),
+            _ => Err(format!("unknown operation {}", name)),
+        }
+    }
+}
+
+fn object_argument(
+    arguments: &Map<String, Value>,
+    name: &str,
+) -> Result<HashMap<String, Value>, String> {
+    match arguments.get(name) {
+        Some(Value::Object(fields)) => Ok(fields
+            .iter()
+            .map(|(field, value)| (field.clone(), value.clone()))
+            .collect()),
+        _ => Err(format!("{} must be an object", name)),
+    }
+}
+
+fn document_argument(arguments: &Map<String, Value>, name: &str) -> Result<Document, String> {
+    Ok(object_argument(arguments, name)?
+        .iter()
+        .map(|(field, value)| (field.clone(), datatypes::from_json(value)))
+        .collect())
+}
+
+/// Produces the document an upsert inserts when nothing matches its filter:
+/// the filter's equality fields with the update applied, or the
+/// replacement keeping the filter's `_id`
+///
+/// # Arguments
+///
+/// * `filter` - filter document of the upsert
+/// * `update` - the update of the upsert
+pub fn upsert_document(filter: &Document, update: &Update) -> Result<Document, DatastoreError> {
+    let mut document: Document = filter
+        .iter()
+        .filter(|(field, value)| {
+            !field.contains('.')
+                && !matches!(value, DataType::Document(fields)
+                    if fields.keys().any(|key| key.starts_with('$')))
+        })
+        .map(|(field, value)| (field.clone(), value.clone()))
+        .collect();
+    match update {
+        Update::Replace(replacement) => {
+            let id = document.remove("_id");
+            document = replacement.clone();
+            if let Some(id) = id {
+                document.entry(String::from("_id")).or_insert(id);
+            }
+        }
+        Update::Operators(_) => {
+            update
+                .apply(&mut document)
+                .map_err(DatastoreError::InvalidUpdate)?;
+        }
+    }
+    Ok(document)
+}
+
+/// Applies a list of operations to a collection one after another. An
+/// ordered bulk write stops at the first operation that cannot be applied,
+/// keeping the writes before it; an unordered one tries every operation.
+///
+/// # Arguments
+///
+/// * `collection` - the collection to write to, held for the whole bulk
+///   write
+/// * `operations` - the operations, in order
+/// * `ordered` - whether an operation that cannot be applied stops the
+///   bulk write
+pub fn execute(
+    collection: &mut Collection,
+    operations: &[BulkOperation],
+    ordered: bool,
+) -> BulkResult {
+    let mut result = BulkResult::default();
+    for (index, operation) in operations.iter().enumerate() {
+        if let Err(error) = apply(collection, index, operation, &mut result) {
+            result.errors.push((index, error));
+            if ordered {
+                break;
+            }
+        }
+    }
+    result
+}
+
+fn apply(
+    collection: &mut Collection,
+    index: usize,
+    operation: &BulkOperation,
+    result: &mut BulkResult,
+) -> Result<(), DatastoreError> {
+    match operation {
+        BulkOperation::InsertOne(document) => {
+            result.inserted.push(collection.insert(document.clone())?);
+        }
+        BulkOperation::Update {
+            filter,
+            update,
+            multi,
+            upsert,
+        } => {
+            let mut ids = collection.run(&Query::from_json(filter)?)?.ids;
+            if !multi {
+                ids.truncate(1);
+            }
+            if ids.is_empty() && *upsert {
+                let filter = match datatypes::from_json(filter) {
+                    DataType::Document(fields) => fields.into_iter().collect(),
+                    _ => Document::new(),
+                };
+                let key = collection.insert(upsert_document(&filter, update)?)?;
+                result.upserted.push((index, key));
+                return Ok(());
+            }
+            let updated = collection.update_ids(&ids, update)?;
+            result.matched += updated.matched;
+            result.modified += updated.modified.len();
+        }
+        BulkOperation::Delete { filter, multi } => {
+            let mut ids = collection.run(&Query::from_json(filter)?)?.ids;
+            if !multi {
+                ids.truncate(1);
+            }
+            result.deleted += ids
+                .into_iter()
+                .filter(|id| collection.remove(*id).is_some())
+                .count();
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    fn operations(values: Value) -> Vec<BulkOperation> {
+        values
+            .as_array()
+            .unwrap()
+            .iter()
+            .map(|value| BulkOperation::from_json(value).unwrap())
+            .collect()
+    }
+
+    #[test]
+    fn parse_operations() {
+        assert!(BulkOperation::from_json(&json!({"updateOne": {"update": {"a": 1}}})).is_err());
+        assert!(
+            BulkOperation::from_json(&json!({"replaceOne": {"replacement": {"$set": {}}}}))
+                .is_err()
+        );
+        assert!(BulkOperation::from_json(&json!({"insertOne": {}, "deleteOne": {}})).is_err());
+        assert!(BulkOperation::from_json(&json!({"upsertOne": {}})).is_err());
+        assert_eq!(
+            BulkOperation::Delete {
+                filter: Value::Null,
+                multi: true
+            },
+            BulkOperation::from_json(&json!({"deleteMany": {}})).unwrap()
+        );
+    }
+
+    #[test]
+    fn execute_mixed_operations() {
+        let mut collection = Collection::new(String::from("crew"));
+        let result = execute(
+            &mut collection,
+            &operations(json!([
+                {"insertOne": {"document": {"username": "johnperry", "rank": "private"}}},
+                {"insertOne": {"document": {"username": "louiswu", "rank": "private"}}},
+                {"updateMany": {"filter": {"rank": "private"}, "update": {"$set": {"rank": "captain"}}}},
+                {"replaceOne": {"filter": {"username": "louiswu"}, "replacement": {"username": "speaker"}}},
+                {"updateOne": {"filter": {"username": "jane"}, "update": {"$set": {"age": 30}}, "upsert": true}},
+                {"deleteOne": {"filter": {"rank": "captain"}}}
+            ])),
+            true,
+        );
+        assert_eq!(vec![1, 2], result.inserted);
+        assert_eq!((3, 3, 1), (result.matched, result.modified, result.deleted));
+        assert_eq!(vec![(4, 3)], result.upserted);
+        assert!(result.errors.is_empty());
+        assert!(collection.get(1).is_none());
+        assert_eq!(
+            Some(&DataType::String(String::from("speaker"))),
+            collection.get(2).unwrap().get("username")
+        );
+        assert_eq!(
+            Some(&DataType::String(String::from("jane"))),
+            collection.get(3).unwrap().get("username")
+        );
+    }
+
+    #[test]
+    fn ordered_stops_at_first_error() {
+        let mut collection = Collection::new(String::from("accounts"));
+        collection.create_index("email", true).unwrap();
+        let batch = operations(json!([
+            {"insertOne": {"document": {"email": "a"}}},
+            {"insertOne": {"document": {"email": "a"}}},
+            {"deleteMany": {"filter": {"email": "a"}}}
+        ]));
+        let ordered = execute(&mut collection, &batch, true);
+        assert_eq!(vec![1], ordered.inserted);
+        assert_eq!(0, ordered.deleted);
+        assert_eq!(1, ordered.errors[0].0);
+
+        let unordered = execute(&mut collection, &batch, false);
+        assert_eq!(
+            vec![0, 1],
+            unordered.errors.iter().map(|e| e.0).collect::<Vec<_>>()
+        );
+        assert_eq!(1, unordered.deleted);
+    }
+}
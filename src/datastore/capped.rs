@@ -0,0 +1,88 @@
+use crate::datastore::bson;
+use crate::datastore::collection::Document;
+use crate::datastore::datatypes;
+use serde::Serialize;
+use serde_json::Value;
+
+/// The limits of a capped collection. Inserting past either limit evicts
+/// the oldest documents, those under the lowest keys.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+pub struct Cap {
+    /// Largest total size of the documents, in bytes
+    #[serde(rename = "size", skip_serializing_if = "Option::is_none")]
+    pub max_bytes: Option<usize>,
+    /// Largest number of documents
+    #[serde(rename = "max", skip_serializing_if = "Option::is_none")]
+    pub max_documents: Option<usize>,
+}
+
+impl Cap {
+    /// Produces whether a collection holding `documents` documents of
+    /// `bytes` bytes in total is past the limits
+    ///
+    /// # Arguments
+    ///
+    /// * `documents` - the number of documents
+    /// * `bytes` - the total size of the documents, see `document_size`
+    ///
+    /// # Examples
+    ///
+    /// ```rust
+    /// use rockumentdb::datastore::capped::Cap;
+    ///
+    /// let cap = Cap { max_bytes: Some(4096), max_documents: Some(2) };
+    /// assert!(!cap.exceeded(2, 4096));
+    /// assert!(cap.exceeded(3, 100));
+    /// assert!(cap.exceeded(1, 4097));
+    /// ```
+    pub fn exceeded(&self, documents: usize, bytes: usize) -> bool {
+        self.max_documents.is_some_and(|max| documents > max)
+            || self.max_bytes.is_some_and(|max| bytes > max)
+    }
+}
+
+/// Produces the size a document counts for against a Cap, that of its
+/// BSON encoding. A document BSON cannot hold, with a null character in a
+/// field name, counts the size of its JSON encoding instead.
+///
+/// # Arguments
+///
+/// * `document` - the document
+pub fn document_size(document: &Document) -> usize {
+    match bson::encode(document) {
+        Ok(bytes) => bytes.len(),
+        Err(_) => {
+            let json: serde_json::Map<String, Value> = document
+                .iter()
+                .map(|(field, value)| (field.clone(), datatypes::to_json(value)))
+                .collect();
+            Value::Object(json).to_string().len()
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::datastore::datatypes::DataType;
+
+    #[test]
+    fn measure_documents() {
+        let mut document = Document::new();
+        assert_eq!(5, document_size(&document));
+        document.insert(String::from("age"), DataType::I64(75));
+        assert_eq!(5 + 1 + 4 + 8, document_size(&document));
+        document.insert(String::from("a\0b"), DataType::Null);
+        assert!(document_size(&document) > 0);
+    }
+
+    #[test]
+    fn unlimited_cap() {
+        let cap = Cap {
+            max_bytes: None,
+            max_documents: Some(1),
+        };
+        assert!(!cap.exceeded(1, usize::MAX));
+        assert!(cap.exceeded(2, 0));
+    }
+}
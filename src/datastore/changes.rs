@@ -0,0 +1,167 @@
+use crate::datastore::collection::Document;
+use crate::datastore::error::DatastoreError;
+use serde::Serialize;
+use std::collections::VecDeque;
+
+/// Number of change events a collection keeps for clients resuming a watch
+pub const CHANGE_LOG_CAPACITY: usize = 10_000;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Operation {
+    Insert,
+    Update,
+    Delete,
+}
+
+impl Operation {
+    /// Produces the name of the operation, e.g. `insert`
+    pub fn name(&self) -> &'static str {
+        match self {
+            Operation::Insert => "insert",
+            Operation::Update => "update",
+            Operation::Delete => "delete",
+        }
+    }
+}
+
+/// A write to one document of a collection
+#[derive(Debug, Clone, PartialEq)]
+pub struct ChangeEvent {
+    /// The collection version the write brought the collection to. Events
+    /// are recorded in token order, so a client resumes after the token of
+    /// the last event it saw.
+    pub token: u64,
+    pub operation: Operation,
+    pub key: usize,
+    /// The document after an insert or update, or the removed document of
+    /// a delete
+    pub document: Document,
+}
+
+/// The latest change events of a collection, oldest first, discarding the
+/// oldest once it holds `capacity` events
+#[derive(Debug)]
+pub struct ChangeLog {
+    events: VecDeque<ChangeEvent>,
+    capacity: usize,
+    /// Token of the latest event discarded, 0 while none has been
+    discarded: u64,
+}
+
+impl ChangeLog {
+    /// Produces a new, empty ChangeLog
+    ///
+    /// # Arguments
+    ///
+    /// * `capacity` - the most events the log keeps
+    pub fn new(capacity: usize) -> ChangeLog {
+        ChangeLog {
+            events: VecDeque::new(),
+            capacity,
+            discarded: 0,
+        }
+    }
+
+    /// Appends an event, discarding the oldest when the log is full
+    ///
+    /// # Arguments
+    ///
+    /// * `event` - an event with a greater token than any recorded yet
+    pub fn record(&mut self, event: ChangeEvent) {
+        if self.events.len() >= self.capacity {
+            match self.events.pop_front() {
+                Some(oldest) => self.discarded = oldest.token,
+                None => self.discarded = event.token,
+            }
+        }
+        if self.capacity > 0 {
+            self.events.push_back(event);
+        }
+    }
+
+    /// Produces the events recorded after a token, oldest first
+    ///
+    /// # Arguments
+    ///
+    /// * `token` - the token of the last event already seen, 0 for every
+    ///   event since the collection was created
+    ///
+    /// # Examples
+    ///
+    /// ```rust
+    /// use rockumentdb::datastore::changes::{ChangeEvent, ChangeLog, Operation};
+    /// use std::collections::HashMap;
+    ///
+    /// let mut log = ChangeLog::new(1);
+    /// for token in 1..=2 {
+    ///     log.record(ChangeEvent {
+    ///         token,
+    ///         operation: Operation::Insert,
+    ///         key: token as usize,
+    ///         document: HashMap::new(),
+    ///     });
+    /// }
+    /// assert_eq!(2, log.since(1).unwrap()[0].key);
+    /// assert!(log.since(0).is_err());
+    /// ```
+    pub fn since(&self, token: u64) -> Result<Vec<ChangeEvent>, DatastoreError> {
+        if token < self.discarded {
+            return Err(DatastoreError::ChangeHistoryLost(token));
+        }
+        let start = self.events.partition_point(|event| event.token <= token);
+        Ok(self.events.range(start..).cloned().collect())
+    }
+}
+
+/// Copies of a collection, such as a transaction's snapshot, start with an
+/// empty log: only the writes to the live collection are watched
+impl Clone for ChangeLog {
+    fn clone(&self) -> Self {
+        ChangeLog {
+            events: VecDeque::new(),
+            capacity: self.capacity,
+            discarded: self
+                .events
+                .back()
+                .map_or(self.discarded, |event| event.token),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn event(token: u64, operation: Operation) -> ChangeEvent {
+        ChangeEvent {
+            token,
+            operation,
+            key: 1,
+            document: Document::new(),
+        }
+    }
+
+    #[test]
+    fn resume_after_token() {
+        let mut log = ChangeLog::new(3);
+        for token in 1..=5 {
+            log.record(event(token, Operation::Update));
+        }
+        let tokens = |events: Vec<ChangeEvent>| events.iter().map(|e| e.token).collect::<Vec<_>>();
+        assert_eq!(vec![3, 4, 5], tokens(log.since(2).unwrap()));
+        assert_eq!(vec![5], tokens(log.since(4).unwrap()));
+        assert!(log.since(5).unwrap().is_empty());
+        assert!(log.since(9).unwrap().is_empty());
+        assert_eq!(Err(DatastoreError::ChangeHistoryLost(1)), log.since(1));
+    }
+
+    #[test]
+    fn clones_start_empty() {
+        let mut log = ChangeLog::new(3);
+        log.record(event(1, Operation::Insert));
+        let copy = log.clone();
+        assert!(copy.since(1).unwrap().is_empty());
+        assert!(copy.since(0).is_err());
+    }
+}
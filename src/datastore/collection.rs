@@ -1,17 +1,172 @@
+use crate::datastore::capped::{self, Cap};
+use crate::datastore::changes::{ChangeEvent, ChangeLog, Operation, CHANGE_LOG_CAPACITY};
+use crate::datastore::database::{Clock, Recover};
 use crate::datastore::datatypes::DataType;
-use crate::datastore::index::Index;
-use crate::datastore::query_proc::{self, QueryResult};
-use std::collections::{BTreeMap, HashMap};
+use crate::datastore::error::DatastoreError;
+use crate::datastore::index::{self, CompoundIndex, Index};
+use crate::datastore::query_proc::{Explain, Filter, Indexes, Matches, Query, QueryResult};
+use crate::datastore::schema::{ValidationAction, Validator};
+use crate::datastore::text::TextIndex;
+use crate::datastore::ttl::TtlIndex;
+use crate::datastore::update::Update;
+use serde_json::Value;
+use std::collections::{BTreeMap, BTreeSet, HashMap};
+use std::ops::Bound;
+use std::sync::Arc;
 
 pub type Store = BTreeMap<usize, Document>;
 pub type Document = HashMap<String, DataType>;
 pub type Indices = HashMap<String, Index>;
+pub type CompoundIndices = Vec<CompoundIndex>;
 
+#[derive(Debug, Clone, PartialEq)]
+pub struct UpdateResult {
+    pub matched: usize,
+    pub modified: Vec<usize>,
+}
+
+/// Options of a collection, each left as it is when absent
+#[derive(Debug, Clone, Default)]
+pub struct CollectionOptions {
+    /// The validator to set, or None inside to remove the collection's
+    pub validator: Option<Option<Validator>>,
+    /// The limits to cap the collection to, or None inside to uncap it
+    pub cap: Option<Option<Cap>>,
+    /// The date field documents expire by and how many seconds after its
+    /// date, kept as a TTL index on the field
+    pub ttl: Option<(String, u64)>,
+}
+
+impl CollectionOptions {
+    /// Produces the CollectionOptions of a request body
+    ///
+    /// # Arguments
+    ///
+    /// * `value` - an object of options: a `schema` and `validationAction`,
+    ///   see `Validator::from_json`, a null schema removing the validator;
+    ///   `capped` with a `size` in bytes, a `max` number of documents or
+    ///   both, `capped: false` uncapping the collection; `expireAfterSeconds`
+    ///   with the `timeField` holding the date documents expire after
+    ///
+    /// # Examples
+    ///
+    /// ```rust
+    /// use rockumentdb::datastore::collection::CollectionOptions;
+    /// use serde_json::json;
+    ///
+    /// let options = CollectionOptions::from_json(&json!({"schema": null})).unwrap();
+    /// assert!(matches!(options.validator, Some(None)));
+    ///
+    /// let options = CollectionOptions::from_json(&json!({"capped": true, "max": 100})).unwrap();
+    /// assert_eq!(Some(100), options.cap.unwrap().unwrap().max_documents);
+    ///
+    /// let options =
+    ///     CollectionOptions::from_json(&json!({"expireAfterSeconds": 60, "timeField": "at"}))
+    ///         .unwrap();
+    /// assert_eq!(Some((String::from("at"), 60)), options.ttl);
+    /// ```
+    pub fn from_json(value: &Value) -> Result<CollectionOptions, String> {
+        let fields = match value {
+            Value::Object(fields) => fields,
+            _ => return Err(String::from("options must be an object")),
+        };
+        let mut options = CollectionOptions::default();
+        for (option, argument) in fields.iter() {
+            match option.as_str() {
+                "schema" if argument.is_null() => options.validator = Some(None),
+                "schema" => options.validator = Some(Some(Validator::from_json(value)?)),
+                "validationAction" if !fields.contains_key("schema") => {
+                    return Err(String::from("validationAction requires a schema"))
+                }
+                "validationAction" => {}
+                "capped" => {
+                    options.cap = match argument {
+                        Value::Bool(true) => Some(Some(Cap {
+                            max_bytes: limit(fields, "size")?,
+                            max_documents: limit(fields, "max")?,
+                        })),
+                        Value::Bool(false) => Some(None),
+                        _ => return Err(String::from("capped must be true or false")),
+                    }
+                }
+                "size" | "max" if fields.get("capped") != Some(&Value::Bool(true)) => {
+                    return Err(format!("{} requires capped: true", option))
+                }
+                "size" | "max" => {}
+                "expireAfterSeconds" => {
+                    let seconds = argument.as_u64().ok_or_else(|| {
+                        String::from("expireAfterSeconds must be a non-negative integer")
+                    })?;
+                    let field = match fields.get("timeField") {
+                        Some(Value::String(field)) if !field.is_empty() => field.clone(),
+                        Some(_) => return Err(String::from("timeField must be a field name")),
+                        None => {
+                            return Err(String::from("expireAfterSeconds requires a timeField"))
+                        }
+                    };
+                    options.ttl = Some((field, seconds));
+                }
+                "timeField" if !fields.contains_key("expireAfterSeconds") => {
+                    return Err(String::from("timeField requires expireAfterSeconds"))
+                }
+                "timeField" => {}
+                _ => return Err(format!("unknown option {}", option)),
+            }
+        }
+        if let Some(Some(Cap {
+            max_bytes: None,
+            max_documents: None,
+        })) = options.cap
+        {
+            return Err(String::from("a capped collection requires a size or max"));
+        }
+        Ok(options)
+    }
+}
+
+/// Produces a limit of a capped collection, a positive integer if present
+fn limit(fields: &serde_json::Map<String, Value>, option: &str) -> Result<Option<usize>, String> {
+    match fields.get(option) {
+        None => Ok(None),
+        Some(argument) => match argument.as_u64() {
+            Some(limit) if limit > 0 => Ok(Some(limit as usize)),
+            _ => Err(format!("{} must be a positive integer", option)),
+        },
+    }
+}
+
+#[derive(Clone)]
 pub struct Collection {
     pub name: String,
     store: Store,
     last_key: usize,
     indices: HashMap<String, Index>,
+    compound_indices: CompoundIndices,
+    /// Expiry of documents by date fields, keyed by field name; each field
+    /// also has an Index
+    ttl_indices: HashMap<String, TtlIndex>,
+    /// The inverted index answering `$text` searches, if there is one
+    text_index: Option<TextIndex>,
+    /// Incremented on every write to the collection
+    version: u64,
+    /// The clock of the Database holding the collection, if any
+    clock: Option<Arc<Clock>>,
+    /// Each key written while a snapshot was open, with the sequence of
+    /// every such write and the document it replaced, oldest first, see
+    /// `get_at`
+    history: HashMap<usize, Vec<(u64, Option<Document>)>>,
+    /// The oldest open snapshot when the history was last pruned
+    history_floor: u64,
+    validator: Option<Validator>,
+    /// The latest writes, for clients watching the collection
+    changes: ChangeLog,
+    /// The limits the oldest documents are evicted to stay within, if the
+    /// collection is capped
+    cap: Option<Cap>,
+    /// Total size of the documents, kept while the collection is capped
+    bytes: usize,
+    /// The highest key evicted by the cap, 0 if none was
+    evicted_through: usize,
 }
 
 impl Collection {
@@ -19,52 +174,971 @@ impl Collection {
     ///
     /// # Arugments
     ///
-    /// * `name` - the name of the collection
+    /// * `name` - the name of the collection
+    ///
+    /// # Examples
+    ///
+    /// ```rust
+    /// use rockumentdb::datastore::collection::Collection;
+    /// let mut collection = Collection::new(String::from("users"));
+    /// ```
+    pub fn new(name: String) -> Collection {
+        Collection {
+            name,
+            store: BTreeMap::new(),
+            last_key: 0,
+            indices: HashMap::new(),
+            compound_indices: Vec::new(),
+            ttl_indices: HashMap::new(),
+            text_index: None,
+            version: 0,
+            clock: None,
+            history: HashMap::new(),
+            history_floor: 0,
+            validator: None,
+            changes: ChangeLog::new(CHANGE_LOG_CAPACITY),
+            cap: None,
+            bytes: 0,
+            evicted_through: 0,
+        }
+    }
+
+    /// Inserts a document into the collection
+    ///
+    /// # Arguments
+    ///
+    /// * `value` - A document in the form of a hashmap with string keys
+    ///   and DataType values.
+    ///
+    /// # Examples
+    ///
+    /// ```rust
+    /// use rockumentdb::datastore::collection::Collection;
+    /// use rockumentdb::datastore::datatypes::DataType;
+    /// use std::collections::HashMap;
+    ///
+    /// let mut collection = Collection::new(String::from("users"));
+    ///
+    /// let mut document = HashMap::new();
+    /// document.insert(
+    ///     String::from("username"),
+    ///     DataType::String(String::from("johnperry")),
+    /// );
+    /// document.insert(String::from("age"), DataType::U64(75u64));
+    /// document.insert(String::from("active"), DataType::Bool(true));
+    ///
+    /// let key = collection.insert(document).unwrap();
+    /// ```
+    pub fn insert(&mut self, value: HashMap<String, DataType>) -> Result<usize, DatastoreError> {
+        self.check_write(self.last_key + 1, &value)?;
+        let key = self.reserve_key();
+        self.put(key, value);
+        Ok(key)
+    }
+
+    /// Checks that a document could be stored under a key without breaking
+    /// the collection's schema or unique indexes
+    ///
+    /// # Arguments
+    ///
+    /// * `key` - the key the document would be stored under
+    /// * `document` - the document about to be written
+    pub fn check_write(&self, key: usize, document: &Document) -> Result<(), DatastoreError> {
+        self.validate(document)?;
+        self.check_unique(&[(key, Some(document))])
+    }
+
+    /// Checks that storing a set of documents would not give two documents
+    /// the same key in a unique index. Documents without every indexed
+    /// field are not constrained.
+    ///
+    /// # Arguments
+    ///
+    /// * `changes` - the keys about to be written, with the document each
+    ///   will hold or None if it is about to be removed
+    pub fn check_unique(
+        &self,
+        changes: &[(usize, Option<&Document>)],
+    ) -> Result<(), DatastoreError> {
+        for fields in self.unique_fields() {
+            let mut claimed: Vec<(Vec<&DataType>, usize)> = Vec::new();
+            for (key, document) in changes.iter() {
+                let document = match document {
+                    Some(document) => document,
+                    None => continue,
+                };
+                let values = match key_values(&fields, document) {
+                    Some(values) => values,
+                    None => continue,
+                };
+                let duplicate_in_changes = claimed.iter().any(|(claimed_values, claimed_key)| {
+                    *claimed_values == values && claimed_key != key
+                });
+                // Documents being overwritten or removed by this write no
+                // longer hold their old keys
+                let duplicate_in_store = self.indexed_ids(&fields, document).iter().any(|id| {
+                    !changes.iter().any(|(changed_key, _)| changed_key == id)
+                        && self
+                            .store
+                            .get(id)
+                            .and_then(|stored| key_values(&fields, stored))
+                            .as_ref()
+                            == Some(&values)
+                });
+                if duplicate_in_changes || duplicate_in_store {
+                    return Err(duplicate_key(&fields, &values));
+                }
+                claimed.push((values, *key));
+            }
+        }
+        Ok(())
+    }
+
+    /// Produces the field lists of the collection's unique indexes
+    fn unique_fields(&self) -> Vec<Vec<String>> {
+        let single = self
+            .indices
+            .iter()
+            .filter(|(_, index)| index.unique)
+            .map(|(field, _)| vec![field.clone()]);
+        let compound = self
+            .compound_indices
+            .iter()
+            .filter(|index| index.unique)
+            .map(|index| index.fields.clone());
+        single.chain(compound).collect()
+    }
+
+    /// Produces the ids the index over `fields` holds under a document's key
+    fn indexed_ids(&self, fields: &[String], document: &Document) -> Vec<usize> {
+        let ids = if let [field] = fields {
+            self.indices
+                .get(field)
+                .zip(field_value(document, field))
+                .and_then(|(index, value)| index.search(value))
+        } else {
+            self.compound_indices
+                .iter()
+                .find(|index| index.fields == fields)
+                .and_then(|index| index.search(&index.key(document)))
+        };
+        ids.cloned().unwrap_or_default()
+    }
+
+    /// Indexes a field of every document in the collection, replacing any
+    /// index the field already has
+    ///
+    /// # Arguments
+    ///
+    /// * `field` - the field to index
+    /// * `unique` - whether to reject documents whose value for the field
+    ///   another document already holds
+    ///
+    /// # Examples
+    ///
+    /// ```rust
+    /// use rockumentdb::datastore::collection::Collection;
+    ///
+    /// let mut collection = Collection::new(String::from("users"));
+    /// collection.create_index("email", true).unwrap();
+    /// ```
+    pub fn create_index(&mut self, field: &str, unique: bool) -> Result<(), DatastoreError> {
+        let mut index = if unique {
+            Index::new_unique()
+        } else {
+            Index::new()
+        };
+        for (key, document) in self.store.iter() {
+            if let Some(value) = field_value(document, field) {
+                index.insert(value, *key);
+            }
+        }
+        if unique {
+            self.check_built_index(&[String::from(field)], index.tree.values())?;
+        }
+        self.indices.insert(String::from(field), index);
+        self.ttl_indices.remove(field);
+        Ok(())
+    }
+
+    /// Indexes a date field of every document in the collection, like
+    /// `create_index`, and removes each document `expire_after_seconds`
+    /// after the date in the field whenever `expire` runs
+    ///
+    /// # Arguments
+    ///
+    /// * `field` - the date field to index
+    /// * `unique` - whether to reject documents whose value for the field
+    ///   another document already holds
+    /// * `expire_after_seconds` - how long after its date a document expires
+    ///
+    /// # Examples
+    ///
+    /// ```rust
+    /// use rockumentdb::datastore::collection::Collection;
+    ///
+    /// let mut collection = Collection::new(String::from("sessions"));
+    /// collection.create_ttl_index("created", false, 3600).unwrap();
+    /// assert_eq!(Some(3600), collection.expire_after("created"));
+    /// ```
+    pub fn create_ttl_index(
+        &mut self,
+        field: &str,
+        unique: bool,
+        expire_after_seconds: u64,
+    ) -> Result<(), DatastoreError> {
+        self.create_index(field, unique)?;
+        let mut index = TtlIndex::new(field, expire_after_seconds);
+        for (key, document) in self.store.iter() {
+            index.insert(document, *key);
+        }
+        self.ttl_indices.insert(String::from(field), index);
+        Ok(())
+    }
+
+    /// Produces how long after the date in a field documents expire, if the
+    /// field has a TTL index
+    ///
+    /// # Arguments
+    ///
+    /// * `field` - the indexed field
+    pub fn expire_after(&self, field: &str) -> Option<u64> {
+        self.ttl_indices
+            .get(field)
+            .map(|index| index.expire_after_seconds)
+    }
+
+    /// Produces whether any field of the collection has a TTL index
+    pub fn expires(&self) -> bool {
+        !self.ttl_indices.is_empty()
+    }
+
+    /// Removes the documents expired at a time by the collection's TTL
+    /// indexes, producing their keys. The documents are removed like any
+    /// other, from every index and with a change event each.
+    ///
+    /// # Arguments
+    ///
+    /// * `now` - the time, in milliseconds since the Unix epoch
+    pub fn expire(&mut self, now: i64) -> Vec<usize> {
+        let mut expired: Vec<usize> = self
+            .ttl_indices
+            .values()
+            .flat_map(|index| index.expired(now))
+            .collect();
+        expired.sort_unstable();
+        expired.dedup();
+        expired.retain(|key| self.remove(*key).is_some());
+        expired
+    }
+
+    /// Indexes a list of fields of every document in the collection,
+    /// replacing any index over the same list. Queries on a prefix of the
+    /// fields, optionally followed by a range on the next field, can use
+    /// the index.
+    ///
+    /// # Arguments
+    ///
+    /// * `fields` - the fields to index, in the order they are matched
+    /// * `unique` - whether to reject documents whose values for every
+    ///   field another document already holds
+    ///
+    /// # Examples
+    ///
+    /// ```rust
+    /// use rockumentdb::datastore::collection::Collection;
+    ///
+    /// let mut collection = Collection::new(String::from("tickets"));
+    /// collection
+    ///     .create_compound_index(vec![String::from("tenant"), String::from("status")], false)
+    ///     .unwrap();
+    /// ```
+    pub fn create_compound_index(
+        &mut self,
+        fields: Vec<String>,
+        unique: bool,
+    ) -> Result<(), DatastoreError> {
+        let mut index = if unique {
+            CompoundIndex::new_unique(fields)
+        } else {
+            CompoundIndex::new(fields)
+        };
+        for (key, document) in self.store.iter() {
+            index.insert(document, *key);
+        }
+        if unique {
+            self.check_built_index(&index.fields, index.tree.values())?;
+        }
+        self.drop_compound_index(&index.fields);
+        self.compound_indices.push(index);
+        Ok(())
+    }
+
+    /// Checks that no two documents sharing an entry of a newly built
+    /// unique index hold the same values for its fields
+    fn check_built_index<'a>(
+        &self,
+        fields: &[String],
+        entries: impl Iterator<Item = &'a Vec<usize>>,
+    ) -> Result<(), DatastoreError> {
+        for ids in entries.filter(|ids| ids.len() > 1) {
+            let mut seen: Vec<Vec<&DataType>> = Vec::new();
+            let documents = ids.iter().filter_map(|id| self.store.get(id));
+            for values in documents.filter_map(|document| key_values(fields, document)) {
+                if seen.contains(&values) {
+                    return Err(duplicate_key(fields, &values));
+                }
+                seen.push(values);
+            }
+        }
+        Ok(())
+    }
+
+    /// Removes the compound index over a list of fields, producing whether
+    /// there was one
+    ///
+    /// # Arguments
+    ///
+    /// * `fields` - the indexed fields
+    pub fn drop_compound_index(&mut self, fields: &[String]) -> bool {
+        let count = self.compound_indices.len();
+        self.compound_indices.retain(|index| index.fields != fields);
+        self.compound_indices.len() != count
+    }
+
+    /// Produces the collection's compound indexes
+    pub fn compound_indices(&self) -> &CompoundIndices {
+        &self.compound_indices
+    }
+
+    /// Removes the index on a field, producing whether there was one
+    ///
+    /// # Arguments
+    ///
+    /// * `field` - the indexed field
+    pub fn drop_index(&mut self, field: &str) -> bool {
+        self.ttl_indices.remove(field);
+        self.indices.remove(field).is_some()
+    }
+
+    /// Produces the collection's indexes, keyed by field name
+    pub fn indices(&self) -> &Indices {
+        &self.indices
+    }
+
+    /// Sets or, given None, removes the schema documents written to the
+    /// collection must match
+    ///
+    /// # Arguments
+    ///
+    /// * `validator` - the schema and what to do with documents that do not
+    ///   match it
+    pub fn set_validator(&mut self, validator: Option<Validator>) {
+        self.validator = validator;
+    }
+
+    /// Applies the given options, leaving those absent as they are. A TTL
+    /// replaces any index on its field, keeping whether it is unique.
+    ///
+    /// # Arguments
+    ///
+    /// * `options` - the options to set
+    pub fn set_options(&mut self, options: CollectionOptions) -> Result<(), DatastoreError> {
+        if let Some(validator) = options.validator {
+            self.validator = validator;
+        }
+        if let Some(cap) = options.cap {
+            self.set_cap(cap);
+        }
+        if let Some((field, seconds)) = options.ttl {
+            let unique = self.indices.get(&field).is_some_and(|index| index.unique);
+            self.create_ttl_index(&field, unique, seconds)?;
+        }
+        Ok(())
+    }
+
+    /// Caps or uncaps the collection. Capping a collection already past the
+    /// limits evicts its oldest documents at once, but for the newest.
+    ///
+    /// # Arguments
+    ///
+    /// * `cap` - the limits, None to uncap the collection
+    ///
+    /// # Examples
+    ///
+    /// ```rust
+    /// use rockumentdb::datastore::capped::Cap;
+    /// use rockumentdb::datastore::collection::Collection;
+    ///
+    /// let mut collection = Collection::new(String::from("audit"));
+    /// collection.set_cap(Some(Cap { max_bytes: None, max_documents: Some(2) }));
+    /// for _ in 0..3 {
+    ///     collection.insert(Default::default()).unwrap();
+    /// }
+    /// let keys: Vec<usize> = collection.documents_after(0, 10).iter().map(|e| e.0).collect();
+    /// assert_eq!(vec![2, 3], keys);
+    /// ```
+    pub fn set_cap(&mut self, cap: Option<Cap>) {
+        self.cap = cap;
+        self.bytes = match cap {
+            Some(_) => self.store.values().map(capped::document_size).sum(),
+            None => 0,
+        };
+        if let Some(&newest) = self.store.keys().next_back() {
+            self.evict(newest);
+        }
+    }
+
+    /// Produces the collection's limits, if it is capped
+    pub fn cap(&self) -> Option<&Cap> {
+        self.cap.as_ref()
+    }
+
+    /// Produces the highest key the cap evicted, 0 if it evicted none. A
+    /// reader that has not read past this key missed documents.
+    pub fn evicted_through(&self) -> usize {
+        self.evicted_through
+    }
+
+    /// Indexes the words of string fields of every document for `$text`
+    /// searches, replacing the collection's text index if it has one
+    ///
+    /// # Arguments
+    ///
+    /// * `fields` - the fields, or dotted paths, whose text is searched
+    ///
+    /// # Examples
+    ///
+    /// ```rust
+    /// use rockumentdb::datastore::collection::Collection;
+    /// use rockumentdb::datastore::datatypes::DataType;
+    /// use std::collections::HashMap;
+    ///
+    /// let mut collection = Collection::new(String::from("books"));
+    /// let mut document = HashMap::new();
+    /// let title = DataType::String(String::from("The Ringworld Engineers"));
+    /// document.insert(String::from("title"), title);
+    /// let key = collection.insert(document).unwrap();
+    /// collection.create_text_index(vec![String::from("title")]);
+    /// let found = collection.find_ids("{$text: {$search: \"engineering\"}}");
+    /// assert_eq!(Ok(vec![key]), found);
+    /// ```
+    pub fn create_text_index(&mut self, fields: Vec<String>) {
+        let mut index = TextIndex::new(fields);
+        for (key, document) in self.store.iter() {
+            index.insert(document, *key);
+        }
+        self.text_index = Some(index);
+    }
+
+    /// Removes the collection's text index, producing whether it had one
+    pub fn drop_text_index(&mut self) -> bool {
+        self.text_index.take().is_some()
+    }
+
+    /// Produces the collection's text index, if it has one
+    pub fn text_index(&self) -> Option<&TextIndex> {
+        self.text_index.as_ref()
+    }
+
+    /// Produces the collection's validator, if it has one
+    pub fn validator(&self) -> Option<&Validator> {
+        self.validator.as_ref()
+    }
+
+    /// Checks a document against the collection's schema
+    ///
+    /// # Arguments
+    ///
+    /// * `document` - the document about to be written
+    pub fn validate(&self, document: &Document) -> Result<(), DatastoreError> {
+        let validator = match &self.validator {
+            Some(validator) => validator,
+            None => return Ok(()),
+        };
+        let violations = validator.schema.validate(document);
+        if violations.is_empty() {
+            return Ok(());
+        }
+        match validator.action {
+            ValidationAction::Error => Err(DatastoreError::Validation(violations)),
+            ValidationAction::Warn => {
+                for violation in violations.iter() {
+                    println!(
+                        "VALIDATION: Collection - {} - {} {}",
+                        &self.name, &violation.field, &violation.message
+                    );
+                }
+                Ok(())
+            }
+        }
+    }
+
+    /// Produces a key that no other document in the collection will be
+    /// given, without storing anything under it
+    pub fn reserve_key(&mut self) -> usize {
+        self.last_key += 1;
+        self.last_key
+    }
+
+    /// Reserves every key up to one, so that none of them is given out
+    ///
+    /// # Arguments
+    ///
+    /// * `last` - the highest key to reserve
+    pub fn reserve_keys(&mut self, last: usize) {
+        self.last_key = self.last_key.max(last);
+    }
+
+    /// Stores a document under a key, replacing any document already there.
+    ///
+    /// The document is not checked against the collection's schema or
+    /// unique indexes, see `check_write`. In a capped collection the oldest
+    /// other documents are then evicted until the collection is within its
+    /// limits.
+    ///
+    /// # Arguments
+    ///
+    /// * `key` - a key produced by `insert` or `reserve_key`
+    /// * `value` - the document to store
+    pub fn put(&mut self, key: usize, value: Document) {
+        // Keys a transaction reserved for a collection that was dropped and
+        // created again before it committed are never given out again
+        self.reserve_keys(key);
+        self.record_write(key);
+        let operation = match self.store.remove(&key) {
+            Some(previous) => {
+                self.unindex_document(key, &previous);
+                self.unmeasure(&previous);
+                Operation::Update
+            }
+            None => Operation::Insert,
+        };
+        self.index_document(key, &value);
+        self.measure(&value);
+        self.record_change(operation, key, value.clone());
+        self.store.insert(key, value);
+        self.evict(key);
+    }
+
+    /// Removes the document stored under a key, producing it if it existed
+    ///
+    /// # Arguments
+    ///
+    /// * `key` - the key of the document to remove
+    pub fn remove(&mut self, key: usize) -> Option<Document> {
+        if !self.store.contains_key(&key) {
+            return None;
+        }
+        self.record_write(key);
+        let removed = self.store.remove(&key);
+        if let Some(document) = &removed {
+            self.unindex_document(key, document);
+            self.unmeasure(document);
+            self.record_change(Operation::Delete, key, document.clone());
+        }
+        removed
+    }
+
+    fn measure(&mut self, document: &Document) {
+        if self.cap.is_some() {
+            self.bytes += capped::document_size(document);
+        }
+    }
+
+    fn unmeasure(&mut self, document: &Document) {
+        if self.cap.is_some() {
+            self.bytes = self.bytes.saturating_sub(capped::document_size(document));
+        }
+    }
+
+    /// Removes the oldest documents until the collection is within its
+    /// cap, keeping the document just written however large it is
+    fn evict(&mut self, written: usize) {
+        let cap = match self.cap {
+            Some(cap) => cap,
+            None => return,
+        };
+        while cap.exceeded(self.store.len(), self.bytes) {
+            let oldest = match self.store.keys().next() {
+                Some(&oldest) if oldest != written => oldest,
+                _ => break,
+            };
+            self.remove(oldest);
+            self.evicted_through = self.evicted_through.max(oldest);
+        }
+    }
+
+    fn index_document(&mut self, key: usize, document: &Document) {
+        for (field, index) in self.indices.iter_mut() {
+            if let Some(value) = field_value(document, field) {
+                index.insert(value, key);
+            }
+        }
+        for index in self.compound_indices.iter_mut() {
+            index.insert(document, key);
+        }
+        for index in self.ttl_indices.values_mut() {
+            index.insert(document, key);
+        }
+        if let Some(index) = self.text_index.as_mut() {
+            index.insert(document, key);
+        }
+    }
+
+    fn unindex_document(&mut self, key: usize, document: &Document) {
+        for (field, index) in self.indices.iter_mut() {
+            if let Some(value) = field_value(document, field) {
+                index.remove(value, key);
+            }
+        }
+        for index in self.compound_indices.iter_mut() {
+            index.remove(document, key);
+        }
+        for index in self.ttl_indices.values_mut() {
+            index.remove(document, key);
+        }
+        if let Some(index) = self.text_index.as_mut() {
+            index.remove(document, key);
+        }
+    }
+
+    /// Counts a write to a key about to be made and, while a snapshot
+    /// is open, keeps the document it replaces
+    fn record_write(&mut self, key: usize) {
+        self.version += 1;
+        let stamp = match &self.clock {
+            Some(clock) => clock.stamp(),
+            None => return,
+        };
+        let oldest = match stamp.oldest {
+            Some(oldest) => oldest,
+            None => {
+                self.history.clear();
+                return;
+            }
+        };
+        // Writes no open snapshot is older than are seen by all of them
+        if oldest > self.history_floor {
+            self.history.retain(|_, writes| {
+                writes.retain(|(sequence, _)| *sequence > oldest);
+                !writes.is_empty()
+            });
+            self.history_floor = oldest;
+        }
+        let previous = self.store.get(&key).cloned();
+        self.history
+            .entry(key)
+            .or_default()
+            .push((stamp.sequence, previous));
+    }
+
+    fn record_change(&mut self, operation: Operation, key: usize, document: Document) {
+        self.changes.record(ChangeEvent {
+            token: self.version,
+            operation,
+            key,
+            document,
+        });
+    }
+
+    /// Produces the writes to the collection after a resume token, oldest
+    /// first. Each event's token is the collection version it wrote, so
+    /// the collection's current version resumes from now on.
+    ///
+    /// # Arguments
+    ///
+    /// * `token` - the token of the last event already seen
+    ///
+    /// # Examples
+    ///
+    /// ```rust
+    /// use rockumentdb::datastore::changes::Operation;
+    /// use rockumentdb::datastore::collection::Collection;
+    ///
+    /// let mut collection = Collection::new(String::from("users"));
+    /// let token = collection.version();
+    /// let key = collection.insert(Default::default()).unwrap();
+    /// collection.remove(key);
+    /// let changes = collection.changes_since(token).unwrap();
+    /// assert_eq!(Operation::Insert, changes[0].operation);
+    /// assert_eq!(Operation::Delete, changes[1].operation);
+    /// ```
+    pub fn changes_since(&self, token: u64) -> Result<Vec<ChangeEvent>, DatastoreError> {
+        // A token ahead of the collection was given by a collection since
+        // dropped under the same name
+        if token > self.version {
+            return Err(DatastoreError::ChangeHistoryLost(token));
+        }
+        self.changes.since(token)
+    }
+
+    /// Produces the document stored under a key
+    pub fn get(&self, key: usize) -> Option<&Document> {
+        self.store.get(&key)
+    }
+
+    /// Produces the number of documents in the collection
+    pub fn len(&self) -> usize {
+        self.store.len()
+    }
+
+    /// Produces whether the collection holds no documents
+    pub fn is_empty(&self) -> bool {
+        self.store.is_empty()
+    }
+
+    /// Produces up to `count` documents stored under keys greater than
+    /// `after`, in key order, so a collection can be read a page at a time
+    ///
+    /// # Arguments
+    ///
+    /// * `after` - the last key already read, 0 to start from the first
+    /// * `count` - the most documents to produce
+    pub fn documents_after(&self, after: usize, count: usize) -> Vec<(usize, &Document)> {
+        self.store
+            .range((Bound::Excluded(after), Bound::Unbounded))
+            .take(count)
+            .map(|(key, document)| (*key, document))
+            .collect()
+    }
+
+    /// Produces the current version of the collection, which increases with
+    /// every write
+    pub fn version(&self) -> u64 {
+        self.version
+    }
+
+    /// Produces the highest key stored or reserved so far
+    pub fn last_key(&self) -> usize {
+        self.last_key
+    }
+
+    /// Stamps the collection's writes with a Database's clock, so that
+    /// snapshots taken from it read the collection as it was then
+    ///
+    /// # Arguments
+    ///
+    /// * `clock` - the clock of the Database holding the collection
+    pub fn set_clock(&mut self, clock: Arc<Clock>) {
+        self.clock = Some(clock);
+    }
+
+    /// Produces the document stored under a key when a snapshot was taken
+    ///
+    /// # Arguments
+    ///
+    /// * `key` - the key of the document
+    /// * `sequence` - the sequence the snapshot was taken at, see
+    ///   `Snapshot::sequence`
+    pub fn get_at(&self, key: usize, sequence: u64) -> Option<&Document> {
+        let replaced = self
+            .history
+            .get(&key)
+            .and_then(|writes| writes.iter().find(|(written, _)| *written > sequence));
+        match replaced {
+            Some((_, previous)) => previous.as_ref(),
+            None => self.store.get(&key),
+        }
+    }
+
+    /// Produces whether the document under a key was written after a
+    /// snapshot was taken
+    ///
+    /// # Arguments
+    ///
+    /// * `key` - the key of the document
+    /// * `sequence` - the sequence the snapshot was taken at
+    pub fn written_since(&self, key: usize, sequence: u64) -> bool {
+        self.history
+            .get(&key)
+            .and_then(|writes| writes.last())
+            .is_some_and(|(written, _)| *written > sequence)
+    }
+
+    /// Produces the keys written after a snapshot was taken, in key order
+    ///
+    /// # Arguments
+    ///
+    /// * `sequence` - the sequence the snapshot was taken at
+    pub fn keys_written_since(&self, sequence: u64) -> BTreeSet<usize> {
+        self.history
+            .keys()
+            .copied()
+            .filter(|key| self.written_since(*key, sequence))
+            .collect()
+    }
+
+    /// Applies an update to every document matching a query
+    ///
+    /// # Arguments
+    ///
+    /// * `query` - query statement selecting the documents to update
+    /// * `update` - the update to apply
     ///
     /// # Examples
     ///
     /// ```rust
-    /// use collection::Collection;
-    /// let mut collection = Collection::new();
+    /// use rockumentdb::datastore::collection::Collection;
+    /// use rockumentdb::datastore::update::{Operation, Update};
+    /// use rockumentdb::datastore::datatypes::DataType;
+    ///
+    /// let mut collection = Collection::new(String::from("users"));
+    /// let update = Update::Operators(vec![Operation::Set(
+    ///     String::from("active"),
+    ///     DataType::Bool(false),
+    /// )]);
+    /// let result = collection.update("{username:\"johnperry\"}", &update);
     /// ```
-    pub fn new(name: String) -> Collection {
-        Collection {
-            name,
-            store: BTreeMap::new(),
-            last_key: 0,
-            indices: HashMap::new(),
+    pub fn update(&mut self, query: &str, update: &Update) -> Result<UpdateResult, DatastoreError> {
+        let ids = self.find_ids(query)?;
+        self.update_ids(&ids, update)
+    }
+
+    /// Applies an update to the documents stored under the given keys
+    ///
+    /// Either every document is updated or, if the update cannot be applied
+    /// to one of them or produces a document that does not match the
+    /// collection's schema or unique indexes, none are.
+    ///
+    /// # Arguments
+    ///
+    /// * `ids` - keys of the documents to update
+    /// * `update` - the update to apply
+    pub fn update_ids(
+        &mut self,
+        ids: &[usize],
+        update: &Update,
+    ) -> Result<UpdateResult, DatastoreError> {
+        let mut matched = 0;
+        let mut changed = Vec::new();
+        for id in ids.iter() {
+            if let Some(document) = self.store.get(id) {
+                matched += 1;
+                let mut updated = document.clone();
+                if update
+                    .apply(&mut updated)
+                    .map_err(DatastoreError::InvalidUpdate)?
+                {
+                    self.validate(&updated)?;
+                    changed.push((*id, updated));
+                }
+            }
+        }
+        let pending: Vec<(usize, Option<&Document>)> = changed
+            .iter()
+            .map(|(id, document)| (*id, Some(document)))
+            .collect();
+        self.check_unique(&pending)?;
+        let mut modified = Vec::new();
+        for (id, document) in changed.into_iter() {
+            self.put(id, document);
+            modified.push(id);
         }
+        Ok(UpdateResult { matched, modified })
     }
 
-    /// Inserts a document into the collection
+    /// Removes every document matching a query, producing their keys
     ///
     /// # Arguments
     ///
-    /// * `value` - A document in the form of a hashmap with string keys
-    ///     and DataType values.
+    /// * `query` - query statement selecting the documents to remove
+    pub fn delete(&mut self, query: &str) -> Result<Vec<usize>, DatastoreError> {
+        let ids = self.find_ids(query)?;
+        Ok(ids
+            .into_iter()
+            .filter(|id| self.remove(*id).is_some())
+            .collect())
+    }
+
+    /// Produces the store and indexes a Query runs against
+    pub fn indexes(&self) -> Indexes<'_> {
+        Indexes {
+            store: &self.store,
+            indices: &self.indices,
+            compound_indices: &self.compound_indices,
+            text_index: self.text_index.as_ref(),
+        }
+    }
+
+    /// Produces the keys of the documents matching a Query and how they
+    /// were found
+    ///
+    /// # Arguments
+    ///
+    /// * `query` - a query string, JSON filter or filter document parsed
+    ///   into a Query
     ///
     /// # Examples
     ///
     /// ```rust
-    /// use collection::Collection;
+    /// use rockumentdb::datastore::collection::Collection;
+    /// use rockumentdb::datastore::query_proc::Query;
+    /// use serde_json::json;
     ///
-    /// let mut collection = Collection::new(String::from("users"));
+    /// let collection = Collection::new(String::from("users"));
+    /// let query = Query::from_json(&json!({"username": {"$in": ["johnperry", "louiswu"]}}));
+    /// let matches = collection.run(&query.unwrap()).unwrap();
+    /// assert!(matches.ids.is_empty());
+    /// assert_eq!("COLLECTION_SCAN", matches.explain.stages[0].stage);
+    /// ```
+    pub fn run(&self, query: &Query) -> Result<Matches, DatastoreError> {
+        query.run(self.indexes())
+    }
+
+    /// Produces the documents stored under keys, in the order given
     ///
-    /// let mut document = HashMap::new();
-    /// document.insert(
-    ///     String::from("username"),
-    ///     DataType::String(String::from("johnperry")),
-    /// );
-    /// document.insert(String::from("age"), DataType::Usize(75usize));
-    /// document.insert(String::from("active"), DataType::Bool(true));
+    /// # Arguments
     ///
-    /// let key = collection.insert(document);
+    /// * `ids` - keys of documents in the collection
+    pub fn documents(&self, ids: &[usize]) -> QueryResult<'_> {
+        ids.iter()
+            .map(|id| self.store.get(id).ok_or(DatastoreError::InvalidId))
+            .collect()
+    }
+
+    /// Produces the keys of the documents matching a query
+    ///
+    /// # Arguments
+    ///
+    /// * `query` - query statement
+    pub fn find_ids(&self, query: &str) -> Result<Vec<usize>, DatastoreError> {
+        Ok(self.run(&Query::parse(query)?)?.ids)
+    }
+
+    /// Produces the keys, in order, of the documents inserted after a key
+    /// that match a Filter, reading only those documents
+    ///
+    /// # Arguments
+    ///
+    /// * `filter` - the Filter the documents must match
+    /// * `after` - the key the documents were inserted after
+    pub fn find_ids_after(&self, filter: &Filter, after: usize) -> Vec<usize> {
+        self.store
+            .range(after.saturating_add(1)..)
+            .filter(|(_, document)| filter.matches(document))
+            .map(|(key, _)| *key)
+            .collect()
+    }
+
+    /// Produces the plan chosen for a query and the number of documents
+    /// each of its stages examined and returned
+    ///
+    /// # Arguments
+    ///
+    /// * `query` - query statement
+    ///
+    /// # Examples
+    ///
+    /// ```rust
+    /// use rockumentdb::datastore::collection::Collection;
+    ///
+    /// let collection = Collection::new(String::from("users"));
+    /// let explain = collection.explain("{username:\"johnperry\"}").unwrap();
+    /// assert_eq!("COLLECTION_SCAN", explain.stages[0].stage);
     /// ```
-    pub fn insert(&mut self, value: HashMap<String, DataType>) -> usize {
-        self.last_key += 1;
-        self.store.insert(self.last_key, value);
-        self.last_key
-        // TODO: Implement Index management functions
+    pub fn explain(&self, query: &str) -> Result<Explain, DatastoreError> {
+        Ok(self.run(&Query::parse(query)?)?.explain)
     }
 
     /// Produces the results of a query against the collection
@@ -76,7 +1150,9 @@ impl Collection {
     /// # Examples
     ///
     /// ```rust
-    /// use collection::Collection;
+    /// use rockumentdb::datastore::collection::Collection;
+    /// use rockumentdb::datastore::datatypes::DataType;
+    /// use std::collections::HashMap;
     ///
     /// let mut collection = Collection::new(String::from("users"));
     ///
@@ -85,21 +1161,123 @@ impl Collection {
     ///     String::from("username"),
     ///     DataType::String(String::from("johnperry")),
     /// );
-    /// document.insert(String::from("age"), DataType::Usize(75usize));
+    /// document.insert(String::from("age"), DataType::U64(75u64));
     /// document.insert(String::from("active"), DataType::Bool(true));
     ///
     /// let key = collection.insert(document);
     ///
     /// let results = collection.find("{username:\"johnperry\"}");
     /// ```
-    pub fn find(&self, query: &String) -> QueryResult {
-        query_proc::process_query(query, &self.store, &self.indices)
+    pub fn find(&self, query: &str) -> QueryResult<'_> {
+        self.documents(&self.find_ids(query)?)
+    }
+}
+
+/// A panic part way through a write can leave the indexes out of step with
+/// the stored documents, so they are built again from the store
+impl Recover for Collection {
+    fn recover(&mut self) {
+        for index in self.indices.values_mut() {
+            index.tree.clear();
+        }
+        for index in self.compound_indices.iter_mut() {
+            index.tree.clear();
+        }
+        for (field, index) in self.ttl_indices.iter_mut() {
+            *index = TtlIndex::new(field, index.expire_after_seconds);
+        }
+        if let Some(index) = self.text_index.as_mut() {
+            *index = TextIndex::new(index.fields.clone());
+        }
+        let documents = std::mem::take(&mut self.store);
+        for (key, document) in documents.iter() {
+            self.index_document(*key, document);
+        }
+        self.store = documents;
+        if self.cap.is_some() {
+            self.bytes = self.store.values().map(capped::document_size).sum();
+        }
+        if let Some(&newest) = self.store.keys().next_back() {
+            self.last_key = self.last_key.max(newest);
+        }
+    }
+}
+
+/// Produces a document's values for a list of fields, or None if it does
+/// not have all of them
+fn key_values<'a>(fields: &[String], document: &'a Document) -> Option<Vec<&'a DataType>> {
+    fields
+        .iter()
+        .map(|field| field_value(document, field))
+        .collect()
+}
+
+/// Produces the value of a field of a document. A dotted path such as
+/// `address.city` reaches into embedded documents, unless the document has
+/// a field with the dotted name itself.
+///
+/// # Arguments
+///
+/// * `document` - the document to read
+/// * `path` - the field's name or dotted path
+///
+/// # Examples
+///
+/// ```rust
+/// use rockumentdb::datastore::collection::field_value;
+/// use rockumentdb::datastore::datatypes::{from_json, DataType};
+/// use serde_json::json;
+/// use std::collections::HashMap;
+///
+/// let mut document = HashMap::new();
+/// document.insert(String::from("address"), from_json(&json!({"city": "Paris"})));
+/// assert_eq!(
+///     Some(&DataType::String(String::from("Paris"))),
+///     field_value(&document, "address.city")
+/// );
+/// ```
+pub fn field_value<'a>(document: &'a Document, path: &str) -> Option<&'a DataType> {
+    if let Some(value) = document.get(path) {
+        return Some(value);
+    }
+    let (field, rest) = path.split_once('.')?;
+    embedded_value(document.get(field)?, rest)
+}
+
+fn embedded_value<'a>(value: &'a DataType, path: &str) -> Option<&'a DataType> {
+    let fields = match value {
+        DataType::Document(fields) => fields,
+        _ => return None,
+    };
+    if let Some(value) = fields.get(path) {
+        return Some(value);
+    }
+    let (field, rest) = path.split_once('.')?;
+    embedded_value(fields.get(field)?, rest)
+}
+
+fn duplicate_key(fields: &[String], values: &[&DataType]) -> DatastoreError {
+    DatastoreError::DuplicateKey {
+        index: index::index_name(fields),
+        key: fields
+            .iter()
+            .cloned()
+            .zip(values.iter().map(|value| (*value).clone()))
+            .collect(),
     }
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::datastore::changes;
+    use crate::datastore::database::{self, Database};
+    use crate::datastore::datatypes;
+    use crate::datastore::query_proc::Filter;
+    use crate::datastore::schema::{Schema, Violation};
+    use crate::datastore::update::Operation;
+    use serde_json::json;
+    use std::convert::TryFrom;
 
     #[test]
     fn create_a_new_collection() {
@@ -116,7 +1294,7 @@ mod tests {
         );
         document.insert(String::from("age"), DataType::U64(75u64));
         document.insert(String::from("active"), DataType::Bool(true));
-        let key = collection.insert(document);
+        let key = collection.insert(document).unwrap();
         assert_eq!(1usize, key)
     }
 
@@ -131,8 +1309,8 @@ mod tests {
         document.insert(String::from("age"), DataType::U64(75u64));
         document.insert(String::from("active"), DataType::Bool(true));
         let document2 = document.clone();
-        collection.insert(document);
-        assert_eq!(2usize, collection.insert(document2))
+        collection.insert(document).unwrap();
+        assert_eq!(Ok(2usize), collection.insert(document2))
     }
 
     #[test]
@@ -145,10 +1323,10 @@ mod tests {
         );
         document.insert(String::from("age"), DataType::U64(75u64));
         document.insert(String::from("active"), DataType::Bool(true));
-        collection.insert(document.clone());
+        collection.insert(document.clone()).unwrap();
         let results = match collection.find(&String::from("{username:\"johnperry\"}")) {
-            QueryResult::Data(data) => data,
-            _ => {
+            Ok(data) => data,
+            Err(_) => {
                 println!("InvalidCommand");
                 Vec::new()
             }
@@ -156,4 +1334,677 @@ mod tests {
         println!("Results {:?}", &results);
         assert_eq!(&document, results[0])
     }
+
+    fn john() -> Document {
+        let mut document = HashMap::new();
+        document.insert(
+            String::from("username"),
+            DataType::String(String::from("johnperry")),
+        );
+        document.insert(String::from("age"), DataType::U64(75u64));
+        document
+    }
+
+    #[test]
+    fn update_matching_documents() {
+        let mut collection = Collection::new(String::from("users"));
+        let key = collection.insert(john()).unwrap();
+        let update = Update::Operators(vec![Operation::Set(
+            String::from("age"),
+            DataType::U64(76u64),
+        )]);
+        let result = collection
+            .update("{username:\"johnperry\"}", &update)
+            .unwrap();
+        assert_eq!(
+            UpdateResult {
+                matched: 1,
+                modified: vec![key]
+            },
+            result
+        );
+        assert_eq!(
+            Some(&DataType::U64(76u64)),
+            collection.get(key).unwrap().get("age")
+        );
+    }
+
+    #[test]
+    fn recover_rebuilds_indexes() {
+        let mut collection = Collection::new(String::from("users"));
+        collection.create_index("username", false).unwrap();
+        let key = collection.insert(john()).unwrap();
+        collection.indices.get_mut("username").unwrap().tree.clear();
+        collection.recover();
+        assert_eq!(
+            Ok(vec![key]),
+            collection.find_ids("{username:\"johnperry\"}")
+        );
+    }
+
+    #[test]
+    fn update_is_all_or_nothing() {
+        let mut collection = Collection::new(String::from("users"));
+        let first = collection.insert(john()).unwrap();
+        let mut louis = john();
+        louis.insert(String::from("age"), DataType::String(String::from("old")));
+        let second = collection.insert(louis.clone()).unwrap();
+        let update = Update::Operators(vec![Operation::Inc(
+            String::from("age"),
+            DataType::U64(1u64),
+        )]);
+        assert!(collection.update_ids(&[first, second], &update).is_err());
+        assert_eq!(Some(&john()), collection.get(first));
+        assert_eq!(Some(&louis), collection.get(second));
+    }
+
+    #[test]
+    fn delete_matching_documents() {
+        let mut collection = Collection::new(String::from("users"));
+        let key = collection.insert(john()).unwrap();
+        assert_eq!(Ok(vec![key]), collection.delete("{username:\"johnperry\"}"));
+        assert_eq!(None, collection.get(key));
+    }
+
+    fn age_validator(action: ValidationAction) -> Option<Validator> {
+        let schema = serde_json::json!({
+            "properties": {"age": {"type": "integer", "maximum": 150}}
+        });
+        Some(Validator {
+            schema: Schema::from_json(&schema).unwrap(),
+            action,
+        })
+    }
+
+    #[test]
+    fn insert_invalid_document() {
+        let mut collection = Collection::new(String::from("users"));
+        collection.set_validator(age_validator(ValidationAction::Error));
+        let mut document = john();
+        document.insert(String::from("age"), DataType::U64(200u64));
+        assert_eq!(
+            Err(DatastoreError::Validation(vec![Violation {
+                field: String::from("age"),
+                message: String::from("must be less than or equal to 150")
+            }])),
+            collection.insert(document)
+        );
+        assert_eq!(Ok(1usize), collection.insert(john()));
+    }
+
+    #[test]
+    fn insert_invalid_document_with_warning() {
+        let mut collection = Collection::new(String::from("users"));
+        collection.set_validator(age_validator(ValidationAction::Warn));
+        let mut document = john();
+        document.insert(String::from("age"), DataType::U64(200u64));
+        assert_eq!(Ok(1usize), collection.insert(document));
+    }
+
+    #[test]
+    fn update_to_invalid_document() {
+        let mut collection = Collection::new(String::from("users"));
+        collection.set_validator(age_validator(ValidationAction::Error));
+        let key = collection.insert(john()).unwrap();
+        let update = Update::Operators(vec![Operation::Inc(
+            String::from("age"),
+            DataType::U64(100u64),
+        )]);
+        assert!(matches!(
+            collection.update_ids(&[key], &update),
+            Err(DatastoreError::Validation(_))
+        ));
+        assert_eq!(Some(&john()), collection.get(key));
+    }
+
+    fn with_email(username: &str, email: &str) -> Document {
+        let mut document = HashMap::new();
+        document.insert(
+            String::from("username"),
+            DataType::String(String::from(username)),
+        );
+        document.insert(String::from("email"), DataType::String(String::from(email)));
+        document
+    }
+
+    fn duplicate_email(email: &str) -> DatastoreError {
+        DatastoreError::DuplicateKey {
+            index: String::from("email_1"),
+            key: vec![(String::from("email"), DataType::String(String::from(email)))],
+        }
+    }
+
+    #[test]
+    fn insert_maintains_indices() {
+        let mut collection = Collection::new(String::from("users"));
+        collection.create_index("username", false).unwrap();
+        let key = collection.insert(john()).unwrap();
+        let username = DataType::String(String::from("johnperry"));
+        assert_eq!(
+            Some(&vec![key]),
+            collection
+                .indices()
+                .get("username")
+                .unwrap()
+                .search(&username)
+        );
+        collection.remove(key);
+        assert_eq!(
+            None,
+            collection
+                .indices()
+                .get("username")
+                .unwrap()
+                .search(&username)
+        );
+    }
+
+    #[test]
+    fn insert_duplicate_unique_value() {
+        let mut collection = Collection::new(String::from("users"));
+        collection.create_index("email", true).unwrap();
+        collection
+            .insert(with_email("johnperry", "john@example.com"))
+            .unwrap();
+        assert_eq!(
+            Err(duplicate_email("john@example.com")),
+            collection.insert(with_email("jperry", "john@example.com"))
+        );
+        assert_eq!(Ok(2usize), collection.insert(john()));
+    }
+
+    #[test]
+    fn update_to_duplicate_unique_value() {
+        let mut collection = Collection::new(String::from("users"));
+        collection.create_index("email", true).unwrap();
+        collection
+            .insert(with_email("johnperry", "john@example.com"))
+            .unwrap();
+        collection
+            .insert(with_email("louiswu", "louis@example.com"))
+            .unwrap();
+        let update = Update::Operators(vec![Operation::Set(
+            String::from("email"),
+            DataType::String(String::from("john@example.com")),
+        )]);
+        assert_eq!(
+            Err(duplicate_email("john@example.com")),
+            collection.update("{username:\"louiswu\"}", &update)
+        );
+        // A document may keep its own value
+        assert!(collection
+            .update("{username:\"johnperry\"}", &update)
+            .is_ok());
+    }
+
+    #[test]
+    fn create_unique_index_over_duplicates() {
+        let mut collection = Collection::new(String::from("users"));
+        collection
+            .insert(with_email("johnperry", "john@example.com"))
+            .unwrap();
+        collection
+            .insert(with_email("jperry", "john@example.com"))
+            .unwrap();
+        assert_eq!(
+            Err(duplicate_email("john@example.com")),
+            collection.create_index("email", true)
+        );
+        assert!(collection.indices().get("email").is_none());
+        assert!(collection.create_index("email", false).is_ok());
+    }
+
+    fn ticket(tenant: &str, status: &str, priority: u64) -> Document {
+        let mut document = HashMap::new();
+        document.insert(
+            String::from("tenant"),
+            DataType::String(String::from(tenant)),
+        );
+        document.insert(
+            String::from("status"),
+            DataType::String(String::from(status)),
+        );
+        document.insert(String::from("priority"), DataType::U64(priority));
+        document
+    }
+
+    fn tickets() -> Collection {
+        let mut collection = Collection::new(String::from("tickets"));
+        collection
+            .create_compound_index(
+                vec![
+                    String::from("tenant"),
+                    String::from("status"),
+                    String::from("priority"),
+                ],
+                false,
+            )
+            .unwrap();
+        collection.insert(ticket("a", "open", 1)).unwrap();
+        collection.insert(ticket("a", "closed", 2)).unwrap();
+        collection.insert(ticket("b", "open", 3)).unwrap();
+        collection.insert(ticket("a", "open", 4)).unwrap();
+        collection
+    }
+
+    #[test]
+    fn find_with_compound_prefix() {
+        let collection = tickets();
+        assert_eq!(
+            Ok(vec![1usize, 4]),
+            collection.find_ids("{tenant:\"a\", status:\"open\"}")
+        );
+    }
+
+    #[test]
+    fn find_with_compound_range() {
+        let mut collection = tickets();
+        collection.remove(1);
+        assert_eq!(
+            Ok(vec![4usize]),
+            collection.find_ids("{status:\"open\", tenant:\"a\", priority: {$gte: 1}}")
+        );
+        assert_eq!(
+            Ok(vec![3usize]),
+            collection.find_ids("{tenant:\"b\", status:\"open\", priority: {$gt: 2, $lt: 4}}")
+        );
+    }
+
+    #[test]
+    fn find_with_range_only() {
+        let collection = tickets();
+        assert_eq!(
+            Ok(vec![2usize, 3]),
+            collection.find_ids("{priority: {$gt: 1, $lte: 3}}")
+        );
+    }
+
+    #[test]
+    fn unique_compound_index() {
+        let mut collection = tickets();
+        assert!(collection
+            .create_compound_index(vec![String::from("tenant"), String::from("status")], true)
+            .is_err());
+        collection
+            .create_compound_index(vec![String::from("tenant"), String::from("priority")], true)
+            .unwrap();
+        assert_eq!(
+            Err(DatastoreError::DuplicateKey {
+                index: String::from("tenant_1_priority_1"),
+                key: vec![
+                    (String::from("tenant"), DataType::String(String::from("a"))),
+                    (String::from("priority"), DataType::U64(4u64)),
+                ],
+            }),
+            collection.insert(ticket("a", "closed", 4))
+        );
+        assert!(collection.insert(ticket("b", "closed", 4)).is_ok());
+    }
+
+    #[test]
+    fn explain_index_lookup() {
+        let mut collection = tickets();
+        collection.create_index("status", false).unwrap();
+        collection.insert(ticket("c", "open", 5)).unwrap();
+        let explain = collection
+            .explain("{status:\"closed\", priority: {$gt: 1}}")
+            .unwrap();
+        let stages: Vec<(&str, usize, usize)> = explain
+            .stages
+            .iter()
+            .map(|stage| (stage.stage, stage.examined, stage.returned))
+            .collect();
+        assert_eq!(vec![("INDEX_LOOKUP", 1, 1), ("FILTER", 1, 1)], stages);
+        assert_eq!(1, explain.returned);
+    }
+
+    #[test]
+    fn explain_compound_scan() {
+        let collection = tickets();
+        let explain = collection
+            .explain("{tenant:\"a\", status:\"open\"}")
+            .unwrap();
+        assert_eq!(1, explain.stages.len());
+        assert_eq!("COMPOUND_INDEX_SCAN", explain.stages[0].stage);
+        assert_eq!(
+            Some(String::from("tenant_1_status_1_priority_1")),
+            explain.stages[0].index
+        );
+        assert_eq!(2, explain.returned);
+    }
+
+    #[test]
+    fn keep_history_for_open_snapshots() {
+        let db = Database::new();
+        let users = db.get_or_create("users");
+        let mut collection = database::write(&users);
+        let key = collection.insert(john()).unwrap();
+        let (snapshot, _) = db.snapshot();
+        let sequence = snapshot.sequence();
+        assert!(!collection.written_since(key, sequence));
+        collection.remove(key);
+        let inserted = collection.insert(john()).unwrap();
+        assert!(collection.written_since(key, sequence));
+        assert_eq!(Some(&john()), collection.get_at(key, sequence));
+        assert_eq!(None, collection.get_at(inserted, sequence));
+        assert_eq!(
+            vec![key, inserted],
+            collection
+                .keys_written_since(sequence)
+                .into_iter()
+                .collect::<Vec<_>>()
+        );
+
+        // Nothing is kept once no snapshot is open, however many
+        // documents are evicted
+        drop(snapshot);
+        collection.set_cap(Some(Cap {
+            max_bytes: None,
+            max_documents: Some(1),
+        }));
+        for _ in 0..100 {
+            collection.insert(john()).unwrap();
+        }
+        assert!(collection.history.is_empty());
+    }
+
+    #[test]
+    fn read_documents_in_pages() {
+        let mut collection = Collection::new(String::from("users"));
+        let keys: Vec<usize> = (0..5).map(|_| collection.insert(john()).unwrap()).collect();
+        collection.remove(keys[1]);
+        let page = collection.documents_after(0, 2);
+        assert_eq!(
+            vec![keys[0], keys[2]],
+            page.iter().map(|(key, _)| *key).collect::<Vec<_>>()
+        );
+        let page = collection.documents_after(keys[2], 10);
+        assert_eq!(
+            vec![keys[3], keys[4]],
+            page.iter().map(|(key, _)| *key).collect::<Vec<_>>()
+        );
+        assert!(collection.documents_after(keys[4], 10).is_empty());
+    }
+
+    #[test]
+    fn find_by_embedded_field() {
+        let mut collection = Collection::new(String::from("users"));
+        for city in ["Paris", "Ohio"] {
+            let mut document = john();
+            document.insert(
+                String::from("address"),
+                datatypes::from_json(&serde_json::json!({ "city": city })),
+            );
+            collection.insert(document).unwrap();
+        }
+        collection.create_index("address.city", true).unwrap();
+        assert_eq!(
+            1,
+            collection.find("{address.city: \"Ohio\"}").unwrap().len()
+        );
+        let explain = collection.explain("{address.city: \"Ohio\"}").unwrap();
+        assert_eq!(
+            "INDEX_LOOKUP",
+            serde_json::to_value(&explain).unwrap()["stages"][0]["stage"]
+        );
+        let mut duplicate = john();
+        duplicate.insert(
+            String::from("address"),
+            datatypes::from_json(&serde_json::json!({"city": "Paris"})),
+        );
+        assert!(matches!(
+            collection.insert(duplicate),
+            Err(DatastoreError::DuplicateKey { .. })
+        ));
+    }
+
+    #[test]
+    fn record_change_events() {
+        let mut collection = Collection::new(String::from("users"));
+        let key = collection.insert(john()).unwrap();
+        let token = collection.version();
+        let update =
+            Update::Operators(vec![Operation::Set(String::from("age"), DataType::I64(76))]);
+        collection
+            .update("{username: \"johnperry\"}", &update)
+            .unwrap();
+        collection.remove(key);
+        let changes = collection.changes_since(token).unwrap();
+        assert_eq!(
+            vec![
+                (changes::Operation::Update, key),
+                (changes::Operation::Delete, key)
+            ],
+            changes
+                .iter()
+                .map(|event| (event.operation, event.key))
+                .collect::<Vec<_>>()
+        );
+        assert_eq!(Some(&DataType::I64(76)), changes[0].document.get("age"));
+        assert_eq!(3, changes[1].token);
+        assert_eq!(
+            Err(DatastoreError::ChangeHistoryLost(4)),
+            collection.changes_since(4)
+        );
+        assert!(collection.clone().changes_since(3).unwrap().is_empty());
+    }
+
+    #[test]
+    fn search_text_index() {
+        let mut collection = Collection::new(String::from("books"));
+        let book = |title: &str, year: u64| {
+            let mut document = HashMap::new();
+            document.insert(String::from("title"), DataType::String(String::from(title)));
+            document.insert(String::from("year"), DataType::U64(year));
+            document
+        };
+        let search = "{$text: {$search: \"ringworld\"}}";
+        assert!(collection.find_ids(search).is_err());
+
+        let first = collection.insert(book("Ringworld", 1970)).unwrap();
+        let second = collection
+            .insert(book("The Ringworld Engineers", 1980))
+            .unwrap();
+        collection.create_text_index(vec![String::from("title")]);
+        let third = collection
+            .insert(book("The Ringworld Throne", 1996))
+            .unwrap();
+        assert_eq!(Ok(vec![first, second, third]), collection.find_ids(search));
+        assert_eq!(
+            Ok(vec![second, third]),
+            collection
+                .run(
+                    &Query::from_json(
+                        &json!({"$text": {"$search": "Ringworlds"}, "year": {"$gt": 1975}})
+                    )
+                    .unwrap()
+                )
+                .map(|matches| matches.ids)
+        );
+        let explain = collection
+            .explain("{$text: {$search: \"engineer\"}, year: 1980}")
+            .unwrap();
+        let stages: Vec<&str> = explain.stages.iter().map(|stage| stage.stage).collect();
+        assert_eq!(vec!["TEXT_SEARCH", "FILTER"], stages);
+
+        // Updates and deletes keep the index consistent
+        let update = Update::Operators(vec![Operation::Set(
+            String::from("title"),
+            DataType::String(String::from("Protector")),
+        )]);
+        collection.update_ids(&[first], &update).unwrap();
+        collection.remove(third);
+        assert_eq!(Ok(vec![second]), collection.find_ids(search));
+        let matches = collection
+            .run(&Query::parse("{$text: {$search: \"protector engineers\"}}").unwrap())
+            .unwrap();
+        assert_eq!(2, matches.scores.len());
+        let matches = collection
+            .run(&Query::parse("{$text: {$search: \"protector engineers\"}, year: 1970}").unwrap())
+            .unwrap();
+        assert_eq!(
+            vec![first],
+            matches.scores.keys().copied().collect::<Vec<_>>()
+        );
+        let query = Query::from_json(&json!({"$text": {"$search": "ringworld"}})).unwrap();
+        assert!(Filter::try_from(query).is_err());
+
+        assert!(collection.drop_text_index());
+        assert!(collection.find_ids(search).is_err());
+    }
+
+    #[test]
+    fn find_ids_after_a_key() {
+        let mut collection = Collection::new(String::from("audit"));
+        let first = collection.insert(john()).unwrap();
+        let mut louis = john();
+        louis.insert(String::from("age"), DataType::U64(200u64));
+        let second = collection.insert(louis).unwrap();
+        let third = collection.insert(john()).unwrap();
+        let to_filter = |value: Value| Filter::try_from(Query::from_json(&value).unwrap()).unwrap();
+        let filter = to_filter(json!({"age": 75}));
+        assert_eq!(vec![first, third], collection.find_ids_after(&filter, 0));
+        assert_eq!(vec![third], collection.find_ids_after(&filter, first));
+        assert!(collection.find_ids_after(&filter, third).is_empty());
+        assert_eq!(
+            vec![second, third],
+            collection.find_ids_after(&to_filter(json!({})), first)
+        );
+    }
+
+    #[test]
+    fn evict_oldest_documents() {
+        let mut collection = Collection::new(String::from("audit"));
+        let first = collection.insert(john()).unwrap();
+        let size = capped::document_size(&john());
+        collection.set_cap(Some(Cap {
+            max_bytes: Some(3 * size),
+            max_documents: Some(4),
+        }));
+        collection.create_index("username", false).unwrap();
+        let keys: Vec<usize> = (0..3).map(|_| collection.insert(john()).unwrap()).collect();
+        assert_eq!(3, collection.len());
+        assert!(collection.get(first).is_none());
+        assert_eq!(first, collection.evicted_through());
+        assert_eq!(
+            keys,
+            collection.find_ids("{username:\"johnperry\"}").unwrap()
+        );
+
+        // A larger document evicts as many as it takes, but never itself
+        let token = collection.version();
+        let mut large = john();
+        large.insert(
+            String::from("notes"),
+            DataType::String("x".repeat(4 * size)),
+        );
+        let newest = collection.insert(large).unwrap();
+        assert_eq!(
+            vec![newest],
+            collection.find_ids("{username:\"johnperry\"}").unwrap()
+        );
+        let evicted: Vec<usize> = collection
+            .changes_since(token)
+            .unwrap()
+            .iter()
+            .filter(|change| change.operation == changes::Operation::Delete)
+            .map(|change| change.key)
+            .collect();
+        assert_eq!(keys, evicted);
+
+        collection.set_cap(None);
+        collection.insert(john()).unwrap();
+        collection.insert(john()).unwrap();
+        assert_eq!(3, collection.len());
+    }
+
+    #[test]
+    fn parse_capped_options() {
+        let options = CollectionOptions::from_json(&json!({"capped": true, "size": 4096})).unwrap();
+        assert_eq!(
+            Some(Some(Cap {
+                max_bytes: Some(4096),
+                max_documents: None
+            })),
+            options.cap
+        );
+        let options = CollectionOptions::from_json(&json!({"capped": false})).unwrap();
+        assert_eq!(Some(None), options.cap);
+        for invalid in [
+            json!({"capped": true}),
+            json!({"capped": true, "max": 0}),
+            json!({"capped": "yes", "max": 1}),
+            json!({"max": 10}),
+        ] {
+            assert!(CollectionOptions::from_json(&invalid).is_err());
+        }
+    }
+
+    #[test]
+    fn expire_by_collection_option() {
+        let options = json!({"expireAfterSeconds": 10, "timeField": "created"});
+        let mut collection = Collection::new(String::from("sessions"));
+        collection.create_index("created", true).unwrap();
+        collection
+            .set_options(CollectionOptions::from_json(&options).unwrap())
+            .unwrap();
+        assert_eq!(Some(10), collection.expire_after("created"));
+        assert!(collection.indices()["created"].unique);
+
+        let mut session = john();
+        session.insert(String::from("created"), DataType::DateTime(1_000));
+        let key = collection.insert(session).unwrap();
+        assert!(collection.expire(10_999).is_empty());
+        assert_eq!(vec![key], collection.expire(11_000));
+
+        for invalid in [
+            json!({"expireAfterSeconds": 10}),
+            json!({"expireAfterSeconds": -1, "timeField": "created"}),
+            json!({"expireAfterSeconds": 10, "timeField": 1}),
+            json!({"timeField": "created"}),
+        ] {
+            assert!(CollectionOptions::from_json(&invalid).is_err());
+        }
+    }
+
+    #[test]
+    fn expire_with_ttl_index() {
+        let mut collection = Collection::new(String::from("sessions"));
+        let mut session = john();
+        session.insert(String::from("created"), DataType::DateTime(1_000));
+        let first = collection.insert(session.clone()).unwrap();
+        collection.create_ttl_index("created", false, 10).unwrap();
+        session.insert(String::from("created"), DataType::DateTime(5_000));
+        let second = collection.insert(session).unwrap();
+        collection.insert(john()).unwrap();
+
+        assert!(collection.expire(10_999).is_empty());
+        let token = collection.version();
+        assert_eq!(vec![first], collection.expire(11_000));
+        assert_eq!(
+            changes::Operation::Delete,
+            collection.changes_since(token).unwrap()[0].operation
+        );
+        assert_eq!(
+            1,
+            collection
+                .find("{created: {$lt: {$date: 6000}}}")
+                .unwrap()
+                .len()
+        );
+
+        // Moving the date forward postpones the expiry
+        let update = Update::Operators(vec![Operation::Set(
+            String::from("created"),
+            DataType::DateTime(20_000),
+        )]);
+        collection.update_ids(&[second], &update).unwrap();
+        assert!(collection.expire(15_000).is_empty());
+        assert_eq!(vec![second], collection.expire(30_000));
+        assert_eq!(1, collection.len());
+
+        collection.create_index("created", false).unwrap();
+        assert_eq!(None, collection.expire_after("created"));
+        assert!(!collection.expires());
+    }
 }
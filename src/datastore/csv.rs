@@ -0,0 +1,385 @@
+use crate::datastore::collection::Document;
+use crate::datastore::datatypes::{self, DataType, Float};
+use std::collections::{BTreeMap, HashMap};
+
+/// A CSV record and the line of the text it starts on
+#[derive(Debug, PartialEq)]
+pub struct Record {
+    pub line: usize,
+    pub fields: Vec<String>,
+}
+
+/// Produces the records of RFC 4180 CSV text. Fields may be quoted with
+/// `"`, quoted fields may hold commas, newlines and `""` for a quote.
+/// Lines end with `\n` or `\r\n`; blank lines are skipped.
+///
+/// # Arguments
+///
+/// * `text` - the CSV text
+///
+/// # Examples
+///
+/// ```rust
+/// use rockumentdb::datastore::csv;
+/// let records = csv::parse("name,quote\njohn,\"a \"\"b\"\", c\"\n").unwrap();
+/// assert_eq!(vec!["john", "a \"b\", c"], records[1].fields);
+/// ```
+pub fn parse(text: &str) -> Result<Vec<Record>, String> {
+    let mut records = Vec::new();
+    let mut fields = Vec::new();
+    let mut field = String::new();
+    let (mut line, mut record_line) = (1, 1);
+    let (mut quoted, mut was_quoted) = (false, false);
+    let mut characters = text.chars().peekable();
+    while let Some(character) = characters.next() {
+        if quoted {
+            match character {
+                '"' if characters.peek() == Some(&'"') => {
+                    characters.next();
+                    field.push('"');
+                }
+                '"' => quoted = false,
+                '\n' => {
+                    line += 1;
+                    field.push(character);
+                }
+                _ => field.push(character),
+            }
+            continue;
+        }
+        match character {
+            '"' if field.is_empty() && !was_quoted => {
+                quoted = true;
+                was_quoted = true;
+            }
+            '"' => return Err(format!("line {}: unexpected quote", line)),
+            ',' => {
+                fields.push(std::mem::take(&mut field));
+                was_quoted = false;
+            }
+            '\r' if characters.peek() == Some(&'\n') => {}
+            '\n' => {
+                if !fields.is_empty() || !field.is_empty() || was_quoted {
+                    fields.push(std::mem::take(&mut field));
+                    records.push(Record {
+                        line: record_line,
+                        fields: std::mem::take(&mut fields),
+                    });
+                }
+                was_quoted = false;
+                line += 1;
+                record_line = line;
+            }
+            _ if was_quoted => {
+                return Err(format!("line {}: text after a quoted field", line));
+            }
+            _ => field.push(character),
+        }
+    }
+    if quoted {
+        return Err(format!("line {}: unterminated quoted field", record_line));
+    }
+    if !fields.is_empty() || !field.is_empty() || was_quoted {
+        fields.push(field);
+        records.push(Record {
+            line: record_line,
+            fields,
+        });
+    }
+    Ok(records)
+}
+
+/// Produces a CSV line of fields, quoting those that need it
+///
+/// # Arguments
+///
+/// * `fields` - the fields of the line
+pub fn write_record(fields: &[String]) -> String {
+    let quoted: Vec<String> = fields
+        .iter()
+        .map(|field| {
+            if field.contains([',', '"', '\n', '\r']) {
+                format!("\"{}\"", field.replace('"', "\"\""))
+            } else {
+                field.clone()
+            }
+        })
+        .collect();
+    format!("{}\n", quoted.join(","))
+}
+
+/// Produces the paths of a header row, split at dots. Headers must be
+/// distinct and no header may be a prefix path of another, e.g. `address`
+/// and `address.city`.
+///
+/// # Arguments
+///
+/// * `headers` - the header row
+pub fn header_paths(headers: &[String]) -> Result<Vec<Vec<String>>, String> {
+    let paths: Vec<Vec<String>> = headers
+        .iter()
+        .map(|header| header.split('.').map(String::from).collect())
+        .collect();
+    for (position, path) in paths.iter().enumerate() {
+        if path.iter().any(|segment| segment.is_empty()) {
+            return Err(format!(
+                "header {:?} has an empty field name",
+                headers[position]
+            ));
+        }
+        for (other_position, other) in paths.iter().enumerate() {
+            if position != other_position && other.starts_with(path) {
+                return Err(format!(
+                    "headers {:?} and {:?} conflict",
+                    headers[position], headers[other_position]
+                ));
+            }
+        }
+    }
+    Ok(paths)
+}
+
+/// Produces the Document of a CSV record. Values are strings unless
+/// `infer` is set, see `infer_value`; dotted header paths produce embedded
+/// documents.
+///
+/// # Arguments
+///
+/// * `paths` - the header paths, see `header_paths`
+/// * `record` - the record's fields
+/// * `infer` - whether to infer the values' types
+pub fn to_document(
+    paths: &[Vec<String>],
+    record: &Record,
+    infer: bool,
+) -> Result<Document, String> {
+    if record.fields.len() != paths.len() {
+        return Err(format!(
+            "has {} fields but the header has {}",
+            record.fields.len(),
+            paths.len()
+        ));
+    }
+    let mut document = HashMap::new();
+    for (path, text) in paths.iter().zip(record.fields.iter()) {
+        let value = if infer {
+            infer_value(text)
+        } else {
+            DataType::String(text.clone())
+        };
+        let (field, rest) = path.split_first().unwrap();
+        if rest.is_empty() {
+            document.insert(field.clone(), value);
+            continue;
+        }
+        let embedded = document
+            .entry(field.clone())
+            .or_insert_with(|| DataType::Document(BTreeMap::new()));
+        set_path(embedded, rest, value);
+    }
+    Ok(document)
+}
+
+/// Sets a value at a path of an embedded document, creating the documents
+/// on the way. `header_paths` ensures none of them is already a value.
+fn set_path(embedded: &mut DataType, path: &[String], value: DataType) {
+    if let DataType::Document(fields) = embedded {
+        let (field, rest) = path.split_first().unwrap();
+        if rest.is_empty() {
+            fields.insert(field.clone(), value);
+        } else {
+            let inner = fields
+                .entry(field.clone())
+                .or_insert_with(|| DataType::Document(BTreeMap::new()));
+            set_path(inner, rest, value);
+        }
+    }
+}
+
+/// Produces the DataType a CSV field reads as: `null` and empty fields are
+/// null, `true` and `false` booleans in any case, integers without leading
+/// zeros integers, decimal or scientific numbers floats and anything else a
+/// string. Zero padded numbers such as zip codes stay strings.
+///
+/// # Arguments
+///
+/// * `text` - the field
+///
+/// # Examples
+///
+/// ```rust
+/// use rockumentdb::datastore::csv::infer_value;
+/// use rockumentdb::datastore::datatypes::DataType;
+/// assert_eq!(DataType::I64(-75), infer_value("-75"));
+/// assert_eq!(DataType::String(String::from("007")), infer_value("007"));
+/// ```
+pub fn infer_value(text: &str) -> DataType {
+    if text.is_empty() || text == "null" {
+        return DataType::Null;
+    }
+    if text.eq_ignore_ascii_case("true") {
+        return DataType::Bool(true);
+    }
+    if text.eq_ignore_ascii_case("false") {
+        return DataType::Bool(false);
+    }
+    let digits = text.strip_prefix('-').unwrap_or(text);
+    let numeric = digits.starts_with(|c: char| c.is_ascii_digit())
+        && digits
+            .chars()
+            .all(|c| c.is_ascii_digit() || "eE.+-".contains(c));
+    let zero_padded = digits.len() > 1 && digits.starts_with('0') && !digits.starts_with("0.");
+    if numeric && !zero_padded {
+        if let Ok(integer) = text.parse::<i64>() {
+            return DataType::I64(integer);
+        }
+        if let Ok(integer) = text.parse::<u64>() {
+            return DataType::U64(integer);
+        }
+        if let Ok(float) = text.parse::<f64>() {
+            if float.is_finite() {
+                return DataType::F64(Float::new(float));
+            }
+        }
+    }
+    DataType::String(String::from(text))
+}
+
+/// Produces the CSV field of a value: null is empty, strings are
+/// themselves, date-times ISO-8601, ObjectIds hexadecimal, binary data
+/// base64, and embedded documents and arrays JSON
+///
+/// # Arguments
+///
+/// * `value` - the value, None for a missing field
+pub fn field_text(value: Option<&DataType>) -> String {
+    match value {
+        None | Some(DataType::Null) => String::new(),
+        Some(DataType::Bool(val)) => val.to_string(),
+        Some(DataType::F64(val)) => val.get().to_string(),
+        Some(DataType::I64(val)) => val.to_string(),
+        Some(DataType::U64(val)) => val.to_string(),
+        Some(DataType::Decimal128(val)) => val.to_string(),
+        Some(DataType::String(val)) => val.clone(),
+        Some(DataType::DateTime(val)) => {
+            datatypes::format_iso(*val).unwrap_or_else(|| val.to_string())
+        }
+        Some(DataType::Binary { bytes, .. }) => base64::encode(bytes),
+        Some(DataType::ObjectId(val)) => val.to_string(),
+        Some(value @ DataType::Document(_)) | Some(value @ DataType::Array(_)) => {
+            datatypes::to_json(value).to_string()
+        }
+    }
+}
+
+/// Produces the dotted paths of a document's fields, descending into
+/// embedded documents, in path order
+///
+/// # Arguments
+///
+/// * `document` - the document
+pub fn field_paths(document: &Document) -> Vec<String> {
+    let mut paths = Vec::new();
+    for (field, value) in document.iter() {
+        push_paths(field.clone(), value, &mut paths);
+    }
+    paths.sort();
+    paths
+}
+
+fn push_paths(path: String, value: &DataType, paths: &mut Vec<String>) {
+    match value {
+        DataType::Document(fields) if !fields.is_empty() => {
+            for (field, value) in fields.iter() {
+                push_paths(format!("{}.{}", path, field), value, paths);
+            }
+        }
+        _ => paths.push(path),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parse_quoted_fields() {
+        let records = parse("a,b\r\n\"x,1\",\"multi\nline\"\n\n\"\",\n").unwrap();
+        assert_eq!(
+            vec![
+                Record {
+                    line: 1,
+                    fields: vec![String::from("a"), String::from("b")]
+                },
+                Record {
+                    line: 2,
+                    fields: vec![String::from("x,1"), String::from("multi\nline")]
+                },
+                Record {
+                    line: 5,
+                    fields: vec![String::new(), String::new()]
+                },
+            ],
+            records
+        );
+        assert!(parse("a\n\"open").is_err());
+        assert!(parse("a\nb\"c").is_err());
+    }
+
+    #[test]
+    fn write_and_parse_back() {
+        let fields = vec![
+            String::from("x,1"),
+            String::from("say \"hi\""),
+            String::new(),
+        ];
+        let records = parse(&write_record(&fields)).unwrap();
+        assert_eq!(fields, records[0].fields);
+    }
+
+    #[test]
+    fn infer_types() {
+        assert_eq!(DataType::Null, infer_value(""));
+        assert_eq!(DataType::Bool(true), infer_value("TRUE"));
+        assert_eq!(DataType::U64(u64::MAX), infer_value("18446744073709551615"));
+        assert_eq!(DataType::F64(Float::new(0.5)), infer_value("0.5"));
+        assert_eq!(DataType::F64(Float::new(1500.0)), infer_value("1.5e3"));
+        assert_eq!(DataType::String(String::from("inf")), infer_value("inf"));
+        assert_eq!(DataType::String(String::from("1-2")), infer_value("1-2"));
+        assert_eq!(DataType::I64(0), infer_value("0"));
+    }
+
+    #[test]
+    fn dotted_headers_nest() {
+        let headers = vec![
+            String::from("name"),
+            String::from("address.city"),
+            String::from("address.geo.lat"),
+        ];
+        let paths = header_paths(&headers).unwrap();
+        let record = Record {
+            line: 2,
+            fields: vec![
+                String::from("john"),
+                String::from("Paris"),
+                String::from("48.8"),
+            ],
+        };
+        let document = to_document(&paths, &record, true).unwrap();
+        assert_eq!(
+            serde_json::json!({"city": "Paris", "geo": {"lat": 48.8}}),
+            datatypes::to_json(&document["address"])
+        );
+        assert_eq!(
+            vec!["address.city", "address.geo.lat", "name"],
+            field_paths(&document)
+        );
+        assert!(header_paths(&[String::from("a"), String::from("a.b")]).is_err());
+        assert!(header_paths(&[String::from("a"), String::from("a")]).is_err());
+        let short = Record {
+            line: 3,
+            fields: vec![String::from("john")],
+        };
+        assert!(to_document(&paths, &short, true).is_err());
+    }
+}
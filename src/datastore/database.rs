@@ -0,0 +1,470 @@
+use crate::datastore::collection::Collection;
+use crate::datastore::error::DatastoreError;
+use std::collections::{BTreeMap, HashMap};
+use std::sync::{Arc, Mutex, MutexGuard, RwLock, RwLockReadGuard, RwLockWriteGuard};
+use std::time::Instant;
+
+pub type SafeCollection = Arc<RwLock<Collection>>;
+
+/// Registry of every Collection, each behind its own lock so that readers
+/// of one collection never wait on writers of another. Clones share the
+/// same collections, so that listeners other than Rocket can serve them.
+#[derive(Clone)]
+pub struct Database {
+    collections: Arc<RwLock<HashMap<String, SafeCollection>>>,
+    /// The last key given out under the name of each collection that does
+    /// not exist yet, see `reserve_key`
+    reserved: Arc<Mutex<HashMap<String, usize>>>,
+    clock: Arc<Clock>,
+}
+
+/// The sequence the writes to every collection of a Database are stamped
+/// with, and the points in it open snapshots were taken at. While a
+/// snapshot is open, each collection keeps the documents written since,
+/// as they were before, see `Collection::get_at`.
+#[derive(Default)]
+pub struct Clock {
+    state: Mutex<ClockState>,
+    /// Held shared while a transaction commits and exclusively to take a
+    /// snapshot, so that no snapshot holds only part of a commit
+    commits: RwLock<()>,
+}
+
+#[derive(Default)]
+struct ClockState {
+    /// The sequence of the latest write
+    sequence: u64,
+    /// The sequences open snapshots were taken at, with how many were
+    /// taken at each
+    open: BTreeMap<u64, usize>,
+}
+
+/// A write's place in a Clock's sequence
+pub struct Stamp {
+    pub sequence: u64,
+    /// The sequence the oldest open snapshot was taken at, None if no
+    /// snapshot is open
+    pub oldest: Option<u64>,
+}
+
+impl Clock {
+    /// Produces the next place in the sequence, for a write about to be
+    /// made
+    pub fn stamp(&self) -> Stamp {
+        let mut state = lock(&self.state);
+        state.sequence += 1;
+        Stamp {
+            sequence: state.sequence,
+            oldest: state.open.keys().next().copied(),
+        }
+    }
+}
+
+/// The point in a Database's Clock a snapshot was taken at: it sees every
+/// write stamped up to its sequence and none after. Dropping it lets the
+/// collections discard the documents they kept for it.
+#[derive(Default)]
+pub struct Snapshot {
+    clock: Option<Arc<Clock>>,
+    sequence: u64,
+}
+
+impl Snapshot {
+    /// Produces the sequence of the last write the snapshot sees
+    pub fn sequence(&self) -> u64 {
+        self.sequence
+    }
+}
+
+impl Drop for Snapshot {
+    fn drop(&mut self) {
+        let clock = match &self.clock {
+            Some(clock) => clock,
+            None => return,
+        };
+        let mut state = lock(&clock.state);
+        if let Some(count) = state.open.get_mut(&self.sequence) {
+            *count -= 1;
+            if *count == 0 {
+                state.open.remove(&self.sequence);
+            }
+        }
+    }
+}
+
+impl Database {
+    /// Produces a new, empty Database
+    ///
+    /// # Examples
+    ///
+    /// ```rust
+    /// use rockumentdb::datastore::database::Database;
+    /// let db = Database::new();
+    /// ```
+    pub fn new() -> Database {
+        Database {
+            collections: Arc::new(RwLock::new(HashMap::new())),
+            reserved: Arc::new(Mutex::new(HashMap::new())),
+            clock: Arc::new(Clock::default()),
+        }
+    }
+
+    /// Produces the named collection if it exists
+    ///
+    /// # Arguments
+    ///
+    /// * `name` - the name of the collection
+    pub fn get(&self, name: &str) -> Option<SafeCollection> {
+        read(&self.collections).get(name).cloned()
+    }
+
+    /// Produces the named collection, creating it if it does not exist yet
+    ///
+    /// # Arguments
+    ///
+    /// * `name` - the name of the collection
+    ///
+    /// # Examples
+    ///
+    /// ```rust
+    /// use rockumentdb::datastore::database::{self, Database};
+    /// let db = Database::new();
+    /// let users = db.get_or_create("users");
+    /// let key = database::write(&users).insert(Default::default()).unwrap();
+    /// ```
+    pub fn get_or_create(&self, name: &str) -> SafeCollection {
+        if let Some(collection) = self.get(name) {
+            return collection;
+        }
+        write(&self.collections)
+            .entry(String::from(name))
+            .or_insert_with(|| {
+                let mut collection = Collection::new(String::from(name));
+                collection.set_clock(self.clock.clone());
+                if let Some(last) = lock(&self.reserved).remove(name) {
+                    collection.reserve_keys(last);
+                }
+                Arc::new(RwLock::new(collection))
+            })
+            .clone()
+    }
+
+    /// Produces a key no document of the named collection will be given,
+    /// without storing anything under it. A collection that does not exist
+    /// yet is not created; once it is, its keys start after the ones
+    /// reserved for it.
+    ///
+    /// # Arguments
+    ///
+    /// * `name` - the name of the collection
+    ///
+    /// # Examples
+    ///
+    /// ```rust
+    /// use rockumentdb::datastore::database::{self, Database};
+    /// let db = Database::new();
+    /// assert_eq!(1, db.reserve_key("users"));
+    /// let users = db.get_or_create("users");
+    /// assert_eq!(Ok(2), database::write(&users).insert(Default::default()));
+    /// ```
+    pub fn reserve_key(&self, name: &str) -> usize {
+        let collections = read(&self.collections);
+        if let Some(collection) = collections.get(name).cloned() {
+            // The registry lock is released first, as in `rename`
+            drop(collections);
+            return write(&collection).reserve_key();
+        }
+        // Holding the registry lock keeps the collection from being created
+        // until the key is reserved
+        let mut reserved = lock(&self.reserved);
+        let last = reserved.entry(String::from(name)).or_insert(0);
+        *last += 1;
+        *last
+    }
+
+    /// Takes a snapshot of every collection, producing it with the
+    /// collections it was taken of
+    ///
+    /// # Examples
+    ///
+    /// ```rust
+    /// use rockumentdb::datastore::database::{self, Database};
+    /// let db = Database::new();
+    /// let users = db.get_or_create("users");
+    /// let (snapshot, collections) = db.snapshot();
+    /// let key = database::write(&users).insert(Default::default()).unwrap();
+    /// assert!(database::read(&collections["users"]).get_at(key, snapshot.sequence()).is_none());
+    /// ```
+    pub fn snapshot(&self) -> (Snapshot, HashMap<String, SafeCollection>) {
+        let _commits = write(&self.clock.commits);
+        let collections = read(&self.collections).clone();
+        let mut state = lock(&self.clock.state);
+        let sequence = state.sequence;
+        *state.open.entry(sequence).or_insert(0) += 1;
+        let snapshot = Snapshot {
+            clock: Some(self.clock.clone()),
+            sequence,
+        };
+        (snapshot, collections)
+    }
+
+    /// Holds off new snapshots until the produced guard is dropped, so that
+    /// a write to several collections is seen by a snapshot either whole
+    /// or not at all
+    pub fn hold_snapshots(&self) -> RwLockReadGuard<'_, ()> {
+        read(&self.clock.commits)
+    }
+
+    /// Produces every collection, ordered by name
+    pub fn list(&self) -> Vec<(String, SafeCollection)> {
+        let mut collections: Vec<(String, SafeCollection)> = read(&self.collections)
+            .iter()
+            .map(|(name, collection)| (name.clone(), collection.clone()))
+            .collect();
+        collections.sort_by(|a, b| a.0.cmp(&b.0));
+        collections
+    }
+
+    /// Removes the named collection and every document in it, producing the
+    /// collection if it existed. Requests already holding the collection
+    /// finish against the removed collection.
+    ///
+    /// # Arguments
+    ///
+    /// * `name` - the name of the collection
+    pub fn remove(&self, name: &str) -> Option<SafeCollection> {
+        write(&self.collections).remove(name)
+    }
+
+    /// Moves a collection, its documents, indexes and options to a new name
+    ///
+    /// # Arguments
+    ///
+    /// * `from` - the name of the collection
+    /// * `to` - the new name of the collection
+    /// * `drop_target` - whether a collection already named `to` is removed,
+    ///   rather than failing the rename
+    ///
+    /// # Examples
+    ///
+    /// ```rust
+    /// use rockumentdb::datastore::database::Database;
+    /// let db = Database::new();
+    /// db.get_or_create("users");
+    /// db.rename("users", "accounts", false).unwrap();
+    /// assert!(db.get("users").is_none());
+    /// ```
+    pub fn rename(&self, from: &str, to: &str, drop_target: bool) -> Result<(), DatastoreError> {
+        if from == to {
+            return Err(DatastoreError::InvalidOptions(String::from(
+                "a collection cannot be renamed to itself",
+            )));
+        }
+        let renamed = {
+            let mut collections = write(&self.collections);
+            if !collections.contains_key(from) {
+                return Err(DatastoreError::CollectionNotFound(String::from(from)));
+            }
+            if collections.contains_key(to) && !drop_target {
+                return Err(DatastoreError::CollectionExists(String::from(to)));
+            }
+            let renamed = collections.remove(from).unwrap();
+            collections.insert(String::from(to), renamed.clone());
+            renamed
+        };
+        // The registry lock is released first, so that a request holding
+        // the collection never waits on it while the rename waits on them
+        write(&renamed).name = String::from(to);
+        Ok(())
+    }
+
+    /// Removes the documents expired at a time from every collection with
+    /// a TTL index, producing the collections documents were removed from
+    /// and how many, ordered by name
+    ///
+    /// # Arguments
+    ///
+    /// * `now` - the time, in milliseconds since the Unix epoch
+    pub fn expire(&self, now: i64) -> Vec<(String, usize)> {
+        self.list()
+            .into_iter()
+            .filter(|(_, collection)| read(collection).expires())
+            .map(|(name, collection)| (name, write(&collection).expire(now).len()))
+            .filter(|(_, expired)| *expired > 0)
+            .collect()
+    }
+}
+
+impl Default for Database {
+    fn default() -> Self {
+        Database::new()
+    }
+}
+
+/// State behind a lock, which a holder that panicked may have left part
+/// way through a change
+pub trait Recover {
+    /// Makes the state consistent again before the lock is handed out. The
+    /// default keeps the state as it is, for state every change to which
+    /// is a single step.
+    fn recover(&mut self) {}
+}
+
+impl<K, V> Recover for HashMap<K, V> {}
+
+impl Recover for Instant {}
+
+impl Recover for () {}
+
+impl Recover for ClockState {}
+
+/// Acquires a shared lock, recovering it first if a previous holder
+/// panicked. See [`write`].
+pub fn read<T: Recover>(lock: &RwLock<T>) -> RwLockReadGuard<'_, T> {
+    match lock.read() {
+        Ok(guard) => guard,
+        Err(poisoned) => {
+            drop(poisoned);
+            drop(write(lock));
+            read(lock)
+        }
+    }
+}
+
+/// Acquires an exclusive lock. If a previous holder panicked, the data
+/// behind the lock is recovered, see `Recover`, before it is handed out,
+/// so that one failed request does not fail every later one.
+pub fn write<T: Recover>(lock: &RwLock<T>) -> RwLockWriteGuard<'_, T> {
+    match lock.write() {
+        Ok(guard) => guard,
+        Err(poisoned) => {
+            println!("LOCK: recovering from a poisoned lock");
+            let mut guard = poisoned.into_inner();
+            guard.recover();
+            lock.clear_poison();
+            guard
+        }
+    }
+}
+
+/// Acquires a mutex, recovering it first if a previous holder panicked.
+/// See [`write`].
+pub fn lock<T: Recover>(mutex: &Mutex<T>) -> MutexGuard<'_, T> {
+    match mutex.lock() {
+        Ok(guard) => guard,
+        Err(poisoned) => {
+            println!("LOCK: recovering from a poisoned lock");
+            let mut guard = poisoned.into_inner();
+            guard.recover();
+            mutex.clear_poison();
+            guard
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::datastore::datatypes::DataType;
+    use std::collections::HashMap;
+    use std::panic;
+    use std::thread;
+
+    #[test]
+    fn get_missing_collection() {
+        let db = Database::new();
+        assert!(db.get("users").is_none());
+    }
+
+    #[test]
+    fn get_or_create_collection() {
+        let db = Database::new();
+        let created = db.get_or_create("users");
+        write(&created).insert(HashMap::new()).unwrap();
+        let fetched = db.get("users").unwrap();
+        assert!(Arc::ptr_eq(&created, &fetched));
+    }
+
+    #[test]
+    fn list_remove_and_rename() {
+        let db = Database::new();
+        let users = db.get_or_create("users");
+        db.get_or_create("accounts");
+        let names: Vec<String> = db.list().into_iter().map(|(name, _)| name).collect();
+        assert_eq!(vec!["accounts", "users"], names);
+
+        assert_eq!(
+            Err(DatastoreError::CollectionExists(String::from("accounts"))),
+            db.rename("users", "accounts", false)
+        );
+        db.rename("users", "accounts", true).unwrap();
+        assert!(Arc::ptr_eq(&users, &db.get("accounts").unwrap()));
+        assert_eq!("accounts", read(&users).name);
+        assert_eq!(
+            Err(DatastoreError::CollectionNotFound(String::from("users"))),
+            db.rename("users", "members", false)
+        );
+
+        assert!(db.remove("accounts").is_some());
+        assert!(db.remove("accounts").is_none());
+        assert!(db.list().is_empty());
+    }
+
+    #[test]
+    fn concurrent_readers() {
+        let db = Database::new();
+        let users = db.get_or_create("users");
+        let first = read(&users);
+        // A second reader on another thread must not block on the first
+        let other = users.clone();
+        let handle = thread::spawn(move || read(&other).find("{}").is_ok());
+        assert!(handle.join().unwrap());
+        drop(first);
+    }
+
+    #[test]
+    fn recover_poisoned_lock() {
+        let db = Database::new();
+        let users = db.get_or_create("users");
+        write(&users).create_index("username", false).unwrap();
+        let other = users.clone();
+        let result = thread::spawn(move || {
+            let mut collection = write(&other);
+            let mut document = HashMap::new();
+            document.insert(
+                String::from("username"),
+                DataType::String(String::from("johnperry")),
+            );
+            collection.insert(document).unwrap();
+            panic::panic_any("request handler panicked");
+        })
+        .join();
+        assert!(result.is_err());
+        assert!(users.is_poisoned());
+        let reader = users.clone();
+        let found = thread::spawn(move || read(&reader).find_ids("{username:\"johnperry\"}"))
+            .join()
+            .unwrap();
+        assert_eq!(Ok(vec![1usize]), found);
+        assert!(!users.is_poisoned());
+        assert_eq!(Ok(2usize), write(&users).insert(HashMap::new()));
+    }
+
+    #[test]
+    fn expire_documents() {
+        let db = Database::new();
+        let sessions = db.get_or_create("sessions");
+        db.get_or_create("users");
+        write(&sessions)
+            .create_ttl_index("created", false, 60)
+            .unwrap();
+        let mut session = HashMap::new();
+        session.insert(String::from("created"), DataType::DateTime(0));
+        write(&sessions).insert(session.clone()).unwrap();
+        session.insert(String::from("created"), DataType::DateTime(50_000));
+        write(&sessions).insert(session).unwrap();
+        assert!(db.expire(59_999).is_empty());
+        assert_eq!(vec![(String::from("sessions"), 1)], db.expire(60_000));
+        assert_eq!(1, read(&sessions).len());
+    }
+}
@@ -0,0 +1,163 @@
+//! Conversions between milliseconds since the Unix epoch and ISO-8601
+//! date-times in UTC
+
+const MILLIS_PER_DAY: i64 = 86_400_000;
+
+/// Produces the days since 1970-01-01 of a proleptic Gregorian date
+fn days_from_civil(year: i64, month: i64, day: i64) -> i64 {
+    let year = if month <= 2 { year - 1 } else { year };
+    let era = year.div_euclid(400);
+    let year_of_era = year - era * 400;
+    let day_of_year = (153 * (month + if month > 2 { -3 } else { 9 }) + 2) / 5 + day - 1;
+    let day_of_era = year_of_era * 365 + year_of_era / 4 - year_of_era / 100 + day_of_year;
+    era * 146_097 + day_of_era - 719_468
+}
+
+/// Produces the year, month and day of the days since 1970-01-01
+fn civil_from_days(days: i64) -> (i64, i64, i64) {
+    let days = days + 719_468;
+    let era = days.div_euclid(146_097);
+    let day_of_era = days - era * 146_097;
+    let year_of_era =
+        (day_of_era - day_of_era / 1460 + day_of_era / 36524 - day_of_era / 146_096) / 365;
+    let day_of_year = day_of_era - (365 * year_of_era + year_of_era / 4 - year_of_era / 100);
+    let month_index = (5 * day_of_year + 2) / 153;
+    let day = day_of_year - (153 * month_index + 2) / 5 + 1;
+    let month = if month_index < 10 {
+        month_index + 3
+    } else {
+        month_index - 9
+    };
+    let year = year_of_era + era * 400 + (month <= 2) as i64;
+    (year, month, day)
+}
+
+/// Produces the ISO-8601 form of a date-time, e.g.
+/// `2021-06-01T12:30:00.000Z`, or None outside of the years 0 to 9999
+///
+/// # Arguments
+///
+/// * `millis` - milliseconds since the Unix epoch
+pub fn format_iso(millis: i64) -> Option<String> {
+    let days = millis.div_euclid(MILLIS_PER_DAY);
+    let time = millis.rem_euclid(MILLIS_PER_DAY);
+    let (year, month, day) = civil_from_days(days);
+    if !(0..=9999).contains(&year) {
+        return None;
+    }
+    Some(format!(
+        "{:04}-{:02}-{:02}T{:02}:{:02}:{:02}.{:03}Z",
+        year,
+        month,
+        day,
+        time / 3_600_000,
+        time / 60_000 % 60,
+        time / 1000 % 60,
+        time % 1000
+    ))
+}
+
+/// Produces the milliseconds since the Unix epoch of an ISO-8601
+/// date-time, or None if it is not one. A date alone is midnight UTC;
+/// otherwise the time needs a `Z` or `±HH:MM` offset. Digits past
+/// milliseconds are truncated.
+///
+/// # Arguments
+///
+/// * `text` - e.g. `2021-06-01`, `2021-06-01T12:30:00Z` or
+///   `2021-06-01T14:30:00.5+02:00`
+pub fn parse_iso(text: &str) -> Option<i64> {
+    let number = |part: &str| -> Option<i64> {
+        if part.is_empty() || !part.bytes().all(|b| b.is_ascii_digit()) {
+            return None;
+        }
+        part.parse().ok()
+    };
+    let (date, time) = match text.split_once('T') {
+        Some((date, time)) => (date, Some(time)),
+        None => (text, None),
+    };
+    let mut date_parts = date.splitn(3, '-');
+    let year = number(date_parts.next()?)?;
+    let month = number(date_parts.next()?)?;
+    let day = number(date_parts.next()?)?;
+    if !(1..=12).contains(&month) || day < 1 || day > days_in_month(year, month) {
+        return None;
+    }
+    let mut millis = days_from_civil(year, month, day) * MILLIS_PER_DAY;
+    let time = match time {
+        Some(time) => time,
+        None => return Some(millis),
+    };
+
+    let (clock, offset) = if let Some(clock) = time.strip_suffix('Z') {
+        (clock, 0)
+    } else {
+        let position = time.rfind(['+', '-'])?;
+        let (clock, offset) = time.split_at(position);
+        let sign = if offset.starts_with('-') { -1 } else { 1 };
+        let offset = offset[1..].replace(':', "");
+        if offset.len() != 4 {
+            return None;
+        }
+        let (hours, minutes) = (number(&offset[..2])?, number(&offset[2..])?);
+        (clock, sign * (hours * 60 + minutes) * 60_000)
+    };
+    let (clock, fraction) = match clock.split_once('.') {
+        Some((clock, fraction)) => (clock, Some(fraction)),
+        None => (clock, None),
+    };
+    let mut clock_parts = clock.splitn(3, ':');
+    let hour = number(clock_parts.next()?)?;
+    let minute = number(clock_parts.next()?)?;
+    let second = match clock_parts.next() {
+        Some(second) => number(second)?,
+        None => 0,
+    };
+    if hour > 23 || minute > 59 || second > 59 {
+        return None;
+    }
+    millis += (hour * 3600 + minute * 60 + second) * 1000 - offset;
+    if let Some(fraction) = fraction {
+        number(fraction)?;
+        let padded = format!("{:0<3}", &fraction[..fraction.len().min(3)]);
+        millis += number(&padded)?;
+    }
+    Some(millis)
+}
+
+fn days_in_month(year: i64, month: i64) -> i64 {
+    match month {
+        2 if year % 4 == 0 && (year % 100 != 0 || year % 400 == 0) => 29,
+        2 => 28,
+        4 | 6 | 9 | 11 => 30,
+        _ => 31,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn round_trip() {
+        for millis in [0, 1_622_550_600_123, -86_400_001, 951_782_400_000] {
+            assert_eq!(Some(millis), parse_iso(&format_iso(millis).unwrap()));
+        }
+        assert_eq!(
+            Some(String::from("2000-02-29T00:00:00.000Z")),
+            format_iso(951_782_400_000)
+        );
+    }
+
+    #[test]
+    fn parse_forms() {
+        assert_eq!(Some(0), parse_iso("1970-01-01"));
+        assert_eq!(Some(1500), parse_iso("1970-01-01T00:00:01.5Z"));
+        assert_eq!(Some(0), parse_iso("1970-01-01T02:00:00+02:00"));
+        assert_eq!(Some(3_600_000), parse_iso("1970-01-01T00:00-0100"));
+        assert_eq!(None, parse_iso("1970-02-30"));
+        assert_eq!(None, parse_iso("1970-01-01T00:00:00"));
+        assert_eq!(None, parse_iso("yesterday"));
+    }
+}
@@ -0,0 +1,429 @@
+use serde::{Deserialize, Serialize};
+use std::cmp::Ordering;
+use std::fmt;
+use std::hash::{Hash, Hasher};
+use std::str::FromStr;
+
+/// The largest coefficient of a decimal128, 34 nines
+const MAX_COEFFICIENT: i128 = 9_999_999_999_999_999_999_999_999_999_999_999;
+const MIN_EXPONENT: i32 = -6176;
+const MAX_EXPONENT: i32 = 6111;
+
+/// A finite decimal number with the precision and range of an IEEE 754
+/// decimal128: a coefficient of up to 34 digits scaled by a power of ten.
+/// Trailing zeros are kept, `1.50` and `1.5` are equal but display as they
+/// were written.
+#[derive(Debug, Clone, Copy, Serialize, Deserialize)]
+pub struct Decimal128 {
+    coefficient: i128,
+    exponent: i32,
+}
+
+impl Decimal128 {
+    /// Produces the Decimal128 `coefficient * 10^exponent`, or None when
+    /// either is out of range
+    ///
+    /// # Arguments
+    ///
+    /// * `coefficient` - at most 34 digits
+    /// * `exponent` - between -6176 and 6111
+    ///
+    /// # Examples
+    ///
+    /// ```rust
+    /// use rockumentdb::datastore::datatypes::Decimal128;
+    /// let price = Decimal128::new(1999, -2).unwrap();
+    /// assert_eq!("19.99", price.to_string());
+    /// ```
+    pub fn new(coefficient: i128, exponent: i32) -> Option<Decimal128> {
+        if coefficient.abs() > MAX_COEFFICIENT || !(MIN_EXPONENT..=MAX_EXPONENT).contains(&exponent)
+        {
+            return None;
+        }
+        Some(Decimal128 {
+            coefficient,
+            exponent,
+        })
+    }
+
+    pub fn coefficient(&self) -> i128 {
+        self.coefficient
+    }
+
+    pub fn exponent(&self) -> i32 {
+        self.exponent
+    }
+
+    /// Produces the Decimal128 with the fewest digits that reads back as
+    /// `value`, or None for NaN and infinities
+    pub fn from_f64(value: f64) -> Option<Decimal128> {
+        if !value.is_finite() {
+            return None;
+        }
+        format!("{:e}", value).parse().ok()
+    }
+
+    /// Produces the nearest f64
+    pub fn to_f64(&self) -> f64 {
+        self.to_string().parse().unwrap()
+    }
+
+    /// Produces the value as an i128 if it is integral and fits
+    pub fn to_i128(&self) -> Option<i128> {
+        let (coefficient, exponent) = self.normalized();
+        if exponent < 0 {
+            return None;
+        }
+        10i128
+            .checked_pow(exponent as u32)
+            .and_then(|scale| coefficient.checked_mul(scale))
+    }
+
+    /// Produces the exact value, to compare against numbers of other
+    /// representations
+    pub(super) fn exact(&self) -> Exact {
+        let (coefficient, exponent) = self.normalized();
+        Exact::new(
+            coefficient < 0,
+            coefficient
+                .abs()
+                .to_string()
+                .bytes()
+                .map(|b| b - b'0')
+                .collect(),
+            exponent,
+        )
+    }
+
+    /// Produces the coefficient and exponent without trailing zeros
+    fn normalized(&self) -> (i128, i32) {
+        if self.coefficient == 0 {
+            return (0, 0);
+        }
+        let (mut coefficient, mut exponent) = (self.coefficient, self.exponent);
+        while coefficient % 10 == 0 {
+            coefficient /= 10;
+            exponent += 1;
+        }
+        (coefficient, exponent)
+    }
+}
+
+/// Produces the number of decimal digits of a non negative number
+fn digits(value: i128) -> u32 {
+    let mut count = 1;
+    let mut rest = value / 10;
+    while rest > 0 {
+        count += 1;
+        rest /= 10;
+    }
+    count
+}
+
+/// The exact value of a finite number of any precision: its sign, the
+/// exponent of its leading digit and its digits without trailing zeros.
+/// Zero has no digits.
+#[derive(Debug, PartialEq, Eq)]
+pub(super) struct Exact {
+    negative: bool,
+    adjusted: i32,
+    digits: Vec<u8>,
+}
+
+impl Exact {
+    /// Produces `digits * 10^exponent`
+    fn new(negative: bool, mut digits: Vec<u8>, exponent: i32) -> Exact {
+        let leading = digits.iter().take_while(|&&digit| digit == 0).count();
+        digits.drain(..leading);
+        let adjusted = exponent + digits.len() as i32 - 1;
+        while digits.last() == Some(&0) {
+            digits.pop();
+        }
+        if digits.is_empty() {
+            return Exact {
+                negative: false,
+                adjusted: 0,
+                digits,
+            };
+        }
+        Exact {
+            negative,
+            adjusted,
+            digits,
+        }
+    }
+
+    /// Produces the exact decimal expansion of a float, or None for NaN
+    /// and infinities
+    ///
+    /// # Arguments
+    ///
+    /// * `value` - the float, `m * 2^e` with `m` below 2^53
+    pub(super) fn from_f64(value: f64) -> Option<Exact> {
+        if !value.is_finite() {
+            return None;
+        }
+        let bits = value.to_bits();
+        let biased = ((bits >> 52) & 0x7ff) as i32;
+        let fraction = bits & ((1 << 52) - 1);
+        let (mantissa, exponent) = if biased == 0 {
+            (fraction, -1074)
+        } else {
+            (fraction | (1 << 52), biased - 1075)
+        };
+        // m * 2^e is the integer m * 2^e when e is positive, and
+        // m * 5^-e scaled by 10^e otherwise
+        let mut limbs = vec![(mantissa % LIMB) as u32, (mantissa / LIMB) as u32];
+        let (base, scale, chunk) = if exponent > 0 {
+            (2, 0, 29)
+        } else {
+            (5, exponent, 12)
+        };
+        let mut remaining = exponent.unsigned_abs();
+        while remaining > 0 {
+            let power = remaining.min(chunk);
+            multiply(&mut limbs, (base as u64).pow(power));
+            remaining -= power;
+        }
+        let mut digits = Vec::with_capacity(limbs.len() * 9);
+        for limb in limbs.iter().rev() {
+            digits.extend(format!("{:09}", limb).bytes().map(|b| b - b'0'));
+        }
+        Some(Exact::new(value < 0.0, digits, scale))
+    }
+}
+
+/// The base of the limbs of the big integers expanding floats
+const LIMB: u64 = 1_000_000_000;
+
+/// Multiplies a big integer, least significant limb first, in place
+fn multiply(limbs: &mut Vec<u32>, factor: u64) {
+    let mut carry = 0;
+    for limb in limbs.iter_mut() {
+        let product = u64::from(*limb) * factor + carry;
+        *limb = (product % LIMB) as u32;
+        carry = product / LIMB;
+    }
+    while carry > 0 {
+        limbs.push((carry % LIMB) as u32);
+        carry /= LIMB;
+    }
+}
+
+impl Ord for Exact {
+    fn cmp(&self, other: &Self) -> Ordering {
+        let sign = |exact: &Exact| match (exact.negative, exact.digits.is_empty()) {
+            (_, true) => 0,
+            (true, false) => -1,
+            (false, false) => 1,
+        };
+        let (left, right) = (sign(self), sign(other));
+        if left != right || left == 0 {
+            return left.cmp(&right);
+        }
+        // Digits without trailing zeros compare like the fractions they
+        // are once the leading digits are aligned
+        let magnitude = self
+            .adjusted
+            .cmp(&other.adjusted)
+            .then_with(|| self.digits.cmp(&other.digits));
+        if left < 0 {
+            magnitude.reverse()
+        } else {
+            magnitude
+        }
+    }
+}
+
+impl PartialOrd for Exact {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl FromStr for Decimal128 {
+    type Err = String;
+
+    /// Parses decimal (`-12.50`) and scientific (`1.25E+3`) notation
+    fn from_str(text: &str) -> Result<Self, Self::Err> {
+        let invalid = || format!("{} is not a decimal number", text);
+        let (mantissa, exponent) = match text.find(['e', 'E']) {
+            Some(position) => (
+                &text[..position],
+                text[position + 1..].parse::<i32>().map_err(|_| invalid())?,
+            ),
+            None => (text, 0),
+        };
+        let (negative, mantissa) = match mantissa.strip_prefix('-') {
+            Some(rest) => (true, rest),
+            None => (false, mantissa.strip_prefix('+').unwrap_or(mantissa)),
+        };
+        let (whole, fraction) = match mantissa.split_once('.') {
+            Some((whole, fraction)) => (whole, fraction),
+            None => (mantissa, ""),
+        };
+        if whole.is_empty() && fraction.is_empty()
+            || !whole
+                .chars()
+                .chain(fraction.chars())
+                .all(|c| c.is_ascii_digit())
+        {
+            return Err(invalid());
+        }
+        let significant = format!("{}{}", whole, fraction);
+        let significant = significant.trim_start_matches('0');
+        if significant.len() > 34 {
+            return Err(format!("{} has more than 34 significant digits", text));
+        }
+        let coefficient = if significant.is_empty() {
+            0
+        } else {
+            significant.parse::<i128>().map_err(|_| invalid())?
+        };
+        let coefficient = if negative { -coefficient } else { coefficient };
+        exponent
+            .checked_sub(fraction.len() as i32)
+            .and_then(|exponent| Decimal128::new(coefficient, exponent))
+            .ok_or_else(|| format!("{} is out of the range of a decimal128", text))
+    }
+}
+
+impl fmt::Display for Decimal128 {
+    /// Writes the IEEE 754 scientific string: plain notation unless the
+    /// exponent is positive or the number is very small
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        if self.coefficient < 0 {
+            write!(f, "-")?;
+        }
+        let digits = self.coefficient.abs().to_string();
+        let adjusted = digits.len() as i32 - 1 + self.exponent;
+        if self.exponent <= 0 && adjusted >= -6 {
+            let point = digits.len() as i32 + self.exponent;
+            if self.exponent == 0 {
+                write!(f, "{}", digits)
+            } else if point > 0 {
+                let (whole, fraction) = digits.split_at(point as usize);
+                write!(f, "{}.{}", whole, fraction)
+            } else {
+                write!(f, "0.{}{}", "0".repeat(-point as usize), digits)
+            }
+        } else {
+            let (first, rest) = digits.split_at(1);
+            write!(f, "{}", first)?;
+            if !rest.is_empty() {
+                write!(f, ".{}", rest)?;
+            }
+            write!(
+                f,
+                "E{}{}",
+                if adjusted < 0 { "-" } else { "+" },
+                adjusted.abs()
+            )
+        }
+    }
+}
+
+impl Ord for Decimal128 {
+    fn cmp(&self, other: &Self) -> Ordering {
+        let (left, left_exponent) = self.normalized();
+        let (right, right_exponent) = other.normalized();
+        let sign = left.signum().cmp(&right.signum());
+        if sign != Ordering::Equal || left == 0 {
+            return sign;
+        }
+        let (left_digits, right_digits) = (digits(left.abs()), digits(right.abs()));
+        // The exponent of the leading digit decides unless they are equal,
+        // then the digits aligned to the same length do
+        let magnitude = (left_digits as i32 - 1 + left_exponent)
+            .cmp(&(right_digits as i32 - 1 + right_exponent))
+            .then_with(|| {
+                let width = left_digits.max(right_digits);
+                (left.abs() * 10i128.pow(width - left_digits))
+                    .cmp(&(right.abs() * 10i128.pow(width - right_digits)))
+            });
+        if left < 0 {
+            magnitude.reverse()
+        } else {
+            magnitude
+        }
+    }
+}
+
+impl PartialOrd for Decimal128 {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl PartialEq for Decimal128 {
+    fn eq(&self, other: &Self) -> bool {
+        self.cmp(other) == Ordering::Equal
+    }
+}
+
+impl Eq for Decimal128 {}
+
+impl Hash for Decimal128 {
+    fn hash<H: Hasher>(&self, state: &mut H) {
+        self.normalized().hash(state);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn decimal(text: &str) -> Decimal128 {
+        text.parse().unwrap()
+    }
+
+    #[test]
+    fn parse_and_display() {
+        assert_eq!("19.99", decimal("19.99").to_string());
+        assert_eq!("-1.50", decimal("-1.50").to_string());
+        assert_eq!("0.001", decimal("1E-3").to_string());
+        assert_eq!("1.25E+5", decimal("1.25e5").to_string());
+        assert_eq!("1E-7", decimal("0.0000001").to_string());
+        assert!("1.2.3".parse::<Decimal128>().is_err());
+        assert!("".parse::<Decimal128>().is_err());
+        assert!("1E+7000".parse::<Decimal128>().is_err());
+    }
+
+    #[test]
+    fn compare_by_value() {
+        assert_eq!(decimal("1.50"), decimal("1.5"));
+        assert!(decimal("-2") < decimal("-1.5"));
+        assert!(decimal("0.3") > decimal("0.29999"));
+        assert!(decimal("1E+3") > decimal("999.9"));
+        assert_eq!(decimal("0"), decimal("-0.00"));
+    }
+
+    #[test]
+    fn shortest_float() {
+        assert_eq!(Some(decimal("0.1")), Decimal128::from_f64(0.1));
+        assert_eq!(Some(75), decimal("7.50E+1").to_i128());
+        assert_eq!(None, decimal("7.5").to_i128());
+        assert_eq!(None, Decimal128::from_f64(f64::NAN));
+    }
+
+    #[test]
+    fn exact_float() {
+        assert_eq!(Some(decimal("0.5").exact()), Exact::from_f64(0.5));
+        assert_eq!(Some(decimal("-1.2E+3").exact()), Exact::from_f64(-1200.0));
+        assert_eq!(Some(decimal("0").exact()), Exact::from_f64(-0.0));
+        // 0.1 is nearest to 0.1000000000000000055511151231257827021181583404541015625
+        let tenth = "1000000000000000055511151231257827021181583404541015625";
+        assert_eq!(
+            Some(Exact::new(
+                false,
+                tenth.bytes().map(|b| b - b'0').collect(),
+                -55
+            )),
+            Exact::from_f64(0.1)
+        );
+        assert!(Exact::from_f64(0.1).unwrap() > decimal("0.1").exact());
+        assert!(Exact::from_f64(f64::MIN_POSITIVE / 4.0).unwrap() > decimal("0").exact());
+        assert!(Exact::from_f64(f64::MAX).unwrap() > decimal("1.79769E+308").exact());
+        assert_eq!(None, Exact::from_f64(f64::INFINITY));
+    }
+}
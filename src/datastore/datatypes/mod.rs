@@ -0,0 +1,718 @@
+mod date;
+mod decimal;
+mod object_id;
+
+pub use date::{format_iso, parse_iso};
+pub use decimal::Decimal128;
+use decimal::Exact;
+pub use object_id::ObjectId;
+
+use serde::{Deserialize, Serialize};
+use serde_json;
+use std::cmp::Ordering;
+use std::collections::BTreeMap;
+use std::hash::{Hash, Hasher};
+
+/// An f64 with a total order, so it can be hashed, indexed and sorted.
+/// Every NaN equals every other and orders below all other numbers, and
+/// -0.0 equals 0.0.
+#[derive(Debug, Clone, Copy, Serialize, Deserialize)]
+#[serde(transparent)]
+pub struct Float(f64);
+
+impl Float {
+    /// Produces a new Float
+    ///
+    /// # Arguments
+    ///
+    /// * `value` - any f64, NaN and infinities included
+    ///
+    /// # Examples
+    ///
+    /// ```rust
+    /// use rockumentdb::datastore::datatypes::Float;
+    /// assert_eq!(Float::new(f64::NAN), Float::new(-f64::NAN));
+    /// assert_eq!(Float::new(0.0), Float::new(-0.0));
+    /// ```
+    pub fn new(value: f64) -> Float {
+        Float(value)
+    }
+
+    /// Produces the underlying f64
+    pub fn get(self) -> f64 {
+        self.0
+    }
+}
+
+impl From<f64> for Float {
+    fn from(value: f64) -> Self {
+        Float(value)
+    }
+}
+
+impl Ord for Float {
+    fn cmp(&self, other: &Self) -> Ordering {
+        match (self.0.is_nan(), other.0.is_nan()) {
+            (true, true) => Ordering::Equal,
+            (true, false) => Ordering::Less,
+            (false, true) => Ordering::Greater,
+            // Only NaN is unordered
+            (false, false) => self.0.partial_cmp(&other.0).unwrap(),
+        }
+    }
+}
+
+impl PartialOrd for Float {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl PartialEq for Float {
+    fn eq(&self, other: &Self) -> bool {
+        self.cmp(other) == Ordering::Equal
+    }
+}
+
+impl Eq for Float {}
+
+impl Hash for Float {
+    fn hash<H: Hasher>(&self, state: &mut H) {
+        let canonical = if self.0.is_nan() {
+            f64::NAN
+        } else if self.0 == 0.0 {
+            0.0
+        } else {
+            self.0
+        };
+        canonical.to_bits().hash(state);
+    }
+}
+
+/// A document field value.
+///
+/// Like MongoDB, numbers are equal when their values are, whatever their
+/// representation: `I64(75)`, `U64(75)`, `F64(75.0)` and `Decimal128(75)`
+/// are equal and hash alike. A float equals a decimal only of exactly the
+/// same value: `0.5` equals `Decimal128(0.5)`, but `0.1`, nearest to
+/// `0.1000000000000000055511151231257827...`, is greater than
+/// `Decimal128(0.1)`. Values order by type (null, numbers, strings,
+/// embedded documents, arrays, binary data, ObjectIds, booleans then
+/// date-times) and then by value.
+#[derive(Debug, Serialize, Deserialize)]
+pub enum DataType {
+    Null,
+    Bool(bool),
+    F64(Float),
+    I64(i64),
+    U64(u64),
+    Decimal128(Decimal128),
+    String(String),
+    /// Milliseconds since the Unix epoch, UTC
+    DateTime(i64),
+    Binary {
+        subtype: u8,
+        bytes: Vec<u8>,
+    },
+    ObjectId(ObjectId),
+    /// An embedded document, its fields reached with dotted paths such as
+    /// `address.city`
+    Document(BTreeMap<String, DataType>),
+    Array(Vec<DataType>),
+}
+
+impl Clone for DataType {
+    fn clone(&self) -> Self {
+        match &self {
+            DataType::Null => DataType::Null,
+            DataType::Bool(val) => DataType::Bool(*val),
+            DataType::String(val) => DataType::String(String::from(val)),
+            DataType::F64(val) => DataType::F64(*val),
+            DataType::I64(val) => DataType::I64(*val),
+            DataType::U64(val) => DataType::U64(*val),
+            DataType::Decimal128(val) => DataType::Decimal128(*val),
+            DataType::DateTime(val) => DataType::DateTime(*val),
+            DataType::Binary { subtype, bytes } => DataType::Binary {
+                subtype: *subtype,
+                bytes: bytes.clone(),
+            },
+            DataType::ObjectId(val) => DataType::ObjectId(*val),
+            DataType::Document(val) => DataType::Document(val.clone()),
+            DataType::Array(val) => DataType::Array(val.clone()),
+        }
+    }
+}
+
+impl DataType {
+    fn rank(&self) -> u8 {
+        match self {
+            DataType::Null => 0,
+            DataType::F64(_) | DataType::I64(_) | DataType::U64(_) | DataType::Decimal128(_) => 1,
+            DataType::String(_) => 2,
+            DataType::Document(_) => 3,
+            DataType::Array(_) => 4,
+            DataType::Binary { .. } => 5,
+            DataType::ObjectId(_) => 6,
+            DataType::Bool(_) => 7,
+            DataType::DateTime(_) => 8,
+        }
+    }
+}
+
+impl Ord for DataType {
+    fn cmp(&self, other: &Self) -> Ordering {
+        // Values of the same rank always compare
+        self.rank()
+            .cmp(&other.rank())
+            .then_with(|| compare(self, other).unwrap_or(Ordering::Equal))
+    }
+}
+
+impl PartialOrd for DataType {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl PartialEq for DataType {
+    fn eq(&self, other: &Self) -> bool {
+        self.cmp(other) == Ordering::Equal
+    }
+}
+
+impl Eq for DataType {}
+
+impl Hash for DataType {
+    fn hash<H: Hasher>(&self, state: &mut H) {
+        self.rank().hash(state);
+        match self {
+            DataType::Null => {}
+            DataType::Bool(val) => val.hash(state),
+            DataType::String(val) => val.hash(state),
+            DataType::DateTime(val) => val.hash(state),
+            DataType::Binary { subtype, bytes } => {
+                subtype.hash(state);
+                bytes.hash(state);
+            }
+            DataType::ObjectId(val) => val.hash(state),
+            DataType::Document(val) => val.hash(state),
+            DataType::Array(val) => val.hash(state),
+            DataType::F64(_) | DataType::I64(_) | DataType::U64(_) | DataType::Decimal128(_) => {
+                // Integral numbers hash as integers and the rest as the
+                // float of exactly the same value, if any, so equal values
+                // of different representations share a hash
+                if let Some(val) = exact_integer(self) {
+                    0u8.hash(state);
+                    val.hash(state);
+                    return;
+                }
+                let float = match self {
+                    DataType::Decimal128(val) => {
+                        let float = val.to_f64();
+                        Some(float).filter(|_| Exact::from_f64(float) == Some(val.exact()))
+                    }
+                    _ => as_f64(self),
+                };
+                match float {
+                    Some(float) => {
+                        1u8.hash(state);
+                        Float::new(float).hash(state);
+                    }
+                    None => {
+                        2u8.hash(state);
+                        if let DataType::Decimal128(val) = self {
+                            val.hash(state);
+                        }
+                    }
+                }
+            }
+        }
+    }
+}
+
+/// Produces the DataType of a JSON value. Objects in MongoDB's extended
+/// JSON form produce the types JSON lacks:
+///
+/// * `{"$date": "2021-06-01T12:30:00Z"}` or
+///   `{"$date": {"$numberLong": "1622550600000"}}` a DateTime
+/// * `{"$binary": {"base64": "AQID", "subType": "00"}}` a Binary
+/// * `{"$numberDecimal": "19.99"}` a Decimal128
+/// * `{"$oid": "60b5fbd8e1b2c3d4e5f60718"}` an ObjectId
+/// * `{"$numberLong": "1"}`, `{"$numberInt": "1"}` and
+///   `{"$numberDouble": "NaN"}` numbers
+///
+/// Other objects produce embedded documents and arrays produce arrays.
+/// Objects with `$` prefixed fields that are not extended JSON produce
+/// null.
+///
+/// # Arguments
+///
+/// * `value` - the JSON value
+///
+/// # Examples
+///
+/// ```rust
+/// use rockumentdb::datastore::datatypes::{from_json, DataType};
+/// use serde_json::json;
+/// assert_eq!(
+///     DataType::DateTime(0),
+///     from_json(&json!({"$date": "1970-01-01T00:00:00Z"}))
+/// );
+/// ```
+pub fn from_json(value: &serde_json::Value) -> DataType {
+    match value {
+        serde_json::Value::Null => DataType::Null,
+        serde_json::Value::Bool(val) => DataType::Bool(*val),
+        serde_json::Value::Number(val) => {
+            if val.is_f64() {
+                DataType::F64(Float::new(val.as_f64().unwrap()))
+            } else if val.is_i64() {
+                DataType::I64(val.as_i64().unwrap())
+            } else {
+                DataType::U64(val.as_u64().unwrap())
+            }
+        }
+        serde_json::Value::String(val) => DataType::String(String::from(val)),
+        serde_json::Value::Object(object) => match from_extended_json(object) {
+            Some(value) => value,
+            None if object.keys().any(|field| field.starts_with('$')) => DataType::Null,
+            None => DataType::Document(
+                object
+                    .iter()
+                    .map(|(field, value)| (field.clone(), from_json(value)))
+                    .collect(),
+            ),
+        },
+        serde_json::Value::Array(values) => DataType::Array(values.iter().map(from_json).collect()),
+    }
+}
+
+/// Produces the DataType of an extended JSON object, or None if it is not one
+fn from_extended_json(object: &serde_json::Map<String, serde_json::Value>) -> Option<DataType> {
+    if object.len() != 1 {
+        return None;
+    }
+    let (key, value) = object.iter().next()?;
+    match (key.as_str(), value) {
+        ("$date", serde_json::Value::String(text)) => parse_iso(text).map(DataType::DateTime),
+        ("$date", serde_json::Value::Object(_)) => match from_json(value) {
+            DataType::I64(millis) => Some(DataType::DateTime(millis)),
+            _ => None,
+        },
+        ("$date", serde_json::Value::Number(millis)) => millis.as_i64().map(DataType::DateTime),
+        ("$binary", serde_json::Value::Object(binary)) => {
+            let bytes = base64::decode(binary.get("base64")?.as_str()?).ok()?;
+            let subtype = u8::from_str_radix(binary.get("subType")?.as_str()?, 16).ok()?;
+            Some(DataType::Binary { subtype, bytes })
+        }
+        ("$numberDecimal", serde_json::Value::String(text)) => {
+            text.parse().ok().map(DataType::Decimal128)
+        }
+        ("$oid", serde_json::Value::String(text)) => text.parse().ok().map(DataType::ObjectId),
+        ("$numberLong", serde_json::Value::String(text))
+        | ("$numberInt", serde_json::Value::String(text)) => text.parse().ok().map(DataType::I64),
+        ("$numberDouble", serde_json::Value::String(text)) => {
+            let float = match text.as_str() {
+                "Infinity" => f64::INFINITY,
+                "-Infinity" => f64::NEG_INFINITY,
+                text => text.parse().ok()?,
+            };
+            Some(DataType::F64(Float::new(float)))
+        }
+        _ => None,
+    }
+}
+
+/// Produces the JSON form of a DataType. JSON has no NaN or infinities,
+/// they are produced as null. Types JSON lacks are produced in MongoDB's
+/// relaxed extended JSON form, see `from_json`; date-times outside of the
+/// years 1970 to 9999 as `{"$date": {"$numberLong": ...}}`.
+pub fn to_json(value: &DataType) -> serde_json::Value {
+    match value {
+        DataType::Null => serde_json::Value::Null,
+        DataType::Bool(val) => serde_json::Value::Bool(*val),
+        DataType::String(val) => serde_json::to_value(val).unwrap(),
+        DataType::F64(val) => serde_json::Number::from_f64(val.get())
+            .map(serde_json::Value::Number)
+            .unwrap_or(serde_json::Value::Null),
+        DataType::I64(val) => serde_json::to_value(*val).unwrap(),
+        DataType::U64(val) => serde_json::to_value(*val).unwrap(),
+        DataType::Decimal128(val) => serde_json::json!({ "$numberDecimal": val.to_string() }),
+        DataType::DateTime(val) => match format_iso(*val).filter(|_| *val >= 0) {
+            Some(iso) => serde_json::json!({ "$date": iso }),
+            None => serde_json::json!({ "$date": { "$numberLong": val.to_string() } }),
+        },
+        DataType::Binary { subtype, bytes } => serde_json::json!({
+            "$binary": { "base64": base64::encode(bytes), "subType": format!("{:02x}", subtype) }
+        }),
+        DataType::ObjectId(val) => serde_json::json!({ "$oid": val.to_string() }),
+        DataType::Document(val) => serde_json::Value::Object(
+            val.iter()
+                .map(|(field, value)| (field.clone(), to_json(value)))
+                .collect(),
+        ),
+        DataType::Array(val) => serde_json::Value::Array(val.iter().map(to_json).collect()),
+    }
+}
+
+/// Produces the value of a numeric DataType as an f64, the nearest for a
+/// Decimal128
+pub fn as_f64(value: &DataType) -> Option<f64> {
+    match value {
+        DataType::F64(val) => Some(val.get()),
+        DataType::I64(val) => Some(*val as f64),
+        DataType::U64(val) => Some(*val as f64),
+        DataType::Decimal128(val) => Some(val.to_f64()),
+        _ => None,
+    }
+}
+
+/// Produces the order of two DataTypes, or None if they cannot be compared.
+/// Numbers compare by value whatever their representation, with NaN below
+/// every other number, strings lexicographically, embedded documents field
+/// by field in field name order, arrays element by element, binary data by
+/// length,
+/// subtype then bytes, `false` before `true` and date-times
+/// chronologically; values of different types do not compare.
+///
+/// # Arguments
+///
+/// * `left` - the value on the left of the comparison
+/// * `right` - the value on the right of the comparison
+pub fn compare(left: &DataType, right: &DataType) -> Option<Ordering> {
+    match (left, right) {
+        (DataType::Null, DataType::Null) => Some(Ordering::Equal),
+        (DataType::Bool(a), DataType::Bool(b)) => Some(a.cmp(b)),
+        (DataType::String(a), DataType::String(b)) => Some(a.cmp(b)),
+        (DataType::DateTime(a), DataType::DateTime(b)) => Some(a.cmp(b)),
+        (DataType::ObjectId(a), DataType::ObjectId(b)) => Some(a.cmp(b)),
+        (DataType::Document(a), DataType::Document(b)) => Some(a.cmp(b)),
+        (DataType::Array(a), DataType::Array(b)) => Some(a.cmp(b)),
+        (
+            DataType::Binary { subtype, bytes },
+            DataType::Binary {
+                subtype: other_subtype,
+                bytes: other_bytes,
+            },
+        ) => Some(
+            bytes
+                .len()
+                .cmp(&other_bytes.len())
+                .then(subtype.cmp(other_subtype))
+                .then_with(|| bytes.cmp(other_bytes)),
+        ),
+        (DataType::Decimal128(a), DataType::Decimal128(b)) => Some(a.cmp(b)),
+        (DataType::Decimal128(_), _) | (_, DataType::Decimal128(_)) => {
+            Some(decimal_key(left)?.cmp(&decimal_key(right)?))
+        }
+        (DataType::F64(_), _) | (_, DataType::F64(_)) => {
+            let (left_float, right_float) = (as_f64(left)?, as_f64(right)?);
+            // Integral values compare exactly, beyond the precision of f64
+            match (exact_integer(left), exact_integer(right)) {
+                (Some(a), Some(b)) => Some(a.cmp(&b)),
+                _ => Some(Float::new(left_float).cmp(&Float::new(right_float))),
+            }
+        }
+        _ => Some(as_i128(left)?.cmp(&as_i128(right)?)),
+    }
+}
+
+/// A number compared against a Decimal128 by its exact value, floats by
+/// their full binary expansion rather than the decimal they display as
+#[derive(PartialEq, Eq, PartialOrd, Ord)]
+enum DecimalKey {
+    NaN,
+    NegativeInfinity,
+    Finite(Exact),
+    Infinity,
+}
+
+fn decimal_key(value: &DataType) -> Option<DecimalKey> {
+    match value {
+        DataType::Decimal128(val) => Some(DecimalKey::Finite(val.exact())),
+        DataType::F64(val) => {
+            let val = val.get();
+            Some(match Exact::from_f64(val) {
+                Some(exact) => DecimalKey::Finite(exact),
+                None if val.is_nan() => DecimalKey::NaN,
+                None if val < 0.0 => DecimalKey::NegativeInfinity,
+                None => DecimalKey::Infinity,
+            })
+        }
+        _ => Decimal128::new(as_i128(value)?, 0).map(|val| DecimalKey::Finite(val.exact())),
+    }
+}
+
+/// Produces the value of a number without a fractional part as an i128
+fn exact_integer(value: &DataType) -> Option<i128> {
+    match value {
+        DataType::F64(val) => {
+            let val = val.get();
+            // i128 holds every integral f64 below 2^127
+            if val.fract() == 0.0 && val.abs() < 2f64.powi(127) {
+                Some(val as i128)
+            } else {
+                None
+            }
+        }
+        DataType::Decimal128(val) => val.to_i128(),
+        _ => as_i128(value),
+    }
+}
+
+/// Produces the value of an integer DataType as an i128
+pub fn as_i128(value: &DataType) -> Option<i128> {
+    match value {
+        DataType::I64(val) => Some(i128::from(*val)),
+        DataType::U64(val) => Some(i128::from(*val)),
+        _ => None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use proptest::prelude::*;
+
+    #[test]
+    fn compare_numbers() {
+        assert_eq!(
+            Some(Ordering::Less),
+            compare(&DataType::I64(-1i64), &DataType::U64(u64::MAX))
+        );
+        assert_eq!(
+            Some(Ordering::Equal),
+            compare(&DataType::I64(75i64), &DataType::F64(Float::new(75.0)))
+        );
+        assert_eq!(
+            Some(Ordering::Greater),
+            compare(&DataType::F64(Float::new(75.5)), &DataType::U64(75u64))
+        );
+    }
+
+    #[test]
+    fn numbers_equal_across_representations() {
+        let float = DataType::F64(Float::new(75.0));
+        assert_eq!(DataType::I64(75i64), DataType::U64(75u64));
+        assert_eq!(DataType::U64(75u64), float);
+        assert_ne!(DataType::I64(75i64), DataType::F64(Float::new(75.5)));
+        assert_ne!(DataType::U64(75u64), DataType::String(String::from("75")));
+        // 2^53 + 1 has no exact f64 representation
+        assert_ne!(
+            DataType::I64(9007199254740993i64),
+            DataType::F64(Float::new(9007199254740992.0))
+        );
+    }
+
+    #[test]
+    fn equal_numbers_hash_alike() {
+        let hash = |value: &DataType| {
+            let mut hasher = std::collections::hash_map::DefaultHasher::new();
+            value.hash(&mut hasher);
+            hasher.finish()
+        };
+        assert_eq!(hash(&DataType::I64(75i64)), hash(&DataType::U64(75u64)));
+        assert_eq!(
+            hash(&DataType::U64(75u64)),
+            hash(&DataType::F64(Float::new(75.0)))
+        );
+        assert_eq!(
+            hash(&DataType::F64(Float::new(0.0))),
+            hash(&DataType::F64(Float::new(-0.0)))
+        );
+    }
+
+    #[test]
+    fn nan_equals_itself() {
+        let nan = DataType::F64(Float::new(f64::NAN));
+        assert_eq!(nan, nan.clone());
+        assert_eq!(Ordering::Less, nan.cmp(&DataType::I64(i64::MIN)));
+        assert_eq!(Ordering::Greater, nan.cmp(&DataType::Null));
+    }
+
+    #[test]
+    fn order_by_type_then_value() {
+        let mut values = vec![
+            DataType::Bool(false),
+            DataType::String(String::from("a")),
+            DataType::U64(3u64),
+            DataType::F64(Float::new(2.5)),
+            DataType::Null,
+            DataType::DateTime(0),
+            DataType::ObjectId(ObjectId::from_bytes([0; 12])),
+            DataType::Document(BTreeMap::new()),
+            DataType::Array(vec![DataType::Null]),
+            DataType::I64(-1i64),
+        ];
+        values.sort();
+        assert_eq!(
+            vec![
+                DataType::Null,
+                DataType::I64(-1i64),
+                DataType::F64(Float::new(2.5)),
+                DataType::U64(3u64),
+                DataType::String(String::from("a")),
+                DataType::Document(BTreeMap::new()),
+                DataType::Array(vec![DataType::Null]),
+                DataType::ObjectId(ObjectId::from_bytes([0; 12])),
+                DataType::Bool(false),
+                DataType::DateTime(0),
+            ],
+            values
+        );
+    }
+
+    #[test]
+    fn non_finite_json() {
+        assert_eq!(
+            serde_json::Value::Null,
+            to_json(&DataType::F64(Float::new(f64::INFINITY)))
+        );
+    }
+
+    #[test]
+    fn compare_different_types() {
+        assert_eq!(
+            None,
+            compare(&DataType::String(String::from("75")), &DataType::U64(75u64))
+        );
+        assert_eq!(None, compare(&DataType::Null, &DataType::Bool(false)));
+    }
+
+    #[test]
+    fn decimals_equal_numbers() {
+        let decimal = |text: &str| DataType::Decimal128(text.parse().unwrap());
+        let hash = |value: &DataType| {
+            let mut hasher = std::collections::hash_map::DefaultHasher::new();
+            value.hash(&mut hasher);
+            hasher.finish()
+        };
+        assert_eq!(decimal("75.0"), DataType::U64(75u64));
+        assert_eq!(hash(&decimal("75.0")), hash(&DataType::U64(75u64)));
+        assert_eq!(decimal("0.50"), DataType::F64(Float::new(0.5)));
+        assert_eq!(
+            hash(&decimal("0.50")),
+            hash(&DataType::F64(Float::new(0.5)))
+        );
+        assert!(decimal("0.1") < DataType::F64(Float::new(0.1)));
+        assert!(decimal("0.10000000000000001") > DataType::F64(Float::new(0.1)));
+        // Beyond 2^53 a decimal equals only the integers floats hold
+        let float = DataType::F64(Float::new(2f64.powi(60)));
+        assert_eq!(decimal("1152921504606846976"), float);
+        assert!(decimal("1152921504606846977") > float);
+        assert_eq!(hash(&decimal("1.152921504606846976E+18")), hash(&float));
+        assert!(decimal("-1E+400") < DataType::I64(i64::MIN));
+        assert!(decimal("-1E+400") > DataType::F64(Float::new(f64::NEG_INFINITY)));
+    }
+
+    #[test]
+    fn extended_json_round_trip() {
+        let values = vec![
+            serde_json::json!({"$date": "2021-06-01T12:30:00.000Z"}),
+            serde_json::json!({"$date": {"$numberLong": "-1"}}),
+            serde_json::json!({"$binary": {"base64": "AQID", "subType": "04"}}),
+            serde_json::json!({"$numberDecimal": "19.990"}),
+            serde_json::json!({"$oid": "60b5fbd8e1b2c3d4e5f60718"}),
+            serde_json::json!({"address": {"city": "Paris", "since": {"$date": "2021-06-01T00:00:00.000Z"}}}),
+            serde_json::json!(["captain", [1, {"$numberDecimal": "0.5"}], {"city": "Paris"}]),
+        ];
+        for value in values {
+            assert_eq!(value, to_json(&from_json(&value)));
+        }
+        assert_eq!(
+            DataType::DateTime(1_622_550_600_000),
+            from_json(&serde_json::json!({"$date": "2021-06-01T14:30:00+02:00"}))
+        );
+        assert_eq!(
+            DataType::Null,
+            from_json(&serde_json::json!({"$oid": "not an id"}))
+        );
+    }
+
+    #[test]
+    fn compare_binary() {
+        let binary = |subtype: u8, bytes: &[u8]| DataType::Binary {
+            subtype,
+            bytes: bytes.to_vec(),
+        };
+        assert!(binary(0, &[9]) < binary(0, &[1, 2]));
+        assert!(binary(0, &[9]) < binary(4, &[1]));
+        assert!(binary(0, &[1]) < binary(0, &[2]));
+    }
+
+    /// Produces the representations of a number that hold it exactly or,
+    /// for floats, nearly
+    fn representations(text: &str) -> Vec<DataType> {
+        let float = text
+            .parse::<f64>()
+            .ok()
+            .map(|val| DataType::F64(Float::new(val)));
+        let decimal = text.parse::<Decimal128>().ok().map(DataType::Decimal128);
+        let signed = text.parse::<i64>().ok().map(DataType::I64);
+        let unsigned = text.parse::<u64>().ok().map(DataType::U64);
+        vec![float, decimal, signed, unsigned]
+            .into_iter()
+            .flatten()
+            .collect()
+    }
+
+    fn numbers() -> impl Strategy<Value = (DataType, DataType, DataType)> {
+        // Three numbers written in any representation of two values, so
+        // that equal and nearly equal numbers of different types meet
+        let values = vec![
+            "-2",
+            "-0",
+            "0.1",
+            "0.5",
+            "1",
+            "0.3333333333333333",
+            "0.30000000000000004",
+            "0.1000000000000000055511151231257827",
+            "0.1000000000000000055511151231257827021181583404541015625",
+            "9007199254740992",
+            "9007199254740993",
+            "1152921504606846976",
+            "1152921504606846977",
+            "18446744073709551615",
+            "18446744073709551616",
+            "-9223372036854775808",
+            "1E+300",
+            "-1E+400",
+            "1E-400",
+            "NaN",
+            "inf",
+            "-inf",
+        ];
+        let random = any::<f64>().prop_map(|val| format!("{:e}", val));
+        let value = prop_oneof![prop::sample::select(values).prop_map(String::from), random];
+        (value.clone(), value).prop_flat_map(|(first, second)| {
+            let mut pool = representations(&first);
+            pool.extend(representations(&second));
+            let number = prop::sample::select(pool);
+            (number.clone(), number.clone(), number)
+        })
+    }
+
+    proptest! {
+        #[test]
+        fn numbers_compare_and_hash_consistently(
+            (a, b, c) in numbers(),
+        ) {
+            let hash = |value: &DataType| {
+                let mut hasher = std::collections::hash_map::DefaultHasher::new();
+                value.hash(&mut hasher);
+                hasher.finish()
+            };
+            prop_assert_eq!(a.cmp(&b), b.cmp(&a).reverse());
+            if a == b {
+                prop_assert_eq!(hash(&a), hash(&b));
+            }
+            if a == b && b == c {
+                prop_assert_eq!(&a, &c);
+            }
+            if a <= b && b <= c {
+                prop_assert!(a <= c);
+            }
+        }
+    }
+}
@@ -0,0 +1,67 @@
+use serde::{Deserialize, Serialize};
+use std::fmt;
+use std::str::FromStr;
+
+/// A MongoDB ObjectId, 12 bytes written as 24 hexadecimal digits
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash, Serialize, Deserialize)]
+pub struct ObjectId([u8; 12]);
+
+impl ObjectId {
+    /// Produces the ObjectId of 12 bytes
+    ///
+    /// # Arguments
+    ///
+    /// * `bytes` - the id's bytes, the first 4 a big endian timestamp in
+    ///   seconds
+    ///
+    /// # Examples
+    ///
+    /// ```rust
+    /// use rockumentdb::datastore::datatypes::ObjectId;
+    /// let id: ObjectId = "60b5fbd8e1b2c3d4e5f60718".parse().unwrap();
+    /// assert_eq!(ObjectId::from_bytes(id.bytes()), id);
+    /// ```
+    pub fn from_bytes(bytes: [u8; 12]) -> ObjectId {
+        ObjectId(bytes)
+    }
+
+    pub fn bytes(&self) -> [u8; 12] {
+        self.0
+    }
+}
+
+impl FromStr for ObjectId {
+    type Err = String;
+
+    fn from_str(text: &str) -> Result<Self, Self::Err> {
+        let invalid = || format!("{} is not 24 hexadecimal digits", text);
+        if text.len() != 24 || !text.is_ascii() {
+            return Err(invalid());
+        }
+        let mut bytes = [0u8; 12];
+        for (index, byte) in bytes.iter_mut().enumerate() {
+            *byte =
+                u8::from_str_radix(&text[index * 2..index * 2 + 2], 16).map_err(|_| invalid())?;
+        }
+        Ok(ObjectId(bytes))
+    }
+}
+
+impl fmt::Display for ObjectId {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        self.0.iter().try_for_each(|byte| write!(f, "{:02x}", byte))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parse_and_display() {
+        let id: ObjectId = "60B5FBD8E1B2C3D4E5F60718".parse().unwrap();
+        assert_eq!("60b5fbd8e1b2c3d4e5f60718", id.to_string());
+        assert!("60b5fbd8".parse::<ObjectId>().is_err());
+        assert!("zzb5fbd8e1b2c3d4e5f60718".parse::<ObjectId>().is_err());
+    }
+}
@@ -0,0 +1,176 @@
+use crate::datastore::datatypes::{self, DataType};
+use crate::datastore::schema::Violation;
+use serde_json::{json, Map, Value};
+use std::fmt;
+
+/// Every way an operation on the datastore can fail
+#[derive(Debug, Clone, PartialEq)]
+pub enum DatastoreError {
+    /// A query or filter that could not be parsed
+    InvalidQuery,
+    /// A query matched a key that is no longer in the collection
+    InvalidId,
+    InvalidUpdate(String),
+    /// A document did not match its collection's schema
+    Validation(Vec<Violation>),
+    /// A unique index already holds the key for another document
+    DuplicateKey {
+        index: String,
+        key: Vec<(String, DataType)>,
+    },
+    TransactionNotFound,
+    /// A collection that does not exist, or that a transaction wrote was
+    /// dropped or renamed before the transaction committed
+    CollectionNotFound(String),
+    /// A collection already has the name another was to be given
+    CollectionExists(String),
+    /// Another writer changed a document a transaction also wrote
+    WriteConflict {
+        collection: String,
+        key: usize,
+    },
+    /// Options, such as a schema, an index or a find's sort, that are not
+    /// valid
+    InvalidOptions(String),
+    /// A body or import that could not be read as documents
+    Malformed(String),
+    /// A watch resumed after a token whose later change events are no
+    /// longer kept
+    ChangeHistoryLost(u64),
+}
+
+impl DatastoreError {
+    /// Produces the stable, machine readable name of the error, e.g.
+    /// `DUPLICATE_KEY`
+    pub fn code(&self) -> &'static str {
+        match self {
+            DatastoreError::InvalidQuery => "INVALID_QUERY",
+            DatastoreError::InvalidId => "INVALID_ID",
+            DatastoreError::InvalidUpdate(_) => "INVALID_UPDATE",
+            DatastoreError::Validation(_) => "VALIDATION_FAILED",
+            DatastoreError::DuplicateKey { .. } => "DUPLICATE_KEY",
+            DatastoreError::TransactionNotFound => "TRANSACTION_NOT_FOUND",
+            DatastoreError::CollectionNotFound(_) => "COLLECTION_NOT_FOUND",
+            DatastoreError::CollectionExists(_) => "COLLECTION_EXISTS",
+            DatastoreError::WriteConflict { .. } => "WRITE_CONFLICT",
+            DatastoreError::InvalidOptions(_) => "INVALID_OPTIONS",
+            DatastoreError::Malformed(_) => "MALFORMED",
+            DatastoreError::ChangeHistoryLost(_) => "CHANGE_HISTORY_LOST",
+        }
+    }
+
+    /// Produces what a client needs to act on the error beyond its message,
+    /// or null when there is nothing more to say
+    ///
+    /// # Examples
+    ///
+    /// ```rust
+    /// use rockumentdb::datastore::datatypes::DataType;
+    /// use rockumentdb::datastore::error::DatastoreError;
+    /// use serde_json::json;
+    ///
+    /// let error = DatastoreError::DuplicateKey {
+    ///     index: String::from("email_1"),
+    ///     key: vec![(String::from("email"), DataType::String(String::from("a@b.c")))],
+    /// };
+    /// assert_eq!(json!({"index": "email_1", "key": {"email": "a@b.c"}}), error.details());
+    /// ```
+    pub fn details(&self) -> Value {
+        match self {
+            DatastoreError::Validation(violations) => json!({ "violations": violations }),
+            DatastoreError::DuplicateKey { index, key } => {
+                let key: Map<String, Value> = key
+                    .iter()
+                    .map(|(field, value)| (field.clone(), datatypes::to_json(value)))
+                    .collect();
+                json!({"index": index, "key": key})
+            }
+            DatastoreError::WriteConflict { collection, key } => {
+                json!({"collection": collection, "id": key})
+            }
+            DatastoreError::ChangeHistoryLost(token) => json!({ "token": token.to_string() }),
+            _ => Value::Null,
+        }
+    }
+}
+
+impl fmt::Display for DatastoreError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            DatastoreError::InvalidQuery => write!(f, "invalid query"),
+            DatastoreError::InvalidId => write!(f, "a query matched a missing document"),
+            DatastoreError::InvalidUpdate(message) => write!(f, "invalid update: {}", message),
+            DatastoreError::Validation(violations) => {
+                write!(f, "document failed validation")?;
+                for (position, violation) in violations.iter().enumerate() {
+                    let separator = if position == 0 { ": " } else { ", " };
+                    if violation.field.is_empty() {
+                        write!(f, "{}{}", separator, violation.message)?;
+                    } else {
+                        write!(f, "{}{} {}", separator, violation.field, violation.message)?;
+                    }
+                }
+                Ok(())
+            }
+            DatastoreError::DuplicateKey { index, key } => {
+                write!(f, "duplicate key in index {}:", index)?;
+                for (field, value) in key.iter() {
+                    write!(f, " {}={}", field, datatypes::to_json(value))?;
+                }
+                Ok(())
+            }
+            DatastoreError::TransactionNotFound => write!(f, "no such transaction"),
+            DatastoreError::CollectionNotFound(name) => write!(f, "no collection named {}", name),
+            DatastoreError::CollectionExists(name) => {
+                write!(f, "a collection named {} already exists", name)
+            }
+            DatastoreError::WriteConflict { collection, key } => write!(
+                f,
+                "document {} in {} was written since the transaction began",
+                key, collection
+            ),
+            DatastoreError::InvalidOptions(message) => write!(f, "invalid options: {}", message),
+            DatastoreError::Malformed(message) => write!(f, "malformed input: {}", message),
+            DatastoreError::ChangeHistoryLost(token) => write!(
+                f,
+                "the change events after token {} are no longer kept",
+                token
+            ),
+        }
+    }
+}
+
+impl std::error::Error for DatastoreError {}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn codes_and_details() {
+        let error = DatastoreError::WriteConflict {
+            collection: String::from("users"),
+            key: 3,
+        };
+        assert_eq!("WRITE_CONFLICT", error.code());
+        assert_eq!(json!({"collection": "users", "id": 3}), error.details());
+
+        let error = DatastoreError::Validation(vec![Violation {
+            field: String::from("age"),
+            message: String::from("must be at least 0"),
+        }]);
+        assert_eq!("VALIDATION_FAILED", error.code());
+        assert_eq!(
+            json!({"violations": [{"field": "age", "message": "must be at least 0"}]}),
+            error.details()
+        );
+        assert_eq!(
+            "document failed validation: age must be at least 0",
+            error.to_string()
+        );
+
+        let error = DatastoreError::InvalidQuery;
+        assert_eq!("INVALID_QUERY", error.code());
+        assert_eq!(Value::Null, error.details());
+    }
+}
@@ -1,10 +1,16 @@
+use crate::datastore::collection::{field_value, Document};
+use crate::datastore::datatypes::{self, DataType};
+use std::cmp::Ordering;
 use std::collections::hash_map::DefaultHasher;
 use std::collections::BTreeMap;
 use std::hash::{Hash, Hasher};
+use std::ops::Bound;
 
-#[derive(Debug)]
+#[derive(Debug, Clone)]
 pub struct Index {
     pub tree: BTreeMap<u64, Vec<usize>>,
+    /// Whether at most one document may hold each key
+    pub unique: bool,
 }
 
 impl Index {
@@ -13,12 +19,31 @@ impl Index {
     /// # Examples
     ///
     /// ```rust
-    /// use index::Index;
+    /// use rockumentdb::datastore::index::Index;
     /// let mut index = Index::new();
     /// ```
     pub fn new() -> Index {
         Index {
             tree: BTreeMap::new(),
+            unique: false,
+        }
+    }
+
+    /// Produces a new Index whose keys may each belong to one document.
+    ///
+    /// The Index itself only records the flag, the owning Collection
+    /// rejects writes that would break it.
+    ///
+    /// # Examples
+    ///
+    /// ```rust
+    /// use rockumentdb::datastore::index::Index;
+    /// let mut index = Index::new_unique();
+    /// ```
+    pub fn new_unique() -> Index {
+        Index {
+            tree: BTreeMap::new(),
+            unique: true,
         }
     }
 
@@ -32,7 +57,7 @@ impl Index {
     /// # Examples
     ///
     /// ```rust
-    /// use index::Index;
+    /// use rockumentdb::datastore::index::Index;
     /// let mut index = Index::new();
     /// let key = String::from("John Perry");
     /// let value: usize = 1;
@@ -60,18 +85,43 @@ impl Index {
     /// # Examples
     ///
     /// ```rust
-    /// use index::Index;
+    /// use rockumentdb::datastore::index::Index;
     /// let name_index = Index::new();
-    /// let results = name_index.search("John");
-    /// match results {
-    ///     Some(val) => val,
-    ///     None => vec![]
-    /// }
+    /// let results = match name_index.search(&"John") {
+    ///     Some(val) => val.clone(),
+    ///     None => vec![],
+    /// };
     /// ```
     pub fn search<T: Hash>(&self, key: &T) -> Option<&Vec<usize>> {
         let hash_key = calculate_hash(key);
         self.tree.get(&hash_key)
     }
+
+    /// Removes a document's id from the ids associated with a key
+    ///
+    /// # Arguments
+    ///
+    /// * `key` - the key the document was inserted under
+    /// * `value` - usize id of the document
+    ///
+    /// # Examples
+    ///
+    /// ```rust
+    /// use rockumentdb::datastore::index::Index;
+    /// let mut index = Index::new();
+    /// let key = String::from("John Perry");
+    /// index.insert(&key, 1);
+    /// index.remove(&key, 1);
+    /// ```
+    pub fn remove<T: Hash>(&mut self, key: &T, value: usize) {
+        let hash_key = calculate_hash(key);
+        if let Some(existing_values) = self.tree.get_mut(&hash_key) {
+            existing_values.retain(|id| *id != value);
+            if existing_values.is_empty() {
+                self.tree.remove(&hash_key);
+            }
+        }
+    }
 }
 
 impl Default for Index {
@@ -80,6 +130,215 @@ impl Default for Index {
     }
 }
 
+/// The key of a document in a CompoundIndex, `None` where the document does
+/// not have the field
+pub type CompoundKey = Vec<Option<DataType>>;
+
+/// An ordered index over a list of fields. Every document is indexed, so a
+/// CompoundIndex can answer queries on any prefix of its fields, optionally
+/// followed by a range on the next field. Keys follow DataType's order, in
+/// which numbers order by value whatever their representation.
+#[derive(Debug, Clone)]
+pub struct CompoundIndex {
+    pub fields: Vec<String>,
+    pub tree: BTreeMap<CompoundKey, Vec<usize>>,
+    /// Whether at most one document that has every field may hold each key
+    pub unique: bool,
+}
+
+impl CompoundIndex {
+    /// Produces a new CompoundIndex
+    ///
+    /// # Arguments
+    ///
+    /// * `fields` - the indexed fields, in the order they are matched
+    ///
+    /// # Examples
+    ///
+    /// ```rust
+    /// use rockumentdb::datastore::index::CompoundIndex;
+    /// let index = CompoundIndex::new(vec![String::from("tenant"), String::from("status")]);
+    /// ```
+    pub fn new(fields: Vec<String>) -> CompoundIndex {
+        CompoundIndex {
+            fields,
+            tree: BTreeMap::new(),
+            unique: false,
+        }
+    }
+
+    /// Produces a new CompoundIndex whose keys may each belong to one
+    /// document. As with `Index::new_unique`, the owning Collection
+    /// enforces the constraint.
+    ///
+    /// # Arguments
+    ///
+    /// * `fields` - the indexed fields, in the order they are matched
+    pub fn new_unique(fields: Vec<String>) -> CompoundIndex {
+        CompoundIndex {
+            unique: true,
+            ..CompoundIndex::new(fields)
+        }
+    }
+
+    /// Produces the index's name, e.g. `tenant_1_status_1`
+    pub fn name(&self) -> String {
+        index_name(&self.fields)
+    }
+
+    /// Produces the key a document is indexed under
+    ///
+    /// # Arguments
+    ///
+    /// * `document` - the document to index
+    pub fn key(&self, document: &Document) -> CompoundKey {
+        self.fields
+            .iter()
+            .map(|field| field_value(document, field).cloned())
+            .collect()
+    }
+
+    /// Adds a document's id under the document's key
+    ///
+    /// # Arguments
+    ///
+    /// * `document` - the document to index
+    /// * `value` - usize id of the document
+    pub fn insert(&mut self, document: &Document, value: usize) {
+        self.tree.entry(self.key(document)).or_default().push(value);
+    }
+
+    /// Removes a document's id from the ids associated with its key
+    ///
+    /// # Arguments
+    ///
+    /// * `document` - the document as it was indexed
+    /// * `value` - usize id of the document
+    pub fn remove(&mut self, document: &Document, value: usize) {
+        let key = self.key(document);
+        if let Some(existing_values) = self.tree.get_mut(&key) {
+            existing_values.retain(|id| *id != value);
+            if existing_values.is_empty() {
+                self.tree.remove(&key);
+            }
+        }
+    }
+
+    /// Produces all ids stored under an exact key
+    ///
+    /// # Arguments
+    ///
+    /// * `key` - a key produced by `key`
+    pub fn search(&self, key: &[Option<DataType>]) -> Option<&Vec<usize>> {
+        self.tree.get(key)
+    }
+
+    /// Produces the ids of the documents whose leading fields equal
+    /// `prefix` and whose next field, if bounded, lies within the bounds.
+    /// Values of a different type than a bound are not within it.
+    ///
+    /// # Arguments
+    ///
+    /// * `prefix` - values of the leading fields
+    /// * `lower` - lower bound on the field after the prefix
+    /// * `upper` - upper bound on the field after the prefix
+    ///
+    /// # Examples
+    ///
+    /// ```rust
+    /// use rockumentdb::datastore::datatypes::DataType;
+    /// use rockumentdb::datastore::index::CompoundIndex;
+    /// use std::ops::Bound;
+    ///
+    /// let index = CompoundIndex::new(vec![String::from("tenant"), String::from("age")]);
+    /// let ids = index.scan(
+    ///     &[DataType::String(String::from("a"))],
+    ///     Bound::Included(&DataType::U64(30)),
+    ///     Bound::Unbounded,
+    /// );
+    /// ```
+    pub fn scan(
+        &self,
+        prefix: &[DataType],
+        lower: Bound<&DataType>,
+        upper: Bound<&DataType>,
+    ) -> Vec<usize> {
+        let depth = prefix.len();
+        let mut start: CompoundKey = prefix.iter().map(|value| Some(value.clone())).collect();
+        if let Bound::Included(value) | Bound::Excluded(value) = lower {
+            start.push(Some(value.clone()));
+        }
+        let ranged = !matches!((lower, upper), (Bound::Unbounded, Bound::Unbounded));
+
+        let mut ids = Vec::new();
+        for (key, values) in self.tree.range(start.clone()..) {
+            if key[..depth] != start[..depth] {
+                break;
+            }
+            if ranged {
+                let value = match key.get(depth) {
+                    Some(Some(value)) => value,
+                    _ => continue,
+                };
+                let past_upper = match upper {
+                    Bound::Included(bound) => value > bound,
+                    Bound::Excluded(bound) => value >= bound,
+                    Bound::Unbounded => false,
+                };
+                if past_upper {
+                    break;
+                }
+                if !within(value, lower, upper) {
+                    continue;
+                }
+            }
+            ids.extend(values.iter());
+        }
+        ids
+    }
+}
+
+/// Produces the name of the index over a list of fields, e.g.
+/// `tenant_1_status_1`
+///
+/// # Arguments
+///
+/// * `fields` - the indexed fields
+pub fn index_name(fields: &[String]) -> String {
+    fields
+        .iter()
+        .map(|field| format!("{}_1", field))
+        .collect::<Vec<String>>()
+        .join("_")
+}
+
+/// Produces whether a value lies within a pair of bounds
+///
+/// # Arguments
+///
+/// * `value` - the value to check
+/// * `lower` - the lower bound
+/// * `upper` - the upper bound
+pub fn within(value: &DataType, lower: Bound<&DataType>, upper: Bound<&DataType>) -> bool {
+    let above_lower = match lower {
+        Bound::Included(bound) => matches!(
+            datatypes::compare(value, bound),
+            Some(Ordering::Greater) | Some(Ordering::Equal)
+        ),
+        Bound::Excluded(bound) => datatypes::compare(value, bound) == Some(Ordering::Greater),
+        Bound::Unbounded => true,
+    };
+    let below_upper = match upper {
+        Bound::Included(bound) => matches!(
+            datatypes::compare(value, bound),
+            Some(Ordering::Less) | Some(Ordering::Equal)
+        ),
+        Bound::Excluded(bound) => datatypes::compare(value, bound) == Some(Ordering::Less),
+        Bound::Unbounded => true,
+    };
+    above_lower && below_upper
+}
+
 fn calculate_hash<T: Hash>(t: &T) -> u64 {
     let mut s = DefaultHasher::new();
     t.hash(&mut s);
@@ -89,6 +348,7 @@ fn calculate_hash<T: Hash>(t: &T) -> u64 {
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::datastore::datatypes::Float;
 
     #[test]
     fn insert_new_key() {
@@ -134,4 +394,113 @@ mod tests {
         index.insert(&key, value2);
         assert_eq!(Some(&vec![value, value2]), index.search(&key));
     }
+
+    #[test]
+    fn search_equal_number() {
+        let mut index = Index::new();
+        index.insert(&DataType::I64(75i64), 1);
+        index.insert(&DataType::F64(Float::new(75.0)), 2);
+        assert_eq!(Some(&vec![1usize, 2]), index.search(&DataType::U64(75u64)));
+    }
+
+    #[test]
+    fn remove_one_of_two_values() {
+        let mut index = Index::new();
+        let key = String::from("John Perry");
+        index.insert(&key, 1);
+        index.insert(&key, 2);
+        index.remove(&key, 1);
+        assert_eq!(Some(&vec![2usize]), index.search(&key));
+    }
+
+    #[test]
+    fn remove_last_value() {
+        let mut index = Index::new();
+        let key = String::from("John Perry");
+        index.insert(&key, 1);
+        index.remove(&key, 1);
+        assert_eq!(None, index.search(&key));
+        assert!(index.tree.is_empty());
+    }
+
+    fn ticket(tenant: &str, age: u64) -> Document {
+        let mut document = Document::new();
+        document.insert(
+            String::from("tenant"),
+            DataType::String(String::from(tenant)),
+        );
+        document.insert(String::from("age"), DataType::U64(age));
+        document
+    }
+
+    fn tenant_age_index() -> CompoundIndex {
+        let mut index = CompoundIndex::new(vec![String::from("tenant"), String::from("age")]);
+        index.insert(&ticket("a", 10), 1);
+        index.insert(&ticket("a", 30), 2);
+        index.insert(&ticket("b", 20), 3);
+        index.insert(&ticket("a", 50), 4);
+        index.insert(&Document::new(), 5);
+        index
+    }
+
+    #[test]
+    fn compound_name() {
+        assert_eq!("tenant_1_age_1", tenant_age_index().name());
+    }
+
+    #[test]
+    fn compound_scan_prefix() {
+        let index = tenant_age_index();
+        let tenant = [DataType::String(String::from("a"))];
+        assert_eq!(
+            vec![1usize, 2, 4],
+            index.scan(&tenant, Bound::Unbounded, Bound::Unbounded)
+        );
+        // Documents without the fields are indexed first
+        assert_eq!(
+            vec![5usize, 1, 2, 4, 3],
+            index.scan(&[], Bound::Unbounded, Bound::Unbounded)
+        );
+    }
+
+    #[test]
+    fn compound_scan_full_key() {
+        let index = tenant_age_index();
+        let key = [DataType::String(String::from("b")), DataType::I64(20)];
+        assert_eq!(
+            vec![3usize],
+            index.scan(&key, Bound::Unbounded, Bound::Unbounded)
+        );
+    }
+
+    #[test]
+    fn compound_scan_range() {
+        let index = tenant_age_index();
+        let tenant = [DataType::String(String::from("a"))];
+        let low = DataType::U64(10);
+        let high = DataType::F64(Float::new(50.0));
+        assert_eq!(
+            vec![2usize],
+            index.scan(&tenant, Bound::Excluded(&low), Bound::Excluded(&high))
+        );
+        assert_eq!(
+            vec![2usize, 4],
+            index.scan(&tenant, Bound::Excluded(&low), Bound::Unbounded)
+        );
+        assert_eq!(
+            vec![1usize, 2, 4],
+            index.scan(&tenant, Bound::Unbounded, Bound::Included(&high))
+        );
+    }
+
+    #[test]
+    fn compound_remove() {
+        let mut index = tenant_age_index();
+        index.remove(&ticket("a", 30), 2);
+        let tenant = [DataType::String(String::from("a"))];
+        assert_eq!(
+            vec![1usize, 4],
+            index.scan(&tenant, Bound::Unbounded, Bound::Unbounded)
+        );
+    }
 }
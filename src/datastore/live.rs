@@ -0,0 +1,236 @@
+use crate::datastore::changes::{ChangeEvent, Operation};
+use crate::datastore::collection::{Collection, Document};
+use crate::datastore::error::DatastoreError;
+use crate::datastore::query_proc::{Filter, Query};
+use serde_json::Value;
+use std::collections::BTreeSet;
+use std::convert::TryFrom;
+
+/// How the result set of a live query changed
+#[derive(Debug, Clone, PartialEq)]
+pub enum Diff {
+    /// A document entered the result set
+    Added(usize, Document),
+    /// A document in the result set was updated and still matches
+    Changed(usize, Document),
+    /// A document left the result set, updated so it no longer matches or
+    /// deleted
+    Removed(usize),
+}
+
+/// A find filter whose result set is followed as the collection is written,
+/// by reading the collection's change events
+#[derive(Debug, Clone)]
+pub struct LiveQuery {
+    /// The query, to read the result set through the collection's indexes
+    source: Query,
+    filter: Filter,
+    /// Keys of the documents in the result set
+    members: BTreeSet<usize>,
+    /// Token of the last change event applied
+    token: u64,
+}
+
+impl LiveQuery {
+    /// Produces a LiveQuery and its initial result set, in key order
+    ///
+    /// # Arguments
+    ///
+    /// * `collection` - the collection to query
+    /// * `filter` - JSON filter, see `Query::from_json`
+    ///
+    /// # Examples
+    ///
+    /// ```rust
+    /// use rockumentdb::datastore::collection::Collection;
+    /// use rockumentdb::datastore::datatypes::from_json;
+    /// use rockumentdb::datastore::live::{Diff, LiveQuery};
+    /// use serde_json::json;
+    /// use std::collections::HashMap;
+    ///
+    /// let mut collection = Collection::new(String::from("crew"));
+    /// let filter = json!({"rank": "captain"});
+    /// let (mut query, initial) = LiveQuery::new(&collection, &filter).unwrap();
+    /// assert!(initial.is_empty());
+    ///
+    /// let mut document = HashMap::new();
+    /// document.insert(String::from("rank"), from_json(&json!("captain")));
+    /// let key = collection.insert(document.clone()).unwrap();
+    /// assert_eq!(vec![Diff::Added(key, document)], query.poll(&collection).unwrap());
+    /// ```
+    pub fn new(
+        collection: &Collection,
+        filter: &Value,
+    ) -> Result<(LiveQuery, Vec<(usize, Document)>), DatastoreError> {
+        let source = Query::from_json(filter)?;
+        let initial = LiveQuery::result_set(collection, &source)?;
+        let query = LiveQuery {
+            filter: Filter::try_from(source.clone())?,
+            source,
+            members: initial.iter().map(|(key, _)| *key).collect(),
+            token: collection.version(),
+        };
+        Ok((query, initial))
+    }
+
+    fn result_set(
+        collection: &Collection,
+        query: &Query,
+    ) -> Result<Vec<(usize, Document)>, DatastoreError> {
+        let keys = collection.run(query)?.ids;
+        keys.into_iter()
+            .map(|key| match collection.get(key) {
+                Some(document) => Ok((key, document.clone())),
+                None => Err(DatastoreError::InvalidId),
+            })
+            .collect()
+    }
+
+    /// Produces the diffs of the writes to the collection since the last
+    /// poll. When the collection no longer keeps the change events since
+    /// then, the result set is read again: documents that left it are
+    /// removed, and every other document is sent as added or changed.
+    ///
+    /// # Arguments
+    ///
+    /// * `collection` - the collection the LiveQuery was made from
+    pub fn poll(&mut self, collection: &Collection) -> Result<Vec<Diff>, DatastoreError> {
+        match collection.changes_since(self.token) {
+            Ok(changes) => Ok(self.apply(&changes)),
+            Err(DatastoreError::ChangeHistoryLost(_)) => self.resync(collection),
+            Err(error) => Err(error),
+        }
+    }
+
+    /// Produces the diffs of a list of change events, oldest first
+    ///
+    /// # Arguments
+    ///
+    /// * `changes` - change events after the last one applied
+    pub fn apply(&mut self, changes: &[ChangeEvent]) -> Vec<Diff> {
+        let mut diffs = Vec::new();
+        for change in changes {
+            if change.token <= self.token {
+                continue;
+            }
+            self.token = change.token;
+            let matches =
+                change.operation != Operation::Delete && self.filter.matches(&change.document);
+            let member = self.members.contains(&change.key);
+            match (member, matches) {
+                (false, true) => {
+                    self.members.insert(change.key);
+                    diffs.push(Diff::Added(change.key, change.document.clone()));
+                }
+                (true, true) => diffs.push(Diff::Changed(change.key, change.document.clone())),
+                (true, false) => {
+                    self.members.remove(&change.key);
+                    diffs.push(Diff::Removed(change.key));
+                }
+                (false, false) => {}
+            }
+        }
+        diffs
+    }
+
+    fn resync(&mut self, collection: &Collection) -> Result<Vec<Diff>, DatastoreError> {
+        let current = LiveQuery::result_set(collection, &self.source)?;
+        let keys: BTreeSet<usize> = current.iter().map(|(key, _)| *key).collect();
+        let mut diffs: Vec<Diff> = self
+            .members
+            .difference(&keys)
+            .map(|key| Diff::Removed(*key))
+            .collect();
+        for (key, document) in current {
+            if self.members.contains(&key) {
+                diffs.push(Diff::Changed(key, document));
+            } else {
+                diffs.push(Diff::Added(key, document));
+            }
+        }
+        self.members = keys;
+        self.token = collection.version();
+        Ok(diffs)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::datastore::datatypes::{self, DataType};
+    use crate::datastore::update;
+    use serde_json::json;
+
+    fn document(value: Value) -> Document {
+        match datatypes::from_json(&value) {
+            DataType::Document(fields) => fields.into_iter().collect(),
+            _ => panic!("expected a document"),
+        }
+    }
+
+    fn update(collection: &mut Collection, key: usize, value: Value) {
+        let update =
+            update::from_json(&value.as_object().unwrap().clone().into_iter().collect()).unwrap();
+        collection.update_ids(&[key], &update).unwrap();
+    }
+
+    #[test]
+    fn documents_enter_and_leave() {
+        let mut collection = Collection::new(String::from("crew"));
+        let john = collection
+            .insert(document(json!({"username": "johnperry", "age": 75})))
+            .unwrap();
+        let louis = collection
+            .insert(document(json!({"username": "louiswu", "age": 200})))
+            .unwrap();
+        let (mut query, initial) =
+            LiveQuery::new(&collection, &json!({"age": {"$gte": 100}})).unwrap();
+        assert_eq!(vec![louis], initial.iter().map(|e| e.0).collect::<Vec<_>>());
+
+        update(&mut collection, john, json!({"$set": {"age": 120}}));
+        update(&mut collection, louis, json!({"$inc": {"age": 1}}));
+        update(&mut collection, louis, json!({"$set": {"age": 20}}));
+        collection.remove(john);
+        let jane = collection
+            .insert(document(json!({"username": "jane", "age": 30})))
+            .unwrap();
+        assert_eq!(
+            vec![
+                Diff::Added(john, document(json!({"username": "johnperry", "age": 120}))),
+                Diff::Changed(louis, document(json!({"username": "louiswu", "age": 201}))),
+                Diff::Removed(louis),
+                Diff::Removed(john),
+            ],
+            query.poll(&collection).unwrap()
+        );
+        collection.remove(jane);
+        assert!(query.poll(&collection).unwrap().is_empty());
+    }
+
+    #[test]
+    fn resync_after_lost_history() {
+        let mut collection = Collection::new(String::from("crew"));
+        let john = collection
+            .insert(document(json!({"rank": "captain"})))
+            .unwrap();
+        let louis = collection
+            .insert(document(json!({"rank": "captain"})))
+            .unwrap();
+        let (mut query, _) = LiveQuery::new(&collection, &json!({"rank": "captain"})).unwrap();
+        collection.remove(louis);
+        let jane = collection
+            .insert(document(json!({"rank": "captain"})))
+            .unwrap();
+        // A copy keeps no change events, as if they had all been discarded
+        let copy = collection.clone();
+        assert_eq!(
+            vec![
+                Diff::Removed(louis),
+                Diff::Changed(john, document(json!({"rank": "captain"}))),
+                Diff::Added(jane, document(json!({"rank": "captain"}))),
+            ],
+            query.poll(&copy).unwrap()
+        );
+        assert!(LiveQuery::new(&collection, &json!([1])).is_err());
+    }
+}
@@ -1,4 +1,18 @@
+pub mod bson;
+pub mod bulk;
+pub mod capped;
+pub mod changes;
 pub mod collection;
+pub mod csv;
+pub mod database;
 pub mod datatypes;
+pub mod error;
 pub mod index;
+pub mod live;
 pub mod query_proc;
+pub mod schema;
+pub mod text;
+pub mod transaction;
+pub mod ttl;
+pub mod typed;
+pub mod update;
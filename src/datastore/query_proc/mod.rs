@@ -1,27 +1,221 @@
+mod options;
 mod query_executor;
 mod query_ingestor;
+mod query_planner;
 
-use crate::datastore::collection::{Document, Indices, Store};
+pub use options::{FindOptions, Projection, SortOrder, NATURAL};
+
+use crate::datastore::collection::{CompoundIndices, Document, Indices, Store};
+use crate::datastore::error::DatastoreError;
+use crate::datastore::query_proc::query_ingestor::Instructions;
+use crate::datastore::text::{Search, TextIndex};
 use serde::Serialize;
+use serde_json::Value;
+use std::collections::HashMap;
+use std::convert::TryFrom;
+
+/// The documents a query found, or why it failed
+pub type QueryResult<'a> = Result<Vec<&'a Document>, DatastoreError>;
+
+/// How a query was executed: the stages of the chosen plan in order, with
+/// the planner's estimate and the actual number of documents each stage
+/// examined and returned
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct Explain {
+    pub stages: Vec<StageReport>,
+    /// Estimated number of documents read from indexes and the store
+    pub cost: usize,
+    pub returned: usize,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct StageReport {
+    pub stage: &'static str,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub index: Option<String>,
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub predicates: Vec<String>,
+    pub estimated: usize,
+    pub examined: usize,
+    pub returned: usize,
+}
+
+/// The store of a Collection and every index that may answer part of a
+/// query against it
+#[derive(Debug, Clone, Copy)]
+pub struct Indexes<'a> {
+    pub store: &'a Store,
+    pub indices: &'a Indices,
+    pub compound_indices: &'a CompoundIndices,
+    pub text_index: Option<&'a TextIndex>,
+}
+
+/// The documents a Query matched, and how they were found
+#[derive(Debug, Clone, PartialEq)]
+pub struct Matches {
+    /// Keys of the matching documents, in key order
+    pub ids: Vec<usize>,
+    /// Relevance of each matching document to the query's `$text` search,
+    /// keyed by document key, empty without one
+    pub scores: HashMap<usize, f64>,
+    pub explain: Explain,
+}
+
+/// A query parsed once, from a query string, a JSON filter or a filter
+/// document, to run against a collection
+#[derive(Debug, Clone, PartialEq)]
+pub struct Query {
+    instructions: Vec<Instructions>,
+}
+
+impl Query {
+    /// Produces the Query of a query string
+    ///
+    /// # Arguments
+    ///
+    /// * `query` - query string, see `Collection::find`
+    ///
+    /// # Examples
+    ///
+    /// ```rust
+    /// use rockumentdb::datastore::query_proc::Query;
+    ///
+    /// assert!(Query::parse("{rank:\"captain\"}").is_ok());
+    /// assert!(Query::parse("{rank:").is_err());
+    /// ```
+    pub fn parse(query: &str) -> Result<Query, DatastoreError> {
+        Ok(Query {
+            instructions: query_ingestor::ingest(query)?,
+        })
+    }
+
+    /// Produces the Query of a JSON filter
+    ///
+    /// # Arguments
+    ///
+    /// * `filter` - JSON filter, see `query_ingestor::ingest_json`, null
+    ///   matching every document
+    pub fn from_json(filter: &Value) -> Result<Query, DatastoreError> {
+        Ok(Query {
+            instructions: query_ingestor::ingest_json(filter)?,
+        })
+    }
+
+    /// Produces the Query of a filter document, see
+    /// `query_ingestor::ingest_document`
+    ///
+    /// # Arguments
+    ///
+    /// * `filter` - the filter, empty matching every document
+    pub fn from_document(filter: &Document) -> Result<Query, DatastoreError> {
+        Ok(Query {
+            instructions: query_ingestor::ingest_document(filter)?,
+        })
+    }
+
+    /// Produces the search string of the query's `$text` operator, if it
+    /// has one
+    ///
+    /// # Examples
+    ///
+    /// ```rust
+    /// use rockumentdb::datastore::query_proc::Query;
+    /// use serde_json::json;
+    ///
+    /// let filter = json!({"$text": {"$search": "ringworld"}, "year": 1970});
+    /// let query = Query::from_json(&filter).unwrap();
+    /// assert_eq!(Some("ringworld"), query.text_search());
+    /// ```
+    pub fn text_search(&self) -> Option<&str> {
+        self.instructions
+            .iter()
+            .find_map(|instruction| match instruction {
+                Instructions::Text(search) => Some(search.as_str()),
+                _ => None,
+            })
+    }
+
+    /// Plans and runs the query, producing the keys of the matching
+    /// documents and how they were found. Only a collection with a
+    /// TextIndex can answer a `$text` search.
+    ///
+    /// # Arguments
+    ///
+    /// * `indexes` - the store and indexes of the collection to query
+    pub fn run(&self, indexes: Indexes) -> Result<Matches, DatastoreError> {
+        if self.text_search().is_some() && indexes.text_index.is_none() {
+            return Err(DatastoreError::InvalidQuery);
+        }
+        Ok(query_executor::execute(&self.instructions, indexes))
+    }
+
+    /// Produces the relevance of a document to the query's `$text` search,
+    /// 0 without one, or None if the document does not match the query,
+    /// as it would be judged in a collection with the given TextIndex
+    ///
+    /// # Arguments
+    ///
+    /// * `document` - the document to test
+    /// * `text_index` - the TextIndex of the document's collection, if it
+    ///   has one
+    pub fn relevance(&self, document: &Document, text_index: Option<&TextIndex>) -> Option<f64> {
+        let mut relevance = 0.0;
+        for instruction in self.instructions.iter() {
+            match instruction {
+                Instructions::Text(search) => {
+                    relevance = text_index?.score(&Search::parse(search), document)?
+                }
+                _ if !query_executor::matches_all(document, std::slice::from_ref(instruction)) => {
+                    return None
+                }
+                _ => {}
+            }
+        }
+        Some(relevance)
+    }
+}
+
+/// A Query tested against documents one at a time, such as the documents
+/// of a collection's change events. Without a TextIndex to search, it
+/// cannot hold a `$text` search.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Filter {
+    instructions: Vec<Instructions>,
+}
+
+impl TryFrom<Query> for Filter {
+    type Error = DatastoreError;
+
+    fn try_from(query: Query) -> Result<Filter, DatastoreError> {
+        if query.text_search().is_some() {
+            return Err(DatastoreError::InvalidQuery);
+        }
+        Ok(Filter {
+            instructions: query.instructions,
+        })
+    }
+}
 
-#[derive(Debug, Serialize)]
-pub enum QueryResult<'a> {
-    Data(Vec<&'a Document>),
-    InvalidQueryError,
-    InvalidIdError,
-}
-
-/// Produces the results of a query against a Collection
-///
-/// # Arguments
-///
-/// * `command` - query string
-/// * `store` - the Collection's store to operate on
-/// * `indices` - mapping of the collection's Index structs to their field name.
-///
-pub fn process_query<'a>(command: &String, store: &'a Store, indices: &Indices) -> QueryResult<'a> {
-    match query_ingestor::ingest(command) {
-        Ok(instructions) => query_executor::process_instructions(instructions, store, indices),
-        Err(e) => e,
+impl Filter {
+    /// Produces whether a document matches the filter, exactly as it would
+    /// when found in a collection
+    ///
+    /// # Examples
+    ///
+    /// ```rust
+    /// use rockumentdb::datastore::datatypes::DataType;
+    /// use rockumentdb::datastore::query_proc::{Filter, Query};
+    /// use serde_json::json;
+    /// use std::collections::HashMap;
+    /// use std::convert::TryFrom;
+    ///
+    /// let query = Query::from_json(&json!({"age": {"$gte": 30}})).unwrap();
+    /// let filter = Filter::try_from(query).unwrap();
+    /// let mut document = HashMap::new();
+    /// document.insert(String::from("age"), DataType::I64(75));
+    /// assert!(filter.matches(&document));
+    /// ```
+    pub fn matches(&self, document: &Document) -> bool {
+        query_executor::matches_all(document, &self.instructions)
     }
 }
@@ -0,0 +1,340 @@
+use crate::datastore::collection::{field_value, Document};
+use crate::datastore::datatypes::DataType;
+use serde_json::Value;
+use std::cmp::Ordering;
+use std::collections::BTreeMap;
+
+/// The fields of the found documents a find produces
+#[derive(Debug, Clone, PartialEq)]
+pub enum Projection {
+    /// Only the listed fields or dotted paths
+    Include(Vec<String>),
+    /// Every field but the listed fields or dotted paths
+    Exclude(Vec<String>),
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum SortOrder {
+    Ascending,
+    Descending,
+}
+
+/// The sort field ordering documents by insertion, their natural order
+pub const NATURAL: &str = "$natural";
+
+/// How the documents matching a find's filter are ordered, paged and
+/// projected. By default every matching document is produced whole, in
+/// insertion order.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct FindOptions {
+    pub projection: Option<Projection>,
+    /// Fields to sort by, the first deciding unless documents tie on it
+    pub sort: Vec<(String, SortOrder)>,
+    pub skip: usize,
+    pub limit: Option<usize>,
+}
+
+impl FindOptions {
+    /// Produces the FindOptions of a MongoDB style find
+    ///
+    /// # Arguments
+    ///
+    /// * `projection` - fields mapped to 1 to include them or 0 to exclude
+    ///   them, e.g. `{"username": 1, "address.city": 1}`
+    /// * `sort` - fields mapped to 1 for ascending or -1 for descending
+    ///   order, in order of priority, or `$natural` alone for insertion
+    ///   order, -1 producing the newest documents first
+    /// * `skip` - number of sorted documents to leave out
+    /// * `limit` - largest number of documents to produce, 0 for no limit
+    ///
+    /// # Examples
+    ///
+    /// ```rust
+    /// use rockumentdb::datastore::query_proc::{FindOptions, SortOrder};
+    /// use serde_json::json;
+    ///
+    /// let options = FindOptions::from_json(None, Some(&json!({"age": -1})), 0, 10).unwrap();
+    /// assert_eq!(vec![(String::from("age"), SortOrder::Descending)], options.sort);
+    /// ```
+    pub fn from_json(
+        projection: Option<&Value>,
+        sort: Option<&Value>,
+        skip: usize,
+        limit: usize,
+    ) -> Result<FindOptions, String> {
+        Ok(FindOptions {
+            projection: match projection {
+                None | Some(Value::Null) => None,
+                Some(projection) => projection_from_json(projection)?,
+            },
+            sort: match sort {
+                None | Some(Value::Null) => Vec::new(),
+                Some(sort) => sort_from_json(sort)?,
+            },
+            skip,
+            limit: if limit == 0 { None } else { Some(limit) },
+        })
+    }
+
+    /// Produces the documents to return of those matching a find's filter
+    ///
+    /// # Arguments
+    ///
+    /// * `documents` - the matching documents, in insertion order
+    pub fn apply(&self, mut documents: Vec<&Document>) -> Vec<Document> {
+        if let [(field, order)] = self.sort.as_slice() {
+            if field == NATURAL {
+                if *order == SortOrder::Descending {
+                    documents.reverse();
+                }
+                return self.page(documents);
+            }
+        }
+        if !self.sort.is_empty() {
+            // The sort is stable, documents that tie stay in insertion order
+            documents.sort_by(|left, right| self.compare(left, right));
+        }
+        self.page(documents)
+    }
+
+    /// Produces the sorted documents past `skip`, up to `limit`, projected
+    fn page(&self, documents: Vec<&Document>) -> Vec<Document> {
+        documents
+            .into_iter()
+            .skip(self.skip)
+            .take(self.limit.unwrap_or(usize::MAX))
+            .map(|document| match &self.projection {
+                None => document.clone(),
+                Some(projection) => project(document, projection),
+            })
+            .collect()
+    }
+
+    /// Orders two documents by the sort fields. Missing fields sort before
+    /// every value.
+    fn compare(&self, left: &Document, right: &Document) -> Ordering {
+        for (field, order) in self.sort.iter() {
+            let ordering = field_value(left, field).cmp(&field_value(right, field));
+            let ordering = match order {
+                SortOrder::Ascending => ordering,
+                SortOrder::Descending => ordering.reverse(),
+            };
+            if ordering != Ordering::Equal {
+                return ordering;
+            }
+        }
+        Ordering::Equal
+    }
+}
+
+/// Produces the Projection of a projection document. As in MongoDB an
+/// inclusion keeps `_id` unless it is excluded, the one exclusion it may
+/// hold. An empty projection projects nothing.
+fn projection_from_json(projection: &Value) -> Result<Option<Projection>, String> {
+    let fields = match projection {
+        Value::Object(fields) => fields,
+        _ => return Err(String::from("projection must be an object")),
+    };
+    let (mut included, mut excluded) = (Vec::new(), Vec::new());
+    for (field, value) in fields.iter() {
+        let include = match value {
+            Value::Bool(include) => *include,
+            Value::Number(number) if number.as_f64() == Some(1.0) => true,
+            Value::Number(number) if number.as_f64() == Some(0.0) => false,
+            _ => {
+                return Err(format!(
+                    "projection of {} must be 1, 0, true or false",
+                    field
+                ))
+            }
+        };
+        if include {
+            included.push(field.clone());
+        } else {
+            excluded.push(field.clone());
+        }
+    }
+    if !included.is_empty() {
+        let id_excluded = excluded.iter().any(|field| field == "_id");
+        excluded.retain(|field| field != "_id");
+        if !id_excluded && !included.iter().any(|field| field == "_id") {
+            included.push(String::from("_id"));
+        }
+        if !excluded.is_empty() {
+            return Err(String::from(
+                "a projection cannot both include and exclude fields",
+            ));
+        }
+        return Ok(Some(Projection::Include(included)));
+    }
+    if excluded.is_empty() {
+        return Ok(None);
+    }
+    Ok(Some(Projection::Exclude(excluded)))
+}
+
+fn sort_from_json(sort: &Value) -> Result<Vec<(String, SortOrder)>, String> {
+    let fields = match sort {
+        Value::Object(fields) => fields,
+        _ => return Err(String::from("sort must be an object")),
+    };
+    if fields.contains_key(NATURAL) && fields.len() > 1 {
+        return Err(format!("{} cannot be sorted with other fields", NATURAL));
+    }
+    fields
+        .iter()
+        .map(|(field, order)| match order.as_i64() {
+            Some(1) => Ok((field.clone(), SortOrder::Ascending)),
+            Some(-1) => Ok((field.clone(), SortOrder::Descending)),
+            _ => Err(format!("sort order of {} must be 1 or -1", field)),
+        })
+        .collect()
+}
+
+/// Produces the fields of a document a Projection keeps
+fn project(document: &Document, projection: &Projection) -> Document {
+    match projection {
+        Projection::Include(paths) => {
+            let mut projected = Document::new();
+            for path in paths.iter() {
+                if let Some(value) = document.get(path) {
+                    projected.insert(path.clone(), value.clone());
+                } else if let Some(value) = field_value(document, path) {
+                    let segments: Vec<&str> = path.split('.').collect();
+                    include_path(&mut projected, &segments, value.clone());
+                }
+            }
+            projected
+        }
+        Projection::Exclude(paths) => {
+            let mut projected = document.clone();
+            for path in paths.iter() {
+                if projected.remove(path).is_none() {
+                    let segments: Vec<&str> = path.split('.').collect();
+                    exclude_path(&mut projected, &segments);
+                }
+            }
+            projected
+        }
+    }
+}
+
+/// Sets a value at a dotted path, creating the embedded documents on the
+/// way
+fn include_path(document: &mut Document, segments: &[&str], value: DataType) {
+    let (field, rest) = match segments.split_first() {
+        Some(split) => split,
+        None => return,
+    };
+    if rest.is_empty() {
+        document.insert(String::from(*field), value);
+        return;
+    }
+    let embedded = document
+        .entry(String::from(*field))
+        .or_insert_with(|| DataType::Document(BTreeMap::new()));
+    if let DataType::Document(fields) = embedded {
+        let mut inner: Document = std::mem::take(fields).into_iter().collect();
+        include_path(&mut inner, rest, value);
+        *fields = inner.into_iter().collect();
+    }
+}
+
+/// Removes the value at a dotted path, if it is there
+fn exclude_path(document: &mut Document, segments: &[&str]) {
+    let (field, rest) = match segments.split_first() {
+        Some(split) => split,
+        None => return,
+    };
+    if rest.is_empty() {
+        document.remove(*field);
+        return;
+    }
+    if let Some(DataType::Document(fields)) = document.get_mut(*field) {
+        let mut inner: Document = std::mem::take(fields).into_iter().collect();
+        exclude_path(&mut inner, rest);
+        *fields = inner.into_iter().collect();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::datastore::datatypes::from_json;
+    use serde_json::json;
+
+    fn document(value: Value) -> Document {
+        match from_json(&value) {
+            DataType::Document(fields) => fields.into_iter().collect(),
+            _ => panic!("expected a document"),
+        }
+    }
+
+    #[test]
+    fn sort_skip_and_limit() {
+        let documents = [
+            document(json!({"name": "a", "team": 1, "age": 30})),
+            document(json!({"name": "b", "team": 2, "age": 20})),
+            document(json!({"name": "c", "team": 1, "age": 40})),
+            document(json!({"name": "d"})),
+        ];
+        let options =
+            FindOptions::from_json(None, Some(&json!({"team": -1, "age": 1})), 1, 2).unwrap();
+        let found = options.apply(documents.iter().collect());
+        let names: Vec<&DataType> = found.iter().map(|document| &document["name"]).collect();
+        assert_eq!(
+            vec![
+                &DataType::String(String::from("a")),
+                &DataType::String(String::from("c"))
+            ],
+            names
+        );
+        assert!(FindOptions::from_json(None, Some(&json!({"age": 2})), 0, 0).is_err());
+    }
+
+    #[test]
+    fn sort_in_natural_order() {
+        let documents = [
+            document(json!({"name": "a"})),
+            document(json!({"name": "b"})),
+            document(json!({"name": "c"})),
+        ];
+        let options = FindOptions::from_json(None, Some(&json!({"$natural": -1})), 0, 2).unwrap();
+        let found = options.apply(documents.iter().collect());
+        assert_eq!(vec![documents[2].clone(), documents[1].clone()], found);
+        let sort = json!({"$natural": 1, "name": 1});
+        assert!(FindOptions::from_json(None, Some(&sort), 0, 0).is_err());
+    }
+
+    #[test]
+    fn project_fields() {
+        let john = document(json!({
+            "_id": 1,
+            "username": "johnperry",
+            "address": {"city": "Ohio", "zip": "007"}
+        }));
+        let include = projection_from_json(&json!({"username": 1, "address.city": 1, "_id": 0}))
+            .unwrap()
+            .unwrap();
+        assert_eq!(
+            document(json!({"username": "johnperry", "address": {"city": "Ohio"}})),
+            project(&john, &include)
+        );
+        let exclude = projection_from_json(&json!({"address.zip": 0, "_id": false}))
+            .unwrap()
+            .unwrap();
+        assert_eq!(
+            document(json!({"username": "johnperry", "address": {"city": "Ohio"}})),
+            project(&john, &exclude)
+        );
+        let with_id = projection_from_json(&json!({"username": 1}))
+            .unwrap()
+            .unwrap();
+        assert_eq!(
+            document(json!({"_id": 1, "username": "johnperry"})),
+            project(&john, &with_id)
+        );
+        assert!(projection_from_json(&json!({"username": 1, "age": 0})).is_err());
+        assert_eq!(None, projection_from_json(&json!({})).unwrap());
+    }
+}
@@ -1,19 +1,26 @@
-use crate::datastore::collection::{Indices, Store};
-use crate::datastore::query_proc::query_ingestor::Instructions;
-use crate::datastore::query_proc::QueryResult;
-use std::collections::HashSet;
+use crate::datastore::collection::{field_value, Document, Store};
+use crate::datastore::index;
+use crate::datastore::query_proc::query_ingestor::{Comparison, Instructions};
+use crate::datastore::query_proc::query_planner::{self, Access};
+use crate::datastore::query_proc::{Explain, Indexes, Matches, StageReport};
+use std::collections::{HashMap, HashSet};
+use std::ops::Bound;
 
-/// Produces the results of the query operations
+/// Plans and runs the query operations, producing the sorted ids of the
+/// matching documents and a report of each stage
+///
+/// Instructions are combined with AND: a document matches when it
+/// satisfies every instruction, whichever indexes the plan uses, and an
+/// empty list of instructions matches every document.
 ///
 /// # Arguments
 ///
 /// * `instructions` - a list of Instructions to execute
-/// * `store` - the Collection's store to operate on
-/// * `indices` - mapping of the collection's Index structs to their field name.
+/// * `indexes` - the Collection's store and indexes to operate on
 ///
 /// # Example
 ///
-/// ```rust
+/// ```rust,ignore
 /// let ops = vec![
 ///    Instructions::Equal(
 ///        String::from("username"),
@@ -24,68 +31,355 @@ use std::collections::HashSet;
 ///        DataType::String(String::from("johnperry@example.com"))
 ///    )
 /// ];
-/// let results = process_instructions(ops, store, indices);
+/// let matches = execute(&ops, collection.indexes());
 /// ```
-pub fn process_instructions<'a>(
-    instructions: Vec<Instructions>,
-    store: &'a Store,
-    indices: &Indices,
-) -> QueryResult<'a> {
-    let mut is_first_equal = true;
-    let mut working_results = HashSet::new();
-    for instruction in instructions {
-        match instruction {
-            Instructions::Equal(field, value) => {
-                if let Some(index) = indices.get(&field) {
-                    if let Some(ids) = index.search(&value) {
-                        if is_first_equal {
-                            for id in ids.into_iter() {
-                                working_results.insert(id.clone());
-                            }
-                        } else {
-                            let mut next_set = HashSet::new();
-                            for id in ids.into_iter() {
-                                next_set.insert(id.clone());
-                            }
-                            let mut compared_set = HashSet::new();
-                            for id in working_results.intersection(&next_set).into_iter() {
-                                compared_set.insert(id.clone());
-                            }
-                            working_results = compared_set;
-                        }
-                    }
-                } else {
-                    for (id, document) in store.into_iter() {
-                        if let Some(found_value) = document.get(&field) {
-                            if found_value == &value {
-                                working_results.insert(id.clone());
-                            }
-                        }
+pub fn execute(instructions: &[Instructions], indexes: Indexes) -> Matches {
+    let Indexes { store, indices, .. } = indexes;
+    let plan = query_planner::plan(instructions, indexes);
+    let mut stages = Vec::new();
+    let mut scores = HashMap::new();
+    let mut ids = match &plan.access {
+        Access::CollectionScan => {
+            let ids: Vec<usize> = store.keys().copied().collect();
+            stages.push(StageReport {
+                stage: "COLLECTION_SCAN",
+                index: None,
+                predicates: Vec::new(),
+                estimated: estimate(plan.candidates),
+                examined: ids.len(),
+                returned: ids.len(),
+            });
+            ids
+        }
+        Access::IndexLookups(lookups) => {
+            let mut working: Option<Vec<usize>> = None;
+            let mut examined = 0;
+            for lookup in lookups.iter() {
+                // Keys are hashed, so the ids are checked against the value
+                let found = indices
+                    .get(lookup.field)
+                    .and_then(|index| index.search(lookup.value))
+                    .cloned()
+                    .unwrap_or_default();
+                let matched = matching(&found, store, &[lookup.instruction]);
+                stages.push(StageReport {
+                    stage: "INDEX_LOOKUP",
+                    index: Some(index::index_name(std::slice::from_ref(lookup.field))),
+                    predicates: vec![lookup.instruction.to_string()],
+                    estimated: estimate(lookup.estimated),
+                    examined: found.len(),
+                    returned: matched.len(),
+                });
+                examined += matched.len();
+                working = Some(match working {
+                    None => matched,
+                    Some(mut current) => {
+                        let next: HashSet<usize> = matched.into_iter().collect();
+                        current.retain(|id| next.contains(id));
+                        current
                     }
-                }
-                is_first_equal = false;
+                });
             }
-            _ => {}
+            let ids = working.unwrap_or_default();
+            if lookups.len() > 1 {
+                stages.push(StageReport {
+                    stage: "INTERSECT",
+                    index: None,
+                    predicates: Vec::new(),
+                    estimated: estimate(plan.candidates),
+                    examined,
+                    returned: ids.len(),
+                });
+            }
+            ids
+        }
+        Access::CompoundIndexScan(scan) => {
+            // Numbers share keys whatever their representation, so the ids
+            // are checked against the covered predicates
+            let found = scan.index.scan(&scan.prefix, scan.lower, scan.upper);
+            let matched = matching(&found, store, &scan.covered);
+            stages.push(StageReport {
+                stage: "COMPOUND_INDEX_SCAN",
+                index: Some(scan.index.name()),
+                predicates: scan.covered.iter().map(|i| i.to_string()).collect(),
+                estimated: estimate(scan.estimated),
+                examined: found.len(),
+                returned: matched.len(),
+            });
+            matched
+        }
+        Access::TextSearch(scan) => {
+            scores = scan.index.search(&scan.search).into_iter().collect();
+            let found: Vec<usize> = scores.keys().copied().collect();
+            stages.push(StageReport {
+                stage: "TEXT_SEARCH",
+                index: Some(scan.index.name()),
+                predicates: vec![scan.instruction.to_string()],
+                estimated: estimate(plan.candidates),
+                examined: found.len(),
+                returned: found.len(),
+            });
+            found
         }
+    };
+    if !plan.residual.is_empty() {
+        let examined = ids.len();
+        ids = matching(&ids, store, &plan.residual);
+        stages.push(StageReport {
+            stage: "FILTER",
+            index: None,
+            predicates: plan.residual.iter().map(|i| i.to_string()).collect(),
+            estimated: estimate(plan.estimated),
+            examined,
+            returned: ids.len(),
+        });
     }
-    gather_documents(working_results.into_iter().collect(), store)
+    ids.sort_unstable();
+    if !scores.is_empty() {
+        let matched: HashSet<&usize> = ids.iter().collect();
+        scores.retain(|id, _| matched.contains(id));
+    }
+    let explain = Explain {
+        stages,
+        cost: estimate(plan.cost),
+        returned: ids.len(),
+    };
+    Matches {
+        ids,
+        scores,
+        explain,
+    }
+}
+
+fn estimate(value: f64) -> usize {
+    value.ceil() as usize
 }
 
-/// Produces the documents associated with the ids
+/// Produces the ids whose documents satisfy every instruction
+fn matching(ids: &[usize], store: &Store, instructions: &[&Instructions]) -> Vec<usize> {
+    ids.iter()
+        .copied()
+        .filter(|id| {
+            store.get(id).is_some_and(|document| {
+                instructions
+                    .iter()
+                    .all(|instruction| matches(document, instruction))
+            })
+        })
+        .collect()
+}
+
+/// Produces whether a document satisfies an instruction
+fn matches(document: &Document, instruction: &Instructions) -> bool {
+    match instruction {
+        Instructions::Equal(field, value) => field_value(document, field) == Some(value),
+        Instructions::Compare(field, comparison, value) => {
+            let found_value = match field_value(document, field) {
+                Some(found_value) => found_value,
+                None => return false,
+            };
+            let (lower, upper) = match comparison {
+                Comparison::GreaterThan => (Bound::Excluded(value), Bound::Unbounded),
+                Comparison::GreaterThanOrEqual => (Bound::Included(value), Bound::Unbounded),
+                Comparison::LessThan => (Bound::Unbounded, Bound::Excluded(value)),
+                Comparison::LessThanOrEqual => (Bound::Unbounded, Bound::Included(value)),
+            };
+            index::within(found_value, lower, upper)
+        }
+        Instructions::In(field, values) => {
+            field_value(document, field).is_some_and(|found_value| values.contains(found_value))
+        }
+        // Only the TextIndex knows which fields are searched
+        Instructions::Text(_) => false,
+        Instructions::None => true,
+    }
+}
+
+/// Produces whether a document satisfies every instruction, the same
+/// predicate a query applies to the documents its plan reads
 ///
 /// # Arguments
 ///
-/// * `ids` - a list of document ids
-/// * `store` - the Collection's store to operate on
-///
-fn gather_documents<'a>(ids: Vec<usize>, store: &'a Store) -> QueryResult<'a> {
-    let mut results = Vec::new();
-    for id in ids {
-        if let Some(document) = store.get(&id) {
-            results.push(document)
-        } else {
-            return QueryResult::InvalidIdError;
+/// * `document` - the document to test
+/// * `instructions` - a list of Instructions, combined with AND
+pub fn matches_all(document: &Document, instructions: &[Instructions]) -> bool {
+    instructions
+        .iter()
+        .all(|instruction| matches(document, instruction))
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::datastore::collection::{Collection, Document};
+    use crate::datastore::datatypes::{self, DataType, Float};
+    use proptest::prelude::*;
+    use std::cmp::Ordering;
+    use std::collections::HashMap;
+
+    const FIELDS: [&str; 3] = ["a", "b", "c"];
+
+    #[derive(Debug, Clone)]
+    enum Predicate {
+        Equal(&'static str, DataType),
+        Compare(&'static str, &'static str, DataType),
+    }
+
+    fn value() -> impl Strategy<Value = DataType> {
+        // Numbers overlap across representations, halves included
+        let half = |val: i64| DataType::F64(Float::new(val as f64 / 2.0));
+        prop_oneof![
+            (0u64..6).prop_map(DataType::U64),
+            (-2i64..6).prop_map(DataType::I64),
+            (-4i64..12).prop_map(half),
+            prop::sample::select(vec!["x", "y", "z"])
+                .prop_map(|val| DataType::String(String::from(val))),
+        ]
+    }
+
+    fn document() -> impl Strategy<Value = Document> {
+        prop::collection::vec(prop::option::of(value()), FIELDS.len()).prop_map(|values| {
+            FIELDS
+                .iter()
+                .zip(values)
+                .filter_map(|(field, value)| value.map(|value| (String::from(*field), value)))
+                .collect()
+        })
+    }
+
+    fn predicate() -> impl Strategy<Value = Predicate> {
+        let field = prop::sample::select(FIELDS.to_vec());
+        let operator = prop::sample::select(vec!["$gt", "$gte", "$lt", "$lte"]);
+        prop_oneof![
+            (field.clone(), value()).prop_map(|(field, value)| Predicate::Equal(field, value)),
+            (field, operator, value())
+                .prop_map(|(field, operator, value)| Predicate::Compare(field, operator, value)),
+        ]
+    }
+
+    fn query(predicates: &[Predicate]) -> String {
+        let fields: Vec<String> = predicates
+            .iter()
+            .map(|predicate| match predicate {
+                Predicate::Equal(field, value) => {
+                    format!("{}: {}", field, datatypes::to_json(value))
+                }
+                Predicate::Compare(field, operator, value) => {
+                    format!("{}: {{{}: {}}}", field, operator, datatypes::to_json(value))
+                }
+            })
+            .collect();
+        format!("{{{}}}", fields.join(", "))
+    }
+
+    /// Evaluates a predicate without any of the query machinery
+    fn satisfies(document: &Document, predicate: &Predicate) -> bool {
+        match predicate {
+            Predicate::Equal(field, value) => document.get(*field) == Some(value),
+            Predicate::Compare(field, operator, value) => {
+                let ordering = match document.get(*field) {
+                    Some(found) => datatypes::compare(found, value),
+                    None => return false,
+                };
+                matches!(
+                    (*operator, ordering),
+                    ("$gt", Some(Ordering::Greater))
+                        | ("$gte", Some(Ordering::Greater))
+                        | ("$gte", Some(Ordering::Equal))
+                        | ("$lt", Some(Ordering::Less))
+                        | ("$lte", Some(Ordering::Less))
+                        | ("$lte", Some(Ordering::Equal))
+                )
+            }
+        }
+    }
+
+    fn collection(documents: &[Document], indexed: bool) -> Collection {
+        let mut collection = Collection::new(String::from("documents"));
+        if indexed {
+            collection.create_index("a", false).unwrap();
+            collection.create_index("b", false).unwrap();
+            let fields = |fields: &[&str]| fields.iter().map(|f| String::from(*f)).collect();
+            collection
+                .create_compound_index(fields(&["a", "b", "c"]), false)
+                .unwrap();
+            collection
+                .create_compound_index(fields(&["c", "b"]), false)
+                .unwrap();
+        }
+        for document in documents.iter() {
+            collection.insert(document.clone()).unwrap();
+        }
+        collection
+    }
+
+    fn users() -> Collection {
+        let mut collection = Collection::new(String::from("users"));
+        collection.create_index("username", false).unwrap();
+        for (username, age) in [("johnperry", 75u64), ("louiswu", 75), ("nessus", 200)] {
+            let mut document = HashMap::new();
+            document.insert(
+                String::from("username"),
+                DataType::String(String::from(username)),
+            );
+            document.insert(String::from("age"), DataType::U64(age));
+            collection.insert(document).unwrap();
+        }
+        collection
+    }
+
+    #[test]
+    fn indexed_field_without_match() {
+        assert_eq!(
+            Ok(Vec::<usize>::new()),
+            users().find_ids("{username:\"nobody\", age:75}")
+        );
+    }
+
+    #[test]
+    fn unindexed_fields_intersect() {
+        let mut collection = users();
+        collection.drop_index("username");
+        assert_eq!(
+            Ok(vec![2usize]),
+            collection.find_ids("{age:75, username:\"louiswu\"}")
+        );
+    }
+
+    #[test]
+    fn numbers_match_across_representations() {
+        let mut collection = users();
+        let mut document = HashMap::new();
+        document.insert(String::from("age"), DataType::I64(75i64));
+        collection.insert(document).unwrap();
+        assert_eq!(Ok(vec![1usize, 2, 4]), collection.find_ids("{age: 75}"));
+        assert_eq!(Ok(vec![1usize, 2, 4]), collection.find_ids("{age: 75.0}"));
+        collection.create_index("age", false).unwrap();
+        assert_eq!(Ok(vec![1usize, 2, 4]), collection.find_ids("{age: 75.0}"));
+        assert_eq!(Ok(vec![3usize]), collection.find_ids("{age: {$gt: 75.5}}"));
+    }
+
+    #[test]
+    fn empty_query_matches_everything() {
+        assert_eq!(Ok(vec![1usize, 2, 3]), users().find_ids("{}"));
+    }
+
+    proptest! {
+        #[test]
+        fn indexed_and_unindexed_agree(
+            documents in prop::collection::vec(document(), 0..40),
+            predicates in prop::collection::vec(predicate(), 0..4),
+        ) {
+            let query = query(&predicates);
+            let expected: Vec<usize> = documents
+                .iter()
+                .enumerate()
+                .filter(|(_, document)| {
+                    predicates.iter().all(|predicate| satisfies(document, predicate))
+                })
+                .map(|(position, _)| position + 1)
+                .collect();
+            prop_assert_eq!(Ok(expected.clone()), collection(&documents, false).find_ids(&query));
+            prop_assert_eq!(Ok(expected), collection(&documents, true).find_ids(&query));
         }
     }
-    QueryResult::Data(results)
 }
@@ -1,5 +1,7 @@
-use crate::datastore::datatypes::DataType;
-use crate::datastore::query_proc::QueryResult;
+use crate::datastore::collection::Document;
+use crate::datastore::datatypes::{self, DataType, Float};
+use crate::datastore::error::DatastoreError;
+use std::fmt;
 
 #[derive(Debug, Clone, PartialEq)]
 enum Token {
@@ -7,6 +9,8 @@ enum Token {
     CloseCurly,
     String(String),
     Number(u64),
+    Integer(i64),
+    Float(f64),
     Field(String),
     None,
 }
@@ -19,18 +23,165 @@ enum Token {
 ///
 /// # Example
 ///
-/// ```rust
+/// ```rust,ignore
 /// use crate::datastore::query_proc::query_ingestor;
 /// let query = String::from("{username:\"johnperry\"}")
 /// ingest(&query);
 /// ```
-pub fn ingest<'a>(query: &String) -> Result<Vec<Instructions>, QueryResult<'a>> {
+pub fn ingest(query: &str) -> Result<Vec<Instructions>, DatastoreError> {
     match lexer(query) {
-        Ok(tokens) => Ok(parser(tokens)),
+        Ok(tokens) => parser(tokens),
         Err(e) => Err(e),
     }
 }
 
+/// Produces a set of Instructions from a filter document, such as the
+/// `filter` of a MongoDB find command
+///
+/// A field's value is compared for equality unless it is an embedded
+/// document of query operators, e.g. `{"age": {"$gte": 30}}`, see
+/// `operation`.
+///
+/// # Arguments
+///
+/// * `filter` - the filter document
+pub fn ingest_document(filter: &Document) -> Result<Vec<Instructions>, DatastoreError> {
+    let mut fields: Vec<(&String, &DataType)> = filter.iter().collect();
+    fields.sort_by(|a, b| a.0.cmp(b.0));
+    let mut instructions = Vec::new();
+    for (field, value) in fields {
+        if field == TEXT {
+            let search = match value {
+                DataType::Document(operators) => operators.get(SEARCH),
+                _ => None,
+            };
+            match search {
+                Some(DataType::String(search)) => {
+                    instructions.push(Instructions::Text(search.clone()))
+                }
+                _ => return Err(DatastoreError::InvalidQuery),
+            }
+            continue;
+        }
+        if field.starts_with('$') {
+            return Err(DatastoreError::InvalidQuery);
+        }
+        let operators = match value {
+            DataType::Document(operators) if operators.keys().any(|key| key.starts_with('$')) => {
+                operators
+            }
+            _ => {
+                instructions.push(Instructions::Equal(field.clone(), value.clone()));
+                continue;
+            }
+        };
+        for (operator, operand) in operators.iter() {
+            instructions.push(operation(field, operator, operand.clone())?);
+        }
+    }
+    Ok(instructions)
+}
+
+/// Produces a set of Instructions from a JSON filter, such as the body of a
+/// find request
+///
+/// A field's value is compared for equality unless it is an object of
+/// query operators, e.g. `{"age": {"$gte": 30}, "team": {"$in": [1, 2]}}`,
+/// see `operation`. Values of types without a JSON literal are written as
+/// extended JSON.
+/// `{"$text": {"$search": "..."}}` searches the collection's text index.
+///
+/// # Arguments
+///
+/// * `filter` - the filter, an object or null for every document
+pub fn ingest_json(filter: &serde_json::Value) -> Result<Vec<Instructions>, DatastoreError> {
+    let fields = match filter {
+        serde_json::Value::Object(fields) => fields,
+        serde_json::Value::Null => return Ok(Vec::new()),
+        _ => return Err(DatastoreError::InvalidQuery),
+    };
+    let mut instructions = Vec::new();
+    for (field, value) in fields.iter() {
+        if field == TEXT {
+            match value.get(SEARCH) {
+                Some(serde_json::Value::String(search))
+                    if value.as_object().map(|o| o.len()) == Some(1) =>
+                {
+                    instructions.push(Instructions::Text(search.clone()))
+                }
+                _ => return Err(DatastoreError::InvalidQuery),
+            }
+            continue;
+        }
+        if field.starts_with('$') {
+            return Err(DatastoreError::InvalidQuery);
+        }
+        let operators = match value {
+            serde_json::Value::Object(operators)
+                if operators.keys().any(|key| key.starts_with('$'))
+                    && datatypes::from_json(value) == DataType::Null =>
+            {
+                operators
+            }
+            _ => {
+                instructions.push(Instructions::Equal(field.clone(), json_value(value)?));
+                continue;
+            }
+        };
+        for (operator, operand) in operators.iter() {
+            instructions.push(operation(field, operator, json_value(operand)?)?);
+        }
+    }
+    Ok(instructions)
+}
+
+/// Produces the DataType of a JSON operand. Objects of unknown operators,
+/// also inside arrays, have none.
+fn json_value(value: &serde_json::Value) -> Result<DataType, DatastoreError> {
+    match (value, datatypes::from_json(value)) {
+        (serde_json::Value::Array(values), _) => values
+            .iter()
+            .map(json_value)
+            .collect::<Result<Vec<DataType>, DatastoreError>>()
+            .map(DataType::Array),
+        (serde_json::Value::Object(_), DataType::Null) => Err(DatastoreError::InvalidQuery),
+        (_, value) => Ok(value),
+    }
+}
+
+/// Produces the Instruction of a query operator on a field, whichever
+/// form the query was written in: `$eq`, `$gt`, `$gte`, `$lt` and `$lte`
+/// compare the field to the operand, `$in` matches it against each value
+/// of an array
+///
+/// # Arguments
+///
+/// * `field` - the field the operator applies to
+/// * `operator` - the operator, e.g. `$gte`
+/// * `operand` - the value the operator is given
+fn operation(
+    field: &str,
+    operator: &str,
+    operand: DataType,
+) -> Result<Instructions, DatastoreError> {
+    let field = String::from(field);
+    let comparison = match operator {
+        "$eq" => return Ok(Instructions::Equal(field, operand)),
+        "$in" => {
+            return match operand {
+                DataType::Array(values) => Ok(Instructions::In(field, values)),
+                _ => Err(DatastoreError::InvalidQuery),
+            }
+        }
+        "$gt" => Comparison::GreaterThan,
+        "$gte" => Comparison::GreaterThanOrEqual,
+        "$lt" => Comparison::LessThan,
+        "$lte" => Comparison::LessThanOrEqual,
+        _ => return Err(DatastoreError::InvalidQuery),
+    };
+    Ok(Instructions::Compare(field, comparison, operand))
+}
+
 /// Parses the raw query string and converts it into Tokens
 ///
 /// # Reference
@@ -41,9 +192,9 @@ pub fn ingest<'a>(query: &String) -> Result<Vec<Instructions>, QueryResult<'a>>
 /// https://realpython.com/cpython-source-code-guide/#lexing-and-parsing
 /// https://en.wikipedia.org/wiki/Compilers:_Principles,_Techniques,_and_Tools
 ///
-fn lexer<'a>(query: &String) -> Result<Vec<Token>, QueryResult<'a>> {
+fn lexer(query: &str) -> Result<Vec<Token>, DatastoreError> {
     if !query.starts_with('{') || !query.ends_with('}') {
-        return Err(QueryResult::InvalidQueryError);
+        return Err(DatastoreError::InvalidQuery);
     };
     let mut tokens = Vec::new();
     let mut in_string = false;
@@ -65,9 +216,9 @@ fn lexer<'a>(query: &String) -> Result<Vec<Token>, QueryResult<'a>> {
                     Token::None
                 } else if in_number {
                     in_number = false;
-                    let finished_token: u64 = current_token.parse().unwrap();
+                    let finished_token = number_token(&current_token)?;
                     current_token = String::new();
-                    tokens.push(Token::Number(finished_token));
+                    tokens.push(finished_token);
                     Token::CloseCurly
                 } else {
                     Token::CloseCurly
@@ -108,9 +259,9 @@ fn lexer<'a>(query: &String) -> Result<Vec<Token>, QueryResult<'a>> {
                     Token::Field(finished_token)
                 } else if in_number {
                     in_number = false;
-                    let finished_token: u64 = current_token.parse().unwrap();
+                    let finished_token = number_token(&current_token)?;
                     current_token = String::new();
-                    Token::Number(finished_token)
+                    finished_token
                 } else {
                     Token::None
                 }
@@ -121,34 +272,31 @@ fn lexer<'a>(query: &String) -> Result<Vec<Token>, QueryResult<'a>> {
                     Token::None
                 } else if in_number {
                     in_number = false;
-                    let finished_token: u64 = current_token.parse().unwrap();
+                    let finished_token = number_token(&current_token)?;
                     current_token = String::new();
-                    Token::Number(finished_token)
+                    finished_token
                 } else if in_field {
-                    return Err(QueryResult::InvalidQueryError);
+                    return Err(DatastoreError::InvalidQuery);
                 } else {
                     Token::None
                 }
             }
-            // TODO: Add other number types
-            '0' => {
-                if in_string || in_field {
-                    current_token.push(character);
-                    Token::None
-                } else {
-                    return Err(QueryResult::InvalidQueryError);
-                }
-            }
-            '1'..='9' => {
+            '0'..='9' | '-' => {
                 if in_string || in_field {
                     current_token.push(character);
                     Token::None
+                } else if character == '-' && in_number {
+                    return Err(DatastoreError::InvalidQuery);
                 } else {
                     in_number = true;
                     current_token.push(character);
                     Token::None
                 }
             }
+            '.' if in_number => {
+                current_token.push(character);
+                Token::None
+            }
             _ => {
                 if in_string || in_field {
                     current_token.push(character);
@@ -171,48 +319,170 @@ fn lexer<'a>(query: &String) -> Result<Vec<Token>, QueryResult<'a>> {
     Ok(tokens)
 }
 
+/// Produces the Token for a number: unsigned unless it has a sign, a float
+/// if it has a decimal point
+fn number_token(text: &str) -> Result<Token, DatastoreError> {
+    let token = if text.contains('.') {
+        text.parse().ok().map(Token::Float)
+    } else if text.starts_with('-') {
+        text.parse().ok().map(Token::Integer)
+    } else {
+        text.parse().ok().map(Token::Number)
+    };
+    token.ok_or(DatastoreError::InvalidQuery)
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Comparison {
+    GreaterThan,
+    GreaterThanOrEqual,
+    LessThan,
+    LessThanOrEqual,
+}
+
+impl Comparison {
+    /// Produces the query operator for the comparison, e.g. `$gt`
+    pub fn operator(&self) -> &'static str {
+        match self {
+            Comparison::GreaterThan => "$gt",
+            Comparison::GreaterThanOrEqual => "$gte",
+            Comparison::LessThan => "$lt",
+            Comparison::LessThanOrEqual => "$lte",
+        }
+    }
+}
+
+/// The query operator searching a collection's text index
+pub const TEXT: &str = "$text";
+/// The argument of `$text` holding the words to search for
+const SEARCH: &str = "$search";
+
 #[derive(Debug, Clone, PartialEq)]
 pub enum Instructions {
     Equal(String, DataType),
+    Compare(String, Comparison, DataType),
+    /// The field equals one of the values
+    In(String, Vec<DataType>),
+    /// The document is found by a search of the collection's text index
+    Text(String),
     None,
 }
 
+impl fmt::Display for Instructions {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Instructions::Equal(field, value) => {
+                write!(f, "{} $eq {}", field, datatypes::to_json(value))
+            }
+            Instructions::Compare(field, comparison, value) => write!(
+                f,
+                "{} {} {}",
+                field,
+                comparison.operator(),
+                datatypes::to_json(value)
+            ),
+            Instructions::In(field, values) => write!(
+                f,
+                "{} $in {}",
+                field,
+                serde_json::Value::Array(values.iter().map(datatypes::to_json).collect())
+            ),
+            Instructions::Text(search) => write!(
+                f,
+                "{} {} {}",
+                TEXT,
+                SEARCH,
+                serde_json::Value::String(search.clone())
+            ),
+            Instructions::None => Ok(()),
+        }
+    }
+}
+
 /// Converts a list of Tokens into a set of Instructions to be executed
-fn parser(tokens: Vec<Token>) -> Vec<Instructions> {
+///
+/// A field's value is either compared for equality or is a document of
+/// comparison operators, e.g. `{age: {$gte: 30, $lt: 40}}`. Values of types
+/// without a literal are written as extended JSON, e.g.
+/// `{created: {$gte: {$date: "2021-06-01T00:00:00Z"}}}`.
+fn parser(tokens: Vec<Token>) -> Result<Vec<Instructions>, DatastoreError> {
     let mut previous_token_value = Token::None;
+    // The field whose document of operators is open, if any
+    let mut operand_field: Option<String> = None;
+    // The operator whose extended JSON value is open, if any
+    let mut wrapped_operator: Option<String> = None;
     let mut instructions = Vec::new();
     for token in tokens {
-        let instruction = match token {
+        let value = match token {
+            Token::OpenCurly => {
+                if let Token::Field(field) =
+                    std::mem::replace(&mut previous_token_value, Token::None)
+                {
+                    if operand_field.is_none() {
+                        operand_field = Some(field);
+                    } else {
+                        wrapped_operator = Some(field);
+                    }
+                }
+                continue;
+            }
+            Token::CloseCurly => {
+                if wrapped_operator.take().is_none() {
+                    operand_field = None;
+                }
+                continue;
+            }
             Token::Field(_) => {
                 previous_token_value = token;
-                Instructions::None
+                continue;
             }
-            Token::String(val) => {
-                if let Token::Field(field) = previous_token_value {
-                    previous_token_value = Token::None;
-                    Instructions::Equal(field, DataType::String(val))
-                } else {
-                    Instructions::None
+            Token::String(val) => DataType::String(val),
+            Token::Number(val) => DataType::U64(val),
+            Token::Integer(val) => DataType::I64(val),
+            Token::Float(val) => DataType::F64(Float::new(val)),
+            Token::None => continue,
+        };
+        let instruction = match (
+            std::mem::replace(&mut previous_token_value, Token::None),
+            &operand_field,
+        ) {
+            (Token::Field(operator), Some(field)) if field == TEXT => {
+                match (operator.as_str(), value) {
+                    (SEARCH, DataType::String(search)) => Instructions::Text(search),
+                    _ => return Err(DatastoreError::InvalidQuery),
                 }
             }
-            // TODO: Add other number types
-            Token::Number(val) => {
-                if let Token::Field(field) = previous_token_value {
-                    previous_token_value = Token::None;
-                    Instructions::Equal(field, DataType::U64(val))
+            (Token::Field(operator), Some(field)) => {
+                let (operator, value) = if EXTENDED_TYPES.contains(&operator.as_str()) {
+                    let mut wrapped = serde_json::Map::new();
+                    wrapped.insert(operator, datatypes::to_json(&value));
+                    match datatypes::from_json(&serde_json::Value::Object(wrapped)) {
+                        DataType::Null => return Err(DatastoreError::InvalidQuery),
+                        value => (
+                            wrapped_operator
+                                .clone()
+                                .unwrap_or_else(|| String::from("$eq")),
+                            value,
+                        ),
+                    }
                 } else {
-                    Instructions::None
-                }
+                    (operator, value)
+                };
+                operation(field, &operator, value)?
             }
+            (Token::Field(field), None) => Instructions::Equal(field, value),
             _ => Instructions::None,
         };
         if instruction != Instructions::None {
             instructions.push(instruction);
         }
     }
-    instructions
+    Ok(instructions)
 }
 
+/// Extended JSON keys that wrap a value of a type without a literal
+const EXTENDED_TYPES: [&str; 4] = ["$date", "$oid", "$numberDecimal", "$numberLong"];
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -289,12 +559,52 @@ mod tests {
         )
     }
 
+    #[test]
+    fn get_tokens_number_with_zero() {
+        let query = String::from("{id: 30}");
+        let results = lexer(&query).unwrap();
+        assert_eq!(
+            results,
+            vec![
+                Token::OpenCurly,
+                Token::Field(String::from("id")),
+                Token::Number(30u64),
+                Token::CloseCurly
+            ]
+        )
+    }
+
+    #[test]
+    fn get_tokens_signed_and_float() {
+        let query = String::from("{low: -3, high: 0, ratio: 75.50}");
+        let results = lexer(&query).unwrap();
+        assert_eq!(
+            results,
+            vec![
+                Token::OpenCurly,
+                Token::Field(String::from("low")),
+                Token::Integer(-3i64),
+                Token::Field(String::from("high")),
+                Token::Number(0u64),
+                Token::Field(String::from("ratio")),
+                Token::Float(75.5f64),
+                Token::CloseCurly
+            ]
+        )
+    }
+
+    #[test]
+    fn get_tokens_invalid_number() {
+        assert!(lexer("{id: 1-2}").is_err());
+        assert!(lexer("{id: 99999999999999999999}").is_err());
+    }
+
     #[test]
     fn parse_tokens() {
         let query = String::from("{username: \"johnperry\", email:\"johnperry@example.com\"}");
         let results = lexer(&query).unwrap();
         println!("{:?}", results);
-        let parsed = parser(results);
+        let parsed = parser(results).unwrap();
         println!("{:?}", parsed);
         assert_eq!(
             parsed,
@@ -316,7 +626,7 @@ mod tests {
         let query = String::from("{username: \"johnperry\", email:\"johnperry@example.com\"}");
         let results = lexer(&query).unwrap();
         println!("{:?}", results);
-        let parsed = parser(results);
+        let parsed = parser(results).unwrap();
         println!("{:?}", parsed);
         assert_eq!(
             parsed,
@@ -332,4 +642,146 @@ mod tests {
             ]
         )
     }
+
+    #[test]
+    fn parse_comparisons() {
+        let query = String::from("{tenant: \"a\", age: {$gte: 30, $lt: 40}}");
+        let parsed = parser(lexer(&query).unwrap()).unwrap();
+        assert_eq!(
+            parsed,
+            vec![
+                Instructions::Equal(String::from("tenant"), DataType::String(String::from("a"))),
+                Instructions::Compare(
+                    String::from("age"),
+                    Comparison::GreaterThanOrEqual,
+                    DataType::U64(30u64)
+                ),
+                Instructions::Compare(
+                    String::from("age"),
+                    Comparison::LessThan,
+                    DataType::U64(40u64)
+                )
+            ]
+        )
+    }
+
+    #[test]
+    fn parse_unknown_operator() {
+        let query = String::from("{age: {$near: 30}}");
+        assert!(parser(lexer(&query).unwrap()).is_err());
+    }
+
+    #[test]
+    fn ingest_filter_document() {
+        let mut range = std::collections::BTreeMap::new();
+        range.insert(String::from("$gte"), DataType::I64(30));
+        range.insert(String::from("$lt"), DataType::I64(40));
+        let mut filter = std::collections::HashMap::new();
+        filter.insert(String::from("age"), DataType::Document(range));
+        filter.insert(
+            String::from("username"),
+            DataType::String(String::from("johnperry")),
+        );
+        assert_eq!(
+            vec![
+                Instructions::Compare(
+                    String::from("age"),
+                    Comparison::GreaterThanOrEqual,
+                    DataType::I64(30)
+                ),
+                Instructions::Compare(String::from("age"), Comparison::LessThan, DataType::I64(40)),
+                Instructions::Equal(
+                    String::from("username"),
+                    DataType::String(String::from("johnperry"))
+                ),
+            ],
+            ingest_document(&filter).unwrap()
+        );
+        let mut listed = std::collections::BTreeMap::new();
+        listed.insert(
+            String::from("$in"),
+            DataType::Array(vec![DataType::I64(30), DataType::I64(75)]),
+        );
+        filter.insert(String::from("age"), DataType::Document(listed));
+        assert_eq!(
+            Instructions::In(
+                String::from("age"),
+                vec![DataType::I64(30), DataType::I64(75)]
+            ),
+            ingest_document(&filter).unwrap()[0]
+        );
+        let mut unknown = std::collections::BTreeMap::new();
+        unknown.insert(String::from("$in"), DataType::Null);
+        filter.insert(String::from("age"), DataType::Document(unknown));
+        assert!(ingest_document(&filter).is_err());
+    }
+
+    #[test]
+    fn ingest_json_filter() {
+        let filter = serde_json::json!({
+            "team": {"$in": [1, "a"]},
+            "created": {"$gte": {"$date": "2021-06-01T00:00:00Z"}},
+            "address": {"city": "Ohio"}
+        });
+        let instructions = ingest_json(&filter).unwrap();
+        assert_eq!(
+            Instructions::In(
+                String::from("team"),
+                vec![DataType::U64(1), DataType::String(String::from("a"))]
+            ),
+            instructions[0]
+        );
+        assert_eq!(
+            Instructions::Compare(
+                String::from("created"),
+                Comparison::GreaterThanOrEqual,
+                DataType::DateTime(1_622_505_600_000)
+            ),
+            instructions[1]
+        );
+        assert!(
+            matches!(&instructions[2], Instructions::Equal(field, DataType::Document(_)) if field == "address")
+        );
+        assert!(ingest_json(&serde_json::Value::Null).unwrap().is_empty());
+        assert_eq!(
+            vec![Instructions::Equal(
+                String::from("team"),
+                DataType::Array(vec![DataType::U64(1), DataType::U64(2)])
+            )],
+            ingest_json(&serde_json::json!({"team": [1, 2]})).unwrap()
+        );
+        for invalid in [
+            serde_json::json!({"team": {"$in": 1}}),
+            serde_json::json!({"team": {"$in": [{"$ne": 1}]}}),
+            serde_json::json!({"team": {"$ne": 1}}),
+            serde_json::json!({"$or": []}),
+            serde_json::json!([]),
+        ] {
+            assert!(ingest_json(&invalid).is_err(), "{}", invalid);
+        }
+    }
+
+    #[test]
+    fn parse_extended_values() {
+        let query = String::from(
+            "{created: {$gte: {$date: \"1970-01-01T00:00:01Z\"}}, price: {$numberDecimal: \"1.50\"}}",
+        );
+        let parsed = parser(lexer(&query).unwrap()).unwrap();
+        assert_eq!(
+            parsed,
+            vec![
+                Instructions::Compare(
+                    String::from("created"),
+                    Comparison::GreaterThanOrEqual,
+                    DataType::DateTime(1000)
+                ),
+                Instructions::Equal(
+                    String::from("price"),
+                    DataType::Decimal128("1.5".parse().unwrap())
+                )
+            ]
+        );
+        let query = String::from("{created: {$date: \"tomorrow\"}}");
+        assert!(parser(lexer(&query).unwrap()).is_err());
+    }
 }
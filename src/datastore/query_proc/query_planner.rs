@@ -0,0 +1,415 @@
+use crate::datastore::collection::{CompoundIndices, Indices, Store};
+use crate::datastore::datatypes::DataType;
+use crate::datastore::index::CompoundIndex;
+use crate::datastore::query_proc::query_ingestor::{Comparison, Instructions};
+use crate::datastore::query_proc::Indexes;
+use crate::datastore::text::{Search, TextIndex};
+use std::ops::Bound;
+
+/// Selectivity assumed for an equality predicate on an unindexed field
+const EQUAL_SELECTIVITY: f64 = 0.1;
+/// Selectivity assumed for each bound of a range predicate
+const RANGE_SELECTIVITY: f64 = 1.0 / 3.0;
+
+/// How a Plan finds its candidate documents
+#[derive(Debug)]
+pub enum Access<'a> {
+    /// Read every document in the store
+    CollectionScan,
+    /// Intersect the ids of single field Index lookups, most selective first
+    IndexLookups(Vec<Lookup<'a>>),
+    /// Scan a prefix of a CompoundIndex, optionally ranged on the next field
+    CompoundIndexScan(CompoundScan<'a>),
+    /// Search the TextIndex, the only access answering a `$text` search
+    TextSearch(TextScan<'a>),
+}
+
+/// A `$text` search answered by the collection's TextIndex
+#[derive(Debug)]
+pub struct TextScan<'a> {
+    pub instruction: &'a Instructions,
+    pub index: &'a TextIndex,
+    pub search: Search,
+}
+
+/// An equality predicate answered by a single field Index
+#[derive(Debug)]
+pub struct Lookup<'a> {
+    pub instruction: &'a Instructions,
+    pub field: &'a String,
+    pub value: &'a DataType,
+    pub estimated: f64,
+}
+
+/// Equality predicates on a prefix of a CompoundIndex's fields, optionally
+/// followed by range predicates on the next field
+#[derive(Debug)]
+pub struct CompoundScan<'a> {
+    pub index: &'a CompoundIndex,
+    pub prefix: Vec<DataType>,
+    pub lower: Bound<&'a DataType>,
+    pub upper: Bound<&'a DataType>,
+    pub covered: Vec<&'a Instructions>,
+    pub estimated: f64,
+}
+
+impl CompoundScan<'_> {
+    /// The number of the index's fields the scan narrows
+    fn fields_used(&self) -> usize {
+        let ranged = !matches!(
+            (self.lower, self.upper),
+            (Bound::Unbounded, Bound::Unbounded)
+        );
+        self.prefix.len() + ranged as usize
+    }
+}
+
+/// The cheapest way found to execute a query
+#[derive(Debug)]
+pub struct Plan<'a> {
+    pub access: Access<'a>,
+    /// Predicates the access path does not apply, checked against each
+    /// candidate document
+    pub residual: Vec<&'a Instructions>,
+    /// Estimated number of documents read from indexes and the store
+    pub cost: f64,
+    /// Estimated number of candidates the access path produces
+    pub candidates: f64,
+    /// Estimated number of documents matching the query
+    pub estimated: f64,
+}
+
+/// Produces the cheapest Plan for a query
+///
+/// Selectivity is estimated from index cardinalities: an equality
+/// predicate on a field with an Index selects one of its distinct keys, and
+/// on a prefix of a CompoundIndex one of the distinct prefixes, assuming
+/// keys are spread evenly. Predicates without an index use fixed
+/// estimates. A collection scan costs every document, index access the
+/// estimated ids it reads. A `$text` search is always answered by the
+/// TextIndex, reading the documents holding any of its terms.
+///
+/// # Arguments
+///
+/// * `instructions` - the query's Instructions
+/// * `indexes` - the Collection's store and indexes to operate on
+///
+/// # Example
+///
+/// ```rust,ignore
+/// let plan = plan(&instructions, collection.indexes());
+/// ```
+pub fn plan<'a>(instructions: &'a [Instructions], indexes: Indexes<'a>) -> Plan<'a> {
+    let Indexes {
+        store,
+        indices,
+        compound_indices,
+        text_index,
+    } = indexes;
+    let total = store.len() as f64;
+    let estimated = instructions.iter().fold(total, |estimate, instruction| {
+        estimate * selectivity(instruction, indices)
+    });
+
+    let text = instructions
+        .iter()
+        .find_map(|instruction| match instruction {
+            Instructions::Text(search) => Some((instruction, search)),
+            _ => None,
+        });
+    if let (Some((instruction, search)), Some(index)) = (text, text_index) {
+        let search = Search::parse(search);
+        let candidates = search
+            .terms
+            .iter()
+            .map(|term| index.frequency(term) as f64)
+            .sum::<f64>()
+            .min(total);
+        return Plan {
+            residual: residual(instructions, |other| other == instruction),
+            access: Access::TextSearch(TextScan {
+                instruction,
+                index,
+                search,
+            }),
+            cost: candidates,
+            candidates,
+            estimated,
+        };
+    }
+
+    let mut best = Plan {
+        access: Access::CollectionScan,
+        residual: instructions.iter().collect(),
+        cost: total,
+        candidates: total,
+        estimated,
+    };
+
+    // Lookups are added to the intersection while they read fewer ids than
+    // the candidates they would remove need to be filtered
+    let mut lookups: Vec<Lookup> = instructions
+        .iter()
+        .filter_map(|instruction| match instruction {
+            Instructions::Equal(field, value) => indices.get(field).map(|index| Lookup {
+                instruction,
+                field,
+                value,
+                estimated: total / index.tree.len().max(1) as f64,
+            }),
+            _ => None,
+        })
+        .collect();
+    lookups.sort_by(|a, b| a.estimated.total_cmp(&b.estimated));
+    let mut intersected: Vec<Lookup> = Vec::new();
+    let mut candidates = total;
+    let mut cost = 0.0;
+    for lookup in lookups {
+        if !intersected.is_empty() && lookup.estimated >= candidates {
+            continue;
+        }
+        if intersected
+            .iter()
+            .any(|existing| existing.instruction == lookup.instruction)
+        {
+            continue;
+        }
+        candidates *= lookup.estimated / total.max(1.0);
+        cost += lookup.estimated;
+        intersected.push(lookup);
+    }
+    if !intersected.is_empty() && cost < best.cost {
+        best = Plan {
+            residual: residual(instructions, |instruction| {
+                intersected
+                    .iter()
+                    .any(|lookup| lookup.instruction == instruction)
+            }),
+            access: Access::IndexLookups(intersected),
+            cost,
+            candidates,
+            estimated,
+        };
+    }
+
+    if let Some(scan) = compound_scan(instructions, store, compound_indices) {
+        if scan.estimated < best.cost {
+            best = Plan {
+                residual: residual(instructions, |instruction| {
+                    scan.covered.contains(&instruction)
+                }),
+                cost: scan.estimated,
+                candidates: scan.estimated,
+                access: Access::CompoundIndexScan(scan),
+                estimated,
+            };
+        }
+    }
+    best
+}
+
+/// Produces the predicates a filter outside the access path applies
+fn residual(
+    instructions: &[Instructions],
+    covered: impl Fn(&Instructions) -> bool,
+) -> Vec<&Instructions> {
+    instructions
+        .iter()
+        .filter(|instruction| !covered(instruction))
+        .collect()
+}
+
+/// Produces the estimated fraction of documents matching a predicate
+fn selectivity(instruction: &Instructions, indices: &Indices) -> f64 {
+    match instruction {
+        Instructions::Equal(field, _) => match indices.get(field) {
+            Some(index) => 1.0 / index.tree.len().max(1) as f64,
+            None => EQUAL_SELECTIVITY,
+        },
+        Instructions::Compare(..) => RANGE_SELECTIVITY,
+        Instructions::In(field, values) => {
+            let equal = match indices.get(field) {
+                Some(index) => 1.0 / index.tree.len().max(1) as f64,
+                None => EQUAL_SELECTIVITY,
+            };
+            (equal * values.len() as f64).min(1.0)
+        }
+        Instructions::Text(_) => EQUAL_SELECTIVITY,
+        Instructions::None => 1.0,
+    }
+}
+
+/// Produces the CompoundIndex scan narrowing the most query fields, if one
+/// narrows at least two: equality on a prefix of the index's fields,
+/// optionally followed by a range on the next field.
+fn compound_scan<'a>(
+    instructions: &'a [Instructions],
+    store: &Store,
+    compound_indices: &'a CompoundIndices,
+) -> Option<CompoundScan<'a>> {
+    let total = store.len() as f64;
+    let mut best: Option<CompoundScan> = None;
+    for compound_index in compound_indices.iter() {
+        let mut scan = CompoundScan {
+            index: compound_index,
+            prefix: Vec::new(),
+            lower: Bound::Unbounded,
+            upper: Bound::Unbounded,
+            covered: Vec::new(),
+            estimated: total,
+        };
+        let mut range_selectivity = 1.0;
+        for field in compound_index.fields.iter() {
+            let equal = instructions.iter().find(
+                |instruction| matches!(instruction, Instructions::Equal(equal_field, _) if equal_field == field),
+            );
+            if let Some(instruction @ Instructions::Equal(_, value)) = equal {
+                scan.prefix.push(value.clone());
+                scan.covered.push(instruction);
+                continue;
+            }
+            for instruction in instructions.iter() {
+                if let Instructions::Compare(compare_field, comparison, value) = instruction {
+                    if compare_field != field {
+                        continue;
+                    }
+                    // Further bounds on the field are left to the filter
+                    let bound = match comparison {
+                        Comparison::GreaterThan | Comparison::GreaterThanOrEqual => &mut scan.lower,
+                        Comparison::LessThan | Comparison::LessThanOrEqual => &mut scan.upper,
+                    };
+                    if !matches!(bound, Bound::Unbounded) {
+                        continue;
+                    }
+                    *bound = match comparison {
+                        Comparison::GreaterThan | Comparison::LessThan => Bound::Excluded(value),
+                        _ => Bound::Included(value),
+                    };
+                    range_selectivity *= RANGE_SELECTIVITY;
+                    scan.covered.push(instruction);
+                }
+            }
+            break;
+        }
+        // Distinct prefixes are estimated from the distinct full keys
+        let distinct = compound_index.tree.len().max(1) as f64;
+        let prefix_share = scan.prefix.len() as f64 / compound_index.fields.len() as f64;
+        scan.estimated = total / distinct.powf(prefix_share) * range_selectivity;
+
+        let better = match &best {
+            Some(best) => {
+                scan.fields_used() > best.fields_used()
+                    || (scan.fields_used() == best.fields_used() && scan.estimated < best.estimated)
+            }
+            None => scan.fields_used() >= 2,
+        };
+        if better {
+            best = Some(scan);
+        }
+    }
+    best
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::datastore::collection::Document;
+    use crate::datastore::index::Index;
+    use std::collections::HashMap;
+
+    fn user(id: usize) -> Document {
+        let mut document = HashMap::new();
+        document.insert(
+            String::from("username"),
+            DataType::String(format!("user{}", id)),
+        );
+        document.insert(String::from("team"), DataType::U64((id % 2) as u64));
+        document.insert(String::from("age"), DataType::U64((id % 50) as u64));
+        document
+    }
+
+    fn indexes<'a>(
+        store: &'a Store,
+        indices: &'a Indices,
+        compound_indices: &'a CompoundIndices,
+    ) -> Indexes<'a> {
+        Indexes {
+            store,
+            indices,
+            compound_indices,
+            text_index: None,
+        }
+    }
+
+    fn users() -> (Store, Indices) {
+        let mut store = Store::new();
+        let mut username = Index::new();
+        let mut team = Index::new();
+        for id in 1..=100 {
+            let document = user(id);
+            username.insert(document.get("username").unwrap(), id);
+            team.insert(document.get("team").unwrap(), id);
+            store.insert(id, document);
+        }
+        let mut indices = HashMap::new();
+        indices.insert(String::from("username"), username);
+        indices.insert(String::from("team"), team);
+        (store, indices)
+    }
+
+    #[test]
+    fn scan_without_indexes() {
+        let (store, _) = users();
+        let instructions = vec![Instructions::Equal(String::from("age"), DataType::U64(30))];
+        let (indices, compound_indices) = (HashMap::new(), Vec::new());
+        let plan = plan(&instructions, indexes(&store, &indices, &compound_indices));
+        assert!(matches!(plan.access, Access::CollectionScan));
+        assert_eq!(1, plan.residual.len());
+        assert_eq!(100.0, plan.cost);
+    }
+
+    #[test]
+    fn most_selective_index_first() {
+        let (store, indices) = users();
+        let instructions = vec![
+            Instructions::Equal(String::from("team"), DataType::U64(1)),
+            Instructions::Equal(
+                String::from("username"),
+                DataType::String(String::from("user7")),
+            ),
+        ];
+        let compound_indices = Vec::new();
+        let plan = plan(&instructions, indexes(&store, &indices, &compound_indices));
+        match plan.access {
+            Access::IndexLookups(lookups) => {
+                // Intersecting the team index would read 50 ids to remove
+                // at most one candidate
+                assert_eq!(1, lookups.len());
+                assert_eq!("username", lookups[0].field);
+            }
+            _ => panic!("expected an index lookup"),
+        }
+        assert_eq!(vec![&instructions[0]], plan.residual);
+    }
+
+    #[test]
+    fn compound_index_over_lookups() {
+        let (store, indices) = users();
+        let mut compound = CompoundIndex::new(vec![String::from("team"), String::from("age")]);
+        for (id, document) in store.iter() {
+            compound.insert(document, *id);
+        }
+        let instructions = vec![
+            Instructions::Equal(String::from("team"), DataType::U64(1)),
+            Instructions::Compare(
+                String::from("age"),
+                Comparison::GreaterThan,
+                DataType::U64(40),
+            ),
+        ];
+        let compound_indices = vec![compound];
+        let plan = plan(&instructions, indexes(&store, &indices, &compound_indices));
+        assert!(matches!(plan.access, Access::CompoundIndexScan(_)));
+        assert!(plan.residual.is_empty());
+    }
+}
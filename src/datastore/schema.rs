@@ -0,0 +1,408 @@
+use crate::datastore::collection::Document;
+use crate::datastore::datatypes::{self, DataType};
+use regex::Regex;
+use serde::Serialize;
+use serde_json::Value;
+use std::collections::BTreeMap;
+
+/// What a Collection does with a document that does not match its schema
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum ValidationAction {
+    /// Reject the write
+    Error,
+    /// Accept the write and log the violations
+    Warn,
+}
+
+/// A reason a document does not match a schema
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct Violation {
+    /// Dotted path of the offending field, empty for the document itself
+    pub field: String,
+    pub message: String,
+}
+
+/// A JSON Schema and what to do when a document does not match it
+#[derive(Debug, Clone)]
+pub struct Validator {
+    pub schema: Schema,
+    pub action: ValidationAction,
+}
+
+/// The subset of JSON Schema (draft 2020-12) that documents can be
+/// validated against: `type`, `required`, `properties`, `enum`,
+/// `minimum`, `maximum` and `pattern`.
+#[derive(Debug, Clone, Default)]
+pub struct Schema {
+    types: Option<Vec<String>>,
+    required: Vec<String>,
+    properties: BTreeMap<String, Schema>,
+    enumeration: Option<Vec<DataType>>,
+    minimum: Option<f64>,
+    maximum: Option<f64>,
+    pattern: Option<Regex>,
+}
+
+const TYPES: [&str; 7] = [
+    "null", "boolean", "object", "array", "number", "integer", "string",
+];
+
+impl Schema {
+    /// Produces a Schema from its JSON representation
+    ///
+    /// # Arguments
+    ///
+    /// * `value` - the JSON Schema
+    ///
+    /// # Example
+    ///
+    /// ```json
+    /// {
+    ///   "type": "object",
+    ///   "required": ["username"],
+    ///   "properties": {
+    ///     "username": {"type": "string", "pattern": "^[a-z]+$"},
+    ///     "age": {"type": "integer", "minimum": 0}
+    ///   }
+    /// }
+    /// ```
+    pub fn from_json(value: &Value) -> Result<Schema, String> {
+        let keywords = match value {
+            Value::Object(keywords) => keywords,
+            _ => return Err(String::from("a schema must be an object")),
+        };
+        let mut schema = Schema::default();
+        for (keyword, argument) in keywords.iter() {
+            match keyword.as_str() {
+                "type" => {
+                    let names = match argument {
+                        Value::String(name) => vec![name.clone()],
+                        Value::Array(names) => names
+                            .iter()
+                            .map(|name| match name {
+                                Value::String(name) => Ok(name.clone()),
+                                _ => Err(String::from("type names must be strings")),
+                            })
+                            .collect::<Result<Vec<String>, String>>()?,
+                        _ => return Err(String::from("type must be a string or array")),
+                    };
+                    if let Some(unknown) = names.iter().find(|name| !TYPES.contains(&name.as_str()))
+                    {
+                        return Err(format!("unknown type {}", unknown));
+                    }
+                    schema.types = Some(names);
+                }
+                "required" => {
+                    schema.required = match argument {
+                        Value::Array(fields) => fields
+                            .iter()
+                            .map(|field| match field {
+                                Value::String(field) => Ok(field.clone()),
+                                _ => Err(String::from("required fields must be strings")),
+                            })
+                            .collect::<Result<Vec<String>, String>>()?,
+                        _ => return Err(String::from("required must be an array")),
+                    }
+                }
+                "properties" => {
+                    let properties = match argument {
+                        Value::Object(properties) => properties,
+                        _ => return Err(String::from("properties must be an object")),
+                    };
+                    for (field, property) in properties.iter() {
+                        schema
+                            .properties
+                            .insert(field.clone(), Schema::from_json(property)?);
+                    }
+                }
+                "enum" => {
+                    schema.enumeration = match argument {
+                        Value::Array(values) => {
+                            Some(values.iter().map(datatypes::from_json).collect())
+                        }
+                        _ => return Err(String::from("enum must be an array")),
+                    }
+                }
+                "minimum" => schema.minimum = Some(number_argument(keyword, argument)?),
+                "maximum" => schema.maximum = Some(number_argument(keyword, argument)?),
+                "pattern" => {
+                    schema.pattern = match argument {
+                        Value::String(pattern) => Some(
+                            Regex::new(pattern)
+                                .map_err(|e| format!("invalid pattern {}: {}", pattern, e))?,
+                        ),
+                        _ => return Err(String::from("pattern must be a string")),
+                    }
+                }
+                // Annotations and unsupported keywords are ignored
+                _ => {}
+            }
+        }
+        Ok(schema)
+    }
+
+    /// Produces every way a document does not match the schema
+    ///
+    /// # Arguments
+    ///
+    /// * `document` - the document to validate
+    pub fn validate(&self, document: &Document) -> Vec<Violation> {
+        let mut violations = Vec::new();
+        if let Some(types) = &self.types {
+            if !types.iter().any(|name| name == "object") {
+                violations.push(Violation {
+                    field: String::new(),
+                    message: format!("must be of type {}", types.join(" or ")),
+                });
+                return violations;
+            }
+        }
+        for field in self.required.iter() {
+            if !document.contains_key(field) {
+                violations.push(Violation {
+                    field: field.clone(),
+                    message: String::from("is required"),
+                });
+            }
+        }
+        for (field, property) in self.properties.iter() {
+            if let Some(value) = document.get(field) {
+                property.validate_value(field, value, &mut violations);
+            }
+        }
+        violations
+    }
+
+    fn validate_value(&self, field: &str, value: &DataType, violations: &mut Vec<Violation>) {
+        let mut violation = |message: String| {
+            violations.push(Violation {
+                field: String::from(field),
+                message,
+            })
+        };
+        if let Some(types) = &self.types {
+            if !types.iter().any(|name| is_type(value, name)) {
+                violation(format!("must be of type {}", types.join(" or ")));
+            }
+        }
+        if let Some(allowed) = &self.enumeration {
+            if !allowed.contains(value) {
+                let names: Vec<String> = allowed
+                    .iter()
+                    .map(|allowed| datatypes::to_json(allowed).to_string())
+                    .collect();
+                violation(format!("must be one of {}", names.join(", ")));
+            }
+        }
+        if let Some(number) = datatypes::as_f64(value) {
+            if let Some(minimum) = self.minimum {
+                if number < minimum {
+                    violation(format!("must be greater than or equal to {}", minimum));
+                }
+            }
+            if let Some(maximum) = self.maximum {
+                if number > maximum {
+                    violation(format!("must be less than or equal to {}", maximum));
+                }
+            }
+        }
+        if let (Some(pattern), DataType::String(text)) = (&self.pattern, value) {
+            if !pattern.is_match(text) {
+                violation(format!("must match the pattern {}", pattern.as_str()));
+            }
+        }
+        if let DataType::Document(fields) = value {
+            for name in self.required.iter() {
+                if !fields.contains_key(name) {
+                    violations.push(Violation {
+                        field: format!("{}.{}", field, name),
+                        message: String::from("is required"),
+                    });
+                }
+            }
+            for (name, property) in self.properties.iter() {
+                if let Some(value) = fields.get(name) {
+                    property.validate_value(&format!("{}.{}", field, name), value, violations);
+                }
+            }
+        }
+    }
+}
+
+impl Validator {
+    /// Produces a Validator from a request body of the form
+    /// `{"schema": {...}, "validationAction": "error" | "warn"}`
+    ///
+    /// # Arguments
+    ///
+    /// * `value` - the validator options
+    pub fn from_json(value: &Value) -> Result<Validator, String> {
+        let schema = match value.get("schema") {
+            Some(schema) => Schema::from_json(schema)?,
+            None => return Err(String::from("a validator requires a schema")),
+        };
+        let action = match value.get("validationAction") {
+            None => ValidationAction::Error,
+            Some(Value::String(action)) if action == "error" => ValidationAction::Error,
+            Some(Value::String(action)) if action == "warn" => ValidationAction::Warn,
+            Some(_) => {
+                return Err(String::from(
+                    "validationAction must be \"error\" or \"warn\"",
+                ))
+            }
+        };
+        Ok(Validator { schema, action })
+    }
+}
+
+fn number_argument(keyword: &str, argument: &Value) -> Result<f64, String> {
+    match argument.as_f64() {
+        Some(number) => Ok(number),
+        None => Err(format!("{} must be a number", keyword)),
+    }
+}
+
+fn is_type(value: &DataType, name: &str) -> bool {
+    match (value, name) {
+        (DataType::Null, "null") => true,
+        (DataType::Bool(_), "boolean") => true,
+        (DataType::String(_), "string") => true,
+        (DataType::Document(_), "object") => true,
+        (DataType::Array(_), "array") => true,
+        (DataType::I64(_), "integer") | (DataType::U64(_), "integer") => true,
+        (DataType::I64(_), "number") | (DataType::U64(_), "number") => true,
+        (DataType::F64(_), "number") => true,
+        // JSON Schema counts any number without a fractional part as an
+        // integer, 1.0 included
+        (DataType::F64(_), "integer") => {
+            datatypes::as_f64(value).is_some_and(|num| num.fract() == 0.0)
+        }
+        _ => false,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+    use std::collections::HashMap;
+
+    fn user_schema() -> Schema {
+        Schema::from_json(&json!({
+            "type": "object",
+            "required": ["username", "age"],
+            "properties": {
+                "username": {"type": "string", "pattern": "^[a-z]+$"},
+                "age": {"type": "integer", "minimum": 0, "maximum": 200},
+                "role": {"enum": ["captain", "crew"]}
+            }
+        }))
+        .unwrap()
+    }
+
+    fn document(value: Value) -> Document {
+        let map: HashMap<String, Value> = serde_json::from_value(value).unwrap();
+        map.iter()
+            .map(|(field, value)| (field.clone(), datatypes::from_json(value)))
+            .collect()
+    }
+
+    #[test]
+    fn valid_document() {
+        let doc = document(json!({"username": "johnperry", "age": 75, "role": "crew"}));
+        assert_eq!(Vec::<Violation>::new(), user_schema().validate(&doc));
+    }
+
+    #[test]
+    fn missing_required_field() {
+        let doc = document(json!({"username": "johnperry"}));
+        assert_eq!(
+            vec![Violation {
+                field: String::from("age"),
+                message: String::from("is required")
+            }],
+            user_schema().validate(&doc)
+        );
+    }
+
+    #[test]
+    fn every_violation_is_reported() {
+        let doc = document(json!({"username": "John Perry", "age": 75.5, "role": "admiral"}));
+        let fields: Vec<String> = user_schema()
+            .validate(&doc)
+            .into_iter()
+            .map(|violation| violation.field)
+            .collect();
+        assert_eq!(vec!["age", "role", "username"], fields);
+    }
+
+    #[test]
+    fn array_type() {
+        let schema = Schema::from_json(&json!({
+            "properties": {"tags": {"type": "array"}}
+        }))
+        .unwrap();
+        assert!(schema
+            .validate(&document(json!({"tags": ["crew"]})))
+            .is_empty());
+        assert_eq!(1, schema.validate(&document(json!({"tags": "crew"}))).len());
+    }
+
+    #[test]
+    fn integer_valued_float() {
+        let doc = document(json!({"username": "johnperry", "age": 75.0}));
+        assert!(user_schema().validate(&doc).is_empty());
+    }
+
+    #[test]
+    fn out_of_range() {
+        let doc = document(json!({"username": "johnperry", "age": -1}));
+        assert_eq!(
+            vec![Violation {
+                field: String::from("age"),
+                message: String::from("must be greater than or equal to 0")
+            }],
+            user_schema().validate(&doc)
+        );
+    }
+
+    #[test]
+    fn embedded_document() {
+        let schema = Schema::from_json(&json!({
+            "properties": {
+                "address": {
+                    "type": "object",
+                    "required": ["city"],
+                    "properties": {"zip": {"type": "string"}}
+                }
+            }
+        }))
+        .unwrap();
+        let doc = document(json!({"address": {"zip": 75001}}));
+        let fields: Vec<String> = schema
+            .validate(&doc)
+            .into_iter()
+            .map(|violation| violation.field)
+            .collect();
+        assert_eq!(vec!["address.city", "address.zip"], fields);
+    }
+
+    #[test]
+    fn invalid_schema() {
+        assert!(Schema::from_json(&json!({"type": "text"})).is_err());
+        assert!(Schema::from_json(&json!({"pattern": "("})).is_err());
+    }
+
+    #[test]
+    fn validator_action() {
+        let validator = Validator::from_json(&json!({
+            "schema": {"type": "object"},
+            "validationAction": "warn"
+        }))
+        .unwrap();
+        assert_eq!(ValidationAction::Warn, validator.action);
+        assert!(
+            Validator::from_json(&json!({"schema": {}, "validationAction": "ignore"})).is_err()
+        );
+    }
+}
@@ -0,0 +1,743 @@
+use crate::datastore::collection::{field_value, Document};
+use crate::datastore::datatypes::DataType;
+use std::collections::{BTreeMap, HashMap, HashSet};
+
+/// The field text search results hold their relevance in, reserved so it
+/// never takes the place of a stored field
+pub const SCORE_FIELD: &str = "$score";
+
+/// English words too common to tell documents apart, in order so they can
+/// be binary searched
+const STOP_WORDS: [&str; 127] = [
+    "a",
+    "about",
+    "above",
+    "after",
+    "again",
+    "against",
+    "all",
+    "am",
+    "an",
+    "and",
+    "any",
+    "are",
+    "as",
+    "at",
+    "be",
+    "because",
+    "been",
+    "before",
+    "being",
+    "below",
+    "between",
+    "both",
+    "but",
+    "by",
+    "can",
+    "did",
+    "do",
+    "does",
+    "doing",
+    "don",
+    "down",
+    "during",
+    "each",
+    "few",
+    "for",
+    "from",
+    "further",
+    "had",
+    "has",
+    "have",
+    "having",
+    "he",
+    "her",
+    "here",
+    "hers",
+    "herself",
+    "him",
+    "himself",
+    "his",
+    "how",
+    "i",
+    "if",
+    "in",
+    "into",
+    "is",
+    "it",
+    "its",
+    "itself",
+    "just",
+    "me",
+    "more",
+    "most",
+    "my",
+    "myself",
+    "no",
+    "nor",
+    "not",
+    "now",
+    "of",
+    "off",
+    "on",
+    "once",
+    "only",
+    "or",
+    "other",
+    "our",
+    "ours",
+    "ourselves",
+    "out",
+    "over",
+    "own",
+    "s",
+    "same",
+    "she",
+    "should",
+    "so",
+    "some",
+    "such",
+    "t",
+    "than",
+    "that",
+    "the",
+    "their",
+    "theirs",
+    "them",
+    "themselves",
+    "then",
+    "there",
+    "these",
+    "they",
+    "this",
+    "those",
+    "through",
+    "to",
+    "too",
+    "under",
+    "until",
+    "up",
+    "very",
+    "was",
+    "we",
+    "were",
+    "what",
+    "when",
+    "where",
+    "which",
+    "while",
+    "who",
+    "whom",
+    "why",
+    "will",
+    "with",
+    "you",
+    "your",
+    "yours",
+    "yourself",
+    "yourselves",
+];
+
+/// An inverted index over the string fields of a collection's documents,
+/// kept alongside its Index structs to answer `$text` searches. Text is
+/// split into words, lowercased, stripped of English stop words and
+/// stemmed, so `Running` and `runs` are the same term.
+#[derive(Debug, Clone)]
+pub struct TextIndex {
+    pub fields: Vec<String>,
+    /// Each term mapped to the keys of the documents holding it, with how
+    /// many times they do
+    postings: HashMap<String, BTreeMap<usize, u32>>,
+    /// Number of documents holding at least one term
+    documents: usize,
+}
+
+/// A parsed `$search` string: documents match with any of the terms and
+/// none of the excluded terms, written with a leading `-`
+#[derive(Debug, Clone, PartialEq)]
+pub struct Search {
+    pub terms: Vec<String>,
+    pub excluded: Vec<String>,
+}
+
+impl Search {
+    /// Produces the Search of a `$search` string
+    ///
+    /// # Arguments
+    ///
+    /// * `search` - words to search for, those to exclude prefixed by `-`
+    ///
+    /// # Examples
+    ///
+    /// ```rust
+    /// use rockumentdb::datastore::text::Search;
+    ///
+    /// let search = Search::parse("Running shoes -Trail");
+    /// assert_eq!(vec!["run", "shoe"], search.terms);
+    /// assert_eq!(vec!["trail"], search.excluded);
+    /// ```
+    pub fn parse(search: &str) -> Search {
+        let (mut terms, mut excluded) = (Vec::new(), Vec::new());
+        for word in search.split_whitespace() {
+            match word.strip_prefix('-') {
+                Some(word) => excluded.extend(tokenize(word)),
+                None => terms.extend(tokenize(word)),
+            }
+        }
+        let mut seen = HashSet::new();
+        terms.retain(|term| seen.insert(term.clone()));
+        Search { terms, excluded }
+    }
+}
+
+impl TextIndex {
+    /// Produces a new, empty TextIndex
+    ///
+    /// # Arguments
+    ///
+    /// * `fields` - the string fields, or dotted paths into embedded
+    ///   documents, to index
+    pub fn new(fields: Vec<String>) -> TextIndex {
+        TextIndex {
+            fields,
+            postings: HashMap::new(),
+            documents: 0,
+        }
+    }
+
+    /// Produces the index's name, e.g. `title_text_body_text`
+    pub fn name(&self) -> String {
+        self.fields
+            .iter()
+            .map(|field| format!("{}_text", field))
+            .collect::<Vec<String>>()
+            .join("_")
+    }
+
+    /// Adds a document stored under a key
+    ///
+    /// # Arguments
+    ///
+    /// * `document` - the document
+    /// * `key` - the key the document is stored under
+    pub fn insert(&mut self, document: &Document, key: usize) {
+        let frequencies = self.frequencies(document);
+        if frequencies.is_empty() {
+            return;
+        }
+        self.documents += 1;
+        for (term, frequency) in frequencies {
+            self.postings
+                .entry(term)
+                .or_default()
+                .insert(key, frequency);
+        }
+    }
+
+    /// Removes a document stored under a key
+    ///
+    /// # Arguments
+    ///
+    /// * `document` - the document as it was added
+    /// * `key` - the key the document is stored under
+    pub fn remove(&mut self, document: &Document, key: usize) {
+        let frequencies = self.frequencies(document);
+        if frequencies.is_empty() {
+            return;
+        }
+        self.documents -= 1;
+        for term in frequencies.keys() {
+            if let Some(keys) = self.postings.get_mut(term) {
+                keys.remove(&key);
+                if keys.is_empty() {
+                    self.postings.remove(term);
+                }
+            }
+        }
+    }
+
+    /// Produces how many times each term occurs in a document's indexed
+    /// fields
+    fn frequencies(&self, document: &Document) -> HashMap<String, u32> {
+        let mut frequencies = HashMap::new();
+        for field in self.fields.iter() {
+            if let Some(DataType::String(text)) = field_value(document, field) {
+                for term in tokenize(text) {
+                    *frequencies.entry(term).or_insert(0) += 1;
+                }
+            }
+        }
+        frequencies
+    }
+
+    /// Produces the number of documents holding a term
+    pub fn frequency(&self, term: &str) -> usize {
+        self.postings.get(term).map_or(0, |keys| keys.len())
+    }
+
+    /// Produces the keys of the documents matching a search, each with its
+    /// relevance, in key order. A term counts `(1 + ln tf) * ln(1 + N / df)`
+    /// towards a document's score, `tf` being the times the document holds
+    /// it, `df` the number of documents holding it out of `N`, so repeated
+    /// and rarer terms rank higher.
+    ///
+    /// # Arguments
+    ///
+    /// * `search` - the terms to search for and to exclude
+    ///
+    /// # Examples
+    ///
+    /// ```rust
+    /// use rockumentdb::datastore::datatypes::DataType;
+    /// use rockumentdb::datastore::text::{Search, TextIndex};
+    /// use std::collections::HashMap;
+    ///
+    /// let mut index = TextIndex::new(vec![String::from("title")]);
+    /// let mut document = HashMap::new();
+    /// let title = DataType::String(String::from("The Ringworld Engineers"));
+    /// document.insert(String::from("title"), title);
+    /// index.insert(&document, 1);
+    /// let found = index.search(&Search::parse("engineering"));
+    /// assert_eq!(vec![1], found.iter().map(|e| e.0).collect::<Vec<usize>>());
+    /// ```
+    pub fn search(&self, search: &Search) -> Vec<(usize, f64)> {
+        let excluded: HashSet<usize> = search
+            .excluded
+            .iter()
+            .filter_map(|term| self.postings.get(term))
+            .flat_map(|keys| keys.keys().copied())
+            .collect();
+        let mut scores: BTreeMap<usize, f64> = BTreeMap::new();
+        for term in search.terms.iter() {
+            let keys = match self.postings.get(term) {
+                Some(keys) => keys,
+                None => continue,
+            };
+            let rarity = (1.0 + self.documents as f64 / keys.len() as f64).ln();
+            for (key, frequency) in keys.iter() {
+                if !excluded.contains(key) {
+                    *scores.entry(*key).or_insert(0.0) += (1.0 + (*frequency as f64).ln()) * rarity;
+                }
+            }
+        }
+        scores.into_iter().collect()
+    }
+
+    /// Produces the relevance a document would have to a search, None if it
+    /// does not match, see `search`. The rarity of each term is judged by
+    /// the documents in the index, which need not hold this one.
+    ///
+    /// # Arguments
+    ///
+    /// * `search` - the terms to search for and to exclude
+    /// * `document` - the document
+    pub fn score(&self, search: &Search, document: &Document) -> Option<f64> {
+        let frequencies = self.frequencies(document);
+        if search
+            .excluded
+            .iter()
+            .any(|term| frequencies.contains_key(term))
+        {
+            return None;
+        }
+        let mut score = None;
+        for term in search.terms.iter() {
+            if let Some(frequency) = frequencies.get(term) {
+                let holding = self.frequency(term).max(1);
+                let rarity = (1.0 + self.documents as f64 / holding as f64).ln();
+                *score.get_or_insert(0.0) += (1.0 + (*frequency as f64).ln()) * rarity;
+            }
+        }
+        score
+    }
+}
+
+/// Produces the terms of a text: its words lowercased and stemmed, but for
+/// stop words
+///
+/// # Arguments
+///
+/// * `text` - the text
+///
+/// # Examples
+///
+/// ```rust
+/// use rockumentdb::datastore::text::tokenize;
+///
+/// assert_eq!(vec!["connect", "network"], tokenize("Connecting to the networks!"));
+/// ```
+pub fn tokenize(text: &str) -> Vec<String> {
+    text.split(|c: char| !c.is_alphanumeric())
+        .filter(|word| !word.is_empty())
+        .map(str::to_lowercase)
+        .filter(|word| STOP_WORDS.binary_search(&word.as_str()).is_err())
+        .map(|word| stem(&word))
+        .collect()
+}
+
+/// Produces the stem of a lowercase English word with the Porter stemming
+/// algorithm. Words of other than ASCII letters are left as they are.
+///
+/// # Arguments
+///
+/// * `word` - the lowercase word
+///
+/// # Examples
+///
+/// ```rust
+/// use rockumentdb::datastore::text::stem;
+///
+/// assert_eq!("gener", stem("generalization"));
+/// assert_eq!("hop", stem("hopping"));
+/// ```
+pub fn stem(word: &str) -> String {
+    if word.len() <= 2 || !word.bytes().all(|b| b.is_ascii_lowercase()) {
+        return String::from(word);
+    }
+    let mut stemmer = Stemmer {
+        b: word.as_bytes().to_vec(),
+        k: word.len() as isize - 1,
+        j: 0,
+    };
+    stemmer.step1ab();
+    if stemmer.k > 0 {
+        stemmer.step1c();
+        stemmer.step2();
+        stemmer.step3();
+        stemmer.step4();
+        stemmer.step5();
+    }
+    stemmer.b.truncate(stemmer.k as usize + 1);
+    String::from_utf8(stemmer.b).unwrap_or_else(|_| String::from(word))
+}
+
+/// The state of the Porter stemmer over a word: `b[..=k]` is the word as
+/// stemmed so far, `b[..=j]` the stem left by the last suffix matched
+struct Stemmer {
+    b: Vec<u8>,
+    k: isize,
+    j: isize,
+}
+
+impl Stemmer {
+    fn at(&self, i: isize) -> u8 {
+        self.b[i as usize]
+    }
+
+    /// Whether `b[i]` is a consonant; `y` is one unless after a consonant
+    fn cons(&self, i: isize) -> bool {
+        match self.at(i) {
+            b'a' | b'e' | b'i' | b'o' | b'u' => false,
+            b'y' => i == 0 || !self.cons(i - 1),
+            _ => true,
+        }
+    }
+
+    /// The number of vowel-consonant sequences in `b[..=j]`
+    fn m(&self) -> usize {
+        let mut n = 0;
+        let mut i = 0;
+        loop {
+            if i > self.j {
+                return n;
+            }
+            if !self.cons(i) {
+                break;
+            }
+            i += 1;
+        }
+        i += 1;
+        loop {
+            loop {
+                if i > self.j {
+                    return n;
+                }
+                if self.cons(i) {
+                    break;
+                }
+                i += 1;
+            }
+            i += 1;
+            n += 1;
+            loop {
+                if i > self.j {
+                    return n;
+                }
+                if !self.cons(i) {
+                    break;
+                }
+                i += 1;
+            }
+            i += 1;
+        }
+    }
+
+    /// Whether `b[..=j]` holds a vowel
+    fn vowel_in_stem(&self) -> bool {
+        (0..=self.j).any(|i| !self.cons(i))
+    }
+
+    /// Whether `b[j - 1..=j]` is a double consonant
+    fn double_consonant(&self, j: isize) -> bool {
+        j >= 1 && self.at(j) == self.at(j - 1) && self.cons(j)
+    }
+
+    /// Whether `b[i - 2..=i]` is consonant, vowel, consonant, the last not
+    /// `w`, `x` or `y`, as in `hop` but not `snow`
+    fn cvc(&self, i: isize) -> bool {
+        if i < 2 || !self.cons(i) || self.cons(i - 1) || !self.cons(i - 2) {
+            return false;
+        }
+        !matches!(self.at(i), b'w' | b'x' | b'y')
+    }
+
+    /// Whether the word ends with a suffix, setting `j` before it if so
+    fn ends(&mut self, suffix: &str) -> bool {
+        let length = suffix.len() as isize;
+        if length > self.k + 1 {
+            return false;
+        }
+        let start = (self.k - length + 1) as usize;
+        if &self.b[start..=self.k as usize] != suffix.as_bytes() {
+            return false;
+        }
+        self.j = self.k - length;
+        true
+    }
+
+    /// Replaces `b[j + 1..=k]` with a suffix
+    fn set_to(&mut self, suffix: &str) {
+        self.b.truncate((self.j + 1) as usize);
+        self.b.extend_from_slice(suffix.as_bytes());
+        self.k = self.j + suffix.len() as isize;
+    }
+
+    /// Replaces the suffix matched last if the stem before it has a measure
+    fn replace(&mut self, suffix: &str) {
+        if self.m() > 0 {
+            self.set_to(suffix);
+        }
+    }
+
+    /// Replaces the first suffix the word ends with, if any, with its
+    /// replacement when the stem has a measure
+    fn replace_first(&mut self, rules: &[(&str, &str)]) {
+        for (suffix, replacement) in rules {
+            if self.ends(suffix) {
+                self.replace(replacement);
+                return;
+            }
+        }
+    }
+
+    /// Removes plurals and `-ed` or `-ing`, e.g. caresses to caress,
+    /// ponies to poni, motoring to motor, hopping to hop
+    fn step1ab(&mut self) {
+        if self.at(self.k) == b's' {
+            if self.ends("sses") {
+                self.k -= 2;
+            } else if self.ends("ies") {
+                self.set_to("i");
+            } else if self.at(self.k - 1) != b's' {
+                self.k -= 1;
+            }
+        }
+        if self.ends("eed") {
+            if self.m() > 0 {
+                self.k -= 1;
+            }
+        } else if (self.ends("ed") || self.ends("ing")) && self.vowel_in_stem() {
+            self.k = self.j;
+            if self.ends("at") {
+                self.set_to("ate");
+            } else if self.ends("bl") {
+                self.set_to("ble");
+            } else if self.ends("iz") {
+                self.set_to("ize");
+            } else if self.double_consonant(self.k) {
+                if !matches!(self.at(self.k), b'l' | b's' | b'z') {
+                    self.k -= 1;
+                }
+            } else {
+                self.j = self.k;
+                if self.m() == 1 && self.cvc(self.k) {
+                    self.set_to("e");
+                }
+            }
+        }
+    }
+
+    /// Turns a final `y` into `i` after a vowel in the stem, e.g. happy to
+    /// happi
+    fn step1c(&mut self) {
+        if self.ends("y") && self.vowel_in_stem() {
+            self.b[self.k as usize] = b'i';
+        }
+    }
+
+    /// Maps double suffixes to single ones, e.g. relational to relate
+    fn step2(&mut self) {
+        let rules: &[(&str, &str)] = match self.at(self.k - 1) {
+            b'a' => &[("ational", "ate"), ("tional", "tion")],
+            b'c' => &[("enci", "ence"), ("anci", "ance")],
+            b'e' => &[("izer", "ize")],
+            b'l' => &[
+                ("bli", "ble"),
+                ("alli", "al"),
+                ("entli", "ent"),
+                ("eli", "e"),
+                ("ousli", "ous"),
+            ],
+            b'o' => &[("ization", "ize"), ("ation", "ate"), ("ator", "ate")],
+            b's' => &[
+                ("alism", "al"),
+                ("iveness", "ive"),
+                ("fulness", "ful"),
+                ("ousness", "ous"),
+            ],
+            b't' => &[("aliti", "al"), ("iviti", "ive"), ("biliti", "ble")],
+            b'g' => &[("logi", "log")],
+            _ => return,
+        };
+        self.replace_first(rules);
+    }
+
+    /// Removes or simplifies `-ic-`, `-full` and `-ness` suffixes
+    fn step3(&mut self) {
+        let rules: &[(&str, &str)] = match self.at(self.k) {
+            b'e' => &[("icate", "ic"), ("ative", ""), ("alize", "al")],
+            b'i' => &[("iciti", "ic")],
+            b'l' => &[("ical", "ic"), ("ful", "")],
+            b's' => &[("ness", "")],
+            _ => return,
+        };
+        self.replace_first(rules);
+    }
+
+    /// Removes the remaining suffixes of a stem of measure above one
+    fn step4(&mut self) {
+        let suffixes: &[&str] = match self.at(self.k - 1) {
+            b'a' => &["al"],
+            b'c' => &["ance", "ence"],
+            b'e' => &["er"],
+            b'i' => &["ic"],
+            b'l' => &["able", "ible"],
+            b'n' => &["ant", "ement", "ment", "ent"],
+            b'o' => {
+                if self.ends("ion") && self.j >= 0 && matches!(self.at(self.j), b's' | b't') {
+                    &[]
+                } else {
+                    &["ou"]
+                }
+            }
+            b's' => &["ism"],
+            b't' => &["ate", "iti"],
+            b'u' => &["ous"],
+            b'v' => &["ive"],
+            b'z' => &["ize"],
+            _ => return,
+        };
+        let matched = suffixes.is_empty() || suffixes.iter().any(|suffix| self.ends(suffix));
+        if matched && self.m() > 1 {
+            self.k = self.j;
+        }
+    }
+
+    /// Removes a final `-e` and turns a final `-ll` into `-l` on long stems
+    fn step5(&mut self) {
+        self.j = self.k;
+        if self.at(self.k) == b'e' {
+            let m = self.m();
+            if m > 1 || (m == 1 && !self.cvc(self.k - 1)) {
+                self.k -= 1;
+            }
+        }
+        if self.at(self.k) == b'l' && self.double_consonant(self.k) && self.m() > 1 {
+            self.k -= 1;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn book(title: &str) -> Document {
+        let mut document = Document::new();
+        document.insert(String::from("title"), DataType::String(String::from(title)));
+        document
+    }
+
+    #[test]
+    fn stop_words_are_sorted() {
+        assert!(STOP_WORDS.windows(2).all(|pair| pair[0] < pair[1]));
+    }
+
+    #[test]
+    fn stem_words() {
+        let stems = [
+            ("caresses", "caress"),
+            ("ponies", "poni"),
+            ("cats", "cat"),
+            ("feed", "feed"),
+            ("agreed", "agre"),
+            ("plastered", "plaster"),
+            ("motoring", "motor"),
+            ("sing", "sing"),
+            ("conflated", "conflat"),
+            ("troubled", "troubl"),
+            ("sized", "size"),
+            ("hopping", "hop"),
+            ("falling", "fall"),
+            ("filing", "file"),
+            ("happy", "happi"),
+            ("relational", "relat"),
+            ("conditional", "condit"),
+            ("triplicate", "triplic"),
+            ("adjustment", "adjust"),
+            ("adoption", "adopt"),
+            ("controll", "control"),
+            ("connections", "connect"),
+            ("is", "is"),
+            ("café", "café"),
+        ];
+        for (word, expected) in stems.iter() {
+            assert_eq!(*expected, stem(word), "stem of {}", word);
+        }
+    }
+
+    #[test]
+    fn rank_by_relevance() {
+        let mut index = TextIndex::new(vec![String::from("title")]);
+        index.insert(&book("Ringworld"), 1);
+        index.insert(&book("The Ringworld Engineers"), 2);
+        index.insert(&book("Ringworld's Children, ringworlds all"), 3);
+        index.insert(&book("Protector"), 4);
+        index.insert(&book("The"), 5);
+
+        let found = index.search(&Search::parse("ringworld engineering"));
+        let keys: Vec<usize> = found.iter().map(|(key, _)| *key).collect();
+        assert_eq!(vec![1, 2, 3], keys);
+        // The rarer term outweighs the repeated one
+        assert!(found[1].1 > found[2].1);
+        assert!(found[2].1 > found[0].1);
+
+        // A document scores the same whether or not it is indexed
+        let search = Search::parse("ringworld engineering");
+        assert_eq!(
+            Some(found[1].1),
+            index.score(&search, &book("The Ringworld Engineers"))
+        );
+        assert_eq!(None, index.score(&search, &book("Protector")));
+        let search = Search::parse("ringworld -children");
+        assert_eq!(None, index.score(&search, &book("Ringworld's Children")));
+
+        let found = index.search(&Search::parse("ringworld -children"));
+        assert_eq!(vec![1, 2], found.iter().map(|e| e.0).collect::<Vec<_>>());
+
+        index.remove(&book("The Ringworld Engineers"), 2);
+        assert!(index.search(&Search::parse("engineers")).is_empty());
+        assert_eq!(2, index.frequency("ringworld"));
+        assert_eq!("title_text", index.name());
+    }
+}
@@ -0,0 +1,818 @@
+use crate::datastore::collection::{Collection, Document, UpdateResult};
+use crate::datastore::database::{self, Database, Recover, SafeCollection, Snapshot};
+use crate::datastore::error::DatastoreError;
+use crate::datastore::query_proc::{Matches, Query};
+use crate::datastore::update::Update;
+use std::collections::{BTreeMap, HashMap};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex, RwLock, RwLockWriteGuard};
+use std::time::{Duration, Instant};
+
+/// Time an open transaction may go unused before it is aborted, when no
+/// other timeout is given, as MongoDB's transactionLifetimeLimitSeconds
+pub const DEFAULT_TIMEOUT_SECONDS: u64 = 60;
+
+/// The keys a transaction wrote in one collection, each with the document
+/// it left there or None if it removed the document
+type Writes = BTreeMap<usize, Option<Document>>;
+
+/// A set of writes across one or more collections that are either all
+/// committed or all discarded.
+///
+/// Reads and writes inside the transaction see every collection as it was
+/// when the transaction began, plus the transaction's own writes. Nothing
+/// is copied up front: the collections keep the documents other writers
+/// replace while the transaction is open, see `Collection::get_at`, and the
+/// transaction keeps only the documents it wrote. Commit fails if another
+/// writer changed a document the transaction also wrote since then (first
+/// committer wins).
+#[derive(Default)]
+pub struct Transaction {
+    db: Database,
+    snapshot: Snapshot,
+    /// The collections of the database when the transaction began
+    collections: HashMap<String, SafeCollection>,
+    /// The transaction's writes, by collection name
+    writes: HashMap<String, Writes>,
+}
+
+impl Transaction {
+    /// Produces a new Transaction reading the database as it is now
+    ///
+    /// # Arguments
+    ///
+    /// * `db` - the database the transaction operates on
+    pub fn begin(db: &Database) -> Transaction {
+        let (snapshot, collections) = db.snapshot();
+        Transaction {
+            db: db.clone(),
+            snapshot,
+            collections,
+            writes: HashMap::new(),
+        }
+    }
+
+    /// Produces the transaction's view of a document: the document it
+    /// wrote under the key, or else the one stored there when it began
+    fn document<'a>(
+        &'a self,
+        collection: &'a Collection,
+        name: &str,
+        key: usize,
+    ) -> Option<&'a Document> {
+        match self.writes.get(name).and_then(|writes| writes.get(&key)) {
+            Some(written) => written.as_ref(),
+            None => collection.get_at(key, self.snapshot.sequence()),
+        }
+    }
+
+    /// Calls `read` with the collection as the database held it when the
+    /// transaction began, an empty collection if it held none by that name
+    fn read<T>(&self, name: &str, read: impl FnOnce(&Collection) -> T) -> T {
+        match self.collections.get(name) {
+            Some(live) => read(&database::read(live)),
+            None => read(&Collection::new(String::from(name))),
+        }
+    }
+
+    /// Produces the keys of the documents matching a query in the
+    /// transaction's view of a collection, and how they were found. The
+    /// plan is the one chosen for the collection as it is now.
+    ///
+    /// # Arguments
+    ///
+    /// * `name` - the name of the collection
+    /// * `query` - the query
+    pub fn run(&self, name: &str, query: &Query) -> Result<Matches, DatastoreError> {
+        self.read(name, |collection| {
+            let mut matches = collection.run(query)?;
+            // Only the documents written since the transaction began can
+            // match differently than they do now
+            let mut changed = collection.keys_written_since(self.snapshot.sequence());
+            if let Some(writes) = self.writes.get(name) {
+                changed.extend(writes.keys());
+            }
+            matches.ids.retain(|id| !changed.contains(id));
+            matches.scores.retain(|id, _| !changed.contains(id));
+            for key in changed {
+                let relevance = self
+                    .document(collection, name, key)
+                    .and_then(|document| query.relevance(document, collection.text_index()));
+                if let Some(relevance) = relevance {
+                    matches.ids.push(key);
+                    if query.text_search().is_some() {
+                        matches.scores.insert(key, relevance);
+                    }
+                }
+            }
+            matches.ids.sort_unstable();
+            matches.explain.returned = matches.ids.len();
+            Ok(matches)
+        })
+    }
+
+    /// Produces copies of the documents stored under keys in the
+    /// transaction's view of a collection, in the order given
+    ///
+    /// # Arguments
+    ///
+    /// * `name` - the name of the collection
+    /// * `ids` - keys of documents in the collection
+    pub fn documents(&self, name: &str, ids: &[usize]) -> Result<Vec<Document>, DatastoreError> {
+        self.read(name, |collection| {
+            ids.iter()
+                .map(|id| match self.document(collection, name, *id) {
+                    Some(document) => Ok(document.clone()),
+                    None => Err(DatastoreError::InvalidId),
+                })
+                .collect()
+        })
+    }
+
+    /// Produces the documents matching a query in the transaction's view
+    /// of a collection
+    ///
+    /// # Arguments
+    ///
+    /// * `name` - the name of the collection
+    /// * `query` - query statement
+    pub fn find(&self, name: &str, query: &str) -> Result<Vec<Document>, DatastoreError> {
+        let matches = self.run(name, &Query::parse(query)?)?;
+        self.documents(name, &matches.ids)
+    }
+
+    /// Checks that documents the transaction is about to write match the
+    /// collection's schema and, with the transaction's other writes to it,
+    /// do not break its unique indexes as it is now
+    fn check_writes(
+        &self,
+        collection: &Collection,
+        name: &str,
+        written: &[(usize, Document)],
+    ) -> Result<(), DatastoreError> {
+        for (_, document) in written.iter() {
+            collection.validate(document)?;
+        }
+        let mut changes: Vec<(usize, Option<&Document>)> = match self.writes.get(name) {
+            Some(writes) => writes
+                .iter()
+                .filter(|(key, _)| !written.iter().any(|(id, _)| id == *key))
+                .map(|(key, document)| (*key, document.as_ref()))
+                .collect(),
+            None => Vec::new(),
+        };
+        changes.extend(written.iter().map(|(key, document)| (*key, Some(document))));
+        collection.check_unique(&changes)
+    }
+
+    /// Inserts a document into a collection within the transaction
+    ///
+    /// # Arguments
+    ///
+    /// * `name` - the name of the collection
+    /// * `value` - the document to insert
+    pub fn insert(&mut self, name: &str, value: Document) -> Result<usize, DatastoreError> {
+        // Keys come from the live collection so they stay unique once the
+        // transaction is committed
+        let key = match self.collections.get(name) {
+            Some(live) => {
+                let mut collection = database::write(live);
+                let key = collection.reserve_key();
+                self.check_writes(&collection, name, &[(key, value.clone())])?;
+                key
+            }
+            // A collection that does not exist yet is only created once
+            // the transaction commits a write to it, the database reserves
+            // its keys until then
+            None => self.db.reserve_key(name),
+        };
+        self.writes
+            .entry(String::from(name))
+            .or_default()
+            .insert(key, Some(value));
+        Ok(key)
+    }
+
+    /// Updates the documents matching a query within the transaction
+    ///
+    /// # Arguments
+    ///
+    /// * `name` - the name of the collection
+    /// * `query` - query statement selecting the documents to update
+    /// * `update` - the update to apply
+    pub fn update(
+        &mut self,
+        name: &str,
+        query: &str,
+        update: &Update,
+    ) -> Result<UpdateResult, DatastoreError> {
+        let ids = self.run(name, &Query::parse(query)?)?.ids;
+        let mut changed = Vec::new();
+        for (id, mut document) in ids.iter().copied().zip(self.documents(name, &ids)?) {
+            if update
+                .apply(&mut document)
+                .map_err(DatastoreError::InvalidUpdate)?
+            {
+                changed.push((id, document));
+            }
+        }
+        self.read(name, |collection| {
+            self.check_writes(collection, name, &changed)
+        })?;
+        let writes = self.writes.entry(String::from(name)).or_default();
+        let modified = changed.iter().map(|(id, _)| *id).collect();
+        for (id, document) in changed.into_iter() {
+            writes.insert(id, Some(document));
+        }
+        Ok(UpdateResult {
+            matched: ids.len(),
+            modified,
+        })
+    }
+
+    /// Removes the documents matching a query within the transaction
+    ///
+    /// # Arguments
+    ///
+    /// * `name` - the name of the collection
+    /// * `query` - query statement selecting the documents to remove
+    pub fn delete(&mut self, name: &str, query: &str) -> Result<Vec<usize>, DatastoreError> {
+        let deleted = self.run(name, &Query::parse(query)?)?.ids;
+        let writes = self.writes.entry(String::from(name)).or_default();
+        for id in deleted.iter() {
+            writes.insert(*id, None);
+        }
+        Ok(deleted)
+    }
+
+    /// Applies every write made in the transaction to the live collections
+    pub fn commit(self) -> Result<(), DatastoreError> {
+        let db = &self.db;
+        let _commit = db.hold_snapshots();
+        let mut written: Vec<(&String, &Writes, SafeCollection)> = Vec::new();
+        for (name, writes) in self.writes.iter() {
+            if writes.is_empty() {
+                continue;
+            }
+            written.push((name, writes, self.resolve(name)?));
+        }
+        written.sort_by(|a, b| a.0.cmp(b.0));
+        let mut guards: Vec<RwLockWriteGuard<Collection>> = written
+            .iter()
+            .map(|(_, _, live)| database::write(live))
+            .collect();
+
+        let sequence = self.snapshot.sequence();
+        for ((name, writes, safe_live), live) in written.iter().zip(guards.iter()) {
+            // The collection may have been dropped while its lock was
+            // awaited
+            if !db
+                .get(name)
+                .is_some_and(|current| Arc::ptr_eq(&current, safe_live))
+            {
+                return Err(DatastoreError::CollectionNotFound(String::from(*name)));
+            }
+            if let Some(key) = writes
+                .keys()
+                .find(|key| live.written_since(**key, sequence))
+            {
+                return Err(DatastoreError::WriteConflict {
+                    collection: String::from(*name),
+                    key: *key,
+                });
+            }
+            // The collection's schema may have changed since the documents
+            // were written
+            for document in writes.values().flatten() {
+                live.validate(document)?;
+            }
+            // Other writers may have claimed a unique value since the
+            // transaction began, and documents the transaction removed
+            // free theirs
+            let changes: Vec<(usize, Option<&Document>)> = writes
+                .iter()
+                .map(|(key, document)| (*key, document.as_ref()))
+                .collect();
+            live.check_unique(&changes)?;
+        }
+        for ((_, writes, _), live) in written.iter().zip(guards.iter_mut()) {
+            for (key, document) in writes.iter() {
+                match document {
+                    Some(document) => live.put(*key, document.clone()),
+                    None => {
+                        live.remove(*key);
+                    }
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// Produces the live collection the transaction's writes to a
+    /// collection are committed to: the collection it began with if that is
+    /// still in the database under the same name, or the collection to
+    /// create if there was none
+    fn resolve(&self, name: &str) -> Result<SafeCollection, DatastoreError> {
+        match (self.collections.get(name), self.db.get(name)) {
+            (Some(taken), Some(current)) if Arc::ptr_eq(taken, &current) => Ok(current),
+            (Some(_), _) => Err(DatastoreError::CollectionNotFound(String::from(name))),
+            (None, Some(current)) => Ok(current),
+            (None, None) => Ok(self.db.get_or_create(name)),
+        }
+    }
+}
+
+/// Each write leaves the transaction's state whole, so there is nothing to
+/// recover
+impl Recover for Transaction {}
+
+pub type SafeTransaction = Arc<Mutex<Transaction>>;
+
+/// An open transaction and when it was last used
+struct OpenTransaction {
+    transaction: SafeTransaction,
+    used: Mutex<Instant>,
+}
+
+impl OpenTransaction {
+    fn expired(&self, timeout: Duration) -> bool {
+        database::lock(&self.used).elapsed() >= timeout
+    }
+}
+
+/// Registry of the open transactions, keyed by transaction id. A
+/// transaction left unused for longer than the registry's timeout is
+/// aborted, so that abandoned transactions do not keep the collections
+/// holding every document written since they began.
+pub struct Transactions {
+    next_id: AtomicU64,
+    open: RwLock<HashMap<u64, OpenTransaction>>,
+    timeout: Duration,
+}
+
+impl Transactions {
+    /// Produces a new, empty registry aborting transactions unused for
+    /// `DEFAULT_TIMEOUT_SECONDS`
+    pub fn new() -> Transactions {
+        Transactions::with_timeout(Duration::from_secs(DEFAULT_TIMEOUT_SECONDS))
+    }
+
+    /// Produces a new, empty registry
+    ///
+    /// # Arguments
+    ///
+    /// * `timeout` - how long a transaction may go unused before it is
+    ///   aborted
+    pub fn with_timeout(timeout: Duration) -> Transactions {
+        Transactions {
+            next_id: AtomicU64::new(1),
+            open: RwLock::new(HashMap::new()),
+            timeout,
+        }
+    }
+
+    /// Begins a transaction, producing its id. Transactions that timed out
+    /// are aborted first.
+    ///
+    /// # Arguments
+    ///
+    /// * `db` - the database the transaction operates on
+    ///
+    /// # Examples
+    ///
+    /// ```rust
+    /// use rockumentdb::datastore::database::Database;
+    /// use rockumentdb::datastore::transaction::Transactions;
+    /// use std::collections::HashMap;
+    ///
+    /// let db = Database::new();
+    /// let transactions = Transactions::new();
+    ///
+    /// let id = transactions.begin(&db);
+    /// let txn = transactions.get(id).unwrap();
+    /// txn.lock().unwrap().insert("orders", HashMap::new()).unwrap();
+    /// transactions.commit(id).unwrap();
+    /// ```
+    pub fn begin(&self, db: &Database) -> u64 {
+        self.reap();
+        let id = self.next_id.fetch_add(1, Ordering::SeqCst);
+        let open = OpenTransaction {
+            transaction: Arc::new(Mutex::new(Transaction::begin(db))),
+            used: Mutex::new(Instant::now()),
+        };
+        database::write(&self.open).insert(id, open);
+        id
+    }
+
+    /// Produces an open transaction, marking it used
+    ///
+    /// # Arguments
+    ///
+    /// * `id` - the transaction id
+    pub fn get(&self, id: u64) -> Result<SafeTransaction, DatastoreError> {
+        match database::read(&self.open).get(&id) {
+            Some(open) if !open.expired(self.timeout) => {
+                *database::lock(&open.used) = Instant::now();
+                Ok(open.transaction.clone())
+            }
+            _ => Err(DatastoreError::TransactionNotFound),
+        }
+    }
+
+    /// Aborts every transaction left unused for longer than the timeout,
+    /// producing how many were
+    pub fn reap(&self) -> usize {
+        let mut open = database::write(&self.open);
+        let before = open.len();
+        open.retain(|_, transaction| !transaction.expired(self.timeout));
+        before - open.len()
+    }
+
+    fn take(&self, id: u64) -> Result<Transaction, DatastoreError> {
+        let open = match database::write(&self.open).remove(&id) {
+            Some(open) if !open.expired(self.timeout) => open,
+            _ => return Err(DatastoreError::TransactionNotFound),
+        };
+        // Wait for any request still using the transaction to finish
+        let mut transaction = database::lock(&open.transaction);
+        Ok(std::mem::take(&mut *transaction))
+    }
+
+    /// Commits and closes a transaction. A transaction that fails to
+    /// commit is aborted.
+    ///
+    /// # Arguments
+    ///
+    /// * `id` - the transaction id
+    pub fn commit(&self, id: u64) -> Result<(), DatastoreError> {
+        self.take(id)?.commit()
+    }
+
+    /// Discards every write made in a transaction and closes it
+    ///
+    /// # Arguments
+    ///
+    /// * `id` - the transaction id
+    pub fn abort(&self, id: u64) -> Result<(), DatastoreError> {
+        self.take(id).map(|_| ())
+    }
+}
+
+impl Default for Transactions {
+    fn default() -> Self {
+        Transactions::new()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::datastore::datatypes::DataType;
+    use crate::datastore::schema::Validator;
+    use crate::datastore::update::Operation;
+
+    fn item(name: &str, stock: u64) -> Document {
+        let mut document = HashMap::new();
+        document.insert(String::from("item"), DataType::String(String::from(name)));
+        document.insert(String::from("stock"), DataType::U64(stock));
+        document
+    }
+
+    fn decrement_stock() -> Update {
+        Update::Operators(vec![Operation::Inc(
+            String::from("stock"),
+            DataType::I64(-1i64),
+        )])
+    }
+
+    fn count(db: &Database, name: &str, query: &str) -> usize {
+        let collection = match db.get(name) {
+            Some(collection) => collection,
+            None => return 0,
+        };
+        let collection = database::read(&collection);
+        collection
+            .find(query)
+            .map_or(0, |documents| documents.len())
+    }
+
+    #[test]
+    fn commit_across_collections() {
+        let db = Database::new();
+        let inventory = db.get_or_create("inventory");
+        let key = database::write(&inventory)
+            .insert(item("widget", 5))
+            .unwrap();
+
+        let transactions = Transactions::new();
+        let id = transactions.begin(&db);
+        {
+            let txn = transactions.get(id).unwrap();
+            let mut txn = database::lock(&txn);
+            txn.insert("orders", item("widget", 1)).unwrap();
+            txn.update("inventory", "{item:\"widget\"}", &decrement_stock())
+                .unwrap();
+        }
+        assert_eq!(0, count(&db, "orders", "{item:\"widget\"}"));
+
+        transactions.commit(id).unwrap();
+        assert_eq!(1, count(&db, "orders", "{item:\"widget\"}"));
+        assert_eq!(
+            Some(&DataType::I64(4i64)),
+            database::read(&inventory).get(key).unwrap().get("stock")
+        );
+        assert_eq!(
+            Err(DatastoreError::TransactionNotFound),
+            transactions.commit(id)
+        );
+    }
+
+    #[test]
+    fn abort_discards_writes() {
+        let db = Database::new();
+        let transactions = Transactions::new();
+        let id = transactions.begin(&db);
+        database::lock(&transactions.get(id).unwrap())
+            .insert("orders", item("widget", 1))
+            .unwrap();
+        transactions.abort(id).unwrap();
+        assert!(db.get("orders").is_none());
+    }
+
+    #[test]
+    fn reads_see_snapshot() {
+        let db = Database::new();
+        let inventory = db.get_or_create("inventory");
+        let orders = db.get_or_create("orders");
+        let widget = database::write(&inventory)
+            .insert(item("widget", 5))
+            .unwrap();
+        let transactions = Transactions::new();
+        let id = transactions.begin(&db);
+        let txn = transactions.get(id).unwrap();
+        let mut txn = database::lock(&txn);
+        assert_eq!(1, txn.find("inventory", "{}").unwrap().len());
+
+        // Collections the transaction has not read yet are seen as they
+        // were when it began too
+        database::write(&orders).insert(item("widget", 1)).unwrap();
+        database::write(&inventory)
+            .update("{item:\"widget\"}", &decrement_stock())
+            .unwrap();
+        database::write(&inventory)
+            .insert(item("gadget", 2))
+            .unwrap();
+        assert!(txn.find("orders", "{}").unwrap().is_empty());
+        assert_eq!(
+            Ok(vec![item("widget", 5)]),
+            txn.find("inventory", "{stock:5}")
+        );
+        assert!(txn
+            .find("inventory", "{item:\"gadget\"}")
+            .unwrap()
+            .is_empty());
+
+        // The transaction sees its own writes
+        txn.update("inventory", "{item:\"widget\"}", &decrement_stock())
+            .unwrap();
+        txn.insert("inventory", item("gadget", 3)).unwrap();
+        assert!(txn.find("inventory", "{stock:5}").unwrap().is_empty());
+        assert_eq!(2, txn.find("inventory", "{}").unwrap().len());
+        txn.delete("inventory", "{item:\"widget\"}").unwrap();
+        assert_eq!(
+            Err(DatastoreError::InvalidId),
+            txn.documents("inventory", &[widget])
+        );
+
+        // Reading a collection that does not exist does not create it
+        assert!(txn.find("tickets", "{}").unwrap().is_empty());
+        assert!(db.get("tickets").is_none());
+    }
+
+    #[test]
+    fn text_search_in_snapshot() {
+        let db = Database::new();
+        let books = db.get_or_create("books");
+        let title = |title: &str| {
+            let mut document = HashMap::new();
+            document.insert(String::from("title"), DataType::String(String::from(title)));
+            document
+        };
+        let ringworld = {
+            let mut books = database::write(&books);
+            books.create_text_index(vec![String::from("title")]);
+            books.insert(title("Ringworld")).unwrap()
+        };
+        let transactions = Transactions::new();
+        let id = transactions.begin(&db);
+        database::write(&books).remove(ringworld);
+        let txn = transactions.get(id).unwrap();
+        let mut txn = database::lock(&txn);
+        txn.insert("books", title("The Ringworld Engineers"))
+            .unwrap();
+        let query = Query::parse("{$text: {$search: \"ringworld\"}}").unwrap();
+        let matches = txn.run("books", &query).unwrap();
+        assert_eq!(2, matches.ids.len());
+        assert_eq!(2, matches.scores.len());
+    }
+
+    #[test]
+    fn first_committer_wins() {
+        let db = Database::new();
+        let inventory = db.get_or_create("inventory");
+        database::write(&inventory)
+            .insert(item("widget", 5))
+            .unwrap();
+
+        let transactions = Transactions::new();
+        let first = transactions.begin(&db);
+        let second = transactions.begin(&db);
+        for id in [first, second].iter() {
+            database::lock(&transactions.get(*id).unwrap())
+                .update("inventory", "{item:\"widget\"}", &decrement_stock())
+                .unwrap();
+        }
+        transactions.commit(first).unwrap();
+        assert_eq!(
+            Err(DatastoreError::WriteConflict {
+                collection: String::from("inventory"),
+                key: 1,
+            }),
+            transactions.commit(second)
+        );
+        assert_eq!(
+            Some(&DataType::I64(4i64)),
+            database::read(&inventory).get(1).unwrap().get("stock")
+        );
+    }
+
+    #[test]
+    fn removal_by_another_writer_conflicts() {
+        let db = Database::new();
+        let inventory = db.get_or_create("inventory");
+        let widget = database::write(&inventory)
+            .insert(item("widget", 5))
+            .unwrap();
+
+        let transactions = Transactions::new();
+        let id = transactions.begin(&db);
+        {
+            let safe_transaction = transactions.get(id).unwrap();
+            let mut transaction = database::lock(&safe_transaction);
+            transaction
+                .update("inventory", "{item:\"widget\"}", &decrement_stock())
+                .unwrap();
+            transaction.insert("inventory", item("gadget", 1)).unwrap();
+        }
+        database::write(&inventory).remove(widget);
+        assert_eq!(
+            Err(DatastoreError::WriteConflict {
+                collection: String::from("inventory"),
+                key: widget,
+            }),
+            transactions.commit(id)
+        );
+        assert!(database::read(&inventory).is_empty());
+    }
+
+    #[test]
+    fn unique_index_checked_on_commit() {
+        let db = Database::new();
+        let inventory = db.get_or_create("inventory");
+        database::write(&inventory)
+            .create_index("item", true)
+            .unwrap();
+
+        let transactions = Transactions::new();
+        let id = transactions.begin(&db);
+        database::lock(&transactions.get(id).unwrap())
+            .insert("inventory", item("widget", 1))
+            .unwrap();
+        database::write(&inventory)
+            .insert(item("widget", 5))
+            .unwrap();
+        assert!(matches!(
+            transactions.commit(id),
+            Err(DatastoreError::DuplicateKey { .. })
+        ));
+    }
+
+    #[test]
+    fn schema_checked_on_commit() {
+        let db = Database::new();
+        let transactions = Transactions::new();
+        let id = transactions.begin(&db);
+        database::lock(&transactions.get(id).unwrap())
+            .insert("inventory", item("widget", 1))
+            .unwrap();
+        let schema = serde_json::json!({
+            "schema": {"type": "object", "required": ["price"]}
+        });
+        database::write(&db.get_or_create("inventory"))
+            .set_validator(Some(Validator::from_json(&schema).unwrap()));
+        assert!(matches!(
+            transactions.commit(id),
+            Err(DatastoreError::Validation(_))
+        ));
+        assert_eq!(0, count(&db, "inventory", "{}"));
+    }
+
+    #[test]
+    fn delete_frees_unique_value() {
+        let db = Database::new();
+        let inventory = db.get_or_create("inventory");
+        database::write(&inventory)
+            .create_index("item", true)
+            .unwrap();
+        database::write(&inventory)
+            .insert(item("widget", 5))
+            .unwrap();
+
+        let transactions = Transactions::new();
+        let id = transactions.begin(&db);
+        {
+            let txn = transactions.get(id).unwrap();
+            let mut txn = database::lock(&txn);
+            txn.delete("inventory", "{item:\"widget\"}").unwrap();
+            txn.insert("inventory", item("widget", 1)).unwrap();
+        }
+        transactions.commit(id).unwrap();
+        assert_eq!(1, count(&db, "inventory", "{stock:1}"));
+        assert_eq!(0, count(&db, "inventory", "{stock:5}"));
+    }
+
+    #[test]
+    fn commit_into_dropped_collection() {
+        let db = Database::new();
+        db.get_or_create("orders");
+        let transactions = Transactions::new();
+        let id = transactions.begin(&db);
+        database::lock(&transactions.get(id).unwrap())
+            .insert("orders", item("widget", 1))
+            .unwrap();
+        db.remove("orders");
+        db.get_or_create("orders");
+        assert_eq!(
+            Err(DatastoreError::CollectionNotFound(String::from("orders"))),
+            transactions.commit(id)
+        );
+        assert_eq!(0, count(&db, "orders", "{}"));
+    }
+
+    #[test]
+    fn create_collection_on_commit() {
+        let db = Database::new();
+        let transactions = Transactions::new();
+        let id = transactions.begin(&db);
+        database::lock(&transactions.get(id).unwrap())
+            .insert("orders", item("widget", 1))
+            .unwrap();
+        assert!(db.get("orders").is_none());
+        transactions.commit(id).unwrap();
+        let orders = db.get("orders").unwrap();
+        // Keys the transaction gave out are not given out again
+        assert_eq!(
+            Ok(2usize),
+            database::write(&orders).insert(item("gadget", 1))
+        );
+    }
+
+    #[test]
+    fn collection_created_by_another_writer() {
+        let db = Database::new();
+        let transactions = Transactions::new();
+        let id = transactions.begin(&db);
+        let key = database::lock(&transactions.get(id).unwrap())
+            .insert("orders", item("widget", 1))
+            .unwrap();
+        // Creating the collection does not give out the key the transaction
+        // was given
+        let orders = db.get_or_create("orders");
+        let other = database::write(&orders).insert(item("gadget", 1)).unwrap();
+        assert_ne!(key, other);
+        transactions.commit(id).unwrap();
+        assert_eq!(2, count(&db, "orders", "{}"));
+        assert_eq!(
+            Some(&DataType::String(String::from("widget"))),
+            database::read(&orders).get(key).unwrap().get("item")
+        );
+    }
+
+    #[test]
+    fn abort_unused_transactions() {
+        let db = Database::new();
+        let transactions = Transactions::with_timeout(Duration::from_millis(20));
+        let id = transactions.begin(&db);
+        database::lock(&transactions.get(id).unwrap())
+            .insert("orders", item("widget", 1))
+            .unwrap();
+        std::thread::sleep(Duration::from_millis(30));
+        assert!(transactions.get(id).is_err());
+        assert_eq!(1, transactions.reap());
+        assert_eq!(
+            Err(DatastoreError::TransactionNotFound),
+            transactions.commit(id)
+        );
+        assert!(db.get("orders").is_none());
+    }
+}
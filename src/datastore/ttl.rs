@@ -0,0 +1,127 @@
+use crate::datastore::collection::{field_value, Document};
+use crate::datastore::datatypes::DataType;
+use std::collections::BTreeSet;
+use std::convert::TryFrom;
+use std::ops::Bound;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+/// Expiry of a collection's documents by a date field, kept alongside the
+/// field's Index. A document expires `expire_after_seconds` after the date
+/// in the field; documents whose field is missing or not a date never
+/// expire.
+#[derive(Debug, Clone)]
+pub struct TtlIndex {
+    pub expire_after_seconds: u64,
+    field: String,
+    /// The date of each document, in milliseconds since the Unix epoch,
+    /// ordered so the expired documents are the first entries
+    dates: BTreeSet<(i64, usize)>,
+}
+
+impl TtlIndex {
+    /// Produces a new, empty TtlIndex
+    ///
+    /// # Arguments
+    ///
+    /// * `field` - the date field, or a dotted path into embedded documents
+    /// * `expire_after_seconds` - how long after its date a document expires
+    pub fn new(field: &str, expire_after_seconds: u64) -> TtlIndex {
+        TtlIndex {
+            expire_after_seconds,
+            field: String::from(field),
+            dates: BTreeSet::new(),
+        }
+    }
+
+    /// Adds a document stored under a key
+    ///
+    /// # Arguments
+    ///
+    /// * `document` - the document
+    /// * `key` - the key the document is stored under
+    pub fn insert(&mut self, document: &Document, key: usize) {
+        if let Some(date) = self.date(document) {
+            self.dates.insert((date, key));
+        }
+    }
+
+    /// Removes a document stored under a key
+    ///
+    /// # Arguments
+    ///
+    /// * `document` - the document as it was added
+    /// * `key` - the key the document is stored under
+    pub fn remove(&mut self, document: &Document, key: usize) {
+        if let Some(date) = self.date(document) {
+            self.dates.remove(&(date, key));
+        }
+    }
+
+    fn date(&self, document: &Document) -> Option<i64> {
+        match field_value(document, &self.field)? {
+            DataType::DateTime(millis) => Some(*millis),
+            _ => None,
+        }
+    }
+
+    /// Produces the keys of the documents expired at a time, oldest first
+    ///
+    /// # Arguments
+    ///
+    /// * `now` - the time, in milliseconds since the Unix epoch
+    ///
+    /// # Examples
+    ///
+    /// ```rust
+    /// use rockumentdb::datastore::datatypes::DataType;
+    /// use rockumentdb::datastore::ttl::TtlIndex;
+    /// use std::collections::HashMap;
+    ///
+    /// let mut index = TtlIndex::new("created", 60);
+    /// let mut document = HashMap::new();
+    /// document.insert(String::from("created"), DataType::DateTime(0));
+    /// index.insert(&document, 1);
+    /// assert!(index.expired(59_999).is_empty());
+    /// assert_eq!(vec![1], index.expired(60_000));
+    /// ```
+    pub fn expired(&self, now: i64) -> Vec<usize> {
+        let lifetime =
+            i64::try_from(self.expire_after_seconds.saturating_mul(1000)).unwrap_or(i64::MAX);
+        let cutoff = now.saturating_sub(lifetime);
+        self.dates
+            .range((Bound::Unbounded, Bound::Included((cutoff, usize::MAX))))
+            .map(|(_, key)| *key)
+            .collect()
+    }
+}
+
+/// Produces the current time in milliseconds since the Unix epoch, the
+/// unit of dates
+pub fn now_millis() -> i64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map_or(0, |elapsed| elapsed.as_millis() as i64)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn session(created: DataType) -> Document {
+        let mut document = Document::new();
+        document.insert(String::from("created"), created);
+        document
+    }
+
+    #[test]
+    fn expire_by_date() {
+        let mut index = TtlIndex::new("created", 1);
+        index.insert(&session(DataType::DateTime(5_000)), 1);
+        index.insert(&session(DataType::DateTime(1_000)), 2);
+        index.insert(&session(DataType::DateTime(3_000)), 3);
+        index.insert(&session(DataType::I64(0)), 4);
+        assert_eq!(vec![2, 3], index.expired(4_000));
+        index.remove(&session(DataType::DateTime(1_000)), 2);
+        assert_eq!(vec![3, 1], index.expired(i64::MAX));
+    }
+}
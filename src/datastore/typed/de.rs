@@ -0,0 +1,174 @@
+use crate::datastore::collection::Document;
+use crate::datastore::datatypes::DataType;
+use crate::datastore::error::DatastoreError;
+use serde::de::value::{MapDeserializer, SeqDeserializer};
+use serde::de::{self, DeserializeOwned, IntoDeserializer, Visitor};
+
+/// Produces a value of type `T` from a Document
+///
+/// # Arguments
+///
+/// * `document` - the document to deserialize
+///
+/// # Examples
+///
+/// ```rust
+/// use rockumentdb::datastore::datatypes::DataType;
+/// use rockumentdb::datastore::typed::from_document;
+/// use serde::Deserialize;
+/// use std::collections::HashMap;
+///
+/// #[derive(Deserialize)]
+/// struct User {
+///     username: String,
+/// }
+///
+/// let mut document = HashMap::new();
+/// document.insert(
+///     String::from("username"),
+///     DataType::String(String::from("johnperry")),
+/// );
+/// let user: User = from_document(document).unwrap();
+/// ```
+pub fn from_document<T: DeserializeOwned>(document: Document) -> Result<T, DatastoreError> {
+    T::deserialize(MapDeserializer::new(document.into_iter()))
+}
+
+impl<'de> IntoDeserializer<'de, DatastoreError> for DataType {
+    type Deserializer = DataType;
+
+    fn into_deserializer(self) -> DataType {
+        self
+    }
+}
+
+impl<'de> de::Deserializer<'de> for DataType {
+    type Error = DatastoreError;
+
+    fn deserialize_any<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, DatastoreError> {
+        match self {
+            DataType::Null => visitor.visit_unit(),
+            DataType::Bool(val) => visitor.visit_bool(val),
+            DataType::F64(val) => visitor.visit_f64(val.get()),
+            DataType::I64(val) => visitor.visit_i64(val),
+            DataType::U64(val) => visitor.visit_u64(val),
+            DataType::String(val) => visitor.visit_string(val),
+            DataType::Decimal128(val) => visitor.visit_string(val.to_string()),
+            DataType::DateTime(val) => visitor.visit_i64(val),
+            DataType::Binary { bytes, .. } => visitor.visit_byte_buf(bytes),
+            DataType::ObjectId(val) => visitor.visit_string(val.to_string()),
+            DataType::Document(val) => visitor.visit_map(MapDeserializer::new(val.into_iter())),
+            DataType::Array(val) => visitor.visit_seq(SeqDeserializer::new(val.into_iter())),
+        }
+    }
+
+    fn deserialize_option<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, DatastoreError> {
+        match self {
+            DataType::Null => visitor.visit_none(),
+            _ => visitor.visit_some(self),
+        }
+    }
+
+    fn deserialize_newtype_struct<V: Visitor<'de>>(
+        self,
+        _name: &'static str,
+        visitor: V,
+    ) -> Result<V::Value, DatastoreError> {
+        visitor.visit_newtype_struct(self)
+    }
+
+    // Unit variants are stored by name, see `DataTypeSerializer`
+    fn deserialize_enum<V: Visitor<'de>>(
+        self,
+        _name: &'static str,
+        _variants: &'static [&'static str],
+        visitor: V,
+    ) -> Result<V::Value, DatastoreError> {
+        match self {
+            DataType::String(variant) => visitor.visit_enum(
+                IntoDeserializer::<DatastoreError>::into_deserializer(variant),
+            ),
+            _ => Err(DatastoreError::Malformed(String::from(
+                "expected a string holding an enum variant",
+            ))),
+        }
+    }
+
+    serde::forward_to_deserialize_any! {
+        bool i8 i16 i32 i64 i128 u8 u16 u32 u64 u128 f32 f64 char str string
+        bytes byte_buf unit unit_struct seq tuple tuple_struct map struct
+        identifier ignored_any
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::datastore::datatypes::Float;
+    use serde::Deserialize;
+    use std::collections::HashMap;
+
+    #[derive(Debug, PartialEq, Deserialize)]
+    enum Role {
+        Captain,
+        Crew,
+    }
+
+    #[derive(Debug, PartialEq, Deserialize)]
+    struct User {
+        username: String,
+        age: u8,
+        balance: f64,
+        nickname: Option<String>,
+        role: Role,
+    }
+
+    fn user_document() -> Document {
+        let mut document = HashMap::new();
+        document.insert(
+            String::from("username"),
+            DataType::String(String::from("johnperry")),
+        );
+        document.insert(String::from("age"), DataType::I64(75i64));
+        document.insert(String::from("balance"), DataType::F64(Float::new(12.5)));
+        document.insert(String::from("nickname"), DataType::Null);
+        document.insert(String::from("role"), DataType::String(String::from("Crew")));
+        document
+    }
+
+    #[test]
+    fn deserialize_struct() {
+        let user: User = from_document(user_document()).unwrap();
+        assert_eq!(
+            user,
+            User {
+                username: String::from("johnperry"),
+                age: 75,
+                balance: 12.5,
+                nickname: None,
+                role: Role::Crew,
+            }
+        )
+    }
+
+    #[test]
+    fn deserialize_ignores_unknown_fields() {
+        let mut document = user_document();
+        document.insert(String::from("email"), DataType::Null);
+        assert!(from_document::<User>(document).is_ok());
+    }
+
+    #[test]
+    fn deserialize_missing_field() {
+        let mut document = user_document();
+        document.remove("username");
+        assert!(from_document::<User>(document).is_err());
+    }
+
+    #[test]
+    fn deserialize_out_of_range() {
+        let mut document = user_document();
+        document.insert(String::from("age"), DataType::I64(-1i64));
+        assert!(from_document::<User>(document).is_err());
+    }
+}
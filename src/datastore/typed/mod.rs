@@ -0,0 +1,261 @@
+mod de;
+mod ser;
+
+pub use de::from_document;
+pub use ser::{to_datatype, to_document, DataTypeSerializer, DocumentSerializer};
+
+use crate::datastore::collection::Collection;
+use crate::datastore::database::{self, SafeCollection};
+use crate::datastore::error::DatastoreError;
+use serde::de::DeserializeOwned;
+use serde::Serialize;
+use std::fmt;
+use std::marker::PhantomData;
+use std::sync::{Arc, RwLock};
+
+/// Serde's errors are the values and documents that cannot be converted
+impl serde::ser::Error for DatastoreError {
+    fn custom<T: fmt::Display>(msg: T) -> Self {
+        DatastoreError::Malformed(msg.to_string())
+    }
+}
+
+impl serde::de::Error for DatastoreError {
+    fn custom<T: fmt::Display>(msg: T) -> Self {
+        DatastoreError::Malformed(msg.to_string())
+    }
+}
+
+/// A Collection that stores and produces values of type `T` rather than
+/// raw Documents. It holds the collection behind the same lock as a
+/// Database, so values can be stored in a collection the server serves.
+pub struct TypedCollection<T> {
+    collection: SafeCollection,
+    document_type: PhantomData<T>,
+}
+
+impl<T: Serialize + DeserializeOwned> TypedCollection<T> {
+    /// Produces a new TypedCollection
+    ///
+    /// # Arguments
+    ///
+    /// * `name` - the name of the collection
+    ///
+    /// # Examples
+    ///
+    /// ```rust
+    /// use rockumentdb::datastore::typed::TypedCollection;
+    /// use serde::{Deserialize, Serialize};
+    ///
+    /// #[derive(Serialize, Deserialize)]
+    /// struct User {
+    ///     username: String,
+    /// }
+    ///
+    /// let users: TypedCollection<User> = TypedCollection::new(String::from("users"));
+    /// ```
+    pub fn new(name: String) -> TypedCollection<T> {
+        TypedCollection::from_collection(Arc::new(RwLock::new(Collection::new(name))))
+    }
+
+    /// Wraps an existing Collection, such as one of a Database
+    ///
+    /// # Arguments
+    ///
+    /// * `collection` - the collection to store values in
+    ///
+    /// # Examples
+    ///
+    /// ```rust
+    /// use rockumentdb::datastore::database::{self, Database};
+    /// use rockumentdb::datastore::typed::TypedCollection;
+    /// use serde::{Deserialize, Serialize};
+    ///
+    /// #[derive(Serialize, Deserialize)]
+    /// struct User {
+    ///     username: String,
+    /// }
+    ///
+    /// let db = Database::new();
+    /// let users = TypedCollection::from_collection(db.get_or_create("users"));
+    /// users.insert(&User { username: String::from("johnperry") }).unwrap();
+    /// assert_eq!(1, database::read(&db.get("users").unwrap()).len());
+    /// ```
+    pub fn from_collection(collection: SafeCollection) -> TypedCollection<T> {
+        TypedCollection {
+            collection,
+            document_type: PhantomData,
+        }
+    }
+
+    /// Serializes a value into a document and inserts it into the collection
+    ///
+    /// # Arguments
+    ///
+    /// * `value` - any struct or map with string keys
+    ///
+    /// # Examples
+    ///
+    /// ```rust
+    /// use rockumentdb::datastore::typed::TypedCollection;
+    /// use serde::{Deserialize, Serialize};
+    ///
+    /// #[derive(Serialize, Deserialize)]
+    /// struct User {
+    ///     username: String,
+    ///     age: u64,
+    /// }
+    ///
+    /// let users = TypedCollection::new(String::from("users"));
+    /// let key = users
+    ///     .insert(&User { username: String::from("johnperry"), age: 75 })
+    ///     .unwrap();
+    /// ```
+    pub fn insert(&self, value: &T) -> Result<usize, DatastoreError> {
+        let document = to_document(value)?;
+        database::write(&self.collection).insert(document)
+    }
+
+    /// Produces the results of a query deserialized into `T`
+    ///
+    /// # Arguments
+    ///
+    /// * `query` - query statement
+    ///
+    /// # Examples
+    ///
+    /// ```rust
+    /// use rockumentdb::datastore::typed::TypedCollection;
+    /// use serde::{Deserialize, Serialize};
+    ///
+    /// #[derive(Serialize, Deserialize)]
+    /// struct User {
+    ///     username: String,
+    /// }
+    ///
+    /// let users = TypedCollection::new(String::from("users"));
+    /// users.insert(&User { username: String::from("johnperry") }).unwrap();
+    ///
+    /// let results = users.find("{username:\"johnperry\"}").unwrap();
+    /// ```
+    pub fn find(&self, query: &str) -> Result<Vec<T>, DatastoreError> {
+        database::read(&self.collection)
+            .find(query)?
+            .into_iter()
+            .map(|document| from_document(document.clone()))
+            .collect()
+    }
+
+    /// Produces the underlying Collection
+    pub fn collection(&self) -> &SafeCollection {
+        &self.collection
+    }
+
+    /// Consumes the TypedCollection, producing the underlying Collection
+    pub fn into_inner(self) -> SafeCollection {
+        self.collection
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::datastore::database::Database;
+    use serde::Deserialize;
+
+    #[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+    struct User {
+        username: String,
+        email: String,
+        age: u64,
+        active: bool,
+    }
+
+    fn john() -> User {
+        User {
+            username: String::from("johnperry"),
+            email: String::from("johnperry@example.com"),
+            age: 75,
+            active: true,
+        }
+    }
+
+    #[test]
+    fn insert_struct() {
+        let users = TypedCollection::new(String::from("users"));
+        assert_eq!(Ok(1usize), users.insert(&john()));
+    }
+
+    #[test]
+    fn find_struct() {
+        let users = TypedCollection::new(String::from("users"));
+        users.insert(&john()).unwrap();
+        let results = users.find("{username:\"johnperry\"}").unwrap();
+        assert_eq!(vec![john()], results);
+    }
+
+    #[derive(Debug, PartialEq, Serialize, Deserialize)]
+    struct Address {
+        city: String,
+        zip: u64,
+    }
+
+    #[derive(Debug, PartialEq, Serialize, Deserialize)]
+    struct Resident {
+        username: String,
+        address: Address,
+    }
+
+    #[test]
+    fn round_trip_embedded_struct() {
+        let residents = TypedCollection::new(String::from("residents"));
+        let john = Resident {
+            username: String::from("johnperry"),
+            address: Address {
+                city: String::from("Dayton"),
+                zip: 45402,
+            },
+        };
+        residents.insert(&john).unwrap();
+        let results = residents.find("{address.city:\"Dayton\"}").unwrap();
+        assert_eq!(vec![john], results);
+    }
+
+    #[derive(Debug, PartialEq, Serialize, Deserialize)]
+    struct Crew {
+        ship: String,
+        members: Vec<String>,
+        checksum: Vec<u8>,
+    }
+
+    #[test]
+    fn round_trip_sequences() {
+        let crews = TypedCollection::new(String::from("crews"));
+        let crew = Crew {
+            ship: String::from("Kestrel"),
+            members: vec![String::from("johnperry"), String::from("jane")],
+            checksum: vec![0, 255],
+        };
+        crews.insert(&crew).unwrap();
+        assert_eq!(vec![crew], crews.find("{ship:\"Kestrel\"}").unwrap());
+    }
+
+    #[test]
+    fn share_database_collection() {
+        let db = Database::new();
+        let users = TypedCollection::from_collection(db.get_or_create("users"));
+        users.insert(&john()).unwrap();
+        let stored = db.get("users").unwrap();
+        assert_eq!(1, database::read(&stored).find("{age:75}").unwrap().len());
+        database::write(&stored)
+            .insert(to_document(&john()).unwrap())
+            .unwrap();
+        assert_eq!(2, users.find("{username:\"johnperry\"}").unwrap().len());
+    }
+
+    #[test]
+    fn find_invalid_query() {
+        let users: TypedCollection<User> = TypedCollection::new(String::from("users"));
+        assert_eq!(Err(DatastoreError::InvalidQuery), users.find("username"));
+    }
+}
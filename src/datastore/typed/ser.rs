@@ -0,0 +1,671 @@
+use crate::datastore::collection::Document;
+use crate::datastore::datatypes::{DataType, Float};
+use crate::datastore::error::DatastoreError;
+use serde::ser::{self, Impossible, Serialize};
+use std::collections::HashMap;
+
+/// Produces a Document from any struct or map with string keys
+///
+/// # Arguments
+///
+/// * `value` - the value to serialize
+///
+/// # Examples
+///
+/// ```rust
+/// use rockumentdb::datastore::typed::to_document;
+/// use serde::Serialize;
+///
+/// #[derive(Serialize)]
+/// struct User {
+///     username: String,
+///     age: u64,
+/// }
+///
+/// let document = to_document(&User { username: String::from("johnperry"), age: 75 }).unwrap();
+/// ```
+pub fn to_document<T: Serialize + ?Sized>(value: &T) -> Result<Document, DatastoreError> {
+    value.serialize(DocumentSerializer)
+}
+
+/// Produces a DataType from any scalar value
+///
+/// # Arguments
+///
+/// * `value` - the value to serialize
+pub fn to_datatype<T: Serialize + ?Sized>(value: &T) -> Result<DataType, DatastoreError> {
+    value.serialize(DataTypeSerializer)
+}
+
+fn unsupported(kind: &str) -> DatastoreError {
+    DatastoreError::Malformed(format!(
+        "{} values cannot be stored in a document field",
+        kind
+    ))
+}
+
+fn not_a_document(kind: &str) -> DatastoreError {
+    DatastoreError::Malformed(format!(
+        "expected a struct or map to serialize as a document, found {}",
+        kind
+    ))
+}
+
+/// Serializes a single field value into a DataType
+pub struct DataTypeSerializer;
+
+impl ser::Serializer for DataTypeSerializer {
+    type Ok = DataType;
+    type Error = DatastoreError;
+    type SerializeSeq = ArrayBuilder;
+    type SerializeTuple = ArrayBuilder;
+    type SerializeTupleStruct = ArrayBuilder;
+    type SerializeTupleVariant = Impossible<DataType, DatastoreError>;
+    type SerializeMap = EmbeddedBuilder;
+    type SerializeStruct = EmbeddedBuilder;
+    type SerializeStructVariant = Impossible<DataType, DatastoreError>;
+
+    fn serialize_bool(self, v: bool) -> Result<DataType, DatastoreError> {
+        Ok(DataType::Bool(v))
+    }
+
+    fn serialize_i8(self, v: i8) -> Result<DataType, DatastoreError> {
+        self.serialize_i64(i64::from(v))
+    }
+
+    fn serialize_i16(self, v: i16) -> Result<DataType, DatastoreError> {
+        self.serialize_i64(i64::from(v))
+    }
+
+    fn serialize_i32(self, v: i32) -> Result<DataType, DatastoreError> {
+        self.serialize_i64(i64::from(v))
+    }
+
+    fn serialize_i64(self, v: i64) -> Result<DataType, DatastoreError> {
+        Ok(DataType::I64(v))
+    }
+
+    fn serialize_u8(self, v: u8) -> Result<DataType, DatastoreError> {
+        self.serialize_u64(u64::from(v))
+    }
+
+    fn serialize_u16(self, v: u16) -> Result<DataType, DatastoreError> {
+        self.serialize_u64(u64::from(v))
+    }
+
+    fn serialize_u32(self, v: u32) -> Result<DataType, DatastoreError> {
+        self.serialize_u64(u64::from(v))
+    }
+
+    fn serialize_u64(self, v: u64) -> Result<DataType, DatastoreError> {
+        Ok(DataType::U64(v))
+    }
+
+    fn serialize_f32(self, v: f32) -> Result<DataType, DatastoreError> {
+        self.serialize_f64(f64::from(v))
+    }
+
+    fn serialize_f64(self, v: f64) -> Result<DataType, DatastoreError> {
+        Ok(DataType::F64(Float::new(v)))
+    }
+
+    fn serialize_char(self, v: char) -> Result<DataType, DatastoreError> {
+        Ok(DataType::String(v.to_string()))
+    }
+
+    fn serialize_str(self, v: &str) -> Result<DataType, DatastoreError> {
+        Ok(DataType::String(String::from(v)))
+    }
+
+    fn serialize_bytes(self, v: &[u8]) -> Result<DataType, DatastoreError> {
+        Ok(DataType::Binary {
+            subtype: 0,
+            bytes: v.to_vec(),
+        })
+    }
+
+    fn serialize_none(self) -> Result<DataType, DatastoreError> {
+        Ok(DataType::Null)
+    }
+
+    fn serialize_some<T: Serialize + ?Sized>(self, value: &T) -> Result<DataType, DatastoreError> {
+        value.serialize(self)
+    }
+
+    fn serialize_unit(self) -> Result<DataType, DatastoreError> {
+        Ok(DataType::Null)
+    }
+
+    fn serialize_unit_struct(self, _name: &'static str) -> Result<DataType, DatastoreError> {
+        Ok(DataType::Null)
+    }
+
+    fn serialize_unit_variant(
+        self,
+        _name: &'static str,
+        _variant_index: u32,
+        variant: &'static str,
+    ) -> Result<DataType, DatastoreError> {
+        Ok(DataType::String(String::from(variant)))
+    }
+
+    fn serialize_newtype_struct<T: Serialize + ?Sized>(
+        self,
+        _name: &'static str,
+        value: &T,
+    ) -> Result<DataType, DatastoreError> {
+        value.serialize(self)
+    }
+
+    fn serialize_newtype_variant<T: Serialize + ?Sized>(
+        self,
+        _name: &'static str,
+        _variant_index: u32,
+        _variant: &'static str,
+        _value: &T,
+    ) -> Result<DataType, DatastoreError> {
+        Err(unsupported("enum newtype variant"))
+    }
+
+    fn serialize_seq(self, len: Option<usize>) -> Result<Self::SerializeSeq, DatastoreError> {
+        Ok(ArrayBuilder(Vec::with_capacity(len.unwrap_or(0))))
+    }
+
+    fn serialize_tuple(self, len: usize) -> Result<Self::SerializeTuple, DatastoreError> {
+        self.serialize_seq(Some(len))
+    }
+
+    fn serialize_tuple_struct(
+        self,
+        _name: &'static str,
+        len: usize,
+    ) -> Result<Self::SerializeTupleStruct, DatastoreError> {
+        self.serialize_seq(Some(len))
+    }
+
+    fn serialize_tuple_variant(
+        self,
+        _name: &'static str,
+        _variant_index: u32,
+        _variant: &'static str,
+        _len: usize,
+    ) -> Result<Self::SerializeTupleVariant, DatastoreError> {
+        Err(unsupported("enum tuple variant"))
+    }
+
+    fn serialize_map(self, len: Option<usize>) -> Result<Self::SerializeMap, DatastoreError> {
+        Ok(EmbeddedBuilder(DocumentBuilder::new(len)))
+    }
+
+    fn serialize_struct(
+        self,
+        _name: &'static str,
+        len: usize,
+    ) -> Result<Self::SerializeStruct, DatastoreError> {
+        Ok(EmbeddedBuilder(DocumentBuilder::new(Some(len))))
+    }
+
+    fn serialize_struct_variant(
+        self,
+        _name: &'static str,
+        _variant_index: u32,
+        _variant: &'static str,
+        _len: usize,
+    ) -> Result<Self::SerializeStructVariant, DatastoreError> {
+        Err(unsupported("enum struct variant"))
+    }
+}
+
+/// Serializes a struct or string keyed map into a Document
+pub struct DocumentSerializer;
+
+/// Collects the fields of a struct or map while it is being serialized
+pub struct DocumentBuilder {
+    document: Document,
+    next_key: Option<String>,
+}
+
+impl DocumentBuilder {
+    fn new(len: Option<usize>) -> DocumentBuilder {
+        DocumentBuilder {
+            document: HashMap::with_capacity(len.unwrap_or(0)),
+            next_key: None,
+        }
+    }
+}
+
+impl ser::Serializer for DocumentSerializer {
+    type Ok = Document;
+    type Error = DatastoreError;
+    type SerializeSeq = Impossible<Document, DatastoreError>;
+    type SerializeTuple = Impossible<Document, DatastoreError>;
+    type SerializeTupleStruct = Impossible<Document, DatastoreError>;
+    type SerializeTupleVariant = Impossible<Document, DatastoreError>;
+    type SerializeMap = DocumentBuilder;
+    type SerializeStruct = DocumentBuilder;
+    type SerializeStructVariant = Impossible<Document, DatastoreError>;
+
+    fn serialize_bool(self, _v: bool) -> Result<Document, DatastoreError> {
+        Err(not_a_document("a bool"))
+    }
+
+    fn serialize_i8(self, _v: i8) -> Result<Document, DatastoreError> {
+        Err(not_a_document("an integer"))
+    }
+
+    fn serialize_i16(self, _v: i16) -> Result<Document, DatastoreError> {
+        Err(not_a_document("an integer"))
+    }
+
+    fn serialize_i32(self, _v: i32) -> Result<Document, DatastoreError> {
+        Err(not_a_document("an integer"))
+    }
+
+    fn serialize_i64(self, _v: i64) -> Result<Document, DatastoreError> {
+        Err(not_a_document("an integer"))
+    }
+
+    fn serialize_u8(self, _v: u8) -> Result<Document, DatastoreError> {
+        Err(not_a_document("an integer"))
+    }
+
+    fn serialize_u16(self, _v: u16) -> Result<Document, DatastoreError> {
+        Err(not_a_document("an integer"))
+    }
+
+    fn serialize_u32(self, _v: u32) -> Result<Document, DatastoreError> {
+        Err(not_a_document("an integer"))
+    }
+
+    fn serialize_u64(self, _v: u64) -> Result<Document, DatastoreError> {
+        Err(not_a_document("an integer"))
+    }
+
+    fn serialize_f32(self, _v: f32) -> Result<Document, DatastoreError> {
+        Err(not_a_document("a float"))
+    }
+
+    fn serialize_f64(self, _v: f64) -> Result<Document, DatastoreError> {
+        Err(not_a_document("a float"))
+    }
+
+    fn serialize_char(self, _v: char) -> Result<Document, DatastoreError> {
+        Err(not_a_document("a char"))
+    }
+
+    fn serialize_str(self, _v: &str) -> Result<Document, DatastoreError> {
+        Err(not_a_document("a string"))
+    }
+
+    fn serialize_bytes(self, _v: &[u8]) -> Result<Document, DatastoreError> {
+        Err(not_a_document("a byte array"))
+    }
+
+    fn serialize_none(self) -> Result<Document, DatastoreError> {
+        Err(not_a_document("none"))
+    }
+
+    fn serialize_some<T: Serialize + ?Sized>(self, value: &T) -> Result<Document, DatastoreError> {
+        value.serialize(self)
+    }
+
+    fn serialize_unit(self) -> Result<Document, DatastoreError> {
+        Err(not_a_document("unit"))
+    }
+
+    fn serialize_unit_struct(self, _name: &'static str) -> Result<Document, DatastoreError> {
+        Err(not_a_document("a unit struct"))
+    }
+
+    fn serialize_unit_variant(
+        self,
+        _name: &'static str,
+        _variant_index: u32,
+        _variant: &'static str,
+    ) -> Result<Document, DatastoreError> {
+        Err(not_a_document("an enum"))
+    }
+
+    fn serialize_newtype_struct<T: Serialize + ?Sized>(
+        self,
+        _name: &'static str,
+        value: &T,
+    ) -> Result<Document, DatastoreError> {
+        value.serialize(self)
+    }
+
+    fn serialize_newtype_variant<T: Serialize + ?Sized>(
+        self,
+        _name: &'static str,
+        _variant_index: u32,
+        _variant: &'static str,
+        _value: &T,
+    ) -> Result<Document, DatastoreError> {
+        Err(not_a_document("an enum"))
+    }
+
+    fn serialize_seq(self, _len: Option<usize>) -> Result<Self::SerializeSeq, DatastoreError> {
+        Err(not_a_document("a sequence"))
+    }
+
+    fn serialize_tuple(self, _len: usize) -> Result<Self::SerializeTuple, DatastoreError> {
+        Err(not_a_document("a tuple"))
+    }
+
+    fn serialize_tuple_struct(
+        self,
+        _name: &'static str,
+        _len: usize,
+    ) -> Result<Self::SerializeTupleStruct, DatastoreError> {
+        Err(not_a_document("a tuple struct"))
+    }
+
+    fn serialize_tuple_variant(
+        self,
+        _name: &'static str,
+        _variant_index: u32,
+        _variant: &'static str,
+        _len: usize,
+    ) -> Result<Self::SerializeTupleVariant, DatastoreError> {
+        Err(not_a_document("an enum"))
+    }
+
+    fn serialize_map(self, len: Option<usize>) -> Result<Self::SerializeMap, DatastoreError> {
+        Ok(DocumentBuilder::new(len))
+    }
+
+    fn serialize_struct(
+        self,
+        _name: &'static str,
+        len: usize,
+    ) -> Result<Self::SerializeStruct, DatastoreError> {
+        Ok(DocumentBuilder::new(Some(len)))
+    }
+
+    fn serialize_struct_variant(
+        self,
+        _name: &'static str,
+        _variant_index: u32,
+        _variant: &'static str,
+        _len: usize,
+    ) -> Result<Self::SerializeStructVariant, DatastoreError> {
+        Err(not_a_document("an enum"))
+    }
+}
+
+impl ser::SerializeStruct for DocumentBuilder {
+    type Ok = Document;
+    type Error = DatastoreError;
+
+    fn serialize_field<T: Serialize + ?Sized>(
+        &mut self,
+        key: &'static str,
+        value: &T,
+    ) -> Result<(), DatastoreError> {
+        self.document
+            .insert(String::from(key), value.serialize(DataTypeSerializer)?);
+        Ok(())
+    }
+
+    fn end(self) -> Result<Document, DatastoreError> {
+        Ok(self.document)
+    }
+}
+
+impl ser::SerializeMap for DocumentBuilder {
+    type Ok = Document;
+    type Error = DatastoreError;
+
+    fn serialize_key<T: Serialize + ?Sized>(&mut self, key: &T) -> Result<(), DatastoreError> {
+        match key.serialize(DataTypeSerializer)? {
+            DataType::String(field) => {
+                self.next_key = Some(field);
+                Ok(())
+            }
+            _ => Err(DatastoreError::Malformed(String::from(
+                "document keys must be strings",
+            ))),
+        }
+    }
+
+    fn serialize_value<T: Serialize + ?Sized>(&mut self, value: &T) -> Result<(), DatastoreError> {
+        let field = match self.next_key.take() {
+            Some(field) => field,
+            None => {
+                return Err(DatastoreError::Malformed(String::from(
+                    "serialize_value called before serialize_key",
+                )))
+            }
+        };
+        self.document
+            .insert(field, value.serialize(DataTypeSerializer)?);
+        Ok(())
+    }
+
+    fn end(self) -> Result<Document, DatastoreError> {
+        Ok(self.document)
+    }
+}
+
+/// Collects the fields of a struct or map nested in a field into an
+/// embedded DataType::Document
+pub struct EmbeddedBuilder(DocumentBuilder);
+
+impl EmbeddedBuilder {
+    fn embed(document: Document) -> DataType {
+        DataType::Document(document.into_iter().collect())
+    }
+}
+
+impl ser::SerializeStruct for EmbeddedBuilder {
+    type Ok = DataType;
+    type Error = DatastoreError;
+
+    fn serialize_field<T: Serialize + ?Sized>(
+        &mut self,
+        key: &'static str,
+        value: &T,
+    ) -> Result<(), DatastoreError> {
+        ser::SerializeStruct::serialize_field(&mut self.0, key, value)
+    }
+
+    fn end(self) -> Result<DataType, DatastoreError> {
+        ser::SerializeStruct::end(self.0).map(EmbeddedBuilder::embed)
+    }
+}
+
+impl ser::SerializeMap for EmbeddedBuilder {
+    type Ok = DataType;
+    type Error = DatastoreError;
+
+    fn serialize_key<T: Serialize + ?Sized>(&mut self, key: &T) -> Result<(), DatastoreError> {
+        ser::SerializeMap::serialize_key(&mut self.0, key)
+    }
+
+    fn serialize_value<T: Serialize + ?Sized>(&mut self, value: &T) -> Result<(), DatastoreError> {
+        ser::SerializeMap::serialize_value(&mut self.0, value)
+    }
+
+    fn end(self) -> Result<DataType, DatastoreError> {
+        ser::SerializeMap::end(self.0).map(EmbeddedBuilder::embed)
+    }
+}
+
+/// Collects the elements of a sequence or tuple nested in a field into a
+/// DataType::Array. A `Vec<u8>` is an array of integers too; only values
+/// serialized as bytes, such as `serde_bytes` fields, are binary data.
+pub struct ArrayBuilder(Vec<DataType>);
+
+impl ser::SerializeSeq for ArrayBuilder {
+    type Ok = DataType;
+    type Error = DatastoreError;
+
+    fn serialize_element<T: Serialize + ?Sized>(
+        &mut self,
+        value: &T,
+    ) -> Result<(), DatastoreError> {
+        self.0.push(value.serialize(DataTypeSerializer)?);
+        Ok(())
+    }
+
+    fn end(self) -> Result<DataType, DatastoreError> {
+        Ok(DataType::Array(self.0))
+    }
+}
+
+impl ser::SerializeTuple for ArrayBuilder {
+    type Ok = DataType;
+    type Error = DatastoreError;
+
+    fn serialize_element<T: Serialize + ?Sized>(
+        &mut self,
+        value: &T,
+    ) -> Result<(), DatastoreError> {
+        ser::SerializeSeq::serialize_element(self, value)
+    }
+
+    fn end(self) -> Result<DataType, DatastoreError> {
+        ser::SerializeSeq::end(self)
+    }
+}
+
+impl ser::SerializeTupleStruct for ArrayBuilder {
+    type Ok = DataType;
+    type Error = DatastoreError;
+
+    fn serialize_field<T: Serialize + ?Sized>(&mut self, value: &T) -> Result<(), DatastoreError> {
+        ser::SerializeSeq::serialize_element(self, value)
+    }
+
+    fn end(self) -> Result<DataType, DatastoreError> {
+        ser::SerializeSeq::end(self)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde::Serialize;
+    use std::collections::BTreeMap;
+
+    #[derive(Serialize)]
+    struct User {
+        username: String,
+        age: u8,
+        balance: f64,
+        nickname: Option<String>,
+        active: bool,
+    }
+
+    #[derive(Serialize)]
+    struct Address {
+        city: String,
+        zip: u32,
+    }
+
+    #[derive(Serialize)]
+    struct Resident {
+        username: String,
+        address: Address,
+        tags: BTreeMap<String, bool>,
+    }
+
+    #[derive(Serialize)]
+    struct Team {
+        name: String,
+        members: Vec<String>,
+        checksum: Vec<u8>,
+        origin: (f64, f64),
+    }
+
+    #[test]
+    fn serialize_struct() {
+        let user = User {
+            username: String::from("johnperry"),
+            age: 75,
+            balance: 12.5,
+            nickname: None,
+            active: true,
+        };
+        let document = to_document(&user).unwrap();
+        assert_eq!(
+            document.get("username"),
+            Some(&DataType::String(String::from("johnperry")))
+        );
+        assert_eq!(document.get("age"), Some(&DataType::U64(75u64)));
+        assert_eq!(
+            document.get("balance"),
+            Some(&DataType::F64(Float::new(12.5)))
+        );
+        assert_eq!(document.get("nickname"), Some(&DataType::Null));
+        assert_eq!(document.get("active"), Some(&DataType::Bool(true)));
+    }
+
+    #[test]
+    fn serialize_map() {
+        let mut map = BTreeMap::new();
+        map.insert("balance", -40i32);
+        let document = to_document(&map).unwrap();
+        assert_eq!(document.get("balance"), Some(&DataType::I64(-40i64)));
+    }
+
+    #[test]
+    fn serialize_embedded_documents() {
+        let mut tags = BTreeMap::new();
+        tags.insert(String::from("veteran"), true);
+        let resident = Resident {
+            username: String::from("johnperry"),
+            address: Address {
+                city: String::from("Dayton"),
+                zip: 45402,
+            },
+            tags,
+        };
+        let document = to_document(&resident).unwrap();
+        let mut address = BTreeMap::new();
+        address.insert(
+            String::from("city"),
+            DataType::String(String::from("Dayton")),
+        );
+        address.insert(String::from("zip"), DataType::U64(45402));
+        assert_eq!(document.get("address"), Some(&DataType::Document(address)));
+        let mut tags = BTreeMap::new();
+        tags.insert(String::from("veteran"), DataType::Bool(true));
+        assert_eq!(document.get("tags"), Some(&DataType::Document(tags)));
+    }
+
+    #[test]
+    fn serialize_scalar_as_document() {
+        assert!(to_document(&75u64).is_err());
+    }
+
+    #[test]
+    fn serialize_sequences() {
+        let team = Team {
+            name: String::from("crew"),
+            members: vec![String::from("johnperry"), String::from("jane")],
+            checksum: vec![1, 2],
+            origin: (0.5, -1.0),
+        };
+        let document = to_document(&team).unwrap();
+        assert_eq!(
+            document.get("members"),
+            Some(&DataType::Array(vec![
+                DataType::String(String::from("johnperry")),
+                DataType::String(String::from("jane")),
+            ]))
+        );
+        assert_eq!(
+            document.get("checksum"),
+            Some(&DataType::Array(vec![DataType::U64(1), DataType::U64(2)]))
+        );
+        assert_eq!(
+            document.get("origin"),
+            Some(&DataType::Array(vec![
+                DataType::F64(Float::new(0.5)),
+                DataType::F64(Float::new(-1.0)),
+            ]))
+        );
+        assert!(to_document(&vec![1u8]).is_err());
+    }
+}
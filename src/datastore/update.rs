@@ -0,0 +1,225 @@
+use crate::datastore::collection::Document;
+use crate::datastore::datatypes::{self, DataType, Float};
+use serde_json::Value;
+use std::collections::HashMap;
+use std::convert::TryFrom;
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Operation {
+    Set(String, DataType),
+    Unset(String),
+    Inc(String, DataType),
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Update {
+    Replace(Document),
+    Operators(Vec<Operation>),
+}
+
+/// Produces an Update from a MongoDB style update document
+///
+/// A document whose keys are all update operators (`$set`, `$unset`,
+/// `$inc`) modifies matching documents in place, a document without any
+/// operators replaces them.
+///
+/// # Arguments
+///
+/// * `value` - the update document
+///
+/// # Example
+///
+/// ```json
+/// {"$set": {"email": "john@example.com"}, "$inc": {"age": 1}}
+/// ```
+pub fn from_json(value: &HashMap<String, Value>) -> Result<Update, String> {
+    let operator_count = value.keys().filter(|key| key.starts_with('$')).count();
+    if operator_count == 0 {
+        let mut document = HashMap::new();
+        for (field, field_value) in value.iter() {
+            document.insert(field.clone(), datatypes::from_json(field_value));
+        }
+        return Ok(Update::Replace(document));
+    }
+    if operator_count != value.len() {
+        return Err(String::from(
+            "an update cannot mix update operators and replacement fields",
+        ));
+    }
+
+    let mut operations = Vec::new();
+    for (operator, fields) in value.iter() {
+        let fields = match fields {
+            Value::Object(fields) => fields,
+            _ => return Err(format!("{} expects a document", operator)),
+        };
+        for (field, field_value) in fields.iter() {
+            let converted = datatypes::from_json(field_value);
+            let operation = match operator.as_str() {
+                "$set" => Operation::Set(field.clone(), converted),
+                "$unset" => Operation::Unset(field.clone()),
+                "$inc" => {
+                    if !is_numeric(&converted) {
+                        return Err(format!("$inc of {} requires a number", field));
+                    }
+                    Operation::Inc(field.clone(), converted)
+                }
+                _ => return Err(format!("unknown update operator {}", operator)),
+            };
+            operations.push(operation);
+        }
+    }
+    Ok(Update::Operators(operations))
+}
+
+impl Update {
+    /// Applies the update to a document, producing whether it changed
+    ///
+    /// The document is left untouched when the update fails.
+    ///
+    /// # Arguments
+    ///
+    /// * `document` - the document to modify
+    pub fn apply(&self, document: &mut Document) -> Result<bool, String> {
+        let mut updated = document.clone();
+        match self {
+            Update::Replace(replacement) => updated = replacement.clone(),
+            Update::Operators(operations) => {
+                for operation in operations.iter() {
+                    match operation {
+                        Operation::Set(field, value) => {
+                            updated.insert(field.clone(), value.clone());
+                        }
+                        Operation::Unset(field) => {
+                            updated.remove(field);
+                        }
+                        Operation::Inc(field, amount) => {
+                            let sum = match updated.get(field) {
+                                Some(current) => add(current, amount).ok_or_else(|| {
+                                    format!("cannot apply $inc to the value of {}", field)
+                                })?,
+                                None => amount.clone(),
+                            };
+                            updated.insert(field.clone(), sum);
+                        }
+                    }
+                }
+            }
+        }
+        if &updated == document {
+            return Ok(false);
+        }
+        *document = updated;
+        Ok(true)
+    }
+}
+
+fn is_numeric(value: &DataType) -> bool {
+    matches!(
+        value,
+        DataType::I64(_) | DataType::U64(_) | DataType::F64(_)
+    )
+}
+
+/// Produces the sum of two numeric DataTypes, or None if either is not a
+/// number or the result does not fit
+fn add(left: &DataType, right: &DataType) -> Option<DataType> {
+    match (left, right) {
+        (DataType::F64(_), _) | (_, DataType::F64(_)) => {
+            let sum = datatypes::as_f64(left)? + datatypes::as_f64(right)?;
+            Some(DataType::F64(Float::new(sum)))
+        }
+        (DataType::U64(a), DataType::U64(b)) => a.checked_add(*b).map(DataType::U64),
+        _ => {
+            let sum = datatypes::as_i128(left)? + datatypes::as_i128(right)?;
+            if let Ok(val) = i64::try_from(sum) {
+                Some(DataType::I64(val))
+            } else {
+                u64::try_from(sum).ok().map(DataType::U64)
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    fn parse(value: Value) -> Result<Update, String> {
+        let map: HashMap<String, Value> = serde_json::from_value(value).unwrap();
+        from_json(&map)
+    }
+
+    fn john() -> Document {
+        let mut document = HashMap::new();
+        document.insert(
+            String::from("username"),
+            DataType::String(String::from("johnperry")),
+        );
+        document.insert(String::from("age"), DataType::I64(75i64));
+        document
+    }
+
+    #[test]
+    fn parse_replacement() {
+        let update = parse(json!({"username": "louiswu"})).unwrap();
+        let mut expected = HashMap::new();
+        expected.insert(
+            String::from("username"),
+            DataType::String(String::from("louiswu")),
+        );
+        assert_eq!(Update::Replace(expected), update);
+    }
+
+    #[test]
+    fn parse_mixed_update() {
+        assert!(parse(json!({"$set": {"age": 1}, "username": "louiswu"})).is_err());
+    }
+
+    #[test]
+    fn parse_unknown_operator() {
+        assert!(parse(json!({"$push": {"tags": "crew"}})).is_err());
+    }
+
+    #[test]
+    fn apply_set_and_unset() {
+        let update = parse(json!({"$set": {"active": true}, "$unset": {"age": ""}})).unwrap();
+        let mut document = john();
+        assert_eq!(Ok(true), update.apply(&mut document));
+        assert_eq!(Some(&DataType::Bool(true)), document.get("active"));
+        assert_eq!(None, document.get("age"));
+    }
+
+    #[test]
+    fn apply_inc() {
+        let update = parse(json!({"$inc": {"age": 1, "visits": 1}})).unwrap();
+        let mut document = john();
+        update.apply(&mut document).unwrap();
+        assert_eq!(Some(&DataType::I64(76i64)), document.get("age"));
+        assert_eq!(Some(&DataType::I64(1i64)), document.get("visits"));
+    }
+
+    #[test]
+    fn apply_inc_float() {
+        let update = parse(json!({"$inc": {"age": 0.5}})).unwrap();
+        let mut document = john();
+        update.apply(&mut document).unwrap();
+        assert_eq!(Some(&DataType::F64(Float::new(75.5))), document.get("age"));
+    }
+
+    #[test]
+    fn apply_inc_to_string() {
+        let update = parse(json!({"$inc": {"username": 1}})).unwrap();
+        let mut document = john();
+        assert!(update.apply(&mut document).is_err());
+        assert_eq!(john(), document);
+    }
+
+    #[test]
+    fn apply_without_change() {
+        let update = parse(json!({"$set": {"age": 75}})).unwrap();
+        let mut document = john();
+        assert_eq!(Ok(false), update.apply(&mut document));
+    }
+}
@@ -0,0 +1,5 @@
+pub mod api;
+pub mod datastore;
+
+#[macro_use]
+extern crate rocket;
@@ -1,14 +1,11 @@
-mod api;
-mod datastore;
-
-use crate::datastore::collection::Collection;
-use std::sync::Mutex;
+use rockumentdb::api;
+use rockumentdb::datastore::database::Database;
+use rockumentdb::datastore::transaction::{self, Transactions};
+use std::time::Duration;
 
 #[macro_use]
 extern crate rocket;
 
-type SafeMemStore = Mutex<Collection>;
-
 #[get("/")]
 fn version() -> &'static str {
     "RockumentDB 2.0.0-alpha"
@@ -16,8 +13,44 @@ fn version() -> &'static str {
 
 #[launch]
 fn rocket() -> _ {
-    rocket::build()
+    let rocket = rocket::build();
+    let transaction_timeout = rocket
+        .figment()
+        .extract_inner::<u64>("transaction_timeout_seconds")
+        .unwrap_or(transaction::DEFAULT_TIMEOUT_SECONDS);
+    rocket
         .mount("/", routes![version])
-        .mount("/api/v2", routes![api::v2::find, api::v2::insert])
-        .manage(SafeMemStore::new(Collection::new(String::from("test"))))
+        .mount(
+            "/api/v2",
+            routes![
+                api::v2::list_collections,
+                api::v2::create_collection,
+                api::v2::drop_collection,
+                api::v2::rename_collection,
+                api::v2::find,
+                api::v2::find_request,
+                api::v2::insert,
+                api::v2::bulk_write,
+                api::v2::update,
+                api::v2::delete,
+                api::v2::set_schema,
+                api::v2::create_index,
+                api::v2::export,
+                api::v2::watch,
+                api::v2::import_bson,
+                api::v2::import_ndjson,
+                api::v2::import_csv,
+                api::v2::begin_transaction,
+                api::v2::commit_transaction,
+                api::v2::abort_transaction
+            ],
+        )
+        .register("/", catchers![api::error::default_catcher])
+        .manage(Database::new())
+        .manage(Transactions::with_timeout(Duration::from_secs(
+            transaction_timeout,
+        )))
+        .attach(api::wire::listener())
+        .attach(api::live::listener())
+        .attach(api::sweeper::sweeper())
 }
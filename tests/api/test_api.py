@@ -3,12 +3,19 @@ Test suite for REST API
 """
 
 import httpx
+import base64
 import json
+import os
 import pytest
+import socket
+import struct
 import subprocess
+import time
 
 
 BASE_URL = "http://127.0.0.1:8000/api/v2/test"
+WIRE_ADDRESS = ("127.0.0.1", 27017)
+LIVE_ADDRESS = ("127.0.0.1", 8001)
 
 
 @pytest.fixture(scope="module")
@@ -16,7 +23,14 @@ def server():
     """
     Spins up the Database server
     """
-    proc = subprocess.Popen(["cargo", "run"])
+    env = dict(
+        os.environ,
+        ROCKET_TTL_SWEEP_SECONDS="1",
+        ROCKET_WIRE_PORT=str(WIRE_ADDRESS[1]),
+        ROCKET_LIVE_PORT=str(LIVE_ADDRESS[1]),
+        ROCKET_LIMITS="{ndjson=1024}",
+    )
+    proc = subprocess.Popen(["cargo", "run"], env=env)
 
     yield
 
@@ -44,7 +58,7 @@ def test_insert_one_doc(server):
         ]
     )
     response.raise_for_status()
-    assert response.json() == [1,2]
+    assert response.json() == {"inserted": [1, 2], "errors": []}
 
 
 def test_find_one_field(server):
@@ -75,3 +89,821 @@ def test_find_two_fields(server):
         sort_keys=True,
     )
 
+
+
+def test_update_and_delete(server):
+    url = "http://127.0.0.1:8000/api/v2/crew"
+    response = httpx.post(url, json=[{"username": "johnperry", "rank": "private"}])
+    response.raise_for_status()
+
+    response = httpx.patch(
+        f'{url}?query={{username:"johnperry"}}',
+        json={"$set": {"rank": "captain"}},
+    )
+    response.raise_for_status()
+    assert response.json() == {"matched": 1, "modified": 1}
+
+    response = httpx.get(f'{url}?query={{rank:"captain"}}')
+    assert response.json() == [{"username": "johnperry", "rank": "captain"}]
+
+    response = httpx.delete(f'{url}?query={{username:"johnperry"}}')
+    response.raise_for_status()
+    assert response.json() == {"deleted": 1}
+
+
+def test_bulk_write(server):
+    url = "http://127.0.0.1:8000/api/v2/sync"
+    operations = [
+        {"insertOne": {"document": {"username": "johnperry", "rank": "private"}}},
+        {"insertOne": {"document": {"username": "louiswu", "rank": "private"}}},
+        {"updateMany": {"filter": {"rank": "private"}, "update": {"$set": {"rank": "captain"}}}},
+        {"replaceOne": {"filter": {"username": "jane"}, "replacement": {"username": "jane"}, "upsert": True}},
+        {"deleteOne": {"filter": {"username": "louiswu"}}},
+    ]
+    response = httpx.post(f"{url}/bulk", json=operations)
+    assert response.status_code == 200
+    assert response.json() == {
+        "inserted": [1, 2],
+        "matched": 2,
+        "modified": 2,
+        "deleted": 1,
+        "upserted": [{"index": 3, "id": 3}],
+        "errors": [],
+    }
+
+    response = httpx.post(f"{url}/bulk", json=[{"updateOne": {"update": {"rank": "major"}}}])
+    assert response.status_code == 400
+    assert response.json()["code"] == "MALFORMED"
+
+    response = httpx.post(
+        f"{url}/bulk",
+        json=[
+            {"updateOne": {"filter": {"$bad": 1}, "update": {"$set": {"rank": "major"}}}},
+            {"deleteMany": {"filter": {"rank": "captain"}}},
+        ],
+        params={"ordered": "false"},
+    )
+    assert response.status_code == 400
+    details = response.json()["details"]
+    assert details["deleted"] == 1
+    assert details["errors"][0]["index"] == 0
+    assert details["errors"][0]["code"] == "INVALID_QUERY"
+
+
+def test_transaction_commit(server):
+    base = "http://127.0.0.1:8000/api/v2"
+    response = httpx.post(f"{base}/transactions")
+    assert response.status_code == 201
+    txn = {"X-Transaction-Id": str(response.json()["id"])}
+
+    response = httpx.post(f"{base}/orders", json=[{"item": "widget"}], headers=txn)
+    response.raise_for_status()
+
+    response = httpx.get(f'{base}/orders?query={{item:"widget"}}')
+    assert response.json() == []
+    response = httpx.get(f'{base}/orders?query={{item:"widget"}}', headers=txn)
+    assert response.json() == [{"item": "widget"}]
+
+    response = httpx.post(f"{base}/transactions/{txn['X-Transaction-Id']}/commit")
+    response.raise_for_status()
+    response = httpx.get(f'{base}/orders?query={{item:"widget"}}')
+    assert response.json() == [{"item": "widget"}]
+
+
+def test_schema_validation(server):
+    url = "http://127.0.0.1:8000/api/v2/validated"
+    response = httpx.put(
+        f"{url}/schema",
+        json={
+            "schema": {
+                "type": "object",
+                "required": ["username"],
+                "properties": {"age": {"type": "integer", "minimum": 0}},
+            }
+        },
+    )
+    assert response.status_code == 204
+
+    response = httpx.post(url, json=[{"username": "johnperry", "age": -1}])
+    assert response.status_code == 400
+    assert response.json()["code"] == "BULK_WRITE_ERROR"
+    error = response.json()["details"]["errors"][0]
+    assert error["code"] == "VALIDATION_FAILED"
+    assert error["details"] == {
+        "violations": [
+            {"field": "age", "message": "must be greater than or equal to 0"}
+        ]
+    }
+
+    response = httpx.post(url, json=[{"username": "johnperry", "age": 75}])
+    assert response.status_code == 201
+
+
+def test_collection_management(server):
+    base = "http://127.0.0.1:8000/api/v2"
+    response = httpx.put(f"{base}/staff", json={"schema": {"type": "object"}})
+    assert response.status_code == 201
+    assert response.json() == {"name": "staff", "documents": 0, "indexes": []}
+
+    response = httpx.post(f"{base}/staff", json=[{"username": "johnperry"}])
+    assert response.status_code == 201
+    response = httpx.post(f"{base}/staff/indexes", json={"field": "username"})
+    assert response.status_code == 201
+
+    response = httpx.put(f"{base}/staff", json={"schema": None})
+    assert response.status_code == 200
+    response = httpx.put(f"{base}/staff", json={"unknown": 1})
+    assert response.status_code == 400
+    assert response.json()["code"] == "INVALID_OPTIONS"
+
+    response = httpx.post(f"{base}/staff/rename", json={"to": "personnel"})
+    assert response.status_code == 200
+    response = httpx.get(base)
+    assert response.status_code == 200
+    assert {
+        "name": "personnel",
+        "documents": 1,
+        "indexes": [{"name": "username_1", "fields": ["username"], "unique": False}],
+    } in response.json()
+    assert "staff" not in [collection["name"] for collection in response.json()]
+
+    response = httpx.delete(f"{base}/personnel")
+    assert response.status_code == 400
+    assert response.json()["code"] == "MISSING_QUERY"
+    response = httpx.delete(f"{base}/personnel", params={"qeury": "{}"})
+    assert response.status_code == 400
+    response = httpx.get(f"{base}/personnel")
+    assert len(response.json()) == 1
+
+    response = httpx.delete(f"{base}/personnel/drop")
+    assert response.status_code == 204
+    response = httpx.delete(f"{base}/personnel/drop")
+    assert response.status_code == 404
+    assert response.json()["code"] == "COLLECTION_NOT_FOUND"
+
+
+def read_events(response, count):
+    events, event = [], {}
+    for line in response.iter_lines():
+        if line.startswith(("event:", "id:", "data:")):
+            field, value = line.split(":", 1)
+            event[field] = json.loads(value) if field == "data" else value
+        elif line == "" and event:
+            events.append(event)
+            event = {}
+            if len(events) == count:
+                break
+    return events
+
+
+def test_watch(server):
+    base = "http://127.0.0.1:8000/api/v2"
+    response = httpx.put(f"{base}/watched", json={})
+    assert response.status_code == 201
+    httpx.post(f"{base}/watched", json=[{"username": "johnperry", "rank": "captain"}])
+    httpx.post(f"{base}/watched", json=[{"username": "louiswu", "rank": "private"}])
+    httpx.patch(f"{base}/watched", params={"query": '{username:"johnperry"}'}, json={"$inc": {"age": 1}})
+    httpx.delete(f"{base}/watched", params={"query": '{username:"johnperry"}'})
+
+    params = {"query": '{rank:"captain"}', "resume_after": "0"}
+    with httpx.stream("GET", f"{base}/watched/watch", params=params, timeout=5) as response:
+        assert response.status_code == 200
+        assert response.headers["content-type"].startswith("text/event-stream")
+        events = read_events(response, 3)
+    assert [event["event"] for event in events] == ["insert", "update", "delete"]
+    assert events[1]["data"]["document"] == {"username": "johnperry", "rank": "captain", "age": 1}
+    assert "document" not in events[2]["data"]
+    assert events[2]["id"] == events[2]["data"]["token"]
+
+    headers = {"Last-Event-ID": events[0]["id"]}
+    with httpx.stream("GET", f"{base}/watched/watch", headers=headers, timeout=5) as response:
+        resumed = read_events(response, 1)
+    assert resumed[0]["event"] == "insert"
+    assert resumed[0]["data"]["document"]["username"] == "louiswu"
+
+    response = httpx.get(f"{base}/watched/watch", params={"resume_after": "1000"})
+    assert response.status_code == 410
+    assert response.json()["code"] == "CHANGE_HISTORY_LOST"
+
+    with httpx.stream("GET", f"{base}/watched/watch", timeout=5) as response:
+        httpx.delete(f"{base}/watched/drop")
+        assert read_events(response, 1)[0]["event"] == "invalidate"
+
+
+def test_unique_index(server):
+    url = "http://127.0.0.1:8000/api/v2/accounts"
+    response = httpx.post(f"{url}/indexes", json={"field": "email", "unique": True})
+    assert response.status_code == 201
+
+    response = httpx.post(url, json=[{"email": "johnperry@example.com"}])
+    assert response.status_code == 201
+
+    response = httpx.post(url, json=[{"email": "johnperry@example.com"}])
+    assert response.status_code == 409
+    error = response.json()["details"]["errors"][0]
+    assert error["code"] == "DUPLICATE_KEY"
+    assert error["details"] == {
+        "index": "email_1",
+        "key": {"email": "johnperry@example.com"},
+    }
+
+
+def test_ordered_and_unordered_insert(server):
+    url = "http://127.0.0.1:8000/api/v2/members"
+    response = httpx.post(f"{url}/indexes", json={"field": "email", "unique": True})
+    assert response.status_code == 201
+
+    members = [
+        {"email": "johnperry@example.com"},
+        {"email": "johnperry@example.com"},
+        {"email": "louiswu@example.com"},
+    ]
+    response = httpx.post(url, json=members)
+    assert response.status_code == 409
+    assert response.json()["code"] == "BULK_WRITE_ERROR"
+    details = response.json()["details"]
+    assert details["inserted"] == [1]
+    assert [error["index"] for error in details["errors"]] == [1]
+    assert details["errors"][0]["code"] == "DUPLICATE_KEY"
+
+    members = [
+        {"email": "johnperry@example.com"},
+        {"email": "speaker@example.com"},
+        {"email": "louiswu@example.com"},
+    ]
+    response = httpx.post(url, json=members, params={"ordered": "false"})
+    assert response.status_code == 409
+    details = response.json()["details"]
+    assert details["inserted"] == [2, 3]
+    assert [error["index"] for error in details["errors"]] == [0]
+
+
+def test_compound_index(server):
+    url = "http://127.0.0.1:8000/api/v2/tickets"
+    response = httpx.post(
+        f"{url}/indexes", json={"fields": ["tenant", "status", "priority"]}
+    )
+    assert response.status_code == 201
+    assert response.json()["name"] == "tenant_1_status_1_priority_1"
+
+    tickets = [
+        {"tenant": "a", "status": "open", "priority": 1},
+        {"tenant": "a", "status": "closed", "priority": 2},
+        {"tenant": "a", "status": "open", "priority": 3},
+    ]
+    response = httpx.post(url, json=tickets)
+    assert response.status_code == 201
+
+    query = '{tenant:"a", status:"open", priority:{$gt:1}}'
+    response = httpx.get(url, params={"query": query})
+    assert response.status_code == 200
+    assert response.json() == [tickets[2]]
+
+    response = httpx.get(url, params={"query": query, "explain": "true"})
+    assert response.status_code == 200
+    stages = response.json()["stages"]
+    assert [stage["stage"] for stage in stages] == ["COMPOUND_INDEX_SCAN"]
+    assert stages[0]["returned"] == 1
+
+
+def test_ttl_index(server):
+    url = "http://127.0.0.1:8000/api/v2/sessions"
+    response = httpx.post(
+        f"{url}/indexes", json={"field": "lastSeen", "expireAfterSeconds": 60}
+    )
+    assert response.status_code == 201
+    assert response.json()["expireAfterSeconds"] == 60
+
+    sessions = [
+        {"user": "johnperry", "lastSeen": {"$date": "2020-01-01T00:00:00.000Z"}},
+        {"user": "louiswu", "lastSeen": {"$date": "2999-01-01T00:00:00.000Z"}},
+    ]
+    response = httpx.post(url, json=sessions)
+    assert response.status_code == 201
+
+    for _ in range(50):
+        response = httpx.get(url)
+        if len(response.json()) == 1:
+            break
+        time.sleep(0.1)
+    assert [session["user"] for session in response.json()] == ["louiswu"]
+
+    response = httpx.post(
+        f"{url}/indexes",
+        json={"fields": ["user", "lastSeen"], "expireAfterSeconds": 60},
+    )
+    assert response.status_code == 400
+
+
+def test_ttl_collection(server):
+    url = "http://127.0.0.1:8000/api/v2/visits"
+    response = httpx.put(url, json={"expireAfterSeconds": 60, "timeField": "at"})
+    assert response.status_code == 201
+    assert response.json()["indexes"] == [
+        {"name": "at_1", "fields": ["at"], "unique": False, "expireAfterSeconds": 60}
+    ]
+
+    visits = [
+        {"page": "/", "at": {"$date": "2020-01-01T00:00:00.000Z"}},
+        {"page": "/about", "at": {"$date": "2999-01-01T00:00:00.000Z"}},
+    ]
+    response = httpx.post(url, json=visits)
+    assert response.status_code == 201
+    for _ in range(50):
+        response = httpx.get(url)
+        if len(response.json()) == 1:
+            break
+        time.sleep(0.1)
+    assert [visit["page"] for visit in response.json()] == ["/about"]
+
+    response = httpx.put(url, json={"expireAfterSeconds": 60})
+    assert response.status_code == 400
+
+
+def test_extended_json_types(server):
+    url = "http://127.0.0.1:8000/api/v2/events"
+    events = [
+        {
+            "name": "launch",
+            "at": {"$date": "2021-06-01T12:30:00.000Z"},
+            "owner": {"$oid": "60b5fbd8e1b2c3d4e5f60718"},
+            "price": {"$numberDecimal": "19.99"},
+            "payload": {"$binary": {"base64": "AQID", "subType": "00"}},
+        },
+        {"name": "draft", "at": {"$date": "2020-01-01T00:00:00.000Z"}},
+    ]
+    response = httpx.post(url, json=events)
+    assert response.status_code == 201
+
+    query = '{at:{$gte:{$date:"2021-01-01T00:00:00Z"}}}'
+    response = httpx.get(url, params={"query": query})
+    assert response.status_code == 200
+    assert response.json() == [events[0]]
+
+    query = '{owner:{$oid:"60b5fbd8e1b2c3d4e5f60718"}}'
+    response = httpx.get(url, params={"query": query})
+    assert [event["name"] for event in response.json()] == ["launch"]
+
+
+def test_bson_import_export(server):
+    url = "http://127.0.0.1:8000/api/v2/dump"
+    # {"hello": "world"} from the BSON specification
+    dump = b"\x16\x00\x00\x00\x02hello\x00\x06\x00\x00\x00world\x00\x00"
+    response = httpx.post(
+        f"{url}/import",
+        content=dump * 2,
+        headers={"Content-Type": "application/bson"},
+    )
+    assert response.status_code == 200
+    assert response.json() == {"inserted": [1, 2], "errors": []}
+
+    response = httpx.get(f"{url}/export", headers={"Accept": "application/bson"})
+    assert response.status_code == 200
+    assert response.content == dump * 2
+
+    response = httpx.get(url, headers={"Accept": "application/bson"})
+    assert response.headers["Content-Type"] == "application/bson"
+    assert response.content == dump * 2
+
+    response = httpx.post(
+        f"{url}/import",
+        content=dump[:-1],
+        headers={"Content-Type": "application/bson"},
+    )
+    assert response.status_code == 400
+
+    url = "http://127.0.0.1:8000/api/v2/greetings"
+    response = httpx.post(f"{url}/indexes", json={"field": "hello", "unique": True})
+    assert response.status_code == 201
+    response = httpx.post(
+        f"{url}/import",
+        content=dump * 2,
+        headers={"Content-Type": "application/bson"},
+    )
+    assert response.status_code == 200
+    report = response.json()
+    assert report["inserted"] == [1]
+    assert [error["document"] for error in report["errors"]] == [2]
+
+
+def test_ndjson_import_export(server):
+    url = "http://127.0.0.1:8000/api/v2/fixtures"
+    lines = '{"username": "johnperry"}\n\nnot json\n{"username": "louiswu"}\n'
+    response = httpx.post(
+        f"{url}/import",
+        content=lines,
+        headers={"Content-Type": "application/x-ndjson"},
+    )
+    assert response.status_code == 200
+    report = response.json()
+    assert report["inserted"] == [1, 2]
+    assert [error["line"] for error in report["errors"]] == [3]
+
+    response = httpx.get(f"{url}/export")
+    assert response.status_code == 200
+    assert response.headers["Content-Type"] == "application/x-ndjson"
+    assert [json.loads(line) for line in response.text.splitlines()] == [
+        {"username": "johnperry"},
+        {"username": "louiswu"},
+    ]
+
+
+def test_ndjson_import_limit(server):
+    url = "http://127.0.0.1:8000/api/v2/oversized"
+    line = json.dumps({"padding": "x" * 48}) + "\n"
+    assert len(line) == 64
+    # The limit of 1024 bytes falls exactly at the end of the 16th line
+    response = httpx.post(
+        f"{url}/import",
+        content=line * 17,
+        headers={"Content-Type": "application/x-ndjson"},
+    )
+    assert response.status_code == 200
+    report = response.json()
+    assert len(report["inserted"]) == 16
+    assert [error["line"] for error in report["errors"]] == [17]
+
+    response = httpx.post(
+        f"{url}/import",
+        content=line * 16,
+        headers={"Content-Type": "application/x-ndjson"},
+    )
+    assert response.json()["errors"] == []
+
+
+def test_csv_import_export(server):
+    url = "http://127.0.0.1:8000/api/v2/spreadsheet"
+    sheet = "username,age,zip,address.city\njohnperry,75,007,Ohio\nlouiswu,200,,\n"
+    response = httpx.post(
+        f"{url}/import",
+        params={"infer": "true"},
+        content=sheet,
+        headers={"Content-Type": "text/csv"},
+    )
+    assert response.status_code == 200
+    assert response.json() == {"inserted": [1, 2], "errors": []}
+
+    response = httpx.get(url, params={"query": '{address.city:"Ohio"}'})
+    assert response.json() == [
+        {"username": "johnperry", "age": 75, "zip": "007", "address": {"city": "Ohio"}}
+    ]
+
+    response = httpx.get(
+        f"{url}/export",
+        params={"query": "{age:{$gt:100}}", "fields": "username,age"},
+        headers={"Accept": "text/csv"},
+    )
+    assert response.status_code == 200
+    assert response.text == "username,age\nlouiswu,200\n"
+
+
+def test_find_with_body(server):
+    url = "http://127.0.0.1:8000/api/v2/search"
+    httpx.post(
+        url,
+        json=[
+            {"username": "johnperry", "age": 75, "address": {"city": "Ohio"}},
+            {"username": "louiswu", "age": 200},
+            {"username": "nessus", "age": 30},
+        ],
+    )
+    response = httpx.post(
+        f"{url}/find",
+        json={
+            "filter": {"username": {"$in": ["johnperry", "louiswu", "speaker"]}},
+            "projection": {"username": 1, "address.city": 1},
+            "sort": {"age": -1},
+        },
+    )
+    assert response.status_code == 200
+    assert response.json() == [
+        {"username": "louiswu"},
+        {"username": "johnperry", "address": {"city": "Ohio"}},
+    ]
+
+    response = httpx.post(f"{url}/find", json={"sort": {"age": 1}, "skip": 1, "limit": 1})
+    assert response.json() == [{"username": "johnperry", "age": 75, "address": {"city": "Ohio"}}]
+
+    response = httpx.post(f"{url}/find", json={"filter": {"age": {"$ne": 1}}})
+    assert response.status_code == 400
+    assert response.json()["code"] == "INVALID_QUERY"
+
+
+def test_error_envelope(server):
+    url = "http://127.0.0.1:8000/api/v2/errors"
+    response = httpx.post(
+        url, content='[{"username": ', headers={"Content-Type": "application/json"}
+    )
+    assert response.status_code == 400
+    assert response.json()["code"] == "MALFORMED"
+    assert response.json()["details"] is None
+
+    response = httpx.get(url, params={"query": "not a query"})
+    assert response.status_code == 400
+    assert response.json() == {
+        "code": "INVALID_QUERY",
+        "message": "invalid query",
+        "details": None,
+    }
+
+    response = httpx.get(url, headers={"X-Transaction-Id": "42"})
+    assert response.status_code == 404
+    assert response.json()["code"] == "TRANSACTION_NOT_FOUND"
+
+    response = httpx.get("http://127.0.0.1:8000/api/v2/errors/missing/path")
+    assert response.status_code == 404
+    assert response.json()["code"] == "NOT_FOUND"
+    assert response.json()["details"] == {
+        "method": "GET",
+        "path": "/api/v2/errors/missing/path",
+    }
+
+
+def bson_encode(document):
+    """
+    Encodes the BSON types the wire protocol tests use
+    """
+    elements = b""
+    for field, value in document.items():
+        name = field.encode() + b"\x00"
+        if isinstance(value, bool):
+            elements += b"\x08" + name + bytes([value])
+        elif isinstance(value, int):
+            elements += b"\x12" + name + struct.pack("<q", value)
+        elif isinstance(value, float):
+            elements += b"\x01" + name + struct.pack("<d", value)
+        elif isinstance(value, str):
+            text = value.encode() + b"\x00"
+            elements += b"\x02" + name + struct.pack("<i", len(text)) + text
+        elif isinstance(value, dict):
+            elements += b"\x03" + name + bson_encode(value)
+        elif isinstance(value, list):
+            items = {str(position): item for position, item in enumerate(value)}
+            elements += b"\x04" + name + bson_encode(items)
+        elif value is None:
+            elements += b"\x0a" + name
+    return struct.pack("<i", len(elements) + 5) + elements + b"\x00"
+
+
+def bson_decode(data):
+    """
+    Decodes the BSON types the wire protocol tests use
+    """
+    document, position = {}, 4
+    while data[position] != 0:
+        kind = data[position]
+        end = data.index(b"\x00", position + 1)
+        field = data[position + 1 : end].decode()
+        position = end + 1
+        if kind == 0x01:
+            value = struct.unpack_from("<d", data, position)[0]
+            position += 8
+        elif kind == 0x02:
+            length = struct.unpack_from("<i", data, position)[0]
+            value = data[position + 4 : position + 3 + length].decode()
+            position += 4 + length
+        elif kind in (0x03, 0x04):
+            length = struct.unpack_from("<i", data, position)[0]
+            value = bson_decode(data[position : position + length])
+            if kind == 0x04:
+                value = list(value.values())
+            position += length
+        elif kind == 0x08:
+            value = data[position] == 1
+            position += 1
+        elif kind == 0x09:
+            value = struct.unpack_from("<q", data, position)[0]
+            position += 8
+        elif kind == 0x0A:
+            value = None
+        elif kind == 0x12:
+            value = struct.unpack_from("<q", data, position)[0]
+            position += 8
+        else:
+            raise ValueError(f"unexpected BSON type {kind}")
+        document[field] = value
+    return document
+
+
+def wire_command(connection, command, sequence=None):
+    """
+    Sends a command as an OP_MSG, with an optional document sequence, and
+    produces the reply
+    """
+    body = struct.pack("<I", 0) + b"\x00" + bson_encode(command)
+    if sequence is not None:
+        name, documents = sequence
+        section = name.encode() + b"\x00" + b"".join(map(bson_encode, documents))
+        body += b"\x01" + struct.pack("<i", len(section) + 4) + section
+    connection.sendall(struct.pack("<iiii", len(body) + 16, 1, 0, 2013) + body)
+    header = connection.recv(16, socket.MSG_WAITALL)
+    length, _, response_to, op_code = struct.unpack("<iiii", header)
+    assert (response_to, op_code) == (1, 2013)
+    reply = connection.recv(length - 16, socket.MSG_WAITALL)
+    return bson_decode(reply[5:])
+
+
+def test_wire_protocol(server):
+    with socket.create_connection(WIRE_ADDRESS) as connection:
+        hello = wire_command(connection, {"hello": 1, "$db": "admin"})
+        assert hello["ok"] == 1.0
+        assert hello["isWritablePrimary"] is True
+
+        reply = wire_command(
+            connection,
+            {"insert": "wire", "ordered": True, "$db": "test"},
+            ("documents", [{"username": "johnperry", "age": 75}, {"username": "louiswu", "age": 200}]),
+        )
+        assert reply == {"n": 2, "ok": 1.0}
+
+        reply = wire_command(
+            connection,
+            {"find": "wire", "filter": {"age": {"$gt": 50}}, "batchSize": 1, "$db": "test"},
+        )
+        cursor = reply["cursor"]
+        assert cursor["firstBatch"] == [{"age": 75, "username": "johnperry"}]
+        assert cursor["ns"] == "test.wire"
+        reply = wire_command(
+            connection, {"getMore": cursor["id"], "collection": "wire", "$db": "test"}
+        )
+        assert reply["cursor"]["nextBatch"] == [{"age": 200, "username": "louiswu"}]
+        assert reply["cursor"]["id"] == 0
+
+        reply = wire_command(
+            connection,
+            {"find": "wire", "filter": {"username": {"$in": ["louiswu", "nessus"]}}, "$db": "test"},
+        )
+        assert reply["cursor"]["firstBatch"] == [{"age": 200, "username": "louiswu"}]
+
+        reply = wire_command(
+            connection,
+            {
+                "update": "wire",
+                "updates": [{"q": {"username": "louiswu"}, "u": {"$inc": {"age": 1}}}],
+                "$db": "test",
+            },
+        )
+        assert (reply["n"], reply["nModified"]) == (1, 1)
+
+        reply = wire_command(
+            connection,
+            {"delete": "wire", "deletes": [{"q": {"age": {"$lt": 100}}, "limit": 0}], "$db": "test"},
+        )
+        assert reply == {"n": 1, "ok": 1.0}
+
+        reply = wire_command(connection, {"unknownCommand": 1, "$db": "test"})
+        assert (reply["ok"], reply["code"]) == (0.0, 59)
+
+    response = httpx.get("http://127.0.0.1:8000/api/v2/wire", params={"query": "{age:201}"})
+    assert response.json() == [{"username": "louiswu", "age": 201}]
+
+
+def test_capped_collection(server):
+    url = "http://127.0.0.1:8000/api/v2/audit"
+    response = httpx.put(url, json={"capped": True, "max": 3})
+    assert response.status_code == 201
+    assert response.json()["capped"] == {"max": 3}
+
+    response = httpx.post(url, json=[{"event": n} for n in range(5)])
+    assert response.status_code == 201
+    response = httpx.get(url)
+    assert response.json() == [{"event": 2}, {"event": 3}, {"event": 4}]
+    response = httpx.post(f"{url}/find", json={"sort": {"$natural": -1}, "limit": 1})
+    assert response.json() == [{"event": 4}]
+
+    with socket.create_connection(WIRE_ADDRESS) as connection:
+        reply = wire_command(
+            connection, {"find": "audit", "tailable": True, "$db": "test"}
+        )
+        cursor = reply["cursor"]
+        assert len(cursor["firstBatch"]) == 3
+        more = {"getMore": cursor["id"], "collection": "audit", "$db": "test"}
+        assert wire_command(connection, more)["cursor"]["nextBatch"] == []
+
+        httpx.post(url, json=[{"event": 5}])
+        reply = wire_command(connection, more)
+        assert reply["cursor"]["nextBatch"] == [{"event": 5}]
+        assert reply["cursor"]["id"] == cursor["id"]
+
+        httpx.post(url, json=[{"event": n} for n in range(6, 10)])
+        reply = wire_command(connection, more)
+        assert (reply["ok"], reply["code"]) == (0.0, 136)
+
+    response = httpx.put(url, json={"max": 3})
+    assert response.status_code == 400
+
+
+def test_text_search(server):
+    url = "http://127.0.0.1:8000/api/v2/books"
+    response = httpx.post(f"{url}/find", json={"filter": {"$text": {"$search": "ring"}}})
+    assert response.status_code == 400
+    assert response.json()["code"] == "INVALID_QUERY"
+
+    response = httpx.post(f"{url}/indexes", json={"fields": ["title"], "type": "text"})
+    assert response.status_code == 201
+    assert response.json()["name"] == "title_text"
+    assert response.json()["type"] == "text"
+
+    books = [
+        {"title": "Ringworld"},
+        {"title": "The Ringworld Engineers"},
+        {"title": "The Ringworld Throne"},
+        {"title": "Protector"},
+        {"title": "Ringworld's Children", "score": 4.5},
+    ]
+    response = httpx.post(url, json=books)
+    assert response.status_code == 201
+
+    query = {
+        "filter": {"$text": {"$search": "ringworld engineering"}},
+        "sort": {"$score": -1},
+        "projection": {"title": 1, "score": 1, "$score": 1},
+    }
+    found = httpx.post(f"{url}/find", json=query).json()
+    assert [book["title"] for book in found][0] == "The Ringworld Engineers"
+    assert len(found) == 4
+    assert all(book["$score"] > 0 for book in found)
+    children = next(book for book in found if book["title"] == "Ringworld's Children")
+    assert children["score"] == 4.5
+    assert [book for book in found if "score" in book] == [children]
+
+    query = {"filter": {"$text": {"$search": "ringworld -throne"}}}
+    found = httpx.post(f"{url}/find", json=query).json()
+    assert sorted(book["title"] for book in found) == [
+        "Ringworld",
+        "Ringworld's Children",
+        "The Ringworld Engineers",
+    ]
+
+
+def live_connect():
+    """
+    Opens a WebSocket connection to the live query listener
+    """
+    connection = socket.create_connection(LIVE_ADDRESS, timeout=5)
+    key = base64.b64encode(os.urandom(16)).decode()
+    connection.sendall(
+        (
+            "GET / HTTP/1.1\r\nHost: localhost\r\nUpgrade: websocket\r\n"
+            f"Connection: Upgrade\r\nSec-WebSocket-Key: {key}\r\n"
+            "Sec-WebSocket-Version: 13\r\n\r\n"
+        ).encode()
+    )
+    response = b""
+    while not response.endswith(b"\r\n\r\n"):
+        response += connection.recv(1)
+    assert response.startswith(b"HTTP/1.1 101")
+    return connection
+
+
+def live_send(connection, message):
+    """
+    Sends a JSON message as a masked text frame, as clients must
+    """
+    payload = json.dumps(message).encode()
+    mask = os.urandom(4)
+    assert len(payload) < 126
+    masked = bytes(byte ^ mask[i % 4] for i, byte in enumerate(payload))
+    connection.sendall(bytes([0x81, 0x80 | len(payload)]) + mask + masked)
+
+
+def live_receive(connection):
+    """
+    Produces the JSON message of the next text frame
+    """
+    opcode, length = connection.recv(2, socket.MSG_WAITALL)
+    assert opcode == 0x81
+    if length == 126:
+        (length,) = struct.unpack(">H", connection.recv(2, socket.MSG_WAITALL))
+    elif length == 127:
+        (length,) = struct.unpack(">Q", connection.recv(8, socket.MSG_WAITALL))
+    return json.loads(connection.recv(length, socket.MSG_WAITALL))
+
+
+def test_live_query(server):
+    base = "http://127.0.0.1:8000/api/v2"
+    httpx.put(f"{base}/fleet", json={})
+    httpx.post(f"{base}/fleet", json=[{"ship": "Sparrow", "crew": 4}, {"ship": "Kestrel", "crew": 40}])
+
+    with live_connect() as connection:
+        live_send(connection, {"subscribe": "large", "collection": "fleet", "filter": {"crew": {"$gte": 10}}})
+        initial = live_receive(connection)
+        assert initial["type"] == "initial"
+        assert [document["document"]["ship"] for document in initial["documents"]] == ["Kestrel"]
+
+        httpx.patch(f"{base}/fleet", params={"query": '{ship:"Sparrow"}'}, json={"$set": {"crew": 12}})
+        added = live_receive(connection)
+        assert (added["type"], added["query"]) == ("added", "large")
+        assert added["document"] == {"ship": "Sparrow", "crew": 12}
+
+        httpx.patch(f"{base}/fleet", params={"query": '{ship:"Kestrel"}'}, json={"$inc": {"crew": 1}})
+        assert live_receive(connection)["type"] == "changed"
+        httpx.delete(f"{base}/fleet", params={"query": '{ship:"Kestrel"}'})
+        removed = live_receive(connection)
+        assert removed == {"type": "removed", "query": "large", "id": initial["documents"][0]["id"]}
+
+        live_send(connection, {"subscribe": "large", "collection": "fleet"})
+        assert live_receive(connection)["code"] == "INVALID_OPTIONS"
+
+        httpx.delete(f"{base}/fleet/drop")
+        assert live_receive(connection) == {"type": "invalidate", "query": "large"}